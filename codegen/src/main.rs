@@ -34,8 +34,10 @@ fn main() -> Result<()> {
   config.protoc_arg(&format!("-I={}", proto_dir.display()));
   config.protoc_arg(&format!("-I={}", vendor_dir.display()));
 
+  let descriptor_file = target_dir.join("v1beta1.bin");
   tonic_build::configure()
     .out_dir(&target_dir)
+    .file_descriptor_set_path(&descriptor_file)
     .build_client(true)
     .build_server(true)
     .compile_with_config(config, &[csi_proto_file], &[])?;
@@ -48,6 +50,10 @@ fn main() -> Result<()> {
   }
 
   fs::copy(&csi_file, &target_file)?;
+  fs::copy(
+    &descriptor_file,
+    proto_crate_src_dir.join("v1beta1/descriptor.bin"),
+  )?;
 
   Ok(())
 }
@@ -1,8 +1,13 @@
+#[cfg(feature = "reflection")]
+mod reflection;
 mod server;
+#[cfg(all(feature = "v1beta1", any(test, feature = "test-util")))]
+pub mod testkit;
 pub(crate) mod transport;
+mod watch;
 
 #[cfg(feature = "v1beta1")]
 pub mod v1beta1;
 
-pub use server::KubernetesDevicePluginServer;
+pub use server::{KubernetesDevicePluginServer, Registration};
 pub use tonic;
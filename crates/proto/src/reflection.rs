@@ -0,0 +1,182 @@
+//! gRPC server reflection, served next to the device plugin service for debugging.
+
+use crate::v1beta1::FILE_DESCRIPTOR_SET;
+use futures::future::Either;
+use hyper::{Body, Request, Response};
+use std::task::{Context, Poll};
+use tonic::{
+  body::BoxBody,
+  codegen::{BoxFuture, Never},
+};
+use tonic_reflection::server::{Builder, Error};
+use tower::Service;
+
+const REFLECTION_PATH: &str = "/grpc.reflection.v1alpha.ServerReflection/";
+
+/// What the reflection service answers a request with
+type ReflectionFuture = BoxFuture<Response<BoxBody>, Never>;
+
+/// Reflection service describing the v1beta1 protocol. tonic-reflection doesn't export the
+/// server type it builds, so it's only exposed as the service it is.
+pub(crate) fn service() -> Result<
+  impl Service<Request<Body>, Response = Response<BoxBody>, Error = Never, Future = ReflectionFuture>
+    + Clone,
+  Error,
+> {
+  Builder::configure()
+    .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+    .build()
+}
+
+/// Routes reflection requests to the reflection service when enabled, and everything else to
+/// the wrapped service.
+#[derive(Clone)]
+pub(crate) struct WithReflection<S, R> {
+  inner: S,
+  reflection: R,
+  enabled: bool,
+}
+
+impl<S, R> WithReflection<S, R> {
+  pub fn new(inner: S, reflection: R, enabled: bool) -> Self {
+    Self {
+      inner,
+      reflection,
+      enabled,
+    }
+  }
+}
+
+impl<S, R> Service<Request<Body>> for WithReflection<S, R>
+where
+  S: Service<Request<Body>, Response = Response<BoxBody>, Error = Never>,
+  R: Service<Request<Body>, Response = Response<BoxBody>, Error = Never>,
+{
+  type Response = Response<BoxBody>;
+  type Error = Never;
+  type Future = Either<S::Future, R::Future>;
+
+  fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    match self.inner.poll_ready(cx) {
+      Poll::Ready(Ok(())) => self.reflection.poll_ready(cx),
+      other => other,
+    }
+  }
+
+  fn call(&mut self, req: Request<Body>) -> Self::Future {
+    if self.enabled && req.uri().path().starts_with(REFLECTION_PATH) {
+      Either::Right(self.reflection.call(req))
+    } else {
+      Either::Left(self.inner.call(req))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::transport::{Svc, UnixSocketListener};
+  use futures::stream;
+  use hyper::{http::uri::PathAndQuery, Server, Uri};
+  use std::convert::TryFrom;
+  use tokio::{net::UnixStream, task};
+  use tonic::{client::Grpc, codec::ProstCodec, transport::Endpoint};
+  use tower::service_fn;
+
+  // tonic-reflection keeps its generated types private, so the messages the test needs are
+  // declared here, matching grpc/reflection/v1alpha/reflection.proto
+
+  #[derive(Clone, PartialEq, prost::Message)]
+  struct ServerReflectionRequest {
+    #[prost(string, tag = "1")]
+    host: String,
+    #[prost(oneof = "MessageRequest", tags = "7")]
+    message_request: Option<MessageRequest>,
+  }
+
+  #[derive(Clone, PartialEq, prost::Oneof)]
+  enum MessageRequest {
+    #[prost(string, tag = "7")]
+    ListServices(String),
+  }
+
+  #[derive(Clone, PartialEq, prost::Message)]
+  struct ServerReflectionResponse {
+    #[prost(oneof = "MessageResponse", tags = "6")]
+    message_response: Option<MessageResponse>,
+  }
+
+  #[derive(Clone, PartialEq, prost::Oneof)]
+  enum MessageResponse {
+    #[prost(message, tag = "6")]
+    ListServicesResponse(ListServiceResponse),
+  }
+
+  #[derive(Clone, PartialEq, prost::Message)]
+  struct ListServiceResponse {
+    #[prost(message, repeated, tag = "1")]
+    service: Vec<ServiceResponse>,
+  }
+
+  #[derive(Clone, PartialEq, prost::Message)]
+  struct ServiceResponse {
+    #[prost(string, tag = "1")]
+    name: String,
+  }
+
+  #[tokio::test]
+  async fn lists_device_plugin_service() {
+    let socket_path = std::env::temp_dir().join(format!("reflection-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket_path);
+
+    // nothing but reflection requests are sent, so the plugin side can be a stub
+    let plugin =
+      service_fn(|_: Request<Body>| async { Ok::<_, Never>(Response::new(BoxBody::empty())) });
+    let routed = WithReflection::new(plugin, service().unwrap(), true);
+    let listener = UnixSocketListener::bind(&socket_path).unwrap();
+    let server = task::spawn(
+      Server::builder(listener)
+        .http2_only(true)
+        .serve(Svc::new(routed, None, None)),
+    );
+
+    let path = socket_path.clone();
+    let channel = Endpoint::try_from("http://[::]:50051")
+      .unwrap()
+      .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+      .await
+      .unwrap();
+
+    let request = ServerReflectionRequest {
+      host: String::new(),
+      message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut client = Grpc::new(channel);
+    client.ready().await.unwrap();
+    let mut responses = client
+      .streaming(
+        tonic::Request::new(stream::iter(vec![request])),
+        PathAndQuery::from_static("/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo"),
+        ProstCodec::<ServerReflectionRequest, ServerReflectionResponse>::default(),
+      )
+      .await
+      .unwrap()
+      .into_inner();
+
+    let response = responses.message().await.unwrap().unwrap();
+    let services = match response.message_response {
+      Some(MessageResponse::ListServicesResponse(list)) => {
+        list.service.into_iter().map(|s| s.name).collect::<Vec<_>>()
+      }
+      other => panic!("unexpected response: {:?}", other),
+    };
+
+    server.abort();
+    let _ = std::fs::remove_file(&socket_path);
+    assert!(
+      services.iter().any(|s| s == "v1beta1.DevicePlugin"),
+      "{:?}",
+      services
+    );
+  }
+}
@@ -1,38 +1,79 @@
+use crate::transport::remove_stale_socket;
 use futures::{
-  future::{Fuse, FusedFuture},
+  future::{FusedFuture, Map, Shared},
   FutureExt,
 };
-use pin_project::pin_project;
 use static_assertions::assert_impl_all;
 use std::{
   fmt,
   future::Future,
   panic,
+  path::PathBuf,
   pin::Pin,
   task::{Context, Poll},
+  time::Duration,
 };
 use tokio::{
-  sync::oneshot::{self, Sender},
-  task::JoinHandle,
+  sync::oneshot::{self, error::RecvError, Sender},
+  task::{JoinError, JoinHandle},
+  time,
 };
+use tracing::{event, Level};
 
-#[pin_project]
-pub struct Signal(#[pin] oneshot::Receiver<()>);
+/// Resolves once the server should drain, i.e. stop accepting connections and end open
+/// streams. Clones observe the same signal, so it can be handed to every connection.
+#[derive(Clone)]
+pub struct Signal(Shared<Fired>);
+
+/// The drain channel, resolving to `()` whether it was sent on or dropped
+type Fired = Map<oneshot::Receiver<()>, fn(Result<(), RecvError>)>;
+
+impl Signal {
+  /// A signal, along with the sender firing it. Dropping the sender fires it as well.
+  pub(crate) fn channel() -> (Sender<()>, Self) {
+    let (sender, receiver) = oneshot::channel::<()>();
+    // shared futures need a cloneable output, which the receive error isn't
+    let fired = receiver.map(drop as fn(Result<(), RecvError>));
+    (sender, Self(fired.shared()))
+  }
+}
 
 impl Future for Signal {
   type Output = ();
 
-  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-    match self.project().0.poll(cx) {
-      Poll::Pending => Poll::Pending,
-      Poll::Ready(_) => Poll::Ready(()),
-    }
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    self.0.poll_unpin(cx)
   }
 }
 
+/// What was registered with the kubelet, as accepted by it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+  /// Version of the device plugin API registered against
+  pub version: &'static str,
+
+  /// Resource name the plugin is registered for
+  pub resource_name: String,
+
+  /// Plugin socket the kubelet was told to connect to
+  pub endpoint: PathBuf,
+
+  /// Whether the kubelet calls PreStartContainer before each container start
+  pub pre_start_required: bool,
+
+  /// Whether the kubelet may call GetPreferredAllocation
+  pub get_preferred_allocation_available: bool,
+}
+
+#[must_use = "dropping the server shuts it down"]
 pub struct KubernetesDevicePluginServer {
-  abort_channel: Sender<()>,
-  handle: Fuse<JoinHandle<hyper::Result<()>>>,
+  drain_channel: Sender<()>,
+  drain_signal: Signal,
+  handle: Option<JoinHandle<hyper::Result<()>>>,
+  registration: Option<Registration>,
+
+  /// Socket the server listens on, removed once it's stopped
+  socket: Option<PathBuf>,
 }
 
 assert_impl_all!(KubernetesDevicePluginServer: Unpin);
@@ -40,37 +81,111 @@ assert_impl_all!(KubernetesDevicePluginServer: Unpin);
 impl fmt::Debug for KubernetesDevicePluginServer {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     f.debug_struct(stringify!(KubernetesDevicePluginServer))
+      .field("registration", &self.registration)
       .finish_non_exhaustive()
   }
 }
 
 impl KubernetesDevicePluginServer {
   pub(crate) fn start(f: impl FnOnce(Signal) -> JoinHandle<hyper::Result<()>>) -> Self {
-    let (abort_channel, receiver) = oneshot::channel::<()>();
-    let handle = f(Signal(receiver)).fuse();
+    let (drain_channel, drain_signal) = Signal::channel();
+    let handle = f(drain_signal.clone());
 
     Self {
-      abort_channel,
-      handle,
+      drain_channel,
+      drain_signal,
+      handle: Some(handle),
+      registration: None,
+      socket: None,
     }
   }
 
-  pub async fn abort(self) -> hyper::Result<()> {
-    if self.is_terminated() {
-      return Ok(());
-    }
+  pub(crate) fn set_socket(&mut self, socket: PathBuf) {
+    self.socket = Some(socket);
+  }
 
-    let _ = self.abort_channel.send(());
+  pub(crate) fn set_registration(&mut self, registration: Registration) {
+    self.registration = Some(registration);
+  }
 
-    match self.handle.await {
-      Ok(result) => result,
-      Err(e) if e.is_cancelled() => unreachable!(),
-      Err(e) => panic::resume_unwind(e.into_panic()),
-    }
+  /// Fires once the server starts draining, for tasks which should end along with it.
+  pub(crate) fn drain_signal(&self) -> Signal {
+    self.drain_signal.clone()
+  }
+
+  /// The registration accepted by the kubelet, if the plugin has been registered.
+  pub fn registration(&self) -> Option<&Registration> {
+    self.registration.as_ref()
+  }
+
+  /// Stops the server immediately, dropping open connections and in-flight calls.
+  pub async fn abort(mut self) -> hyper::Result<()> {
+    let result = match self.handle.take() {
+      None => Ok(()),
+      Some(handle) => {
+        handle.abort();
+        join_result(handle.await)
+      }
+    };
+
+    remove_socket(self.socket.take());
+    result
+  }
+
+  /// Stops accepting new connections and ends open ListAndWatch streams cleanly, letting
+  /// in-flight calls finish. Whatever is still running after `grace` is aborted.
+  pub async fn shutdown(mut self, grace: Duration) -> hyper::Result<()> {
+    let mut handle = match self.handle.take() {
+      None => return Ok(()),
+      Some(handle) => handle,
+    };
+
+    // sending consumes the channel, and with it the server
+    let socket = self.socket.take();
+    // the server only stops listening for the signal once it has terminated
+    let _ = self.drain_channel.send(());
+
+    let result = match time::timeout(grace, &mut handle).await {
+      Ok(result) => join_result(result),
+      Err(_) => {
+        handle.abort();
+        join_result(handle.await)
+      }
+    };
+
+    remove_socket(socket);
+    result
   }
 
   pub fn is_terminated(&self) -> bool {
-    self.handle.is_terminated()
+    self.handle.is_none()
+  }
+}
+
+/// Removes the socket of a stopped server, so the kubelet doesn't find it lingering. Sockets
+/// something else listens on by now are left alone.
+fn remove_socket(socket: Option<PathBuf>) {
+  let socket = match socket {
+    None => return,
+    Some(socket) => socket,
+  };
+
+  if let Err(e) = remove_stale_socket(&socket) {
+    event!(
+      Level::WARN,
+      socket = %socket.display(),
+      "failed to remove plugin socket: {}",
+      e
+    );
+  }
+}
+
+fn join_result(result: Result<hyper::Result<()>, JoinError>) -> hyper::Result<()> {
+  match result {
+    Ok(result) => result,
+    // only happens through an explicit abort
+    Err(e) if e.is_cancelled() => Ok(()),
+    Err(e) => panic::resume_unwind(e.into_panic()),
   }
 }
 
@@ -78,13 +193,19 @@ impl Future for KubernetesDevicePluginServer {
   type Output = hyper::Result<()>;
 
   fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-    match self.get_mut().handle.poll_unpin(cx) {
+    let this = self.get_mut();
+    let handle = match this.handle.as_mut() {
+      // fused: a terminated server never resolves again
+      None => return Poll::Pending,
+      Some(handle) => handle,
+    };
+
+    match handle.poll_unpin(cx) {
       Poll::Pending => Poll::Pending,
-      Poll::Ready(result) => match result {
-        Ok(result) => Poll::Ready(result),
-        Err(e) if e.is_cancelled() => unreachable!(),
-        Err(e) => panic::resume_unwind(e.into_panic()),
-      },
+      Poll::Ready(result) => {
+        this.handle = None;
+        Poll::Ready(join_result(result))
+      }
     }
   }
 }
@@ -0,0 +1,161 @@
+//! Test utilities for device plugins, enabled by the `test-util` feature.
+
+use crate::{
+  transport::{Svc, UnixSocketListener},
+  v1beta1::{proto, RegisterRequest},
+};
+use async_trait::async_trait;
+use hyper::Server;
+use std::{
+  io,
+  path::Path,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{
+  sync::Notify,
+  task::{self, JoinHandle},
+  time,
+};
+
+#[derive(Default)]
+struct Registrations {
+  requests: Mutex<Vec<RegisterRequest>>,
+  received: Notify,
+}
+
+#[async_trait]
+impl proto::registration_server::Registration for Arc<Registrations> {
+  async fn register(
+    &self,
+    request: tonic::Request<proto::RegisterRequest>,
+  ) -> Result<tonic::Response<proto::Empty>, tonic::Status> {
+    self
+      .requests
+      .lock()
+      .unwrap()
+      .push(request.into_inner().into());
+    self.received.notify_one();
+
+    Ok(tonic::Response::new(proto::Empty {}))
+  }
+}
+
+/// A kubelet registration server, accepting and recording every plugin registering with it.
+/// The server stops when dropped.
+pub struct MockKubelet {
+  registrations: Arc<Registrations>,
+  seen: usize,
+  server: JoinHandle<hyper::Result<()>>,
+}
+
+impl MockKubelet {
+  /// Serves the registration service on the unix socket at `path`, like the kubelet does on
+  /// its `kubelet.sock`. Must be called within a tokio runtime.
+  pub fn start(path: impl AsRef<Path>) -> io::Result<Self> {
+    let registrations = Arc::new(Registrations::default());
+    let service = proto::registration_server::RegistrationServer::new(registrations.clone());
+    let listener = UnixSocketListener::bind(path)?;
+    let server = task::spawn(
+      Server::builder(listener)
+        .http2_only(true)
+        .serve(Svc::new(service, None, None)),
+    );
+
+    Ok(Self {
+      registrations,
+      seen: 0,
+      server,
+    })
+  }
+
+  /// Every registration received so far, in order
+  pub fn registrations(&self) -> Vec<RegisterRequest> {
+    self.registrations.requests.lock().unwrap().clone()
+  }
+
+  /// The registration following the last one returned from here, waiting up to `timeout`
+  /// for it to arrive.
+  pub async fn next_registration(&mut self, timeout: Duration) -> Option<RegisterRequest> {
+    let registrations = &self.registrations;
+    let seen = self.seen;
+    let next = async {
+      loop {
+        if let Some(request) = registrations.requests.lock().unwrap().get(seen) {
+          return request.clone();
+        }
+
+        registrations.received.notified().await;
+      }
+    };
+
+    let request = time::timeout(timeout, next).await.ok()?;
+    self.seen += 1;
+    Some(request)
+  }
+}
+
+impl Drop for MockKubelet {
+  fn drop(&mut self) {
+    self.server.abort();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::v1beta1::{
+    AllocateRequest, AllocateResponse, DevicePlugin, KubeletDevicePluginV1Beta1,
+    ListAndWatchResponse, PluginPaths, VERSION,
+  };
+  use futures::stream::{self, Pending};
+
+  struct EmptyPlugin;
+
+  #[async_trait]
+  impl DevicePlugin for EmptyPlugin {
+    type ListAndWatchStream = Pending<Result<ListAndWatchResponse, tonic::Status>>;
+
+    async fn list_and_watch(&self) -> Result<Self::ListAndWatchStream, tonic::Status> {
+      Ok(stream::pending())
+    }
+
+    async fn allocate(&self, _: AllocateRequest) -> Result<AllocateResponse, tonic::Status> {
+      Err(tonic::Status::unimplemented("allocate"))
+    }
+  }
+
+  #[tokio::test]
+  async fn records_plugin_registrations() {
+    let dir = std::env::temp_dir().join(format!("mock-kubelet-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let paths = PluginPaths::in_dir(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut kubelet = MockKubelet::start(&paths.kubelet_socket).unwrap();
+    let server = KubeletDevicePluginV1Beta1::new(EmptyPlugin)
+      .with_paths(paths)
+      .start("udev/tty/conbee2")
+      .await
+      .unwrap();
+
+    let registration = kubelet
+      .next_registration(Duration::from_secs(5))
+      .await
+      .unwrap();
+    assert_eq!(registration.version, VERSION);
+    assert_eq!(registration.resource_name, "udev/tty/conbee2");
+    assert_eq!(
+      Path::new(&registration.endpoint),
+      server.registration().unwrap().endpoint
+    );
+    assert_eq!(kubelet.registrations(), vec![registration]);
+    assert!(kubelet
+      .next_registration(Duration::from_millis(50))
+      .await
+      .is_none());
+
+    server.shutdown(Duration::from_secs(1)).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
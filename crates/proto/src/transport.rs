@@ -1,32 +1,155 @@
 // most of this taken from tonic to be able to use hyper directly
 
+use crate::server::Signal;
 use futures::{
   future::{ready, Ready},
-  Stream,
+  FutureExt, Stream,
 };
-use hyper::{server::accept::Accept, Body, Request, Response};
+use hyper::{
+  body::{Bytes, HttpBody},
+  header::HeaderValue,
+  server::accept::Accept,
+  Body, HeaderMap, Request, Response,
+};
+use pin_project::pin_project;
 use std::{
+  fs,
+  future::Future,
   io::{self, IoSlice},
-  path::Path,
+  os::unix::fs::{FileTypeExt, PermissionsExt},
+  path::{Path, PathBuf},
   pin::Pin,
   task::{Context, Poll},
 };
-use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::{
+  io::{AsyncRead, AsyncWrite, ReadBuf},
+  net::UnixListener,
+  sync::mpsc,
+};
 use tokio_stream::wrappers::UnixListenerStream;
-use tonic::{body::BoxBody, codegen::Never, transport::server::Connected};
+use tonic::{body::BoxBody, codegen::Never, transport::server::Connected, Status};
 use tower::Service;
 use tracing::{instrument::Instrumented, Instrument, Span};
 
-pub struct UnixSocketListener(UnixListenerStream);
+pub struct UnixSocketListener {
+  listener: UnixListenerStream,
+  path: PathBuf,
+  mode: Option<u32>,
+
+  /// Sockets bound by a [`SocketRebinder`], replacing the one listened on
+  replacements: Option<mpsc::UnboundedReceiver<UnixListener>>,
+}
+
 pub struct UnixSocket(tokio::net::UnixStream);
 
 impl UnixSocketListener {
+  /// Binds a listener at `path`, replacing a dead socket left there by an earlier run.
+  #[cfg(any(test, feature = "test-util"))]
   pub fn bind<P>(path: P) -> io::Result<Self>
   where
     P: AsRef<Path>,
   {
-    let listener = tokio::net::UnixListener::bind(path)?;
-    Ok(Self(UnixListenerStream::new(listener)))
+    Self::bind_with_mode(path, None)
+  }
+
+  /// Binds a listener at `path`, replacing a dead socket left there by an earlier run. The
+  /// permissions of the socket are changed to `mode` instead of left to the umask, if given.
+  pub fn bind_with_mode<P>(path: P, mode: Option<u32>) -> io::Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let path = path.as_ref();
+    Ok(Self {
+      listener: UnixListenerStream::new(bind_socket(path, mode)?),
+      path: path.to_owned(),
+      mode,
+      replacements: None,
+    })
+  }
+
+  /// A handle binding the socket anew, for when its file was removed from under the listener
+  pub(crate) fn rebinder(&mut self) -> SocketRebinder {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    self.replacements = Some(receiver);
+    SocketRebinder {
+      path: self.path.clone(),
+      mode: self.mode,
+      sender,
+    }
+  }
+}
+
+/// Binds a socket at `path`, with permissions `mode` if given.
+///
+/// The socket is created with the permissions the umask leaves and only changed to `mode`
+/// afterwards, so for a moment it may be more open than asked for. The umask isn't narrowed
+/// instead, as it's shared by every thread of the process; the directory the socket is in
+/// has to keep out whoever `mode` is meant to.
+fn bind_socket(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+  remove_stale_socket(path)?;
+  let listener = UnixListener::bind(path)?;
+
+  if let Some(mode) = mode {
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+      drop(listener);
+      let _ = fs::remove_file(path);
+      return Err(e);
+    }
+  }
+
+  Ok(listener)
+}
+
+/// Binds the socket of a [`UnixSocketListener`] again, which then accepts connections on the
+/// new socket instead. Connections accepted before are left alone.
+#[derive(Debug)]
+pub(crate) struct SocketRebinder {
+  path: PathBuf,
+  mode: Option<u32>,
+  sender: mpsc::UnboundedSender<UnixListener>,
+}
+
+impl SocketRebinder {
+  /// Binds a new socket unless the socket file is still there, like after the kubelet wiped
+  /// the plugin directory on restart. Returns whether it bound one.
+  pub(crate) fn rebind_if_removed(&self) -> io::Result<bool> {
+    match fs::symlink_metadata(&self.path) {
+      Ok(_) => return Ok(false),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+      Err(e) => return Err(e),
+    }
+
+    let listener = bind_socket(&self.path, self.mode)?;
+    if self.sender.send(listener).is_err() {
+      // the listener is gone, so nothing would accept connections on the new socket
+      let _ = fs::remove_file(&self.path);
+      return Err(io::Error::new(
+        io::ErrorKind::NotConnected,
+        "the listener to rebind the socket for is gone",
+      ));
+    }
+
+    Ok(true)
+  }
+}
+
+/// Removes the socket at `path` if nothing accepts connections on it anymore. Returns whether
+/// it was removed; anything that isn't a socket is left alone.
+pub(crate) fn remove_stale_socket(path: &Path) -> io::Result<bool> {
+  match fs::symlink_metadata(path) {
+    Ok(metadata) if metadata.file_type().is_socket() => (),
+    Ok(_) => return Ok(false),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+    Err(e) => return Err(e),
+  }
+
+  match std::os::unix::net::UnixStream::connect(path) {
+    Ok(_) => Ok(false),
+    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+      fs::remove_file(path)?;
+      Ok(true)
+    }
+    Err(e) => Err(e),
   }
 }
 
@@ -46,7 +169,15 @@ impl Stream for UnixSocketListener {
   type Item = io::Result<UnixSocket>;
 
   fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-    match Pin::new(&mut self.0).poll_next(cx) {
+    while let Some(replacements) = self.replacements.as_mut() {
+      match replacements.poll_recv(cx) {
+        Poll::Ready(Some(listener)) => self.listener = UnixListenerStream::new(listener),
+        Poll::Ready(None) => self.replacements = None,
+        Poll::Pending => break,
+      }
+    }
+
+    match Pin::new(&mut self.listener).poll_next(cx) {
       Poll::Ready(None) => Poll::Ready(None),
       Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
       Poll::Ready(Some(Ok(s))) => Poll::Ready(Some(Ok(UnixSocket(s)))),
@@ -109,16 +240,18 @@ pub(crate) struct Svc<S> {
   // timeout: Option<Duration>,
   inner: S,
   span: Option<Span>,
+  drain: Option<Signal>,
 }
 
 impl<S> Svc<S>
 where
   S: Service<Request<Body>, Response = Response<BoxBody>, Error = Never>,
 {
-  pub fn new(service: S, span: Option<Span>) -> Self {
+  pub fn new(service: S, span: Option<Span>, drain: Option<Signal>) -> Self {
     Self {
       inner: service,
       span,
+      drain,
     }
   }
 }
@@ -129,7 +262,7 @@ where
 {
   type Response = Response<BoxBody>;
   type Error = Never;
-  type Future = Instrumented<S::Future>;
+  type Future = DrainFuture<Instrumented<S::Future>>;
 
   fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
     self.inner.poll_ready(cx)
@@ -137,7 +270,10 @@ where
 
   fn call(&mut self, req: Request<Body>) -> Self::Future {
     let span = self.span.clone().unwrap_or_else(Span::none);
-    self.inner.call(req).instrument(span)
+    DrainFuture {
+      inner: self.inner.call(req).instrument(span),
+      drain: self.drain.clone(),
+    }
   }
 }
 
@@ -157,3 +293,91 @@ where
     ready(Ok(self.clone()))
   }
 }
+
+/// Wraps response bodies in a [`DrainBody`], so open streams end when the server drains.
+#[pin_project]
+pub(crate) struct DrainFuture<F> {
+  #[pin]
+  inner: F,
+  drain: Option<Signal>,
+}
+
+impl<F> Future for DrainFuture<F>
+where
+  F: Future<Output = Result<Response<BoxBody>, Never>>,
+{
+  type Output = Result<Response<BoxBody>, Never>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.project();
+    let response = futures::ready!(this.inner.poll(cx))?;
+    let response = match this.drain.take() {
+      None => response,
+      Some(drain) => response.map(|body| BoxBody::new(DrainBody::new(body, drain))),
+    };
+
+    Poll::Ready(Ok(response))
+  }
+}
+
+/// A response body which ends with an OK status once the server drains, instead of waiting
+/// on more data. Frames which are already available are still sent, so unary responses
+/// are never cut short.
+pub(crate) struct DrainBody {
+  inner: BoxBody,
+  drain: Signal,
+  drained: bool,
+}
+
+impl DrainBody {
+  fn new(inner: BoxBody, drain: Signal) -> Self {
+    Self {
+      inner,
+      drain,
+      drained: false,
+    }
+  }
+}
+
+impl HttpBody for DrainBody {
+  type Data = Bytes;
+  type Error = Status;
+
+  fn poll_data(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+    if self.drained {
+      return Poll::Ready(None);
+    }
+
+    if let Poll::Ready(frame) = Pin::new(&mut self.inner).poll_data(cx) {
+      return Poll::Ready(frame);
+    }
+
+    match self.drain.poll_unpin(cx) {
+      Poll::Pending => Poll::Pending,
+      Poll::Ready(()) => {
+        self.drained = true;
+        Poll::Ready(None)
+      }
+    }
+  }
+
+  fn poll_trailers(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+    if !self.drained {
+      return Pin::new(&mut self.inner).poll_trailers(cx);
+    }
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from_static("0"));
+    Poll::Ready(Ok(Some(trailers)))
+  }
+
+  fn is_end_stream(&self) -> bool {
+    !self.drained && self.inner.is_end_stream()
+  }
+}
@@ -1,9 +1,9 @@
-mod proto;
+pub(crate) mod proto;
 mod types;
 
 use async_trait::async_trait;
-use futures::{stream::TryStream, Stream, TryStreamExt};
-use hyper::{Server, Uri};
+use futures::{stream::TryStream, Stream, StreamExt, TryStreamExt};
+use hyper::{server::Builder, Server, Uri};
 use std::{
   convert::TryFrom,
   path::{Path, PathBuf},
@@ -12,16 +12,18 @@ use std::{
   time::Duration,
 };
 use thiserror::Error;
-use tokio::{io, net::UnixStream, task};
+use tokio::{io, net::UnixStream, task, time};
 use tonic::transport::Endpoint;
 use tower::service_fn;
-use tracing::{span, Instrument, Level, Span};
+use tracing::{event, span, Instrument, Level, Span};
 
 pub use types::*;
 
 use crate::{
-  transport::{Svc, UnixSocketListener},
-  KubernetesDevicePluginServer,
+  server::Signal,
+  transport::{remove_stale_socket, SocketRebinder, Svc, UnixSocketListener},
+  watch::SocketWatcher,
+  KubernetesDevicePluginServer, Registration,
 };
 
 /// Means that the device is healthy.
@@ -41,6 +43,9 @@ pub const DEVICE_PLUGIN_PATH: &str = "/var/lib/kubelet/device-plugins/";
 /// The path of the kubelet registry socket.
 pub const KUBELET_SOCKET: &str = "/var/lib/kubelet/device-plugins/kubelet.sock";
 
+/// How long recreations of the kubelet socket are debounced before registering again.
+const KUBELET_SOCKET_DEBOUNCE: Duration = Duration::from_secs(1);
+
 /// Timeout duration in secs for PreStartContainer RPC.
 pub const KUBELET_PRE_START_CONTAINER_RPC_TIMEOUT_IN_SECS: Duration = Duration::from_secs(30);
 
@@ -125,15 +130,203 @@ pub trait DevicePluginService: Send + Sync + 'static {
   ) -> Result<PreferredAllocationResponse, tonic::Status>;
 }
 
+/// Timeout for the self-connect probe done when waiting for the plugin server to be serving.
+const SERVING_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Encoded `FileDescriptorSet` of the v1beta1 protocol, produced by the codegen. Lets tooling
+/// (e.g. gRPC reflection or dynamic clients) understand the API without the `.proto` files.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("v1beta1/descriptor.bin");
+
+/// How registering with the kubelet is retried, e.g. while the kubelet is still starting up
+/// after a node boot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistrationOptions {
+  /// Attempts made before giving up, the first one included
+  pub max_attempts: u32,
+
+  /// Delay before the first retry, doubled for each retry after it
+  pub base_delay: Duration,
+
+  /// Longest delay between two attempts, the doubling stops there
+  pub max_delay: Duration,
+}
+
+impl RegistrationOptions {
+  /// Delay before the retry following one delayed by `delay`
+  fn next_delay(&self, delay: Duration) -> Duration {
+    delay.saturating_mul(2).min(self.max_delay)
+  }
+}
+
+impl Default for RegistrationOptions {
+  fn default() -> Self {
+    Self {
+      max_attempts: 5,
+      base_delay: Duration::from_millis(500),
+      max_delay: Duration::from_secs(30),
+    }
+  }
+}
+
+/// HTTP/2 settings of the plugin server. ListAndWatch streams stay open as long as the kubelet
+/// is connected, so keep-alive pings are on by default to notice a kubelet that went away
+/// without closing its connection. Unset sizes and limits keep hyper's defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerTuning {
+  /// Interval of keep-alive pings, or `None` to not send any
+  pub keep_alive_interval: Option<Duration>,
+
+  /// How long a keep-alive ping may go unacknowledged before the connection is closed
+  pub keep_alive_timeout: Duration,
+
+  /// Initial flow control window of each stream
+  pub initial_stream_window_size: Option<u32>,
+
+  /// Initial flow control window of each connection
+  pub initial_connection_window_size: Option<u32>,
+
+  /// Concurrent streams allowed per connection
+  pub max_concurrent_streams: Option<u32>,
+
+  /// Largest frame the server accepts
+  pub max_frame_size: Option<u32>,
+}
+
+impl Default for ServerTuning {
+  fn default() -> Self {
+    Self {
+      keep_alive_interval: Some(Duration::from_secs(60)),
+      keep_alive_timeout: Duration::from_secs(20),
+      initial_stream_window_size: None,
+      initial_connection_window_size: None,
+      max_concurrent_streams: None,
+      max_frame_size: None,
+    }
+  }
+}
+
+impl ServerTuning {
+  fn apply<I, E>(&self, builder: Builder<I, E>) -> Builder<I, E> {
+    let builder = builder
+      .http2_keep_alive_interval(self.keep_alive_interval)
+      .http2_keep_alive_timeout(self.keep_alive_timeout)
+      .http2_initial_stream_window_size(self.initial_stream_window_size)
+      .http2_initial_connection_window_size(self.initial_connection_window_size)
+      .http2_max_frame_size(self.max_frame_size);
+
+    match self.max_concurrent_streams {
+      Some(max) => builder.http2_max_concurrent_streams(max),
+      None => builder,
+    }
+  }
+}
+
+/// Where plugin sockets are created and the kubelet is reached. Defaults to the standard
+/// kubelet paths, but distributions like k3s use their own kubelet root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginPaths {
+  /// Directory plugin sockets are created in, created if missing
+  pub device_plugin_dir: PathBuf,
+
+  /// Registration socket of the kubelet
+  pub kubelet_socket: PathBuf,
+}
+
+impl PluginPaths {
+  /// Paths for a kubelet serving its registration socket from its device plugin directory,
+  /// like it does by default.
+  pub fn in_dir(device_plugin_dir: impl Into<PathBuf>) -> Self {
+    let device_plugin_dir = device_plugin_dir.into();
+    Self {
+      kubelet_socket: device_plugin_dir.join("kubelet.sock"),
+      device_plugin_dir,
+    }
+  }
+}
+
+impl Default for PluginPaths {
+  fn default() -> Self {
+    Self {
+      device_plugin_dir: DEVICE_PLUGIN_PATH.into(),
+      kubelet_socket: KUBELET_SOCKET.into(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+struct StartOptions {
+  wait_until_serving: bool,
+  registration: RegistrationOptions,
+  paths: PluginPaths,
+  server_tuning: ServerTuning,
+  socket_mode: Option<u32>,
+  #[cfg(feature = "reflection")]
+  reflection: bool,
+}
+
 pub struct KubeletDevicePluginV1Beta1<
   T: DevicePlugin,
   const GET_PREFERRED_ALLOCATION_AVAILABLE: bool,
   const PRE_START_REQUIRED: bool,
->(Arc<T>);
+> {
+  plugin: Arc<T>,
+  options: StartOptions,
+}
 
 impl<T: DevicePlugin> KubeletDevicePluginV1Beta1<T, false, false> {
   pub fn new(plugin: T) -> Self {
-    Self(Arc::new(plugin))
+    Self {
+      plugin: Arc::new(plugin),
+      options: StartOptions::default(),
+    }
+  }
+}
+
+impl<
+    T: DevicePlugin,
+    const GET_PREFERRED_ALLOCATION_AVAILABLE: bool,
+    const PRE_START_REQUIRED: bool,
+  > KubeletDevicePluginV1Beta1<T, GET_PREFERRED_ALLOCATION_AVAILABLE, PRE_START_REQUIRED>
+{
+  /// Make `start` wait until the plugin server answers a `GetDevicePluginOptions` call on its
+  /// own socket before registering with the kubelet, so the plugin is known to be live once
+  /// `start` returns.
+  pub fn wait_until_serving(mut self) -> Self {
+    self.options.wait_until_serving = true;
+    self
+  }
+
+  /// Use the given plugin directory and kubelet socket, instead of the standard ones.
+  pub fn with_paths(mut self, paths: PluginPaths) -> Self {
+    self.options.paths = paths;
+    self
+  }
+
+  /// Retry registering with the kubelet as configured, instead of with the default options.
+  pub fn with_registration_options(mut self, options: RegistrationOptions) -> Self {
+    self.options.registration = options;
+    self
+  }
+
+  /// Set the permissions of the plugin socket to `mode` (e.g. `0o660`), instead of leaving
+  /// them to the umask of the process.
+  pub fn with_socket_mode(mut self, mode: u32) -> Self {
+    self.options.socket_mode = Some(mode);
+    self
+  }
+
+  /// Serve the plugin with the given HTTP/2 settings, instead of the default ones.
+  pub fn with_server_tuning(mut self, tuning: ServerTuning) -> Self {
+    self.options.server_tuning = tuning;
+    self
+  }
+
+  /// Serve the gRPC reflection service next to the device plugin, so the plugin socket can be
+  /// explored with tools like `grpcurl`. Meant for debugging.
+  #[cfg(feature = "reflection")]
+  pub fn with_reflection(mut self) -> Self {
+    self.options.reflection = true;
+    self
   }
 }
 
@@ -143,7 +336,10 @@ impl<T: DevicePlugin + PreferredAllocation, const GET_PREFERRED_ALLOCATION_AVAIL
   pub fn with_preferred_allocation_support(
     self,
   ) -> KubeletDevicePluginV1Beta1<T, true, GET_PREFERRED_ALLOCATION_AVAILABLE> {
-    KubeletDevicePluginV1Beta1(self.0)
+    KubeletDevicePluginV1Beta1 {
+      plugin: self.plugin,
+      options: self.options,
+    }
   }
 }
 
@@ -151,7 +347,10 @@ impl<T: DevicePlugin + ContainerPrestart, const PRE_START_REQUIRED: bool>
   KubeletDevicePluginV1Beta1<T, PRE_START_REQUIRED, false>
 {
   pub fn with_prestart(self) -> KubeletDevicePluginV1Beta1<T, PRE_START_REQUIRED, true> {
-    KubeletDevicePluginV1Beta1(self.0)
+    KubeletDevicePluginV1Beta1 {
+      plugin: self.plugin,
+      options: self.options,
+    }
   }
 }
 
@@ -163,11 +362,11 @@ impl<T: DevicePlugin> DevicePluginService for KubeletDevicePluginV1Beta1<T, fals
   const GET_PREFERRED_ALLOCATION_AVAILABLE: bool = false;
 
   async fn list_and_watch(&self) -> Result<Self::ListAndWatchStream, tonic::Status> {
-    self.0.list_and_watch().await
+    self.plugin.list_and_watch().await
   }
 
   async fn allocate(&self, request: AllocateRequest) -> Result<AllocateResponse, tonic::Status> {
-    self.0.allocate(request).await
+    self.plugin.allocate(request).await
   }
 
   async fn prestart_container(&self, _: PreStartContainerRequest) -> Result<(), tonic::Status> {
@@ -194,11 +393,11 @@ impl<T: DevicePlugin + PreferredAllocation> DevicePluginService
   const GET_PREFERRED_ALLOCATION_AVAILABLE: bool = true;
 
   async fn list_and_watch(&self) -> Result<Self::ListAndWatchStream, tonic::Status> {
-    self.0.list_and_watch().await
+    self.plugin.list_and_watch().await
   }
 
   async fn allocate(&self, request: AllocateRequest) -> Result<AllocateResponse, tonic::Status> {
-    self.0.allocate(request).await
+    self.plugin.allocate(request).await
   }
 
   async fn prestart_container(&self, _: PreStartContainerRequest) -> Result<(), tonic::Status> {
@@ -209,7 +408,7 @@ impl<T: DevicePlugin + PreferredAllocation> DevicePluginService
     &self,
     request: PreferredAllocationRequest,
   ) -> Result<PreferredAllocationResponse, tonic::Status> {
-    self.0.get_preferred_allocation(request).await
+    self.plugin.get_preferred_allocation(request).await
   }
 }
 
@@ -223,18 +422,18 @@ impl<T: DevicePlugin + ContainerPrestart> DevicePluginService
   const GET_PREFERRED_ALLOCATION_AVAILABLE: bool = false;
 
   async fn list_and_watch(&self) -> Result<Self::ListAndWatchStream, tonic::Status> {
-    self.0.list_and_watch().await
+    self.plugin.list_and_watch().await
   }
 
   async fn allocate(&self, request: AllocateRequest) -> Result<AllocateResponse, tonic::Status> {
-    self.0.allocate(request).await
+    self.plugin.allocate(request).await
   }
 
   async fn prestart_container(
     &self,
     request: PreStartContainerRequest,
   ) -> Result<(), tonic::Status> {
-    self.0.prestart_container(request).await
+    self.plugin.prestart_container(request).await
   }
 
   async fn get_preferred_allocation(
@@ -257,25 +456,34 @@ impl<T: DevicePlugin + PreferredAllocation + ContainerPrestart> DevicePluginServ
   const GET_PREFERRED_ALLOCATION_AVAILABLE: bool = true;
 
   async fn list_and_watch(&self) -> Result<Self::ListAndWatchStream, tonic::Status> {
-    self.0.list_and_watch().await
+    self.plugin.list_and_watch().await
   }
 
   async fn allocate(&self, request: AllocateRequest) -> Result<AllocateResponse, tonic::Status> {
-    self.0.allocate(request).await
+    self.plugin.allocate(request).await
   }
 
   async fn prestart_container(
     &self,
     request: PreStartContainerRequest,
   ) -> Result<(), tonic::Status> {
-    self.0.prestart_container(request).await
+    self.plugin.prestart_container(request).await
   }
 
   async fn get_preferred_allocation(
     &self,
     request: PreferredAllocationRequest,
   ) -> Result<PreferredAllocationResponse, tonic::Status> {
-    self.0.get_preferred_allocation(request).await
+    self.plugin.get_preferred_allocation(request).await
+  }
+}
+
+/// Options a plugin service reports to the kubelet. Registration and `GetDevicePluginOptions`
+/// both use this, so the kubelet is never told something at registration that it isn't served.
+fn device_plugin_options<S: DevicePluginService>() -> proto::DevicePluginOptions {
+  proto::DevicePluginOptions {
+    pre_start_required: S::PRE_START_REQUIRED,
+    get_preferred_allocation_available: S::GET_PREFERRED_ALLOCATION_AVAILABLE,
   }
 }
 
@@ -290,12 +498,7 @@ impl<T: DevicePluginService> proto::device_plugin_server::DevicePlugin for T {
     &self,
     _: tonic::Request<proto::Empty>,
   ) -> Result<tonic::Response<proto::DevicePluginOptions>, tonic::Status> {
-    let options = proto::DevicePluginOptions {
-      pre_start_required: Self::PRE_START_REQUIRED,
-      get_preferred_allocation_available: Self::GET_PREFERRED_ALLOCATION_AVAILABLE,
-    };
-
-    Ok(tonic::Response::new(options))
+    Ok(tonic::Response::new(device_plugin_options::<Self>()))
   }
 
   async fn list_and_watch(
@@ -350,12 +553,22 @@ impl<
     const PRE_START_REQUIRED: bool,
   > KubeletDevicePluginV1Beta1<T, GET_PREFERRED_ALLOCATION_AVAILABLE, PRE_START_REQUIRED>
 where
-  Self: proto::device_plugin_server::DevicePlugin,
+  Self: DevicePluginService,
 {
   pub async fn start(
     self,
     resource_name: impl Into<String>,
   ) -> Result<KubernetesDevicePluginServer, ConnectionError> {
+    // the typestate and the service impl it selects have to agree on what's supported
+    debug_assert_eq!(
+      (PRE_START_REQUIRED, GET_PREFERRED_ALLOCATION_AVAILABLE),
+      (
+        <Self as DevicePluginService>::PRE_START_REQUIRED,
+        <Self as DevicePluginService>::GET_PREFERRED_ALLOCATION_AVAILABLE,
+      ),
+      "typestate doesn't match the device plugin service"
+    );
+
     let resource_name: String = resource_name.into();
     let span = span!(
       Level::INFO,
@@ -370,87 +583,294 @@ where
     self,
     resource_name: String,
   ) -> Result<KubernetesDevicePluginServer, ConnectionError> {
-    let file_name = slug::slugify(&resource_name);
-    let plugins_dir: &Path = DEVICE_PLUGIN_PATH.as_ref();
-    if !plugins_dir.is_dir() {
-      return Err(ConnectionError::PluginDirDoesNotExist);
-    }
+    let options = self.options.clone();
+    let file_name = socket_file_stem(&resource_name);
+    let plugins_dir = options.paths.device_plugin_dir.as_path();
+    std::fs::create_dir_all(plugins_dir)
+      .map_err(|e| ConnectionError::PluginDir(plugins_dir.to_owned(), e))?;
+
+    let socket_path = plugin_socket_path(plugins_dir, &file_name)?;
+
+    let mut socket_listener = UnixSocketListener::bind_with_mode(&socket_path, options.socket_mode)
+      .map_err(|e| ConnectionError::UnixSocketBind(socket_path.clone(), e))?;
+    let rebinder = socket_listener.rebinder();
+
+    let device_plugin_service = proto::device_plugin_server::DevicePluginServer::new(self);
+    #[cfg(feature = "reflection")]
+    let device_plugin_service = crate::reflection::WithReflection::new(
+      device_plugin_service,
+      crate::reflection::service()?,
+      options.reflection,
+    );
+    let span = Span::current();
+    let builder = options
+      .server_tuning
+      .apply(Server::builder(socket_listener).http2_only(true));
+    let mut server = KubernetesDevicePluginServer::start(move |signal| {
+      let server = builder.serve(Svc::new(
+        device_plugin_service,
+        Some(span),
+        Some(signal.clone()),
+      ));
 
-    let socket_path = {
-      let mut index = 0usize;
-      loop {
-        let file_name = match index {
-          0 => format!("{}.sock", file_name),
-          v => format!("{}-{}.sock", file_name, v),
-        };
+      task::spawn(server.with_graceful_shutdown(signal))
+    });
+    server.set_socket(socket_path.clone());
 
-        let path = plugins_dir.join(file_name);
-        if !path.exists() {
-          break path;
-        }
+    if options.wait_until_serving {
+      if let Err(e) = probe_plugin_socket(&socket_path).await {
+        let _ = server.abort().await;
+        return Err(e);
+      }
+    }
 
-        index += 1;
+    let plugin_options = device_plugin_options::<Self>();
+    let request = proto::RegisterRequest {
+      version: VERSION.into(),
+      endpoint: socket_path.to_string_lossy().into(),
+      resource_name: resource_name.clone(),
+      options: Some(plugin_options.clone()),
+    };
+    // watch before registering, so a kubelet restart right after registration isn't missed
+    let kubelet_socket = options.paths.kubelet_socket.as_path();
+    let registered = match SocketWatcher::new(kubelet_socket, KUBELET_SOCKET_DEBOUNCE) {
+      Err(e) => Err(ConnectionError::from(e)),
+      Ok(watcher) => register_with_kubelet(kubelet_socket, request.clone(), &options.registration)
+        .await
+        .map(|()| watcher),
+    };
+    let watcher = match registered {
+      Ok(watcher) => watcher,
+      Err(e) => {
+        let _ = server.abort().await;
+        return Err(e);
       }
     };
 
-    let socket_listener = UnixSocketListener::bind(&socket_path)
-      .map_err(|e| ConnectionError::UnixSocketBind(socket_path.clone(), e))?;
+    task::spawn(
+      reregister_on_kubelet_restart(
+        watcher,
+        rebinder,
+        kubelet_socket.to_owned(),
+        request,
+        options.registration.clone(),
+        server.drain_signal(),
+      )
+      .in_current_span(),
+    );
 
-    let device_plugin_service = proto::device_plugin_server::DevicePluginServer::new(self);
-    let server = Server::builder(socket_listener)
-      .http2_only(true)
-      .serve(Svc::new(device_plugin_service, Some(Span::current())));
-    // .http2_initial_connection_window_size(init_connection_window_size)
-    // .http2_initial_stream_window_size(init_stream_window_size)
-    // .http2_max_concurrent_streams(max_concurrent_streams)
-    // .http2_keep_alive_interval(http2_keepalive_interval)
-    // .http2_keep_alive_timeout(http2_keepalive_timeout)
-    // .http2_max_frame_size(max_frame_size);
+    event!(
+      Level::INFO,
+      endpoint = %socket_path.display(),
+      pre_start_required = plugin_options.pre_start_required,
+      get_preferred_allocation_available = plugin_options.get_preferred_allocation_available,
+      "registered with kubelet"
+    );
 
-    let server = KubernetesDevicePluginServer::start(move |signal| {
-      task::spawn(server.with_graceful_shutdown(signal))
+    server.set_registration(Registration {
+      version: VERSION,
+      resource_name,
+      endpoint: socket_path,
+      pre_start_required: plugin_options.pre_start_required,
+      get_preferred_allocation_available: plugin_options.get_preferred_allocation_available,
     });
 
+    Ok(server)
+  }
+}
+
+/// Picks the plugin socket for a resource, `{stem}.sock` or `{stem}-{index}.sock`. Sockets of
+/// the resource left behind by earlier runs are removed first, so the lowest index not in use
+/// by a live server is reused instead of leaking a new socket file on every restart.
+fn plugin_socket_path(plugins_dir: &Path, file_stem: &str) -> io::Result<PathBuf> {
+  for entry in std::fs::read_dir(plugins_dir)? {
+    let path = entry?.path();
+    let is_resource_socket = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .and_then(|name| name.strip_suffix(".sock"))
+      .and_then(|name| name.strip_prefix(file_stem))
+      .is_some_and(|index| {
+        index.is_empty()
+          || index
+            .strip_prefix('-')
+            .is_some_and(|index| index.parse::<usize>().is_ok())
+      });
+
+    if is_resource_socket && remove_stale_socket(&path)? {
+      event!(
+        Level::DEBUG,
+        socket = %path.display(),
+        "removed stale plugin socket"
+      );
+    }
+  }
+
+  let mut index = 0usize;
+  loop {
+    let file_name = match index {
+      0 => format!("{}.sock", file_stem),
+      v => format!("{}-{}.sock", file_stem, v),
+    };
+
+    let path = plugins_dir.join(file_name);
+    if !path.exists() {
+      return Ok(path);
+    }
+
+    index += 1;
+  }
+}
+
+/// Socket file name (without extension) for a resource. The slug is only used verbatim when
+/// it's identical to the resource name, otherwise a hash of the full resource name is appended,
+/// so different resources never end up sharing a base name and the mapping stays stable.
+fn socket_file_stem(resource_name: &str) -> String {
+  let slug = slug::slugify(resource_name);
+  if slug == resource_name {
+    return slug;
+  }
+
+  let hash = fnv1a(resource_name.as_bytes());
+  match slug.is_empty() {
+    true => format!("plugin-{:08x}", hash),
+    false => format!("{}-{:08x}", slug, hash),
+  }
+}
+
+/// 32-bit FNV-1a, used for its stability across builds and platforms.
+fn fnv1a(bytes: &[u8]) -> u32 {
+  bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+    (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+  })
+}
+
+/// Registers with the kubelet, retrying with exponential backoff as configured. Only the
+/// error of the last attempt is returned.
+async fn register_with_kubelet(
+  kubelet_socket: &Path,
+  request: proto::RegisterRequest,
+  options: &RegistrationOptions,
+) -> Result<(), ConnectionError> {
+  let max_attempts = options.max_attempts.max(1);
+  let mut delay = options.base_delay.min(options.max_delay);
+  let mut attempt = 1;
+  loop {
+    event!(
+      Level::DEBUG,
+      attempt,
+      max_attempts,
+      "registering with kubelet"
+    );
+
+    match try_register(kubelet_socket, request.clone()).await {
+      Ok(()) => return Ok(()),
+      Err(e) if attempt < max_attempts => {
+        event!(
+          Level::WARN,
+          attempt,
+          max_attempts,
+          error = %e,
+          "failed to register with kubelet, retrying in {:?}",
+          delay
+        );
+
+        time::sleep(delay).await;
+        delay = options.next_delay(delay);
+        attempt += 1;
+      }
+      Err(e) => return Err(e),
+    }
+  }
+}
+
+/// Registers again whenever the kubelet socket is recreated, which is how a restarted kubelet
+/// asks plugins to re-register. The kubelet wipes the plugin directory when it restarts, so
+/// the plugin socket is bound again first if it's gone. Stops once the plugin server drains.
+async fn reregister_on_kubelet_restart(
+  watcher: SocketWatcher,
+  rebinder: SocketRebinder,
+  kubelet_socket: PathBuf,
+  request: proto::RegisterRequest,
+  options: RegistrationOptions,
+  stop: Signal,
+) {
+  let mut recreated = watcher.take_until(stop);
+  while recreated.next().await.is_some() {
+    event!(Level::INFO, "kubelet socket recreated, registering again");
+    match rebinder.rebind_if_removed() {
+      Ok(false) => (),
+      Ok(true) => event!(Level::INFO, "plugin socket was removed, bound it again"),
+      Err(e) => {
+        // the kubelet couldn't connect to the plugin, so there's no point in registering
+        event!(
+          Level::ERROR,
+          error = %e,
+          "failed to bind the removed plugin socket again"
+        );
+        continue;
+      }
+    }
+
+    if let Err(e) = register_with_kubelet(&kubelet_socket, request.clone(), &options).await {
+      event!(
+        Level::ERROR,
+        error = %e,
+        "failed to register with restarted kubelet"
+      );
+    }
+  }
+}
+
+async fn try_register(
+  kubelet_socket: &Path,
+  request: proto::RegisterRequest,
+) -> Result<(), ConnectionError> {
+  let path = kubelet_socket.to_owned();
+  let channel = Endpoint::try_from("http://[::]:50051")
+    .unwrap()
+    .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+    .await
+    .map_err(|e| ConnectionError::KubeletSocketConnect(kubelet_socket.to_owned(), e))?;
+
+  let mut kubelet_client = proto::registration_client::RegistrationClient::new(channel);
+  kubelet_client.register(request).await?;
+  Ok(())
+}
+
+async fn probe_plugin_socket(socket_path: &Path) -> Result<(), ConnectionError> {
+  let probe = async {
+    let path = socket_path.to_owned();
     let channel = Endpoint::try_from("http://[::]:50051")
       .unwrap()
-      .connect_with_connector(service_fn(|_: Uri| {
-        // Connect to a Uds socket
-        UnixStream::connect(KUBELET_SOCKET)
-      }))
-      .await
-      .map_err(ConnectionError::KubeletSocketConnect)?;
-
-    let mut kubelet_client = proto::registration_client::RegistrationClient::new(channel);
-    kubelet_client
-      .register(proto::RegisterRequest {
-        version: VERSION.into(),
-        endpoint: socket_path.to_string_lossy().into(),
-        resource_name,
-        options: Some(proto::DevicePluginOptions {
-          pre_start_required: PRE_START_REQUIRED,
-          get_preferred_allocation_available: GET_PREFERRED_ALLOCATION_AVAILABLE,
-        }),
-      })
+      .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
       .await?;
 
-    Ok(server)
+    let mut client = proto::device_plugin_client::DevicePluginClient::new(channel);
+    client.get_device_plugin_options(proto::Empty {}).await?;
+    Ok::<_, ConnectionError>(())
+  };
+
+  match time::timeout(SERVING_PROBE_TIMEOUT, probe).await {
+    Ok(result) => result,
+    Err(_) => Err(ConnectionError::NotServing(socket_path.to_owned())),
   }
 }
 
 #[derive(Debug, Error)]
 pub enum ConnectionError {
-  #[error(
-    "Plugins dir '{}' does not exist or is not a directory",
-    DEVICE_PLUGIN_PATH
-  )]
-  PluginDirDoesNotExist,
+  #[error("Failed to create plugins dir '{}'", .0.display())]
+  PluginDir(PathBuf, #[source] io::Error),
 
-  #[error("Failed to connect to kubelet socket at '{}': {0}", KUBELET_SOCKET)]
-  KubeletSocketConnect(tonic::transport::Error),
+  #[error("Failed to connect to kubelet socket at '{}': {1}", .0.display())]
+  KubeletSocketConnect(PathBuf, tonic::transport::Error),
 
   #[error("Failed to bind unix socket at '{}'", .0.display())]
   UnixSocketBind(PathBuf, #[source] io::Error),
 
+  #[error("Plugin server at '{}' did not start serving in time", .0.display())]
+  NotServing(PathBuf),
+
   #[error(transparent)]
   Transport(#[from] tonic::transport::Error),
 
@@ -462,4 +882,625 @@ pub enum ConnectionError {
 
   #[error(transparent)]
   Join(#[from] tokio::task::JoinError),
+
+  #[error("Failed to watch the kubelet socket for restarts")]
+  Watch(#[from] notify::Error),
+
+  #[cfg(feature = "reflection")]
+  #[error("Failed to build the reflection service")]
+  Reflection(#[from] tonic_reflection::server::Error),
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testkit::MockKubelet;
+  use futures::stream::{self, Chain, StreamExt};
+  use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    vec,
+  };
+
+  struct StaticPlugin;
+
+  #[async_trait]
+  impl DevicePlugin for StaticPlugin {
+    type ListAndWatchStream = Chain<
+      stream::Iter<vec::IntoIter<Result<ListAndWatchResponse, tonic::Status>>>,
+      stream::Pending<Result<ListAndWatchResponse, tonic::Status>>,
+    >;
+
+    async fn list_and_watch(&self) -> Result<Self::ListAndWatchStream, tonic::Status> {
+      let initial = ListAndWatchResponse { devices: vec![] };
+      Ok(stream::iter(vec![Ok(initial)]).chain(stream::pending()))
+    }
+
+    async fn allocate(&self, _: AllocateRequest) -> Result<AllocateResponse, tonic::Status> {
+      Err(tonic::Status::unimplemented("allocate"))
+    }
+  }
+
+  #[async_trait]
+  impl PreferredAllocation for StaticPlugin {
+    async fn get_preferred_allocation(
+      &self,
+      _: PreferredAllocationRequest,
+    ) -> Result<PreferredAllocationResponse, tonic::Status> {
+      Err(tonic::Status::unimplemented("get_preferred_allocation"))
+    }
+  }
+
+  #[async_trait]
+  impl ContainerPrestart for StaticPlugin {
+    async fn prestart_container(&self, _: PreStartContainerRequest) -> Result<(), tonic::Status> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn file_descriptor_set_contains_device_plugin_service() {
+    use prost::Message;
+
+    let set = prost_types::FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).unwrap();
+    let file = set
+      .file
+      .iter()
+      .find(|file| file.package.as_deref() == Some("v1beta1"))
+      .expect("v1beta1 package");
+
+    let services = file
+      .service
+      .iter()
+      .filter_map(|service| service.name.as_deref())
+      .collect::<Vec<_>>();
+    assert!(services.contains(&"DevicePlugin"), "{:?}", services);
+    assert!(services.contains(&"Registration"), "{:?}", services);
+  }
+
+  #[test]
+  fn socket_file_stem_for_symbol_only_name() {
+    let stem = socket_file_stem("///");
+    assert!(stem.starts_with("plugin-"), "{}", stem);
+    assert_eq!(stem, socket_file_stem("///"));
+  }
+
+  #[test]
+  fn socket_file_stem_for_colliding_slugs() {
+    let a = socket_file_stem("udev/tty/conbee");
+    let b = socket_file_stem("udev-tty-conbee");
+    let c = socket_file_stem("udev/tty.conbee");
+
+    assert_eq!(b, "udev-tty-conbee");
+    assert!(a.starts_with("udev-tty-conbee-"), "{}", a);
+    assert_ne!(a, b);
+    assert_ne!(a, c);
+    assert_eq!(a, socket_file_stem("udev/tty/conbee"));
+  }
+
+  #[tokio::test]
+  async fn shutdown_ends_list_and_watch_cleanly() {
+    let socket_path = std::env::temp_dir().join(format!("shutdown-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket_path);
+
+    let listener = UnixSocketListener::bind(&socket_path).unwrap();
+    let service = proto::device_plugin_server::DevicePluginServer::new(
+      KubeletDevicePluginV1Beta1::new(StaticPlugin),
+    );
+    let server = KubernetesDevicePluginServer::start(move |signal| {
+      let server = Server::builder(listener).http2_only(true).serve(Svc::new(
+        service,
+        None,
+        Some(signal.clone()),
+      ));
+      task::spawn(server.with_graceful_shutdown(signal))
+    });
+
+    let path = socket_path.clone();
+    let channel = Endpoint::try_from("http://[::]:50051")
+      .unwrap()
+      .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+      .await
+      .unwrap();
+    let mut responses = proto::device_plugin_client::DevicePluginClient::new(channel)
+      .list_and_watch(proto::Empty {})
+      .await
+      .unwrap()
+      .into_inner();
+    assert!(responses.message().await.unwrap().is_some());
+
+    // the stream never ends on its own, so only the drain can end it
+    let shutdown = task::spawn(server.shutdown(Duration::from_secs(5)));
+    let end = time::timeout(Duration::from_secs(5), responses.message())
+      .await
+      .unwrap();
+    shutdown.await.unwrap().unwrap();
+
+    let _ = std::fs::remove_file(&socket_path);
+    assert!(matches!(end, Ok(None)), "{:?}", end);
+  }
+
+  /// Kubelet registration service rejecting the first `failures` attempts
+  struct FlakyKubelet {
+    failures: usize,
+    attempts: Arc<AtomicUsize>,
+  }
+
+  #[async_trait]
+  impl proto::registration_server::Registration for FlakyKubelet {
+    async fn register(
+      &self,
+      _: tonic::Request<proto::RegisterRequest>,
+    ) -> Result<tonic::Response<proto::Empty>, tonic::Status> {
+      match self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+        true => Err(tonic::Status::unavailable("kubelet is starting")),
+        false => Ok(tonic::Response::new(proto::Empty {})),
+      }
+    }
+  }
+
+  #[test]
+  fn registration_backoff_is_capped() {
+    let options = RegistrationOptions {
+      max_attempts: u32::MAX,
+      base_delay: Duration::from_secs(1),
+      max_delay: Duration::from_secs(5),
+    };
+
+    let delays = std::iter::successors(Some(options.base_delay), |delay| {
+      Some(options.next_delay(*delay))
+    })
+    .take(5)
+    .map(|delay| delay.as_secs())
+    .collect::<Vec<_>>();
+    assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+    assert_eq!(options.next_delay(Duration::MAX), options.max_delay);
+  }
+
+  #[tokio::test]
+  async fn registration_is_retried_with_backoff() {
+    let socket_path = std::env::temp_dir().join(format!("kubelet-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket_path);
+
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let kubelet = proto::registration_server::RegistrationServer::new(FlakyKubelet {
+      failures: 2,
+      attempts: attempts.clone(),
+    });
+    let listener = UnixSocketListener::bind(&socket_path).unwrap();
+    let server = task::spawn(
+      Server::builder(listener)
+        .http2_only(true)
+        .serve(Svc::new(kubelet, None, None)),
+    );
+
+    let request = proto::RegisterRequest {
+      version: VERSION.into(),
+      endpoint: "plugin.sock".into(),
+      resource_name: "udev/tty/conbee2".into(),
+      options: None,
+    };
+    let options = RegistrationOptions {
+      max_attempts: 3,
+      base_delay: Duration::from_millis(10),
+      ..RegistrationOptions::default()
+    };
+    let result = register_with_kubelet(&socket_path, request.clone(), &options).await;
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+    // without enough attempts the last error is returned
+    attempts.store(0, Ordering::SeqCst);
+    let options = RegistrationOptions {
+      max_attempts: 2,
+      ..options
+    };
+    let result = register_with_kubelet(&socket_path, request, &options).await;
+    assert!(
+      matches!(result, Err(ConnectionError::Status(_))),
+      "{:?}",
+      result
+    );
+
+    server.abort();
+    let _ = std::fs::remove_file(&socket_path);
+  }
+
+  #[tokio::test]
+  async fn registers_again_when_kubelet_socket_is_recreated() {
+    let dir = std::env::temp_dir().join(format!("kubelet-restart-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket_path = dir.join("kubelet.sock");
+    let plugin_socket = dir.join("plugin.sock");
+    let mut listener = UnixSocketListener::bind(&plugin_socket).unwrap();
+
+    let request = proto::RegisterRequest {
+      version: VERSION.into(),
+      endpoint: plugin_socket.to_string_lossy().into(),
+      resource_name: "udev/tty/conbee2".into(),
+      options: None,
+    };
+    let options = RegistrationOptions::default();
+
+    let kubelet = MockKubelet::start(&socket_path).unwrap();
+    let watcher = SocketWatcher::new(&socket_path, Duration::from_millis(100)).unwrap();
+    register_with_kubelet(&socket_path, request.clone(), &options)
+      .await
+      .unwrap();
+    let (stop, signal) = Signal::channel();
+    let reregister = task::spawn(reregister_on_kubelet_restart(
+      watcher,
+      listener.rebinder(),
+      socket_path.clone(),
+      request,
+      options,
+      signal,
+    ));
+
+    // restart the kubelet, which wipes the plugin sockets along with its own
+    drop(kubelet);
+    std::fs::remove_file(&socket_path).unwrap();
+    std::fs::remove_file(&plugin_socket).unwrap();
+    let mut kubelet = MockKubelet::start(&socket_path).unwrap();
+    let registration = kubelet.next_registration(Duration::from_secs(10)).await;
+    assert_eq!(
+      registration.map(|r| r.resource_name),
+      Some("udev/tty/conbee2".to_owned())
+    );
+
+    // the plugin socket is back, with the listener accepting connections on it
+    let _client = tokio::net::UnixStream::connect(&plugin_socket)
+      .await
+      .unwrap();
+    let accepted = time::timeout(Duration::from_secs(5), listener.next()).await;
+    assert!(matches!(accepted, Ok(Some(Ok(_)))));
+
+    drop(stop);
+    time::timeout(Duration::from_secs(5), reregister)
+      .await
+      .unwrap()
+      .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn stale_plugin_sockets_are_reclaimed() {
+    let dir = std::env::temp_dir().join(format!("plugin-sockets-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // a dropped listener leaves a socket file nothing accepts connections on anymore
+    let dead = |name: &str| drop(std::os::unix::net::UnixListener::bind(dir.join(name)).unwrap());
+    dead("udev-tty-conbee.sock");
+    dead("udev-tty-conbee-2.sock");
+    let _live = std::os::unix::net::UnixListener::bind(dir.join("udev-tty-conbee-1.sock")).unwrap();
+    dead("other.sock");
+
+    let path = plugin_socket_path(&dir, "udev-tty-conbee").unwrap();
+    assert_eq!(path, dir.join("udev-tty-conbee.sock"));
+    assert!(!dir.join("udev-tty-conbee-2.sock").exists());
+    assert!(dir.join("udev-tty-conbee-1.sock").exists());
+    assert!(dir.join("other.sock").exists());
+
+    // binding replaces a dead socket, but not a live one
+    dead("udev-tty-conbee.sock");
+    assert!(UnixSocketListener::bind(&path).is_ok());
+    assert!(UnixSocketListener::bind(dir.join("udev-tty-conbee-1.sock")).is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn registers_with_kubelet_at_custom_paths() {
+    let dir = std::env::temp_dir().join(format!("kubelet-root-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = PluginPaths {
+      device_plugin_dir: dir.join("device-plugins"),
+      kubelet_socket: dir.join("kubelet.sock"),
+    };
+
+    let kubelet = MockKubelet::start(&paths.kubelet_socket).unwrap();
+
+    let server = KubeletDevicePluginV1Beta1::new(StaticPlugin)
+      .wait_until_serving()
+      .with_paths(paths.clone())
+      .start("udev/tty/conbee2")
+      .await
+      .unwrap();
+
+    let endpoint = server.registration().unwrap().endpoint.clone();
+    assert_eq!(kubelet.registrations().len(), 1);
+    assert_eq!(endpoint.parent(), Some(paths.device_plugin_dir.as_path()));
+    assert!(endpoint.exists());
+
+    server.shutdown(Duration::from_secs(1)).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn serving_as_soon_as_start_returns() {
+    let dir = std::env::temp_dir().join(format!("plugin-serving-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = PluginPaths::in_dir(&dir);
+    let _kubelet = MockKubelet::start(&paths.kubelet_socket).unwrap();
+
+    let server = KubeletDevicePluginV1Beta1::new(StaticPlugin)
+      .wait_until_serving()
+      .with_paths(paths)
+      .start("udev/tty/conbee2")
+      .await
+      .unwrap();
+
+    // no retries, the first connection has to succeed
+    let path = server.registration().unwrap().endpoint.clone();
+    let channel = Endpoint::try_from("http://[::]:50051")
+      .unwrap()
+      .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+      .await
+      .unwrap();
+    let options = proto::device_plugin_client::DevicePluginClient::new(channel)
+      .get_device_plugin_options(proto::Empty {})
+      .await
+      .unwrap()
+      .into_inner();
+    assert!(!options.pre_start_required);
+    assert!(!options.get_preferred_allocation_available);
+
+    server.shutdown(Duration::from_secs(1)).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn accepted_registrations_are_recorded() {
+    let dir = std::env::temp_dir().join(format!("plugin-registration-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = PluginPaths::in_dir(&dir);
+    let mut kubelet = MockKubelet::start(&paths.kubelet_socket).unwrap();
+
+    let server = KubeletDevicePluginV1Beta1::new(StaticPlugin)
+      .with_paths(paths.clone())
+      .with_prestart()
+      .start("udev/tty/conbee2")
+      .await
+      .unwrap();
+    let request = kubelet
+      .next_registration(Duration::from_secs(5))
+      .await
+      .unwrap();
+
+    let registration = server.registration().unwrap();
+    assert_eq!(
+      registration,
+      &Registration {
+        version: VERSION,
+        resource_name: "udev/tty/conbee2".into(),
+        endpoint: PathBuf::from(&request.endpoint),
+        pre_start_required: true,
+        get_preferred_allocation_available: false,
+      }
+    );
+    assert_eq!(
+      request.options,
+      Some(DevicePluginOptions {
+        pre_start_required: registration.pre_start_required,
+        get_preferred_allocation_available: registration.get_preferred_allocation_available,
+      })
+    );
+    server.shutdown(Duration::from_secs(1)).await.unwrap();
+
+    // a kubelet refusing the registration fails the start, so nothing is recorded
+    drop(kubelet);
+    std::fs::remove_file(&paths.kubelet_socket).unwrap();
+    let listener = UnixSocketListener::bind(&paths.kubelet_socket).unwrap();
+    let refusing = proto::registration_server::RegistrationServer::new(FlakyKubelet {
+      failures: usize::MAX,
+      attempts: Arc::new(AtomicUsize::new(0)),
+    });
+    let refusing = task::spawn(
+      Server::builder(listener)
+        .http2_only(true)
+        .serve(Svc::new(refusing, None, None)),
+    );
+    let result = KubeletDevicePluginV1Beta1::new(StaticPlugin)
+      .with_paths(paths)
+      .with_registration_options(RegistrationOptions {
+        max_attempts: 1,
+        ..RegistrationOptions::default()
+      })
+      .start("udev/tty/conbee2")
+      .await;
+    assert!(
+      matches!(result, Err(ConnectionError::Status(_))),
+      "{:?}",
+      result
+    );
+
+    refusing.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  /// Starts `server` against a mock kubelet, returning the options it registered with and the
+  /// options it serves
+  async fn registered_and_served_options<const PREFERRED: bool, const PRESTART: bool>(
+    server: KubeletDevicePluginV1Beta1<StaticPlugin, PREFERRED, PRESTART>,
+  ) -> (Option<DevicePluginOptions>, DevicePluginOptions)
+  where
+    KubeletDevicePluginV1Beta1<StaticPlugin, PREFERRED, PRESTART>: DevicePluginService,
+  {
+    let dir = std::env::temp_dir().join(format!(
+      "plugin-options-{}-{}-{}",
+      std::process::id(),
+      PREFERRED,
+      PRESTART
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = PluginPaths::in_dir(&dir);
+
+    let mut kubelet = MockKubelet::start(&paths.kubelet_socket).unwrap();
+    let server = server
+      .with_paths(paths)
+      .start("udev/tty/conbee2")
+      .await
+      .unwrap();
+    let registered = kubelet
+      .next_registration(Duration::from_secs(5))
+      .await
+      .unwrap()
+      .options;
+
+    let path = server.registration().unwrap().endpoint.clone();
+    let channel = Endpoint::try_from("http://[::]:50051")
+      .unwrap()
+      .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+      .await
+      .unwrap();
+    let served = proto::device_plugin_client::DevicePluginClient::new(channel)
+      .get_device_plugin_options(proto::Empty {})
+      .await
+      .unwrap()
+      .into_inner();
+
+    server.shutdown(Duration::from_secs(1)).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    (registered, served.into())
+  }
+
+  #[tokio::test]
+  async fn tuned_server_keeps_serving_through_keep_alives() {
+    let dir = std::env::temp_dir().join(format!("plugin-server-tuning-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = PluginPaths::in_dir(&dir);
+    let _kubelet = MockKubelet::start(&paths.kubelet_socket).unwrap();
+
+    let tuning = ServerTuning {
+      keep_alive_interval: Some(Duration::from_millis(10)),
+      keep_alive_timeout: Duration::from_secs(1),
+      initial_stream_window_size: Some(1 << 16),
+      initial_connection_window_size: Some(1 << 20),
+      max_concurrent_streams: Some(4),
+      max_frame_size: Some(1 << 14),
+    };
+    let server = KubeletDevicePluginV1Beta1::new(StaticPlugin)
+      .with_paths(paths)
+      .with_server_tuning(tuning)
+      .start("udev/tty/conbee2")
+      .await
+      .unwrap();
+
+    let path = server.registration().unwrap().endpoint.clone();
+    let channel = Endpoint::try_from("http://[::]:50051")
+      .unwrap()
+      .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+      .await
+      .unwrap();
+    let mut client = proto::device_plugin_client::DevicePluginClient::new(channel);
+    let mut responses = client
+      .list_and_watch(proto::Empty {})
+      .await
+      .unwrap()
+      .into_inner();
+    assert!(responses.message().await.unwrap().is_some());
+
+    // plenty of keep-alive pings go back and forth in the meantime
+    time::sleep(Duration::from_millis(200)).await;
+    client
+      .get_device_plugin_options(proto::Empty {})
+      .await
+      .unwrap();
+
+    server.shutdown(Duration::from_secs(1)).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn plugin_socket_gets_the_requested_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("plugin-socket-mode-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = PluginPaths::in_dir(&dir);
+    let _kubelet = MockKubelet::start(&paths.kubelet_socket).unwrap();
+
+    let server = KubeletDevicePluginV1Beta1::new(StaticPlugin)
+      .with_paths(paths)
+      .with_socket_mode(0o640)
+      .start("udev/tty/conbee2")
+      .await
+      .unwrap();
+    let socket = server.registration().unwrap().endpoint.clone();
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+
+    server.shutdown(Duration::from_secs(1)).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn stopped_servers_remove_their_socket() {
+    let dir = std::env::temp_dir().join(format!("plugin-socket-removal-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = PluginPaths::in_dir(&dir);
+    let _kubelet = MockKubelet::start(&paths.kubelet_socket).unwrap();
+
+    let start = |resource_name| {
+      KubeletDevicePluginV1Beta1::new(StaticPlugin)
+        .with_paths(paths.clone())
+        .start(resource_name)
+    };
+
+    let server = start("udev/tty/conbee2").await.unwrap();
+    let socket = server.registration().unwrap().endpoint.clone();
+    assert!(socket.exists());
+    server.shutdown(Duration::from_secs(1)).await.unwrap();
+    assert!(!socket.exists());
+
+    let server = start("udev/tty/ftdi").await.unwrap();
+    let socket = server.registration().unwrap().endpoint.clone();
+    server.abort().await.unwrap();
+    assert!(!socket.exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn registered_options_match_served_options() {
+    let options = |get_preferred_allocation_available, pre_start_required| DevicePluginOptions {
+      pre_start_required,
+      get_preferred_allocation_available,
+    };
+
+    let (registered, served) =
+      registered_and_served_options(KubeletDevicePluginV1Beta1::new(StaticPlugin)).await;
+    assert_eq!(served, options(false, false));
+    assert_eq!(registered, Some(served));
+
+    let (registered, served) = registered_and_served_options(
+      KubeletDevicePluginV1Beta1::new(StaticPlugin).with_preferred_allocation_support(),
+    )
+    .await;
+    assert_eq!(served, options(true, false));
+    assert_eq!(registered, Some(served));
+
+    let (registered, served) =
+      registered_and_served_options(KubeletDevicePluginV1Beta1::new(StaticPlugin).with_prestart())
+        .await;
+    assert_eq!(served, options(false, true));
+    assert_eq!(registered, Some(served));
+
+    let (registered, served) = registered_and_served_options(
+      KubeletDevicePluginV1Beta1::new(StaticPlugin)
+        .with_preferred_allocation_support()
+        .with_prestart(),
+    )
+    .await;
+    assert_eq!(served, options(true, true));
+    assert_eq!(registered, Some(served));
+  }
 }
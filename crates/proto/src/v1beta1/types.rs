@@ -114,56 +114,55 @@ macro_rules! derive_to_from_proto {
   };
 }
 
-// #[derive(Debug, Clone)]
-// pub struct DevicePluginOptions {
-//   /// Indicates if PreStartContainer call is required before each container start
-//   pub pre_start_required: bool,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevicePluginOptions {
+  /// Indicates if PreStartContainer call is required before each container start
+  pub pre_start_required: bool,
 
-//   /// Indicates if GetPreferredAllocation is implemented and available for calling
-//   pub get_preferred_allocation_available: bool,
-// }
+  /// Indicates if GetPreferredAllocation is implemented and available for calling
+  pub get_preferred_allocation_available: bool,
+}
 
-// //trace_macros!(true);
-// derive_to_from_proto!(DevicePluginOptions {
-//   pre_start_required,
-//   get_preferred_allocation_available,
-// });
-
-// #[derive(Debug, Clone)]
-// pub struct RegisterRequest {
-//   /// Version of the API the Device Plugin was built against
-//   pub version: String,
-//   /// Name of the unix socket the device plugin is listening on
-//   /// PATH = path.Join(DevicePluginPath, endpoint)
-//   pub endpoint: String,
-//   /// Schedulable resource name. As of now it's expected to be a DNS Label
-//   pub resource_name: String,
-//   /// Options to be communicated with Device Manager
-//   pub options: Option<DevicePluginOptions>,
-// }
-// derive_to_from_proto!(RegisterRequest {
-//   version,
-//   endpoint,
-//   resource_name,
-//   options,
-// });
+derive_to_from_proto!(DevicePluginOptions {
+  pre_start_required,
+  get_preferred_allocation_available,
+});
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterRequest {
+  /// Version of the API the Device Plugin was built against
+  pub version: String,
+  /// Name of the unix socket the device plugin is listening on
+  /// PATH = path.Join(DevicePluginPath, endpoint)
+  pub endpoint: String,
+  /// Schedulable resource name. As of now it's expected to be a DNS Label
+  pub resource_name: String,
+  /// Options to be communicated with Device Manager
+  pub options: Option<DevicePluginOptions>,
+}
+derive_to_from_proto!(RegisterRequest {
+  version,
+  endpoint,
+  resource_name,
+  options,
+});
 
 /// ListAndWatch returns a stream of List of Devices
 /// Whenever a Device state change or a Device disappears, ListAndWatch
 /// returns the new list
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ListAndWatchResponse {
   pub devices: Vec<Device>,
 }
 derive_to_from_proto!(ListAndWatchResponse { devices });
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TopologyInfo {
   pub nodes: Vec<NumaNode>,
 }
 derive_to_from_proto!(TopologyInfo { nodes });
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NumaNode {
   pub id: i64,
 }
@@ -179,7 +178,7 @@ derive_to_from_proto!(NumaNode { id });
 ///        ID: 1
 /// }
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Device {
   /// A unique ID assigned by the device plugin used
   /// to identify devices during the communication
@@ -196,7 +195,7 @@ derive_to_from_proto!(Device {
   topology,
 });
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DeviceHealth {
   Healthy,
   Unhealthy,
@@ -0,0 +1,58 @@
+use futures::Stream;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::{
+  path::Path,
+  pin::Pin,
+  task::{Context, Poll},
+  time::Duration,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Yields whenever a socket file is (re)created, like the kubelet socket after a kubelet
+/// restart. Events within `delay` of each other are debounced into one.
+pub(crate) struct SocketWatcher {
+  _watcher: notify::RecommendedWatcher,
+  receiver: UnboundedReceiver<()>,
+}
+
+impl SocketWatcher {
+  pub(crate) fn new(socket: &Path, delay: Duration) -> Result<Self, notify::Error> {
+    let (std_sender, std_receiver) = std::sync::mpsc::channel();
+    let (async_sender, async_receiver) = mpsc::unbounded_channel();
+    let mut watcher = notify::watcher(std_sender, delay)?;
+
+    // the socket itself is replaced on restart, so the directory it lives in is watched
+    let dir = socket.parent().unwrap_or_else(|| Path::new("/"));
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    let socket = socket.to_owned();
+    std::thread::Builder::new()
+      .name("socket-watcher-mpsc".into())
+      .spawn(move || {
+        for evt in std_receiver {
+          // a removal followed by a creation within the delay is reported as a write
+          let created = match evt {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => path == socket,
+            _ => false,
+          };
+
+          if created && async_sender.send(()).is_err() {
+            break;
+          }
+        }
+      })?;
+
+    Ok(Self {
+      _watcher: watcher,
+      receiver: async_receiver,
+    })
+  }
+}
+
+impl Stream for SocketWatcher {
+  type Item = ();
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.receiver.poll_recv(cx)
+  }
+}
@@ -1,91 +1,365 @@
 mod args;
+mod command;
 mod device_class;
 mod device_registry;
 mod device_type;
+mod dry_run;
+mod health_check;
+mod http;
+mod instance_lock;
+mod match_test;
+mod metrics;
+mod otel;
+mod preflight;
+mod probes;
+mod shutdown;
 
 use self::{
   args::{Args, ConfigFormat},
-  device_class::DeviceClassRegistry,
+  device_class::{DeviceClassRegistry, PluginOptions},
   device_registry::DeviceRegistry,
   device_type::{DeviceHandle, DeviceTypeDistributor, DeviceTypeHandle, DeviceTypeRegistry},
+  health_check::{CommandRunner, HealthChecks},
+  http::HttpServer,
+  instance_lock::InstanceLock,
+  metrics::Metrics,
+  preflight::Preflight,
+  probes::Probes,
+  shutdown::ShutdownReason,
 };
 use crate::{
   app::args::LogFormat,
-  config::{Config, ConfigError},
+  config::{config_schema, Config, ConfigError},
   signals::Signal,
-  udev::{Udev, UdevDeviceError, UdevEvent},
+  udev::{DeviceScanner, Udev, UdevBuilderError, UdevEvent, UdevStreamError},
 };
 use clap::Clap;
 use color_eyre::{
   eyre::{eyre, Context},
   Result,
 };
-use futures::{pin_mut, select, StreamExt};
-use std::{mem, path::PathBuf};
-use tracing::{event, Level};
-use tracing_subscriber::EnvFilter;
+use futures::{future, pin_mut, select, stream, FutureExt, StreamExt};
+use std::{mem, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::time;
+use tracing::{event, span, Instrument, Level, Span};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 enum Action {
   None,
   Restart,
+  ReloadDeviceTypes,
   Reconcile,
-  Shutdown,
+  ScheduleReconcile(Duration),
+  Shutdown(ShutdownReason),
+}
+
+/// How long removed devices are advertised as unhealthy before they're dropped, giving the
+/// kubelet the chance to see them go unhealthy first
+const REMOVED_DEVICE_GRACE: Duration = Duration::from_secs(1);
+
+/// Periodic reconcile trigger, correcting any drift missed by the event driven reconciles.
+/// Never fires when no interval, or a zero one, is configured, unless a reconcile is
+/// scheduled on it.
+struct ReconcileTimer {
+  interval: Option<time::Interval>,
+
+  /// A one-off reconcile, in addition to the periodic ones
+  scheduled: Option<time::Instant>,
+}
+
+impl ReconcileTimer {
+  fn new(period: Option<Duration>) -> Self {
+    let period = period.filter(|period| *period > Duration::from_secs(0));
+    Self {
+      interval: period.map(|period| time::interval_at(time::Instant::now() + period, period)),
+      scheduled: None,
+    }
+  }
+
+  /// Fires once after `delay`, or earlier if a reconcile is already scheduled before then
+  fn schedule(&mut self, delay: Duration) {
+    let at = time::Instant::now() + delay;
+    self.scheduled = Some(self.scheduled.map_or(at, |scheduled| scheduled.min(at)));
+  }
+
+  async fn tick(&mut self) {
+    {
+      let (interval, scheduled) = (&mut self.interval, self.scheduled);
+      let interval = async {
+        match interval {
+          Some(interval) => {
+            interval.tick().await;
+          }
+          None => future::pending().await,
+        }
+      };
+      let scheduled = async {
+        match scheduled {
+          Some(at) => time::sleep_until(at).await,
+          None => future::pending().await,
+        }
+      };
+      pin_mut!(interval, scheduled);
+      future::select(interval, scheduled).await;
+    }
+
+    // whichever fired, the reconcile it triggers covers the scheduled one
+    self.scheduled = None;
+  }
+}
+
+/// Where udev events come from
+enum UdevSource<S> {
+  /// The netlink monitor's event stream
+  Monitor(S),
+
+  /// Rescans of the devices on this interval, where the monitor is unavailable
+  Polling(Duration),
+}
+
+impl<S> UdevSource<S> {
+  /// Falls back to polling when monitoring isn't possible, failing on any other error
+  fn new(
+    monitor: Result<S, UdevBuilderError>,
+    poll_interval: Duration,
+  ) -> Result<Self, UdevBuilderError> {
+    match monitor {
+      Ok(stream) => Ok(UdevSource::Monitor(stream)),
+      Err(e @ UdevBuilderError::MonitorUnavailable(_)) => {
+        event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          "{}, rescanning devices every {:?} instead",
+          e,
+          poll_interval
+        );
+        Ok(UdevSource::Polling(poll_interval))
+      }
+      Err(e) => Err(e),
+    }
+  }
 }
 
 struct App {
   config_file: PathBuf,
   config_format: ConfigFormat,
+  reconcile_interval: Option<Duration>,
+  udev_poll_interval: Duration,
+  reconcile_concurrency: usize,
+  plugin_options: PluginOptions,
   config: Config,
   devices: DeviceRegistry,
   device_types: DeviceTypeRegistry,
   device_classes: DeviceClassRegistry,
+  health_checks: HealthChecks,
+  metrics: Metrics,
+  metrics_server: Option<HttpServer>,
+  probes: Arc<Probes>,
+  probe_server: Option<HttpServer>,
+}
+
+/// Settings the manager is started with, as given on the command line
+struct AppOptions {
+  config_file: PathBuf,
+  config_format: ConfigFormat,
+  reconcile_interval: Option<Duration>,
+  udev_poll_interval: Duration,
+  reconcile_concurrency: usize,
+  plugin_options: PluginOptions,
+  health_check_concurrency: usize,
+  metrics_addr: Option<SocketAddr>,
+  health_addr: Option<SocketAddr>,
 }
 
 impl App {
-  async fn new(config_file: PathBuf, config_format: ConfigFormat) -> Result<Self> {
-    let config = Config::read(&config_file, config_format.into()).await?;
+  async fn new(options: AppOptions) -> Result<Self> {
+    let AppOptions {
+      config_file,
+      config_format,
+      reconcile_interval,
+      udev_poll_interval,
+      reconcile_concurrency,
+      plugin_options,
+      health_check_concurrency,
+      metrics_addr,
+      health_addr,
+    } = options;
+
+    let metrics = Metrics::new()?;
+    let metrics_server = match metrics_addr {
+      Some(addr) => Some(metrics::serve(addr, metrics.registry().clone())?),
+      None => None,
+    };
+    let probes = Arc::new(Probes::default());
+    let probe_server = match health_addr {
+      Some(addr) => Some(probes::serve(addr, probes.clone())?),
+      None => None,
+    };
+
+    let config = Config::read(&config_file, config_format.into()).await;
+    metrics.config().record(&config);
+    let config = config?;
+    log_config_warnings(&config);
 
     let app = App {
       config_file,
       config_format,
+      reconcile_interval,
+      udev_poll_interval,
+      reconcile_concurrency,
+      plugin_options,
       config,
       devices: DeviceRegistry::new(),
       device_types: DeviceTypeRegistry::default(),
       device_classes: DeviceClassRegistry::default(),
+      health_checks: HealthChecks::new(Arc::new(CommandRunner), health_check_concurrency),
+      metrics,
+      metrics_server,
+      probes,
+      probe_server,
     };
 
     Ok(app)
   }
 
-  async fn run(&mut self) -> Result<()> {
-    let config_stream = Config::watch(self.config_file.clone(), self.config_format.into())?.fuse();
+  /// Address metrics are served on, if enabled
+  fn metrics_addr(&self) -> Option<SocketAddr> {
+    self.metrics_server.as_ref().map(HttpServer::addr)
+  }
+
+  /// Address the probe endpoints are served on, if enabled
+  fn health_addr(&self) -> Option<SocketAddr> {
+    self.probe_server.as_ref().map(HttpServer::addr)
+  }
+
+  /// Runs until asked to shut down or something fails. The device plugins are stopped either
+  /// way, so their sockets don't outlive the manager.
+  async fn run(&mut self) -> Result<ShutdownReason> {
+    let result = self.serve().await;
+    self.shutdown().await;
+    result
+  }
+
+  async fn serve(&mut self) -> Result<ShutdownReason> {
+    if let Some(addr) = self.metrics_addr() {
+      event!(
+        target: "udev-device-manager",
+        Level::INFO,
+        "Serving metrics on http://{}/metrics",
+        addr
+      );
+    }
+    if let Some(addr) = self.health_addr() {
+      event!(
+        target: "udev-device-manager",
+        Level::INFO,
+        "Serving probes on http://{}/livez and http://{}/readyz",
+        addr,
+        addr
+      );
+    }
+
+    let config_stream = Config::watch(
+      self.config_file.clone(),
+      self.config_format.into(),
+      self.metrics.config(),
+    )?
+    .fuse();
     pin_mut!(config_stream);
 
     let signal_stream = Signal::watch()?.fuse();
     pin_mut!(signal_stream);
 
-    let udev_event_stream = Udev::watch().await?.fuse();
+    // the monitor only passes events of the config's subsystems, so it's set up again whenever
+    // a reloaded config names other ones
+    let mut udev_subsystems = self.config.subsystems();
+    let udev_source = UdevSource::new(
+      Udev::watch(udev_subsystems.iter().copied().collect()).await,
+      self.udev_poll_interval,
+    )?;
+    let udev_monitored = matches!(udev_source, UdevSource::Monitor(_));
+    let (udev_event_stream, mut udev_poll_timer) = match udev_source {
+      UdevSource::Monitor(stream) => (stream.left_stream(), ReconcileTimer::new(None)),
+      UdevSource::Polling(period) => (
+        stream::pending().right_stream(),
+        ReconcileTimer::new(Some(period)),
+      ),
+    };
+    let udev_event_stream = udev_event_stream.fuse();
     pin_mut!(udev_event_stream);
 
-    let mut action = Action::Restart;
+    let mut reconcile_timer = ReconcileTimer::new(self.reconcile_interval);
+    let health_transitions = self.health_checks.transitions();
+
+    self.probes.set_live(true);
+    let mut action = self.restart().await?;
     loop {
+      let reloaded = matches!(action, Action::Restart | Action::ReloadDeviceTypes);
+      if reloaded && udev_monitored && self.config.subsystems() != udev_subsystems {
+        udev_subsystems = self.config.subsystems();
+        event!(
+          target: "udev-device-manager",
+          Level::INFO,
+          subsystems = ?udev_subsystems,
+          "config subsystems changed, restarting udev monitor"
+        );
+
+        // events missed in between are caught by the rescan of the reload
+        let stream = Udev::watch(udev_subsystems.iter().copied().collect())
+          .await
+          .context(ShutdownReason::UdevStreamError)?;
+        udev_event_stream.set(stream.left_stream().fuse());
+      }
+
       action = match action {
-        Action::Shutdown => break,
-        Action::Restart => self.restart().await,
+        Action::Shutdown(reason) => return Ok(reason),
+        Action::Restart => self.restart().await.context(ShutdownReason::ReloadFailed),
+        Action::ReloadDeviceTypes => self
+          .reload_device_types()
+          .context(ShutdownReason::ReloadFailed),
         Action::Reconcile => self.reconcile().await,
+        Action::ScheduleReconcile(delay) => {
+          reconcile_timer.schedule(delay);
+          Ok(Action::None)
+        }
         Action::None => select! {
           c = config_stream.next() => self.on_config(c).await,
           s = signal_stream.next() => self.on_signal(s).await,
           e = udev_event_stream.next() => self.on_udev(e).await,
+          _ = udev_poll_timer.tick().fuse() => self.on_udev_poll(),
+          _ = reconcile_timer.tick().fuse() => self.on_timer(),
+          _ = health_transitions.notified().fuse() => self.on_health_transition(),
         },
       }?;
     }
+  }
 
-    Ok(())
+  /// Stops the device plugins, so the kubelet sees them go away and their sockets are
+  /// removed. Failures are only logged, as the manager is going away regardless.
+  async fn shutdown(&mut self) {
+    if let Err(e) = mem::take(&mut self.device_classes).stop().await {
+      event!(
+        target: "udev-device-manager",
+        Level::WARN,
+        "Failed to stop device plugins cleanly: {:?}",
+        e
+      );
+    }
   }
 
   async fn restart(&mut self) -> Result<Action> {
-    if let Err(e) = self.devices.scan_devices() {
+    self.reload_device_types()?;
+    self.device_classes = mem::take(&mut self.device_classes)
+      .reload(self.config.device_classes(), &self.plugin_options)
+      .await?;
+
+    Ok(Action::Reconcile)
+  }
+
+  /// Rescans the devices and applies the config's device types, leaving the device classes and
+  /// their plugins alone
+  fn reload_device_types(&mut self) -> Result<Action> {
+    if let Err(e) = self.devices.scan_devices(&DeviceScanner::new(&self.config)) {
       event!(
         target: "udev-device-manager",
         Level::ERROR,
@@ -94,23 +368,31 @@ impl App {
       );
       return Err(e).context("app restart");
     }
+    self
+      .devices
+      .add_manual_devices(self.config.manual_devices());
 
-    self.device_types = DeviceTypeRegistry::new(self.config.device_types());
-    let device_classes = mem::replace(
-      &mut self.device_classes,
-      DeviceClassRegistry::new(self.config.device_classes()).await?,
-    );
-    device_classes.stop().await?;
-    // TODO: Populate device classes
+    if self.device_types.reload(self.config.device_types()) > 0 {
+      self.health_checks.restart(&self.device_types);
+    }
 
     Ok(Action::Reconcile)
   }
 
   async fn reconcile(&mut self) -> Result<Action> {
     self.device_types.reconcile(&self.devices);
+    // removed devices are advertised as unhealthy by this reconcile, and dropped by the next
+    let forgot_removed = self.devices.forget_removed();
 
     let mut distributor = self.device_types.distributor();
-    self.device_classes.reconcile(&mut distributor);
+    self
+      .device_classes
+      .reconcile(
+        &mut distributor,
+        self.config.maintenance(),
+        self.reconcile_concurrency,
+      )
+      .await;
     let remaining = distributor.remaining();
     event!(
       target: "udev-device-manager",
@@ -119,7 +401,32 @@ impl App {
       remaining.len(),
     );
 
-    Ok(Action::None)
+    let advertised = self.device_classes.advertised();
+    self.metrics.reconciled(
+      self.devices.device_count(),
+      self.device_types.device_types().count(),
+      &advertised,
+    );
+    // the config was loaded by the restart preceding any reconcile
+    self.probes.set_ready(true);
+
+    for resource in advertised {
+      event!(
+        target: "udev-device-manager",
+        Level::DEBUG,
+        resource.name = &*resource.name,
+        resource.device_count = resource.device_count,
+        resource.ready = resource.ready,
+        "advertising {} devices for {}",
+        resource.device_count,
+        resource.name,
+      );
+    }
+
+    match forgot_removed {
+      true => Ok(Action::ScheduleReconcile(REMOVED_DEVICE_GRACE)),
+      false => Ok(Action::None),
+    }
   }
 
   async fn on_config(&mut self, config: Option<Result<Config, ConfigError>>) -> Result<Action> {
@@ -131,7 +438,7 @@ impl App {
           "Config watcher closed."
         );
 
-        Err(eyre!("config watcher closed")).context("on_config")
+        Err(eyre!("config watcher closed")).context(ShutdownReason::ConfigWatcherClosed)
       }
 
       Some(Err(e)) => {
@@ -142,12 +449,33 @@ impl App {
           e
         );
 
-        Err(e).context("on_config")
+        Err(e).context(ShutdownReason::ReloadFailed)
       }
 
       Some(Ok(c)) => {
+        let diff = self.config.diff(&c);
+        if diff.is_empty() {
+          event!(
+            target: "udev-device-manager",
+            Level::DEBUG,
+            "Config reloaded without changes."
+          );
+          return Ok(Action::None);
+        }
+
+        event!(
+          target: "udev-device-manager",
+          Level::INFO,
+          "Config changed: {}",
+          diff
+        );
+        log_config_warnings(&c);
         self.config = c;
-        Ok(Action::Restart)
+        // everything but the device classes is applied by rescanning and reconciling
+        match diff.device_classes.is_empty() {
+          true => Ok(Action::ReloadDeviceTypes),
+          false => Ok(Action::Restart),
+        }
       }
     }
   }
@@ -161,7 +489,7 @@ impl App {
           "Signal stream stopped, shutting down.",
         );
 
-        Err(eyre!("signal stream stopped")).context("on_signal")
+        Err(eyre!("signal stream stopped")).context(ShutdownReason::SignalStreamClosed)
       }
 
       Some(Signal::SigHup) => {
@@ -176,12 +504,45 @@ impl App {
           "Received signal {}, shutting down.",
           s
         );
-        Ok(Action::Shutdown)
+        Ok(Action::Shutdown(ShutdownReason::Signal(s)))
       }
     }
   }
 
-  async fn on_udev(&mut self, event: Option<Result<UdevEvent, UdevDeviceError>>) -> Result<Action> {
+  fn on_timer(&self) -> Result<Action> {
+    event!(target: "udev-device-manager", Level::DEBUG, "periodic reconcile");
+    Ok(Action::Reconcile)
+  }
+
+  fn on_health_transition(&self) -> Result<Action> {
+    event!(target: "udev-device-manager", Level::DEBUG, "device health changed");
+    Ok(Action::Reconcile)
+  }
+
+  fn on_udev_poll(&mut self) -> Result<Action> {
+    let scanned = DeviceScanner::new(&self.config)
+      .scan()
+      .context(ShutdownReason::UdevStreamError)?;
+    let events = self.devices.diff(scanned);
+    if events.is_empty() {
+      return Ok(Action::None);
+    }
+
+    event!(
+      target: "udev-device-manager",
+      Level::DEBUG,
+      "rescan found {} device changes",
+      events.len()
+    );
+    for e in events {
+      self.metrics.udev_event(&e);
+      self.devices.update(e);
+    }
+
+    Ok(Action::Reconcile)
+  }
+
+  async fn on_udev(&mut self, event: Option<Result<UdevEvent, UdevStreamError>>) -> Result<Action> {
     match event {
       None => {
         event!(
@@ -190,7 +551,19 @@ impl App {
           "Udev stream stopped, shutting down.",
         );
 
-        Err(eyre!("udev stream stopped")).context("on_udev")
+        Err(eyre!("udev stream stopped")).context(ShutdownReason::UdevStreamError)
+      }
+
+      // events may have been missed while the monitor was down, so catch up with a rescan
+      Some(Err(e)) if e.is_recoverable() => {
+        event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          "{}, rescanning devices",
+          e
+        );
+
+        self.on_udev_poll()
       }
 
       Some(Err(e)) => {
@@ -201,10 +574,11 @@ impl App {
           e
         );
 
-        Err(e).context("on_udev")
+        Err(e).context(ShutdownReason::UdevStreamError)
       }
 
       Some(Ok(e)) => {
+        self.metrics.udev_event(&e);
         self.devices.update(e);
         Ok(Action::Reconcile)
       }
@@ -214,26 +588,431 @@ impl App {
 
 pub async fn run() -> Result<()> {
   let args = Args::parse();
+  if args.dump_schema {
+    println!("{}", serde_json::to_string_pretty(&config_schema())?);
+    return Ok(());
+  }
+
+  let config_file = match &args.config_file {
+    Some(path) => path.clone(),
+    None => return Err(eyre!("no configuration file given")),
+  };
+
+  // before logging is set up, so the report is all that ends up on stdout
+  if args.dry_run {
+    return dry_run::run(&config_file, args.config_format.into(), args.log_format).await;
+  }
+  if let Some(fixture) = &args.match_test {
+    return match_test::run(
+      &config_file,
+      args.config_format.into(),
+      fixture,
+      args.log_format,
+    )
+    .await;
+  }
+
+  if let Some(format) = args.convert {
+    let config = Config::read(&config_file, args.config_format.into()).await?;
+    print!("{}", config.to_format(format.into())?);
+    return Ok(());
+  }
+
   let filter = EnvFilter::from_default_env()
     // Set the base level when not matched by other directives to INFO.
     .add_directive(tracing::Level::INFO.into());
 
+  let otel = otel::layer(&args)?;
+  let registry = tracing_subscriber::registry().with(filter).with(otel);
   match args.log_format {
     LogFormat::Pretty => {
-      tracing_subscriber::fmt().with_env_filter(filter).init();
+      registry.with(fmt::layer()).init();
     }
     LogFormat::Json => {
-      tracing_subscriber::fmt()
-        .json()
-        .with_env_filter(filter)
-        .with_current_span(false)
-        .with_span_list(false)
+      registry
+        .with(
+          fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true),
+        )
         .init();
     }
   }
 
-  let mut app = App::new(args.config_file, args.config_format).await?;
-  app.run().await?;
+  let node_name = args
+    .node_name
+    .clone()
+    .or_else(hostname)
+    .unwrap_or_else(|| "unknown".into());
+
+  let result = async move {
+    let plugin_options = PluginOptions {
+      #[cfg(feature = "reflection")]
+      reflection: args.grpc_reflection,
+      ..PluginOptions::default()
+    };
+
+    if !args.skip_preflight {
+      Preflight::new(
+        &plugin_options.paths.device_plugin_dir,
+        &plugin_options.paths.kubelet_socket,
+        &config_file,
+        args.config_format.into(),
+      )
+      .run()
+      .await
+      .context(ShutdownReason::PreflightFailed)?;
+    }
+
+    let _lock = InstanceLock::acquire(args.lock_file.clone())?;
+
+    let reconcile_interval = args.reconcile_interval.map(Duration::from_secs);
+
+    let mut app = App::new(AppOptions {
+      config_file,
+      config_format: args.config_format,
+      reconcile_interval,
+      udev_poll_interval: Duration::from_secs(args.udev_poll_interval),
+      reconcile_concurrency: args.reconcile_concurrency,
+      plugin_options,
+      health_check_concurrency: args.health_check_concurrency,
+      metrics_addr: args.metrics_addr,
+      health_addr: args.health_addr,
+    })
+    .await?;
+    app.run().await
+  }
+  .instrument(node_span(&node_name))
+  .await;
+
+  node_span(&node_name).in_scope(|| log_shutdown(&result));
+  otel::shutdown();
+  result.map(|_| ())
+}
+
+/// Logs the likely mistakes found in a config, which is applied regardless
+fn log_config_warnings(config: &Config) {
+  for warning in config.lint() {
+    event!(
+      target: "udev-device-manager",
+      Level::WARN,
+      "Config warning: {}",
+      warning
+    );
+  }
+}
+
+/// Logs why the manager stopped, uniformly for clean exits and failures.
+fn log_shutdown(result: &Result<ShutdownReason>) {
+  match result {
+    Ok(reason) => event!(
+      target: "udev-device-manager",
+      Level::INFO,
+      shutdown.reason = %reason,
+      "Shutting down: {}",
+      reason
+    ),
+    Err(e) => {
+      let reason = ShutdownReason::of(e);
+      event!(
+        target: "udev-device-manager",
+        Level::ERROR,
+        shutdown.reason = %reason,
+        "Shutting down: {}",
+        reason
+      )
+    }
+  }
+}
 
-  Ok(())
+/// Root span carrying the node name, so every event is attributable to a node.
+fn node_span(node_name: &str) -> Span {
+  span!(Level::INFO, "node", node.name = node_name)
+}
+
+fn hostname() -> Option<String> {
+  std::fs::read_to_string("/proc/sys/kernel/hostname")
+    .ok()
+    .map(|name| name.trim().to_owned())
+    .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_log::logged;
+  use std::io;
+
+  fn options(config_file: PathBuf) -> AppOptions {
+    AppOptions {
+      config_file,
+      config_format: ConfigFormat::Toml,
+      reconcile_interval: None,
+      udev_poll_interval: Duration::from_secs(10),
+      reconcile_concurrency: 1,
+      plugin_options: PluginOptions::default(),
+      health_check_concurrency: 1,
+      metrics_addr: None,
+      health_addr: None,
+    }
+  }
+
+  #[test]
+  fn node_name_on_events() {
+    let output = logged(|| {
+      let _guard = node_span("node-a").entered();
+      event!(target: "udev-device-manager", Level::INFO, "reconciled");
+    });
+
+    assert!(output.contains("node-a"), "{}", output);
+  }
+
+  #[tokio::test]
+  async fn reconcile_timer_fires_periodically() {
+    let mut timer = ReconcileTimer::new(Some(Duration::from_millis(10)));
+    for _ in 0..3 {
+      time::timeout(Duration::from_secs(1), timer.tick())
+        .await
+        .expect("timer ticks without any events");
+    }
+
+    let mut disabled = ReconcileTimer::new(None);
+    assert!(time::timeout(Duration::from_millis(50), disabled.tick())
+      .await
+      .is_err());
+
+    // `--reconcile-interval 0` turns periodic reconciles off rather than panicking
+    let mut zero = ReconcileTimer::new(Some(Duration::from_secs(0)));
+    assert!(time::timeout(Duration::from_millis(50), zero.tick())
+      .await
+      .is_err());
+  }
+
+  #[tokio::test]
+  async fn scheduled_reconciles_fire_once() {
+    let mut timer = ReconcileTimer::new(None);
+    timer.schedule(Duration::from_secs(60));
+    timer.schedule(Duration::from_millis(10));
+    time::timeout(Duration::from_secs(1), timer.tick())
+      .await
+      .expect("the earliest scheduled reconcile fires");
+
+    assert!(time::timeout(Duration::from_millis(50), timer.tick())
+      .await
+      .is_err());
+  }
+
+  #[test]
+  fn unavailable_udev_monitor_falls_back_to_polling() {
+    let period = Duration::from_secs(5);
+    let unavailable = UdevBuilderError::MonitorUnavailable(io::Error::from_raw_os_error(1));
+    assert!(matches!(
+      UdevSource::<()>::new(Err(unavailable), period),
+      Ok(UdevSource::Polling(p)) if p == period
+    ));
+    assert!(matches!(
+      UdevSource::new(Ok(()), period),
+      Ok(UdevSource::Monitor(()))
+    ));
+    assert!(UdevSource::<()>::new(Err(UdevBuilderError::SendError), period).is_err());
+  }
+
+  #[tokio::test]
+  async fn triggers_map_to_shutdown_reasons() {
+    let config_file = std::env::temp_dir().join(format!(
+      "udev-device-manager-shutdown-{}.toml",
+      std::process::id()
+    ));
+    std::fs::write(&config_file, "devices = []\ndeviceClasses = []\n").unwrap();
+    let mut app = App::new(options(config_file.clone())).await.unwrap();
+    std::fs::remove_file(&config_file).unwrap();
+
+    let reason = |result: Result<Action>| match result {
+      Ok(Action::Shutdown(reason)) => reason,
+      Ok(_) => panic!("expected the trigger to shut down"),
+      Err(e) => ShutdownReason::of(&e),
+    };
+
+    assert_eq!(
+      reason(app.on_signal(Some(Signal::SigTerm)).await),
+      ShutdownReason::Signal(Signal::SigTerm)
+    );
+    assert_eq!(
+      reason(app.on_signal(None).await),
+      ShutdownReason::SignalStreamClosed
+    );
+    assert_eq!(
+      reason(app.on_config(None).await),
+      ShutdownReason::ConfigWatcherClosed
+    );
+    assert_eq!(
+      reason(
+        app
+          .on_config(Some(Err(ConfigError::UnsupportedSource {
+            detail: "unknown extension".into(),
+          })))
+          .await
+      ),
+      ShutdownReason::ReloadFailed
+    );
+    assert_eq!(
+      reason(app.on_udev(None).await),
+      ShutdownReason::UdevStreamError
+    );
+    assert_eq!(
+      reason(
+        app
+          .on_udev(Some(Err(UdevStreamError::Fatal {
+            error: io::Error::from(io::ErrorKind::ConnectionReset),
+            reconnect: io::Error::from(io::ErrorKind::NotFound),
+          })))
+          .await
+      ),
+      ShutdownReason::UdevStreamError
+    );
+
+    let preflight: Result<()> = Err(eyre!("plugin dir missing"));
+    assert_eq!(
+      reason(
+        preflight
+          .context(ShutdownReason::PreflightFailed)
+          .map(|_| Action::None)
+      ),
+      ShutdownReason::PreflightFailed
+    );
+    assert_eq!(reason(Err(eyre!("anything else"))), ShutdownReason::Error);
+  }
+
+  #[tokio::test]
+  async fn shutdown_removes_plugin_sockets() {
+    let (dir, plugin_options, _kubelet) = device_class::test_plugin_dir("shutdown-sockets");
+    let config_file = dir.join("config.toml");
+    std::fs::write(
+      &config_file,
+      r#"
+        [[devices]]
+        name = "conbee2"
+        subsystem = "tty"
+        labels = { type = "conbee2" }
+        selector = {}
+
+        [[deviceClasses]]
+        name = "zigbee"
+        subsystem = "tty"
+        target = "zigbee"
+        selector = { matchLabels = { type = "conbee2" } }
+      "#,
+    )
+    .unwrap();
+
+    let mut app = App::new(AppOptions {
+      plugin_options: plugin_options.clone(),
+      ..options(config_file)
+    })
+    .await
+    .unwrap();
+    app.device_classes = mem::take(&mut app.device_classes)
+      .reload(app.config.device_classes(), &plugin_options)
+      .await
+      .unwrap();
+
+    let sockets = app
+      .device_classes
+      .advertised()
+      .into_iter()
+      .filter_map(|resource| resource.socket_path)
+      .collect::<Vec<_>>();
+    assert_eq!(sockets.len(), 1);
+    assert!(sockets[0].exists());
+
+    app.shutdown().await;
+    assert!(app.device_classes.advertised().is_empty());
+    assert!(!sockets[0].exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn metrics_endpoint_counts_reconciles() {
+    let config_file = std::env::temp_dir().join(format!(
+      "udev-device-manager-reconcile-metrics-{}.toml",
+      std::process::id()
+    ));
+    std::fs::write(&config_file, "devices = []\ndeviceClasses = []\n").unwrap();
+    let mut app = App::new(AppOptions {
+      metrics_addr: Some(([127, 0, 0, 1], 0).into()),
+      ..options(config_file.clone())
+    })
+    .await
+    .unwrap();
+    std::fs::remove_file(&config_file).unwrap();
+
+    let addr = app.metrics_addr().unwrap();
+    let scrape = || {
+      tokio::task::spawn_blocking(move || {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+          .write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")
+          .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+      })
+    };
+
+    let before = scrape().await.unwrap();
+    assert!(before.contains(" 200 OK"), "{}", before);
+    assert!(before.contains("reconciles_total 0"), "{}", before);
+    assert!(
+      before.contains("udevdm_config_reload_total{result=\"ok\"} 1"),
+      "{}",
+      before
+    );
+
+    app.reconcile().await.unwrap();
+    let after = scrape().await.unwrap();
+    assert!(after.contains("reconciles_total 1"), "{}", after);
+  }
+
+  #[tokio::test]
+  async fn readyz_passes_after_the_first_reconcile() {
+    let config_file = std::env::temp_dir().join(format!(
+      "udev-device-manager-probes-{}.toml",
+      std::process::id()
+    ));
+    std::fs::write(&config_file, "devices = []\ndeviceClasses = []\n").unwrap();
+    let mut app = App::new(AppOptions {
+      health_addr: Some(([127, 0, 0, 1], 0).into()),
+      ..options(config_file.clone())
+    })
+    .await
+    .unwrap();
+    std::fs::remove_file(&config_file).unwrap();
+
+    let addr = app.health_addr().unwrap();
+    let probe = |path: &'static str| {
+      tokio::task::spawn_blocking(move || {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+      })
+    };
+
+    let before = probe("/readyz").await.unwrap();
+    assert!(before.contains(" 503 Service Unavailable"), "{}", before);
+
+    app.reconcile().await.unwrap();
+    let after = probe("/readyz").await.unwrap();
+    assert!(after.contains(" 200 OK"), "{}", after);
+
+    let missing = probe("/healthz").await.unwrap();
+    assert!(missing.contains(" 404 Not Found"), "{}", missing);
+  }
 }
@@ -1,6 +1,6 @@
 use crate::config;
 use clap::Clap;
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
 
 #[derive(Clap, Debug, PartialEq, Clone, Copy)]
 pub enum LogFormat {
@@ -27,6 +27,33 @@ impl From<ConfigFormat> for config::ConfigFormat {
   }
 }
 
+/// Format `--convert` writes a config in, which unlike [`ConfigFormat`] has to be explicit
+#[derive(Clap, Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+  Json,
+  Yaml,
+  Toml,
+}
+
+impl From<OutputFormat> for config::ConfigFormat {
+  fn from(f: OutputFormat) -> Self {
+    match f {
+      OutputFormat::Json => config::ConfigFormat::Json,
+      OutputFormat::Yaml => config::ConfigFormat::Yaml,
+      OutputFormat::Toml => config::ConfigFormat::Toml,
+    }
+  }
+}
+
+/// Parses a number of seconds that has to be more than 0
+fn positive_seconds(value: &str) -> Result<u64, String> {
+  match value.parse::<u64>() {
+    Ok(0) => Err("must be at least 1 second".into()),
+    Ok(secs) => Ok(secs),
+    Err(e) => Err(e.to_string()),
+  }
+}
+
 #[derive(Clap, Debug)]
 pub struct Args {
   /// Log output format
@@ -49,7 +76,139 @@ pub struct Args {
   )]
   pub config_format: ConfigFormat,
 
-  /// Configuration file path
-  #[clap(long = "config", short = 'c', env = "CONFIG_FILE")]
-  pub config_file: PathBuf,
+  /// Configuration file path, or a directory of config files merged in lexical order
+  #[clap(
+    long = "config",
+    short = 'c',
+    env = "CONFIG_FILE",
+    required_unless_present = "dump-schema"
+  )]
+  pub config_file: Option<PathBuf>,
+
+  /// Print the JSON Schema of the configuration file and exit
+  #[clap(long = "dump-schema")]
+  pub dump_schema: bool,
+
+  /// Name of the node the manager runs on, defaults to the hostname
+  #[clap(long = "node-name", env = "NODE_NAME")]
+  pub node_name: Option<String>,
+
+  /// Seconds between periodic reconciles, on top of the event driven ones. Off when unset or 0
+  #[clap(long = "reconcile-interval", env = "RECONCILE_INTERVAL")]
+  pub reconcile_interval: Option<u64>,
+
+  /// Seconds between rescans of the udev devices, when udev events can't be monitored (e.g.
+  /// without `CAP_NET_ADMIN`)
+  #[clap(
+    long = "udev-poll-interval",
+    env = "UDEV_POLL_INTERVAL",
+    default_value = "10",
+    parse(try_from_str = positive_seconds)
+  )]
+  pub udev_poll_interval: u64,
+
+  /// Most device classes reconciled at once
+  #[clap(
+    long = "reconcile-concurrency",
+    env = "RECONCILE_CONCURRENCY",
+    default_value = "1"
+  )]
+  pub reconcile_concurrency: usize,
+
+  /// Most health check commands run at once, per device type
+  #[clap(
+    long = "health-check-concurrency",
+    env = "HEALTH_CHECK_CONCURRENCY",
+    default_value = "4"
+  )]
+  pub health_check_concurrency: usize,
+
+  /// Address to serve Prometheus metrics on, e.g. `0.0.0.0:9100`. Off when unset
+  #[clap(long = "metrics-addr", env = "METRICS_ADDR")]
+  pub metrics_addr: Option<SocketAddr>,
+
+  /// Address to serve the `/livez` and `/readyz` probe endpoints on, e.g. `0.0.0.0:8080`.
+  /// Off when unset
+  #[clap(long = "health-addr", env = "HEALTH_ADDR")]
+  pub health_addr: Option<SocketAddr>,
+
+  /// Node-wide lock file preventing several managers from running on the same node. Has to be
+  /// outside the device plugin directory, which the kubelet empties when it restarts
+  #[clap(
+    long = "lock-file",
+    env = "LOCK_FILE",
+    default_value = "/run/udev-device-manager.lock"
+  )]
+  pub lock_file: PathBuf,
+
+  /// Print which devices each device type matches and which device class claims them, then
+  /// exit without registering any device plugins. The report is JSON with the json log format
+  #[clap(long = "dry-run")]
+  pub dry_run: bool,
+
+  /// Match the synthetic devices of a JSON fixture against the config, print which device
+  /// types and classes each lands in and exit, failing if any of the fixture's assertions do
+  #[clap(long = "match-test")]
+  pub match_test: Option<PathBuf>,
+
+  /// Print the config in the given format and exit, e.g. to migrate it to another format. Keys
+  /// are written in their canonical spelling
+  #[clap(
+    arg_enum,
+    long = "convert",
+    conflicts_with_all = &["dry-run", "match-test"]
+  )]
+  pub convert: Option<OutputFormat>,
+
+  /// Skip the startup self-check
+  #[clap(long = "skip-preflight")]
+  pub skip_preflight: bool,
+
+  /// Serve gRPC reflection on the plugin sockets, for debugging with tools like grpcurl
+  #[cfg(feature = "reflection")]
+  #[clap(long = "grpc-reflection")]
+  pub grpc_reflection: bool,
+
+  /// OTLP collector endpoint to export spans to, export is disabled when unset
+  #[cfg(feature = "otel")]
+  #[clap(long = "otlp-endpoint", env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+  pub otlp_endpoint: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn udev_poll_interval_must_be_positive() {
+    let parse = |interval: &str| {
+      Args::try_parse_from([
+        "udev-device-manager",
+        "--config",
+        "config.toml",
+        "--udev-poll-interval",
+        interval,
+      ])
+    };
+
+    assert_eq!(parse("5").unwrap().udev_poll_interval, 5);
+    assert!(parse("0").is_err());
+    assert!(parse("soon").is_err());
+  }
+
+  #[test]
+  fn convert_needs_an_explicit_format_and_runs_alone() {
+    let parse = |args: &[&str]| {
+      let base = ["udev-device-manager", "--config", "config.toml"];
+      Args::try_parse_from(base.iter().chain(args))
+    };
+
+    assert_eq!(
+      parse(&["--convert", "yaml"]).unwrap().convert,
+      Some(OutputFormat::Yaml)
+    );
+    assert!(parse(&["--convert", "auto"]).is_err());
+    assert!(parse(&["--convert", "json", "--dry-run"]).is_err());
+    assert!(parse(&["--convert", "json", "--match-test", "fixture.json"]).is_err());
+  }
 }
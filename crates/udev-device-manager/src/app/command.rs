@@ -0,0 +1,43 @@
+use std::{process::Stdio, time::Duration};
+use tokio::{io::AsyncWriteExt, process::Command, time};
+
+/// Runs `command` with `input` on its stdin, failing unless it exits successfully within
+/// `timeout`. `what` names the command in errors, e.g. "reset command".
+pub async fn exec(
+  what: &str,
+  command: &[String],
+  input: &[u8],
+  timeout: Duration,
+) -> Result<(), String> {
+  let (program, args) = match command.split_first() {
+    Some(command) => command,
+    None => return Err(format!("{} has no command", what)),
+  };
+
+  let mut child = Command::new(program)
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .kill_on_drop(true)
+    .spawn()
+    .map_err(|e| format!("failed to run {} {:?}: {}", what, command, e))?;
+
+  let mut stdin = child.stdin.take().expect("stdin is piped");
+  let status = async {
+    // commands are free to ignore their input, and close stdin early
+    let _ = stdin.write_all(input).await;
+    drop(stdin);
+    child.wait().await
+  };
+
+  match time::timeout(timeout, status).await {
+    Ok(Ok(status)) if status.success() => Ok(()),
+    Ok(Ok(status)) => Err(format!("{} {:?} failed: {}", what, command, status)),
+    Ok(Err(e)) => Err(format!("failed to run {} {:?}: {}", what, command, e)),
+    Err(_) => Err(format!(
+      "{} {:?} timed out after {:?}",
+      what, command, timeout
+    )),
+  }
+}
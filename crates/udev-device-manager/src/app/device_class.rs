@@ -1,35 +1,309 @@
+mod allocate_hook;
+mod allocations;
 mod device_plugin_server;
 
-use self::device_plugin_server::DevicePlugin;
+use self::device_plugin_server::{DevicePlugin, RestartRequired};
 use crate::{
-  app::DeviceTypeDistributor,
+  app::{DeviceTypeDistributor, DeviceTypeHandle},
   config::{DeviceClass, InternedString},
   utils::AggregateErrorExt,
 };
 use color_eyre::{eyre::WrapErr, Result};
-use futures::future::join_all;
-use kubelet_deviceplugin_proto::{v1beta1, KubernetesDevicePluginServer};
-use std::collections::BTreeMap;
+use futures::{future::join_all, stream, StreamExt};
+use kubelet_deviceplugin_proto::{
+  v1beta1::{self, PluginPaths},
+  KubernetesDevicePluginServer,
+};
+use std::{
+  collections::{btree_map::Entry, BTreeMap},
+  path::PathBuf,
+  time::Duration,
+};
+use tracing::{event, Level};
+
+/// How long stopping plugin servers waits on in-flight calls before aborting them
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// A resource advertised to the kubelet by one of the device class plugins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdvertisedResource {
+  /// Resource name registered with the kubelet
+  pub name: String,
+
+  /// Device class the resource belongs to
+  pub device_class: InternedString,
+
+  /// Plugin socket the kubelet connects to, once registered
+  pub socket_path: Option<PathBuf>,
+
+  /// Number of devices currently advertised
+  pub device_count: usize,
+
+  /// Number of advertised devices held by a lease from an earlier allocation
+  pub leased_count: usize,
+
+  /// Whether the plugin is registered, its server still running and it matched the
+  /// expected number of devices
+  pub ready: bool,
+}
+
+/// Settings shared by all device plugin servers
+#[derive(Debug, Clone, Default)]
+pub struct PluginOptions {
+  /// Where plugin sockets are created and the kubelet is reached
+  pub paths: PluginPaths,
+
+  /// Serve gRPC reflection on the plugin sockets
+  #[cfg(feature = "reflection")]
+  pub reflection: bool,
+}
+
+impl PluginOptions {
+  /// Options for plugins serving from `dir`, reaching the kubelet through a socket in there
+  #[cfg(test)]
+  pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+    Self {
+      paths: PluginPaths::in_dir(dir),
+      #[cfg(feature = "reflection")]
+      reflection: false,
+    }
+  }
+}
+
+/// A fresh plugin directory for the test `name`, plugin options serving from it and a mock
+/// kubelet taking registrations in it
+#[cfg(test)]
+pub(crate) fn test_plugin_dir(
+  name: &str,
+) -> (
+  PathBuf,
+  PluginOptions,
+  kubelet_deviceplugin_proto::testkit::MockKubelet,
+) {
+  let dir = std::env::temp_dir().join(format!(
+    "udev-device-manager-{}-{}",
+    name,
+    std::process::id()
+  ));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  let options = PluginOptions::in_dir(&dir);
+  let kubelet =
+    kubelet_deviceplugin_proto::testkit::MockKubelet::start(&options.paths.kubelet_socket).unwrap();
+
+  (dir, options, kubelet)
+}
 
 #[derive(Debug)]
-pub struct DeviceClassHandle {
+struct DevicePluginInstance {
   plugin: DevicePlugin,
   server: KubernetesDevicePluginServer,
 }
 
-impl DeviceClassHandle {
-  async fn new(config: DeviceClass) -> Result<Self> {
-    let plugin = DevicePlugin::new(config);
+impl DevicePluginInstance {
+  async fn start(
+    config: DeviceClass,
+    resource_name: String,
+    options: &PluginOptions,
+  ) -> Result<Self> {
+    let prefer_numa_alignment = config.prefer_numa_alignment();
+    let pre_start_reset = config.pre_start_reset();
+    let socket_mode = config.socket_mode();
+    let plugin = DevicePlugin::new(config, resource_name.clone());
     let server = v1beta1::KubeletDevicePluginV1Beta1::new(plugin.clone())
-      .start(format!("udev/{}/{}", plugin.subsystem(), plugin.name()))
-      .await
-      .wrap_err("Failed to start kubelet plugin server")?;
+      .wait_until_serving()
+      .with_paths(options.paths.clone());
+    let server = match socket_mode {
+      Some(mode) => server.with_socket_mode(mode.bits()),
+      None => server,
+    };
+    #[cfg(feature = "reflection")]
+    let server = match options.reflection {
+      true => server.with_reflection(),
+      false => server,
+    };
+
+    // each capability changes the server's type, so every combination is started separately
+    let server = match (prefer_numa_alignment, pre_start_reset) {
+      (false, false) => server.start(resource_name).await,
+      (true, false) => {
+        server
+          .with_preferred_allocation_support()
+          .start(resource_name)
+          .await
+      }
+      (false, true) => server.with_prestart().start(resource_name).await,
+      (true, true) => {
+        server
+          .with_preferred_allocation_support()
+          .with_prestart()
+          .start(resource_name)
+          .await
+      }
+    }
+    .wrap_err("Failed to start kubelet plugin server")?;
 
     Ok(Self { plugin, server })
   }
 
-  pub fn reconcile(&self, distributor: &mut impl DeviceTypeDistributor) {
-    self.plugin.reconcile(distributor)
+  fn advertised(&self, device_class: InternedString, name: &str) -> AdvertisedResource {
+    let registration = self.server.registration();
+
+    AdvertisedResource {
+      name: name.into(),
+      device_class,
+      socket_path: registration.map(|r| r.endpoint.clone()),
+      device_count: self.plugin.device_count(),
+      leased_count: self.plugin.leased_count(),
+      ready: registration.is_some()
+        && !self.server.is_terminated()
+        && self.plugin.has_expected_count(),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct DeviceClassHandle {
+  config: DeviceClass,
+  options: PluginOptions,
+  instances: BTreeMap<String, DevicePluginInstance>,
+}
+
+impl DeviceClassHandle {
+  async fn new(config: DeviceClass, options: PluginOptions) -> Result<Self> {
+    let mut instances = BTreeMap::new();
+
+    // grouped classes spawn their plugins on reconcile, once the groups are known
+    if config.group_by().is_none() {
+      let resource_name = config.resource_name(None);
+      let instance =
+        DevicePluginInstance::start(config.clone(), resource_name.clone(), &options).await?;
+      instances.insert(resource_name, instance);
+    }
+
+    Ok(Self {
+      config,
+      options,
+      instances,
+    })
+  }
+
+  pub fn name(&self) -> InternedString {
+    self.config.name()
+  }
+
+  /// Applies a changed config to the running plugins, keeping their servers and sockets
+  fn update_config(&mut self, config: DeviceClass) -> Result<(), RestartRequired> {
+    RestartRequired::check(&self.config, &config)?;
+    for instance in self.instances.values() {
+      instance.plugin.update_config(config.clone())?;
+    }
+
+    self.config = config;
+    Ok(())
+  }
+
+  /// Takes the device types matched by the class from the distributor
+  fn claim(&self, distributor: &mut impl DeviceTypeDistributor) -> Vec<DeviceTypeHandle> {
+    let config = &self.config;
+    distributor.get_device_types(|ty| config.match_with(ty).is_match())
+  }
+
+  async fn reconcile(
+    &mut self,
+    device_types: Vec<DeviceTypeHandle>,
+    maintenance: bool,
+  ) -> Result<()> {
+    let config = &self.config;
+
+    let mut groups: BTreeMap<String, Vec<DeviceTypeHandle>> = BTreeMap::new();
+    if config.group_by().is_none() {
+      groups.insert(config.resource_name(None), Vec::new());
+    }
+
+    for device_type in device_types {
+      match config.resource_name_for(device_type.config()) {
+        Some(resource_name) => groups.entry(resource_name).or_default().push(device_type),
+        // claimed device types always have the label, so only its value can be at fault
+        None => event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          device_class.name = %config.name(),
+          device_type.name = %device_type.config().name(),
+          "group label value doesn't make a valid resource name, skipping device type"
+        ),
+      }
+    }
+
+    let stale = self
+      .instances
+      .keys()
+      .filter(|name| !groups.contains_key(*name))
+      .cloned()
+      .collect::<Vec<_>>();
+
+    let mut results = Vec::with_capacity(stale.len());
+    for resource_name in stale {
+      if let Some(instance) = self.instances.remove(&resource_name) {
+        event!(
+          target: "udev-device-manager",
+          Level::INFO,
+          device_class.name = %config.name(),
+          resource = &*resource_name,
+          "no device types left in group, stopping device plugin"
+        );
+
+        results.push(instance.server.shutdown(SHUTDOWN_GRACE).await);
+      }
+    }
+
+    for (resource_name, device_types) in groups {
+      let instance = match self.instances.entry(resource_name) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+          event!(
+            target: "udev-device-manager",
+            Level::INFO,
+            device_class.name = %config.name(),
+            resource = &**entry.key(),
+            "starting device plugin for new group"
+          );
+
+          match DevicePluginInstance::start(config.clone(), entry.key().clone(), &self.options)
+            .await
+          {
+            Ok(instance) => entry.insert(instance),
+            Err(e) => {
+              event!(
+                target: "udev-device-manager",
+                Level::ERROR,
+                device_class.name = %config.name(),
+                resource = &**entry.key(),
+                "Failed to start device plugin: {:?}",
+                e
+              );
+              continue;
+            }
+          }
+        }
+      };
+
+      instance.plugin.set_maintenance(maintenance);
+      instance.plugin.reconcile(device_types);
+    }
+
+    results.collect_errors()
+  }
+
+  fn advertised(&self) -> impl Iterator<Item = AdvertisedResource> + '_ {
+    self
+      .instances
+      .iter()
+      .map(move |(name, instance)| instance.advertised(self.config.name(), name))
+  }
+
+  fn servers(self) -> impl Iterator<Item = KubernetesDevicePluginServer> {
+    self.instances.into_values().map(|i| i.server)
   }
 }
 
@@ -39,12 +313,68 @@ pub struct DeviceClassRegistry {
 }
 
 impl DeviceClassRegistry {
-  pub async fn new(device_classes: &[DeviceClass]) -> Result<Self> {
+  /// Applies a new set of device classes. Running classes are updated in place where the
+  /// change allows it, the others are stopped once their replacements have started.
+  pub async fn reload(
+    mut self,
+    device_classes: &[DeviceClass],
+    options: &PluginOptions,
+  ) -> Result<Self> {
     let mut handles = BTreeMap::new();
+    let mut stale = BTreeMap::new();
     for item in device_classes {
-      let handle = DeviceClassHandle::new(item.clone()).await?;
-      handles.insert(handle.plugin.name(), handle);
+      if !item.enabled() {
+        event!(
+          target: "udev-device-manager",
+          Level::INFO,
+          device_class.name = %item.name(),
+          "device class is disabled, skipping"
+        );
+        continue;
+      }
+
+      if let Some(mut handle) = self.device_classes.remove(&item.name()) {
+        // unchanged classes keep their plugins (and kubelet registrations) untouched
+        if handle.config == *item {
+          handles.insert(handle.name(), handle);
+          continue;
+        }
+
+        match handle.update_config(item.clone()) {
+          Ok(()) => {
+            event!(
+              target: "udev-device-manager",
+              Level::DEBUG,
+              device_class.name = %item.name(),
+              "updated device class in place"
+            );
+            handles.insert(handle.name(), handle);
+            continue;
+          }
+
+          Err(e) => {
+            event!(
+              target: "udev-device-manager",
+              Level::INFO,
+              device_class.name = %item.name(),
+              "{}",
+              e
+            );
+            stale.insert(handle.name(), handle);
+          }
+        }
+      }
+
+      let handle = DeviceClassHandle::new(item.clone(), options.clone()).await?;
+      handles.insert(handle.name(), handle);
+    }
+
+    stale.extend(self.device_classes);
+    Self {
+      device_classes: stale,
     }
+    .stop()
+    .await?;
 
     Ok(Self {
       device_classes: handles,
@@ -52,15 +382,329 @@ impl DeviceClassRegistry {
   }
 
   pub async fn stop(self) -> Result<()> {
-    let servers = self.device_classes.into_values().map(|h| h.server);
-    let results = join_all(servers.map(|s| s.abort())).await;
+    let servers = self
+      .device_classes
+      .into_values()
+      .flat_map(DeviceClassHandle::servers);
+    let results = join_all(servers.map(|s| s.shutdown(SHUTDOWN_GRACE))).await;
 
     results.collect_errors()
   }
 
-  pub fn reconcile(&self, distributor: &mut impl DeviceTypeDistributor) {
-    for handle in self.device_classes.values() {
-      handle.reconcile(distributor);
+  /// All resources currently advertised, across all device classes
+  pub fn advertised(&self) -> Vec<AdvertisedResource> {
+    self
+      .device_classes
+      .values()
+      .flat_map(DeviceClassHandle::advertised)
+      .collect()
+  }
+
+  /// Reconciles up to `concurrency` device classes at once. The device types are handed out
+  /// up front, in class name order, so which class gets a device type doesn't depend on how
+  /// the reconciles interleave. A class failing to reconcile is logged and doesn't hold up
+  /// the others.
+  pub async fn reconcile(
+    &mut self,
+    distributor: &mut impl DeviceTypeDistributor,
+    maintenance: bool,
+    concurrency: usize,
+  ) {
+    let claims = self
+      .device_classes
+      .values_mut()
+      .map(|handle| {
+        let device_types = handle.claim(distributor);
+        (handle, device_types)
+      })
+      .collect::<Vec<_>>();
+
+    let results = stream::iter(claims)
+      .map(|(handle, device_types)| async move {
+        let name = handle.name();
+        (name, handle.reconcile(device_types, maintenance).await)
+      })
+      .buffer_unordered(concurrency.max(1))
+      .collect::<Vec<_>>()
+      .await;
+
+    for (name, result) in results {
+      if let Err(e) = result {
+        event!(
+          target: "udev-device-manager",
+          Level::ERROR,
+          device_class.name = %name,
+          "Failed to reconcile device class: {:?}",
+          e
+        );
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  /// A tty device class taking every device, with `fields` added to (or replacing) its config
+  fn class(name: &str, fields: serde_json::Value) -> DeviceClass {
+    let mut class = json!({
+      "name": name,
+      "subsystem": "tty",
+      "target": name,
+      "selector": {},
+    });
+    if let (Some(class), Some(fields)) = (class.as_object_mut(), fields.as_object()) {
+      class.extend(fields.clone());
     }
+    serde_json::from_value(class).unwrap()
+  }
+
+  #[tokio::test]
+  async fn disabled_device_classes_advertise_nothing() {
+    let class: DeviceClass = serde_json::from_value(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "target": "conbee2",
+      "enabled": false,
+      "selector": {},
+    }))
+    .unwrap();
+
+    let registry = DeviceClassRegistry::default()
+      .reload(&[class], &PluginOptions::default())
+      .await
+      .unwrap();
+    assert!(registry.advertised().is_empty());
+  }
+
+  #[tokio::test]
+  async fn advertised_lists_every_started_class() {
+    use std::path::Path;
+
+    let (dir, options, kubelet) = test_plugin_dir("advertised");
+
+    let registry = DeviceClassRegistry::default()
+      .reload(
+        &[class("serial", json!({})), class("zigbee", json!({}))],
+        &options,
+      )
+      .await
+      .unwrap();
+
+    let advertised = registry.advertised();
+    assert_eq!(
+      advertised
+        .iter()
+        .map(|resource| (&*resource.name, &*resource.device_class))
+        .collect::<Vec<_>>(),
+      vec![("udev/tty/serial", "serial"), ("udev/tty/zigbee", "zigbee")]
+    );
+    for resource in &advertised {
+      let socket = resource.socket_path.as_ref().unwrap();
+      assert_eq!(socket.parent(), Some(dir.as_path()));
+      assert!(socket.exists(), "{}", socket.display());
+      assert!(kubelet.registrations().iter().any(|registration| {
+        registration.resource_name == resource.name && Path::new(&registration.endpoint) == socket
+      }));
+    }
+    assert_ne!(advertised[0].socket_path, advertised[1].socket_path);
+
+    registry.stop().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn unrelated_edits_keep_plugins_registered() {
+    let (dir, options, mut kubelet) = test_plugin_dir("unrelated-edits");
+    let timeout = Duration::from_secs(5);
+    let mut registered = Vec::new();
+
+    let registry = DeviceClassRegistry::default()
+      .reload(
+        &[class("serial", json!({})), class("zigbee", json!({}))],
+        &options,
+      )
+      .await
+      .unwrap();
+    for _ in 0..2 {
+      registered.push(
+        kubelet
+          .next_registration(timeout)
+          .await
+          .unwrap()
+          .resource_name,
+      );
+    }
+
+    // zigbee changes in place, serial isn't touched, and only the new class registers
+    let registry = registry
+      .reload(
+        &[
+          class("serial", json!({})),
+          class("zigbee", json!({ "annotations": { "radio": "conbee2" } })),
+          class("zwave", json!({})),
+        ],
+        &options,
+      )
+      .await
+      .unwrap();
+    registered.push(
+      kubelet
+        .next_registration(timeout)
+        .await
+        .unwrap()
+        .resource_name,
+    );
+
+    // zigbee has to restart, serial still isn't touched
+    let registry = registry
+      .reload(
+        &[
+          class("serial", json!({})),
+          class("zigbee", json!({ "preferNumaAlignment": true })),
+          class("zwave", json!({})),
+        ],
+        &options,
+      )
+      .await
+      .unwrap();
+    registered.push(
+      kubelet
+        .next_registration(timeout)
+        .await
+        .unwrap()
+        .resource_name,
+    );
+    let settle = Duration::from_millis(200);
+    assert!(kubelet.next_registration(settle).await.is_none());
+
+    registered[..2].sort();
+    assert_eq!(
+      registered,
+      vec![
+        "udev/tty/serial",
+        "udev/tty/zigbee",
+        "udev/tty/zwave",
+        "udev/tty/zigbee",
+      ]
+    );
+    let serial = registry
+      .advertised()
+      .into_iter()
+      .find(|resource| resource.name == "udev/tty/serial")
+      .unwrap();
+    assert!(serial.ready);
+
+    registry.stop().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn pre_start_reset_registers_prestart_support() {
+    let class = class(
+      "conbee2",
+      json!({
+        "preStartReset": true,
+        "resetCommand": ["true"],
+        "preferNumaAlignment": true,
+      }),
+    );
+    let (dir, options, mut kubelet) = test_plugin_dir("pre-start-reset");
+
+    let registry = DeviceClassRegistry::default()
+      .reload(&[class], &options)
+      .await
+      .unwrap();
+    let registration = kubelet
+      .next_registration(Duration::from_secs(5))
+      .await
+      .unwrap();
+    let plugin_options = registration.options.unwrap();
+    assert!(plugin_options.pre_start_required);
+    assert!(plugin_options.get_preferred_allocation_available);
+
+    registry.stop().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn parallel_reconcile_distributes_like_serial() {
+    use crate::{
+      app::{DeviceRegistry, DeviceTypeRegistry},
+      config::DeviceType,
+      udev::{UdevDevice, UdevEvent},
+    };
+
+    let device_types = ["accel", "serial", "zigbee"]
+      .iter()
+      .map(|name| {
+        serde_json::from_value::<DeviceType>(json!({
+          "name": name,
+          "subsystem": "tty",
+          "labels": { "type": name },
+          "selector": { "matchAttributes": { "driver": name } },
+        }))
+        .unwrap()
+      })
+      .collect::<Vec<_>>();
+    let mut devices = DeviceRegistry::new();
+    for (index, driver) in ["accel", "serial", "serial", "zigbee"].iter().enumerate() {
+      devices.update(UdevEvent::Add(UdevDevice::from_parts(
+        "tty",
+        &format!("/sys/devices/{}", index),
+        &format!("/dev/ttyACM{}", index),
+        vec![("driver", *driver)],
+      )));
+    }
+
+    // the catch-all class sorts before zigbee, so it takes the zigbee devices too
+    let classes = [
+      class(
+        "accel",
+        json!({ "selector": { "matchLabels": { "type": "accel" } } }),
+      ),
+      class("catch-all", json!({})),
+      class(
+        "zigbee",
+        json!({ "selector": { "matchLabels": { "type": "zigbee" } } }),
+      ),
+    ];
+
+    let mut distributions = Vec::new();
+    for concurrency in &[1, 3] {
+      let (dir, options, _kubelet) = test_plugin_dir(&format!("reconcile-{}", concurrency));
+
+      let mut registry = DeviceClassRegistry::default()
+        .reload(&classes, &options)
+        .await
+        .unwrap();
+      let mut device_type_registry = DeviceTypeRegistry::new(&device_types);
+      device_type_registry.reconcile(&devices);
+      registry
+        .reconcile(&mut device_type_registry.distributor(), false, *concurrency)
+        .await;
+
+      distributions.push(
+        registry
+          .advertised()
+          .into_iter()
+          .map(|resource| (resource.name, resource.device_count))
+          .collect::<Vec<_>>(),
+      );
+      registry.stop().await.unwrap();
+      let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    assert_eq!(
+      distributions[0],
+      vec![
+        ("udev/tty/accel".to_string(), 1),
+        ("udev/tty/catch-all".to_string(), 3),
+        ("udev/tty/zigbee".to_string(), 0),
+      ]
+    );
+    assert_eq!(distributions[0], distributions[1]);
   }
 }
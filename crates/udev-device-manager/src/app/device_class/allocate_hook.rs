@@ -0,0 +1,62 @@
+use super::allocations::LEASE_ANNOTATION;
+use crate::{app::command, config::AllocateHook};
+use kubelet_deviceplugin_proto::v1beta1;
+use serde::Serialize;
+
+/// An allocation as passed to allocate hooks, on their stdin
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocationRecord<'a> {
+  /// Resource name the devices were allocated from
+  pub resource: &'a str,
+
+  /// Lease the allocation was made under, as annotated on the container
+  pub lease: &'a str,
+
+  pub devices: Vec<AllocatedDevice<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocatedDevice<'a> {
+  pub id: &'a str,
+  pub devnode: &'a str,
+  pub container_path: &'a str,
+}
+
+impl<'a> AllocationRecord<'a> {
+  /// The allocation of a single container, from its request and the response to it
+  pub fn new(
+    resource: &'a str,
+    request: &'a v1beta1::ContainerAllocateRequest,
+    response: &'a v1beta1::ContainerAllocateResponse,
+  ) -> Self {
+    // the device specs are in request order
+    let devices = request
+      .devices_ids
+      .iter()
+      .zip(&response.devices)
+      .map(|(id, spec)| AllocatedDevice {
+        id,
+        devnode: &spec.host_path,
+        container_path: &spec.container_path,
+      })
+      .collect();
+
+    Self {
+      resource,
+      lease: response
+        .annotations
+        .get(LEASE_ANNOTATION)
+        .map(String::as_str)
+        .unwrap_or_default(),
+      devices,
+    }
+  }
+}
+
+/// Runs the hook with `record` on its stdin, failing unless it exits successfully in time
+pub async fn run(hook: &AllocateHook, record: &AllocationRecord<'_>) -> Result<(), String> {
+  let input = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+  command::exec("allocate hook", &hook.command(), &input, hook.timeout()).await
+}
@@ -0,0 +1,282 @@
+use crate::config::InternedString;
+use once_cell::sync::Lazy;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+  },
+  time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{event, Level};
+
+/// Annotation carrying the lease id of an allocation into the container
+pub const LEASE_ANNOTATION: &str = "udev-device-manager/lease";
+
+/// Annotation carrying the tenant of the device class an allocation was made from
+pub const TENANT_ANNOTATION: &str = "udev-device-manager/tenant";
+
+/// Startup time, so lease ids aren't reused across restarts of the manager
+static LEASE_EPOCH: Lazy<u64> = Lazy::new(|| {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or_default()
+});
+
+static NEXT_LEASE: AtomicU64 = AtomicU64::new(0);
+
+/// Mints a new lease id. It only holds devices once [`AllocationTracker::record`]ed, which
+/// happens when the allocation was granted.
+pub fn new_lease() -> String {
+  let seq = NEXT_LEASE.fetch_add(1, Ordering::Relaxed);
+  format!("{:x}-{}", *LEASE_EPOCH, seq)
+}
+
+#[derive(Debug, Clone)]
+struct Lease {
+  id: String,
+
+  /// Order the lease was recorded in, so the oldest ones go first when a device holds too many
+  seq: u64,
+
+  /// Syspath of the physical device the leased slot belongs to
+  device: InternedString,
+}
+
+/// Tracks the lease each device was last allocated under.
+///
+/// The kubelet doesn't tell plugins which pod an allocation is for, so every allocation gets
+/// a lease id which is passed to the container as an annotation. Something that does know the
+/// pod (e.g. an admission webhook or a sidecar) can then map the lease, and with it the
+/// devices, to the pod.
+///
+/// Leases are held per advertised slot, so a physical device never holds more leases than it
+/// has slots as long as the leases of slots which go away are released.
+/// [`enforce_limits`](Self::enforce_limits) checks this holds.
+#[derive(Debug, Default)]
+pub struct AllocationTracker {
+  leases: Mutex<BTreeMap<String, Lease>>,
+  next_seq: AtomicU64,
+}
+
+/// A physical device found holding more leases than its access allows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overleased {
+  pub device: InternedString,
+  pub leases: usize,
+  pub limit: usize,
+}
+
+impl AllocationTracker {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Records `lease` for devices allocated together, replacing whatever lease they had before.
+  /// Devices are given by id, along with the syspath of the physical device behind them.
+  pub fn record<'a>(
+    &self,
+    lease: &str,
+    devices: impl IntoIterator<Item = (&'a String, InternedString)>,
+  ) {
+    let mut leases = self.leases.lock().unwrap();
+    let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+    for (id, device) in devices {
+      let entry = Lease {
+        id: lease.to_owned(),
+        seq,
+        device,
+      };
+      leases.insert(id.clone(), entry);
+    }
+  }
+
+  /// Releases the leases of the devices `keep` returns false for, e.g. slots which stopped
+  /// being advertised or turned unhealthy. Returns the number of leases released.
+  pub fn release_unless(&self, keep: impl Fn(&str) -> bool) -> usize {
+    let mut leases = self.leases.lock().unwrap();
+    let before = leases.len();
+    leases.retain(|id, lease| {
+      let kept = keep(id);
+      if !kept {
+        event!(
+          target: "udev-device-manager",
+          Level::TRACE,
+          device_id = %id,
+          lease = %lease.id,
+          device = %lease.device,
+          "released lease"
+        );
+      }
+      kept
+    });
+    before - leases.len()
+  }
+
+  /// Checks that no physical device holds more leases than `limit` allows it (its access),
+  /// which can only happen when an allocation races a reconcile taking away slots. Devices
+  /// over their limit first lose the leases of slots `advertised` returns false for, then
+  /// their oldest ones. Returns the devices which were over their limit.
+  pub fn enforce_limits(
+    &self,
+    limit: impl Fn(InternedString) -> usize,
+    advertised: impl Fn(&str) -> bool,
+  ) -> Vec<Overleased> {
+    let mut leases = self.leases.lock().unwrap();
+    let mut by_device: BTreeMap<InternedString, Vec<(bool, u64, String)>> = BTreeMap::new();
+    for (id, lease) in leases.iter() {
+      by_device
+        .entry(lease.device)
+        .or_default()
+        .push((advertised(id), lease.seq, id.clone()));
+    }
+
+    let mut overleased = Vec::new();
+    for (device, mut held) in by_device {
+      let limit = limit(device);
+      if held.len() <= limit {
+        continue;
+      }
+
+      overleased.push(Overleased {
+        device,
+        leases: held.len(),
+        limit,
+      });
+      held.sort();
+      for (_, _, id) in &held[..held.len() - limit] {
+        leases.remove(id);
+      }
+    }
+
+    overleased
+  }
+
+  /// Ids of the devices currently held by a lease. The kubelet doesn't tell plugins when a
+  /// device is released again, so a device stays leased until it's allocated anew, stops
+  /// being advertised or turns unhealthy.
+  pub fn leased(&self) -> BTreeSet<String> {
+    let leases = self.leases.lock().unwrap();
+    leases.keys().cloned().collect()
+  }
+
+  /// Lease a device was last allocated under
+  #[cfg(test)]
+  pub fn lease_of(&self, device_id: &str) -> Option<String> {
+    let leases = self.leases.lock().unwrap();
+    leases.get(device_id).map(|lease| lease.id.clone())
+  }
+
+  /// Number of leased slots of a physical device
+  #[cfg(test)]
+  pub fn leased_slots(&self, device: &str) -> usize {
+    let leases = self.leases.lock().unwrap();
+    leases
+      .values()
+      .filter(|lease| lease.device == device)
+      .count()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lease<'a>(
+    tracker: &AllocationTracker,
+    devices: impl IntoIterator<Item = (&'a String, InternedString)>,
+  ) -> String {
+    let lease = new_lease();
+    tracker.record(&lease, devices);
+    lease
+  }
+
+  #[test]
+  fn leases_of_dropped_slots_are_released() {
+    let tracker = AllocationTracker::new();
+    let shared = InternedString::new("/sys/devices/shared");
+    let gone = InternedString::new("/sys/devices/gone");
+    let ids = ["a-0", "a-1", "a-2", "b-0"]
+      .iter()
+      .map(|id| id.to_string())
+      .collect::<Vec<_>>();
+
+    let kept = lease(&tracker, vec![(&ids[0], shared)]);
+    lease(&tracker, vec![(&ids[1], shared), (&ids[3], gone)]);
+    lease(&tracker, vec![(&ids[2], shared)]);
+    assert_eq!(tracker.leased_slots("/sys/devices/shared"), 3);
+
+    // the device's access was lowered to 2 slots, and the other device went away
+    let advertised = &ids[..2];
+    assert_eq!(
+      tracker.release_unless(|id| advertised.iter().any(|a| a == id)),
+      2
+    );
+    assert_eq!(tracker.leased_slots("/sys/devices/shared"), 2);
+    assert_eq!(tracker.leased_slots("/sys/devices/gone"), 0);
+    assert_eq!(tracker.lease_of("a-0"), Some(kept));
+    assert_eq!(tracker.lease_of("a-2"), None);
+    assert_eq!(
+      tracker.release_unless(|id| advertised.iter().any(|a| a == id)),
+      0
+    );
+  }
+
+  #[test]
+  fn overleased_devices_lose_unadvertised_then_oldest_leases() {
+    let tracker = AllocationTracker::new();
+    let shared = InternedString::new("/sys/devices/shared");
+    let other = InternedString::new("/sys/devices/other");
+    let ids = ["a-0", "a-1", "a-2", "b-0"]
+      .iter()
+      .map(|id| id.to_string())
+      .collect::<Vec<_>>();
+
+    let oldest = lease(&tracker, vec![(&ids[0], shared), (&ids[3], other)]);
+    lease(&tracker, vec![(&ids[2], shared)]);
+    let newest = lease(&tracker, vec![(&ids[1], shared)]);
+    let limit = |device: InternedString| if device == shared { 3 } else { 1 };
+    assert_eq!(tracker.enforce_limits(limit, |_| true), vec![]);
+
+    // a-2 was recorded by an allocation racing the reconcile which took it away
+    let limit = |device: InternedString| if device == shared { 2 } else { 1 };
+    let advertised = |id: &str| id != "a-2";
+    assert_eq!(
+      tracker.enforce_limits(limit, advertised),
+      vec![Overleased {
+        device: shared,
+        leases: 3,
+        limit: 2,
+      }]
+    );
+    assert_eq!(tracker.leased_slots("/sys/devices/shared"), 2);
+    assert_eq!(tracker.lease_of("a-2"), None);
+    assert_eq!(tracker.lease_of("b-0"), Some(oldest));
+
+    // with all slots advertised, the oldest lease goes
+    let limit = |_| 1;
+    assert_eq!(tracker.enforce_limits(limit, |_| true).len(), 1);
+    assert_eq!(tracker.lease_of("a-0"), None);
+    assert_eq!(tracker.lease_of("a-1"), Some(newest));
+    assert_eq!(tracker.enforce_limits(limit, |_| true), vec![]);
+  }
+
+  #[test]
+  fn reallocated_devices_stay_leased_once() {
+    let tracker = AllocationTracker::new();
+    let device = InternedString::new("/sys/devices/shared");
+    let ids = ["a-0", "a-1"]
+      .iter()
+      .map(|id| id.to_string())
+      .collect::<Vec<_>>();
+
+    lease(&tracker, vec![(&ids[0], device)]);
+    lease(&tracker, vec![(&ids[0], device)]);
+    assert_eq!(tracker.leased(), ids[..1].iter().cloned().collect());
+
+    lease(&tracker, vec![(&ids[1], device)]);
+    assert_eq!(tracker.leased(), ids.iter().cloned().collect());
+    assert_eq!(tracker.leased_slots("/sys/devices/shared"), 2);
+  }
+}
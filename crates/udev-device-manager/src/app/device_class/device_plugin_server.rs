@@ -1,29 +1,133 @@
-use super::super::{DeviceHandle, DeviceTypeDistributor, DeviceTypeHandle};
+use super::{
+  super::{command, DeviceHandle, DeviceTypeHandle},
+  allocate_hook::{self, AllocationRecord},
+  allocations::{new_lease, AllocationTracker, LEASE_ANNOTATION, TENANT_ANNOTATION},
+};
 use crate::{
-  config::{DeviceClass, InternedString},
-  utils::NotifySingle,
+  config::{DeviceClass, InternedString, UnexpectedCount},
+  utils::SingleFlight,
 };
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
-use futures::{FutureExt, Stream};
+use futures::{future::Future, Stream};
 use kubelet_deviceplugin_proto::{tonic::Status, v1beta1};
 use std::{
+  cmp::Reverse,
+  collections::{BTreeMap, BTreeSet, HashMap},
+  convert::TryFrom,
   pin::Pin,
-  sync::Arc,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
   task::{Context, Poll},
+  time::Duration,
 };
+use thiserror::Error;
+use tokio::{sync::watch, time};
+use tracing::{event, span, Instrument, Level, Span};
+
+/// A device class change which can't be applied to a running plugin, as it changes what the
+/// plugin is registered with the kubelet as
+#[derive(Debug, Error)]
+#[error("device class '{class}' changed its {field}, which requires restarting its plugin")]
+pub struct RestartRequired {
+  class: InternedString,
+  field: &'static str,
+}
+
+impl RestartRequired {
+  /// Checks whether `new` can replace `current` in place
+  pub fn check(current: &DeviceClass, new: &DeviceClass) -> Result<(), Self> {
+    let field = if current.name() != new.name() {
+      "name"
+    } else if current.subsystem() != new.subsystem() {
+      "subsystem"
+    } else if current.resource_name_override() != new.resource_name_override() {
+      "resourceName"
+    } else if current.group_by() != new.group_by() {
+      "groupBy"
+    } else if current.prefer_numa_alignment() != new.prefer_numa_alignment() {
+      "preferNumaAlignment"
+    } else if current.pre_start_reset() != new.pre_start_reset() {
+      "preStartReset"
+    } else if current.socket_mode() != new.socket_mode() {
+      "socketMode"
+    } else {
+      return Ok(());
+    };
+
+    Err(Self {
+      class: current.name(),
+      field,
+    })
+  }
+}
+
+/// A request for devices the plugin can't hand out right now
+#[derive(Debug, Error)]
+enum AllocateError {
+  #[error("{resource} is in maintenance mode")]
+  Maintenance { resource: String },
+
+  #[error("devices {ids:?} are not advertised for {resource}")]
+  NotAdvertised { resource: String, ids: Vec<String> },
+}
+
+impl From<AllocateError> for Status {
+  fn from(error: AllocateError) -> Self {
+    match error {
+      AllocateError::Maintenance { .. } => Status::unavailable(error.to_string()),
+      AllocateError::NotAdvertised { .. } => Status::not_found(error.to_string()),
+    }
+  }
+}
 
 #[derive(Debug, Default)]
 struct DevicesState {
   devices: Vec<DeviceHandle>,
   device_types: Vec<DeviceTypeHandle>,
+
+  /// The devices as advertised when reconciled, to spot changes to their health or topology
+  advertised: Vec<Arc<v1beta1::Device>>,
+}
+
+impl DevicesState {
+  /// The ListAndWatch response advertising these devices
+  fn list_and_watch(&self) -> v1beta1::ListAndWatchResponse {
+    let devices = self
+      .advertised
+      .iter()
+      .map(|device| (**device).clone())
+      .collect();
+
+    v1beta1::ListAndWatchResponse { devices }
+  }
+
+  /// An advertised device by id, along with the device type it's advertised for
+  fn find(&self, id: &str) -> Option<(&DeviceTypeHandle, &DeviceHandle)> {
+    let device = self.devices.iter().find(|d| d.id() == id)?;
+    let device_type = self
+      .device_types
+      .iter()
+      .find(|ty| ty.devices().into_iter().any(|d| d == *device))?;
+
+    Some((device_type, device))
+  }
 }
 
 #[derive(Debug)]
 struct State {
-  config: DeviceClass,
-  devices: ArcSwap<DevicesState>,
-  notifier: NotifySingle,
+  config: ArcSwap<DeviceClass>,
+  resource_name: String,
+  devices_tx: watch::Sender<Arc<DevicesState>>,
+  devices_rx: watch::Receiver<Arc<DevicesState>>,
+  maintenance: AtomicBool,
+  unexpected_count: AtomicBool,
+  reconcile_queue: SingleFlight<Vec<DeviceTypeHandle>>,
+  allocations: AllocationTracker,
+  reconciled_tx: watch::Sender<bool>,
+  reconciled_rx: watch::Receiver<bool>,
 }
 
 #[derive(Debug, Clone)]
@@ -32,45 +136,255 @@ pub struct DevicePlugin {
 }
 
 impl DevicePlugin {
-  pub fn new(config: DeviceClass) -> Self {
+  pub fn new(config: DeviceClass, resource_name: String) -> Self {
+    let (devices_tx, devices_rx) = watch::channel(Arc::default());
+    let (reconciled_tx, reconciled_rx) = watch::channel(false);
     Self {
       state: Arc::new(State {
-        config,
-        devices: ArcSwap::default(),
-        notifier: NotifySingle::new(),
+        config: ArcSwap::from_pointee(config),
+        resource_name,
+        devices_tx,
+        devices_rx,
+        maintenance: AtomicBool::new(false),
+        unexpected_count: AtomicBool::new(false),
+        reconcile_queue: SingleFlight::new(),
+        allocations: AllocationTracker::new(),
+        reconciled_tx,
+        reconciled_rx,
       }),
     }
   }
 
-  fn config(&self) -> &DeviceClass {
-    &self.state.config
+  fn config(&self) -> DeviceClass {
+    DeviceClass::clone(&self.state.config.load())
   }
 
-  pub fn subsystem(&self) -> InternedString {
-    self.config().subsystem()
+  /// Swaps the config of the running plugin, taking effect on the next reconcile. Fails
+  /// without changing anything if the change affects how the plugin is registered.
+  pub fn update_config(&self, config: DeviceClass) -> Result<(), RestartRequired> {
+    RestartRequired::check(&self.config(), &config)?;
+    self.state.config.store(Arc::new(config));
+    Ok(())
   }
 
-  pub fn name(&self) -> InternedString {
-    self.config().name()
+  /// Resource name this plugin is registered with the kubelet as
+  pub fn resource_name(&self) -> &str {
+    &self.state.resource_name
   }
 
-  pub fn reconcile(&self, distributor: &mut impl DeviceTypeDistributor) {
-    let config = self.config();
+  /// The currently advertised devices
+  fn devices(&self) -> Arc<DevicesState> {
+    self.state.devices_rx.borrow().clone()
+  }
+
+  /// Number of devices currently advertised
+  pub fn device_count(&self) -> usize {
+    self.devices().devices.len()
+  }
+
+  /// Number of advertised devices currently held by a lease. Leases are only released when
+  /// a device is allocated anew, stops being advertised or turns unhealthy, so this counts
+  /// devices in use as far as the plugin can tell.
+  pub fn leased_count(&self) -> usize {
+    let leased = self.state.allocations.leased();
+    self
+      .devices()
+      .devices
+      .iter()
+      .filter(|device| leased.contains(&*device.id()))
+      .count()
+  }
+
+  /// The device list a ListAndWatch stream would currently send
+  pub fn current_list_and_watch(&self) -> v1beta1::ListAndWatchResponse {
+    self.devices().list_and_watch()
+  }
+
+  /// Switches maintenance mode, taking effect on the next reconcile. While in maintenance no
+  /// devices are advertised and allocations are refused.
+  pub fn set_maintenance(&self, maintenance: bool) {
+    if self.state.maintenance.swap(maintenance, Ordering::SeqCst) != maintenance {
+      event!(
+        target: "udev-device-manager",
+        Level::INFO,
+        resource = self.resource_name(),
+        maintenance,
+        "maintenance mode {}",
+        if maintenance { "enabled" } else { "disabled" }
+      );
+    }
+  }
+
+  fn in_maintenance(&self) -> bool {
+    self.state.maintenance.load(Ordering::SeqCst)
+  }
+
+  /// Whether the last reconcile matched the number of devices the class expects
+  pub fn has_expected_count(&self) -> bool {
+    !self.state.unexpected_count.load(Ordering::SeqCst)
+  }
 
-    let device_types = distributor.get_device_types(|ty| config.match_with(ty).is_match());
-    let devices = device_types.iter().flat_map(|ty| ty.devices()).collect();
+  /// Updates the advertised devices. Reconciles never overlap, and those requested while one
+  /// is running collapse into a single follow-up run with the latest device types.
+  pub fn reconcile(&self, device_types: Vec<DeviceTypeHandle>) {
+    self
+      .state
+      .reconcile_queue
+      .run(device_types, |device_types| {
+        self.reconcile_now(device_types)
+      });
+  }
 
+  fn reconcile_now(&self, device_types: Vec<DeviceTypeHandle>) {
+    let devices = match self.in_maintenance() {
+      true => Vec::new(),
+      false => self.check_count(self.collect_devices(&device_types)),
+    };
+
+    let advertised = devices.iter().map(DeviceHandle::advertised).collect();
     let devices = DevicesState {
       devices,
       device_types,
+      advertised,
     };
 
+    // slots which went away or turned unhealthy can't be in use anymore
+    let released = self.state.allocations.release_unless(|id| {
+      devices
+        .devices
+        .iter()
+        .any(|device| device.healthy() && *device.id() == *id)
+    });
+    if released > 0 {
+      event!(
+        target: "udev-device-manager",
+        Level::DEBUG,
+        resource = self.resource_name(),
+        released,
+        "released the leases of devices no longer advertised as healthy"
+      );
+    }
+
     let new_state = Arc::new(devices);
-    let old_state = self.state.devices.load();
-    if old_state.devices != new_state.devices {
-      drop(old_state);
-      self.state.devices.store(new_state);
-      self.state.notifier.notify();
+    let old_state = self.devices();
+    let advertised_changed = old_state.advertised.len() != new_state.advertised.len()
+      || old_state
+        .advertised
+        .iter()
+        .zip(&new_state.advertised)
+        .any(|(old, new)| !Arc::ptr_eq(old, new));
+    if old_state.devices != new_state.devices || advertised_changed {
+      let _ = self.state.devices_tx.send(new_state);
+    }
+
+    // we hold a receiver ourselves, so this can't fail
+    let _ = self.state.reconciled_tx.send(true);
+  }
+
+  /// Flattens the devices of all device types into the advertised set, ordered by syspath.
+  /// Unless the class allows duplicates, a device matched by several device types is only
+  /// kept for the first of them.
+  fn collect_devices(&self, device_types: &[DeviceTypeHandle]) -> Vec<DeviceHandle> {
+    let allow_duplicates = self.config().allow_duplicates();
+    let mut owners = BTreeMap::new();
+    let mut devices = Vec::new();
+
+    for device_type in device_types {
+      let name = device_type.config().name();
+      for device in device_type.devices() {
+        let syspath = device.config().syspath();
+        if !allow_duplicates {
+          let owner = *owners.entry(syspath).or_insert(name);
+          if owner != name {
+            event!(
+              target: "udev-device-manager",
+              Level::DEBUG,
+              device.syspath = %syspath,
+              device_type.name = %name,
+              device_type.owner = %owner,
+              "device already advertised for another device type, skipping"
+            );
+            continue;
+          }
+        }
+
+        devices.push(device);
+      }
+    }
+
+    devices.sort_by_key(|device| (device.config().syspath(), device.id()));
+    devices
+  }
+
+  /// Checks the matched devices against the expected count of the class, logging when that
+  /// changes. Unexpected devices are only withheld if the class says so.
+  fn check_count(&self, devices: Vec<DeviceHandle>) -> Vec<DeviceHandle> {
+    let config = self.config();
+    let expected = match config.expected_count() {
+      Some(expected) => expected,
+      None => {
+        self.state.unexpected_count.store(false, Ordering::SeqCst);
+        return devices;
+      }
+    };
+
+    // shared devices are advertised several times, but still count once
+    let count = devices
+      .iter()
+      .map(|device| device.config().syspath())
+      .collect::<BTreeSet<_>>()
+      .len();
+    let unexpected = !expected.allows(count);
+    if self
+      .state
+      .unexpected_count
+      .swap(unexpected, Ordering::SeqCst)
+      != unexpected
+    {
+      match unexpected {
+        true => event!(
+          target: "udev-device-manager",
+          Level::ERROR,
+          resource = self.resource_name(),
+          device_count = count,
+          "matched {} devices, expected {}",
+          count,
+          expected
+        ),
+        false => event!(
+          target: "udev-device-manager",
+          Level::INFO,
+          resource = self.resource_name(),
+          device_count = count,
+          "matched the expected number of devices again"
+        ),
+      }
+    }
+
+    match (unexpected, config.on_unexpected_count()) {
+      (true, UnexpectedCount::AdvertiseNone) => Vec::new(),
+      _ => devices,
+    }
+  }
+
+  /// Waits until the plugin has been reconciled at least once, or `timeout` elapses.
+  async fn wait_for_reconcile(&self, timeout: Duration) {
+    let mut reconciled = self.state.reconciled_rx.clone();
+    let wait = async move {
+      while !*reconciled.borrow() {
+        if reconciled.changed().await.is_err() {
+          break;
+        }
+      }
+    };
+
+    if time::timeout(timeout, wait).await.is_err() {
+      event!(
+        target: "udev-device-manager",
+        Level::WARN,
+        resource = self.resource_name(),
+        "timed out waiting for the initial reconcile, sending current device list"
+      );
     }
   }
 }
@@ -80,6 +394,10 @@ impl v1beta1::DevicePlugin for DevicePlugin {
   type ListAndWatchStream = DevicePluginStream;
 
   async fn list_and_watch(&self) -> Result<Self::ListAndWatchStream, Status> {
+    if let Some(timeout) = self.config().initial_list_timeout() {
+      self.wait_for_reconcile(timeout).await;
+    }
+
     Ok(DevicePluginStream::new(self))
   }
 
@@ -87,32 +405,446 @@ impl v1beta1::DevicePlugin for DevicePlugin {
     &self,
     request: v1beta1::AllocateRequest,
   ) -> Result<v1beta1::AllocateResponse, Status> {
-    todo!()
+    let span = allocate_span(self.resource_name(), &request);
+    async move {
+      let response = self.allocate_devices(&request)?;
+      self.run_allocate_hook(&request, &response).await?;
+      self.record_leases(&request, &response);
+      self.check_leases();
+      self.audit(&request, &response);
+      Ok(response)
+    }
+    .instrument(span)
+    .await
+  }
+}
+
+impl DevicePlugin {
+  fn allocate_devices(
+    &self,
+    request: &v1beta1::AllocateRequest,
+  ) -> Result<v1beta1::AllocateResponse, AllocateError> {
+    if self.in_maintenance() {
+      return Err(AllocateError::Maintenance {
+        resource: self.resource_name().to_owned(),
+      });
+    }
+
+    // a reconcile may have dropped devices the kubelet still thought were advertised
+    let state = self.devices();
+    let mut missing = Vec::new();
+    for id in request
+      .container_requests
+      .iter()
+      .flat_map(|container| &container.devices_ids)
+    {
+      if state.find(id).is_none() && !missing.contains(&id.as_str()) {
+        missing.push(id.as_str());
+      }
+    }
+    if !missing.is_empty() {
+      return Err(self.not_advertised(&missing));
+    }
+
+    let container_responses = request
+      .container_requests
+      .iter()
+      .map(|container| self.allocate_container(&state, container))
+      .collect::<Result<_, _>>()?;
+
+    Ok(v1beta1::AllocateResponse {
+      container_responses,
+    })
+  }
+
+  /// Runs the class's allocate hook for each container, failing the allocation on the first
+  /// failing run
+  async fn run_allocate_hook(
+    &self,
+    request: &v1beta1::AllocateRequest,
+    response: &v1beta1::AllocateResponse,
+  ) -> Result<(), Status> {
+    let config = self.config();
+    let hook = match config.allocate_hook() {
+      Some(hook) => hook,
+      None => return Ok(()),
+    };
+
+    let containers = request
+      .container_requests
+      .iter()
+      .zip(&response.container_responses);
+    for (container, allocated) in containers {
+      let record = AllocationRecord::new(self.resource_name(), container, allocated);
+      if let Err(e) = allocate_hook::run(hook, &record).await {
+        event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          resource = self.resource_name(),
+          devices = ?container.devices_ids,
+          "refusing allocation: {}",
+          e
+        );
+        return Err(Status::failed_precondition(e));
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Records the lease of every granted allocation, so refused ones never hold devices.
+  /// Devices which stopped being advertised in the meantime are left out.
+  fn record_leases(
+    &self,
+    request: &v1beta1::AllocateRequest,
+    response: &v1beta1::AllocateResponse,
+  ) {
+    let state = self.devices();
+    let containers = request
+      .container_requests
+      .iter()
+      .zip(&response.container_responses);
+    for (container, allocated) in containers {
+      let lease = &allocated.annotations[LEASE_ANNOTATION];
+      let devices = container.devices_ids.iter().filter_map(|id| {
+        let (_, device) = state.find(id)?;
+        Some((id, device.config().syspath()))
+      });
+      self.state.allocations.record(lease, devices);
+    }
+  }
+
+  /// Checks that no physical device holds more leases than its access allows, releasing the
+  /// excess ones of devices which do. Recording leases races reconciles taking away slots, so
+  /// this is corrected rather than prevented; tests treat it as a bug though.
+  fn check_leases(&self) {
+    let state = self.devices();
+    let limits = state
+      .device_types
+      .iter()
+      .flat_map(|ty| {
+        let limit = usize::from(ty.config().access());
+        ty.devices()
+          .into_iter()
+          .map(move |device| (device.config().syspath(), limit))
+      })
+      .collect::<BTreeMap<_, _>>();
+
+    let overleased = self.state.allocations.enforce_limits(
+      |syspath| limits.get(&syspath).copied().unwrap_or_default(),
+      |id| state.find(id).is_some(),
+    );
+    for device in &overleased {
+      event!(
+        target: "udev-device-manager",
+        Level::ERROR,
+        resource = self.resource_name(),
+        device.syspath = %device.device,
+        leases = device.leases,
+        limit = device.limit,
+        "device was leased beyond its access limit, released its excess leases"
+      );
+    }
+
+    debug_assert!(overleased.is_empty(), "devices leased beyond their access");
+  }
+
+  /// Records every granted allocation, one event per container
+  fn audit(&self, request: &v1beta1::AllocateRequest, response: &v1beta1::AllocateResponse) {
+    let containers = request
+      .container_requests
+      .iter()
+      .zip(&response.container_responses);
+    for (container, allocated) in containers {
+      let record = AllocationRecord::new(self.resource_name(), container, allocated);
+      let devnodes = record.devices.iter().map(|d| d.devnode).collect::<Vec<_>>();
+      event!(
+        target: "udev-device-manager",
+        Level::INFO,
+        audit = true,
+        resource = record.resource,
+        devices = ?container.devices_ids,
+        devnodes = ?devnodes,
+        lease = record.lease,
+        "granted devices"
+      );
+    }
+  }
+
+  fn not_advertised(&self, ids: &[&str]) -> AllocateError {
+    AllocateError::NotAdvertised {
+      resource: self.resource_name().to_owned(),
+      ids: ids.iter().map(|id| (*id).to_owned()).collect(),
+    }
+  }
+
+  /// Exposes every requested device node in the container, failing if any of the devices
+  /// isn't (or no longer is) advertised.
+  fn allocate_container(
+    &self,
+    state: &DevicesState,
+    request: &v1beta1::ContainerAllocateRequest,
+  ) -> Result<v1beta1::ContainerAllocateResponse, AllocateError> {
+    let config = self.config();
+    let mut devices = Vec::with_capacity(request.devices_ids.len());
+    let mut device_types = Vec::new();
+
+    for id in &request.devices_ids {
+      let (device_type, device) = state
+        .find(id)
+        .ok_or_else(|| self.not_advertised(&[id.as_str()]))?;
+
+      devices.push(device.config());
+      let device_type = device_type.config();
+      if !device_types.contains(&device_type) {
+        device_types.push(device_type);
+      }
+    }
+
+    let specs = devices
+      .iter()
+      .enumerate()
+      .map(|(index, device)| v1beta1::DeviceSpec {
+        container_path: config.container_path(device, index),
+        host_path: device.devnode().to_string(),
+        permissions: config.permissions().to_string(),
+      })
+      .collect();
+
+    let mounts = config
+      .mounts()
+      .iter()
+      .map(|mount| v1beta1::Mount {
+        container_path: mount.container_path.to_string(),
+        host_path: mount.host_path.to_string(),
+        read_only: mount.read_only,
+      })
+      .collect();
+
+    let mut annotations = config.annotations_for(device_types.iter().copied());
+    annotations.insert(LEASE_ANNOTATION.to_owned(), new_lease());
+    annotations.insert(TENANT_ANNOTATION.to_owned(), config.tenant().to_string());
+
+    Ok(v1beta1::ContainerAllocateResponse {
+      envs: config.envs_for(device_types.iter().copied(), &devices),
+      mounts,
+      devices: specs,
+      annotations,
+    })
+  }
+}
+
+#[async_trait]
+impl v1beta1::PreferredAllocation for DevicePlugin {
+  async fn get_preferred_allocation(
+    &self,
+    request: v1beta1::PreferredAllocationRequest,
+  ) -> Result<v1beta1::PreferredAllocationResponse, Status> {
+    let state = self.devices();
+    let numa_nodes = state
+      .devices
+      .iter()
+      .map(|device| (device.id(), device.numa_node()))
+      .collect::<HashMap<_, _>>();
+
+    let numa_node = |id: &str| numa_nodes.get(id).copied().flatten();
+    let leased = self.state.allocations.leased();
+    let is_leased = |id: &str| leased.contains(id);
+    let container_responses = request
+      .container_requests
+      .iter()
+      .map(|request| {
+        let device_ids = numa_aligned(request, numa_node, is_leased);
+
+        // logged so the topology decisions made for the kubelet can be audited
+        let chosen_numa_nodes = device_ids
+          .iter()
+          .filter_map(|id| numa_node(id))
+          .collect::<BTreeSet<_>>();
+        event!(
+          target: "udev-device-manager",
+          Level::DEBUG,
+          resource = self.resource_name(),
+          available = ?request.available_device_ids,
+          must_include = ?request.must_include_device_ids,
+          allocation_size = request.allocation_size,
+          chosen = ?device_ids,
+          numa_nodes = ?chosen_numa_nodes,
+          "preferred allocation");
+
+        v1beta1::ContainerPreferredAllocationResponse { device_ids }
+      })
+      .collect();
+
+    Ok(v1beta1::PreferredAllocationResponse {
+      container_responses,
+    })
+  }
+}
+
+#[async_trait]
+impl v1beta1::ContainerPrestart for DevicePlugin {
+  async fn prestart_container(
+    &self,
+    request: v1beta1::PreStartContainerRequest,
+  ) -> Result<(), Status> {
+    let config = self.config();
+    let state = self.devices();
+
+    // slots of the same physical device share a reset
+    let mut reset = BTreeSet::new();
+    let mut commands = Vec::new();
+    for id in &request.devices_ids {
+      let (_, device) = state
+        .find(id)
+        .ok_or_else(|| self.not_advertised(&[id.as_str()]))?;
+      let device = device.config();
+      if reset.insert(device.syspath()) {
+        commands.extend(config.reset_command(&device, id));
+      }
+    }
+
+    // the kubelet gives up on the call after this long, so all resets share it
+    let deadline = time::Instant::now() + v1beta1::KUBELET_PRE_START_CONTAINER_RPC_TIMEOUT_IN_SECS;
+    for command in commands {
+      let remaining = deadline.saturating_duration_since(time::Instant::now());
+      if let Err(e) = command::exec("reset command", &command, &[], remaining).await {
+        event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          resource = self.resource_name(),
+          devices = ?request.devices_ids,
+          "failed to reset devices: {}",
+          e
+        );
+        return Err(Status::failed_precondition(e));
+      }
+    }
+
+    event!(
+      target: "udev-device-manager",
+      Level::DEBUG,
+      resource = self.resource_name(),
+      devices = ?request.devices_ids,
+      "reset devices before container start"
+    );
+    Ok(())
+  }
+}
+
+/// Picks the devices for a single container, keeping them on as few NUMA nodes as possible.
+/// Devices on the nodes of the must-include devices come first, then those on the nodes with
+/// the most available devices. Within a node, devices without a lease are preferred, so the
+/// slots of a shared device are handed out before any gets reused.
+fn numa_aligned(
+  request: &v1beta1::ContainerPreferredAllocationRequest,
+  numa_node: impl Fn(&str) -> Option<i64>,
+  leased: impl Fn(&str) -> bool,
+) -> Vec<String> {
+  let size = usize::try_from(request.allocation_size).unwrap_or(0);
+  let mut chosen = request.must_include_device_ids.clone();
+  let required_nodes = chosen
+    .iter()
+    .map(|id| numa_node(id))
+    .collect::<BTreeSet<_>>();
+
+  let mut candidates = request
+    .available_device_ids
+    .iter()
+    .filter(|id| !chosen.contains(*id))
+    .map(|id| (id, numa_node(id)))
+    .collect::<Vec<_>>();
+
+  let mut available = BTreeMap::new();
+  for (_, node) in &candidates {
+    *available.entry(*node).or_insert(0usize) += 1;
   }
+
+  candidates.sort_by_key(|(id, node)| {
+    (
+      !required_nodes.contains(node),
+      Reverse(available[node]),
+      *node,
+      leased(id),
+      *id,
+    )
+  });
+
+  let missing = size.saturating_sub(chosen.len());
+  chosen.extend(
+    candidates
+      .into_iter()
+      .take(missing)
+      .map(|(id, _)| id.clone()),
+  );
+  chosen
 }
 
+/// Span covering a single allocate call, with the resource and requested devices as fields.
+fn allocate_span(resource: &str, request: &v1beta1::AllocateRequest) -> Span {
+  let device_ids = request
+    .container_requests
+    .iter()
+    .flat_map(|c| c.devices_ids.iter())
+    .map(String::as_str)
+    .collect::<Vec<_>>();
+
+  span!(
+    target: "udev-device-manager",
+    Level::INFO,
+    "allocate",
+    resource = resource,
+    devices = ?device_ids,
+  )
+}
+
+type DevicesChanged =
+  Pin<Box<dyn Future<Output = Option<watch::Receiver<Arc<DevicesState>>>> + Send + Sync>>;
+
+/// Waits for the next reconcile changing the devices, handing the receiver back. Resolves to
+/// `None` once the plugin is gone.
+fn devices_changed(mut devices: watch::Receiver<Arc<DevicesState>>) -> DevicesChanged {
+  Box::pin(async move { devices.changed().await.ok().map(|()| devices) })
+}
+
+/// The ListAndWatch stream of a single kubelet connection. Each poll renders the latest
+/// devices, so changes made by reconciles in quick succession collapse into one response, and
+/// a response is only sent when it differs from the previous one sent on this stream, or as a
+/// heartbeat if the class configures one.
 pub struct DevicePluginStream {
-  plugin: DevicePlugin,
-  notifier: Option<NotifySingle>,
+  devices: Option<watch::Receiver<Arc<DevicesState>>>,
+  changed: Option<DevicesChanged>,
+  sent: Option<v1beta1::ListAndWatchResponse>,
+  heartbeat: Option<time::Interval>,
 }
 
 impl DevicePluginStream {
   fn new(plugin: &DevicePlugin) -> Self {
+    // the first tick of an interval is immediate, but the first response is sent anyway
+    let heartbeat = plugin
+      .config()
+      .heartbeat_interval()
+      .map(|period| time::interval_at(time::Instant::now() + period, period));
+
     Self {
-      plugin: plugin.clone(),
-      notifier: None,
+      devices: Some(plugin.state.devices_rx.clone()),
+      changed: None,
+      sent: None,
+      heartbeat,
     }
   }
 
-  fn get_response(&self) -> Result<v1beta1::ListAndWatchResponse, Status> {
-    let device_state = self.plugin.state.devices.load().clone();
-    let devices = device_state
-      .devices
-      .iter()
-      .map(v1beta1::Device::from)
-      .collect();
+  /// Sends the last response again once the heartbeat interval passes
+  fn poll_heartbeat(&mut self, cx: &mut Context<'_>) -> Poll<Option<<Self as Stream>::Item>> {
+    let heartbeat = match &mut self.heartbeat {
+      None => return Poll::Pending,
+      Some(heartbeat) => heartbeat,
+    };
 
-    Ok(v1beta1::ListAndWatchResponse { devices })
+    match (heartbeat.poll_tick(cx), &self.sent) {
+      (Poll::Ready(_), Some(sent)) => Poll::Ready(Some(Ok(sent.clone()))),
+      _ => Poll::Pending,
+    }
   }
 }
 
@@ -122,20 +854,1287 @@ impl Stream for DevicePluginStream {
   fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
     let this = self.get_mut();
     loop {
-      match &mut this.notifier {
-        None => {
-          this.notifier = Some(this.plugin.state.notifier.clone());
-          return Poll::Ready(Some(this.get_response()));
+      if let Some(devices) = this.devices.take() {
+        let response = devices.borrow().list_and_watch();
+        this.changed = Some(devices_changed(devices));
+        if this.sent.as_ref() != Some(&response) {
+          this.sent = Some(response.clone());
+          return Poll::Ready(Some(Ok(response)));
         }
+      }
 
-        Some(n) => match n.poll_unpin(cx) {
-          Poll::Pending => return Poll::Pending,
-          Poll::Ready(()) => {
-            this.notifier = None;
-            continue;
+      match &mut this.changed {
+        None => return Poll::Ready(None),
+        Some(changed) => match changed.as_mut().poll(cx) {
+          Poll::Pending => return this.poll_heartbeat(cx),
+          Poll::Ready(devices) => {
+            this.changed = None;
+            this.devices = devices;
+            if this.devices.is_none() {
+              return Poll::Ready(None);
+            }
+          }
+        },
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    app::{
+      device_registry::DeviceRegistry,
+      device_type::{DeviceTypeDistributor, DeviceTypeRegistry},
+    },
+    config::{Config, DeviceIdScheme, DeviceType},
+    test_log::Buffer,
+    udev::{UdevDevice, UdevEvent},
+  };
+  use futures::StreamExt;
+  use serde_json::json;
+  use std::sync::atomic::AtomicUsize;
+  use tracing_subscriber::{layer, prelude::*, Layer};
+
+  fn plugin(options: serde_json::Value) -> DevicePlugin {
+    let mut config = json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "target": "conbee2",
+      "selector": {},
+    });
+    if let (Some(config), Some(options)) = (config.as_object_mut(), options.as_object()) {
+      config.extend(options.clone());
+    }
+
+    let config: DeviceClass = serde_json::from_value(config).unwrap();
+
+    let resource_name = config.resource_name(None);
+    DevicePlugin::new(config, resource_name)
+  }
+
+  fn device_types() -> Vec<DeviceTypeHandle> {
+    device_types_named(&["conbee2"])
+  }
+
+  fn device_types_named(names: &[&str]) -> Vec<DeviceTypeHandle> {
+    let device_types = names
+      .iter()
+      .map(|name| {
+        serde_json::from_value::<DeviceType>(json!({
+          "name": name,
+          "subsystem": "tty",
+          "labels": {},
+          "selector": {},
+        }))
+        .unwrap()
+      })
+      .collect::<Vec<_>>();
+
+    let mut devices = DeviceRegistry::new();
+    devices.update(UdevEvent::Add(UdevDevice::from_parts(
+      "tty",
+      "/sys/devices/a",
+      "/dev/ttyACM0",
+      vec![("idVendor", "1cf1")],
+    )));
+
+    let mut registry = DeviceTypeRegistry::new(&device_types);
+    registry.reconcile(&devices);
+    registry.distributor().get_device_types(|_| true)
+  }
+
+  fn numa_device_types(devices: &[(&str, &str)]) -> Vec<DeviceTypeHandle> {
+    let device_type = serde_json::from_value::<DeviceType>(json!({
+      "name": "accel",
+      "subsystem": "tty",
+      "labels": {},
+      "selector": {},
+    }))
+    .unwrap();
+
+    let mut registry = DeviceRegistry::new();
+    for (syspath, numa_node) in devices {
+      registry.update(UdevEvent::Add(UdevDevice::from_parts(
+        "tty",
+        syspath,
+        "/dev/ttyACM0",
+        vec![("numa_node", *numa_node)],
+      )));
+    }
+
+    let mut device_types = DeviceTypeRegistry::new(&[device_type]);
+    device_types.reconcile(&registry);
+    device_types.distributor().get_device_types(|_| true)
+  }
+
+  fn devices_at(devnodes: &[&str]) -> Vec<DeviceTypeHandle> {
+    let device_type = serde_json::from_value::<DeviceType>(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "labels": {},
+      "selector": {},
+    }))
+    .unwrap();
+
+    let mut registry = DeviceRegistry::new();
+    for devnode in devnodes {
+      let syspath = format!("/sys/devices{}", devnode);
+      registry.update(UdevEvent::Add(UdevDevice::from_parts(
+        "tty",
+        &syspath,
+        devnode,
+        vec![],
+      )));
+    }
+
+    let mut device_types = DeviceTypeRegistry::new(&[device_type]);
+    device_types.reconcile(&registry);
+    device_types.distributor().get_device_types(|_| true)
+  }
+
+  fn advertised_ids(plugin: &DevicePlugin) -> BTreeMap<String, String> {
+    plugin
+      .devices()
+      .devices
+      .iter()
+      .map(|d| (d.config().devnode().to_string(), d.id().to_string()))
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn allocate_exposes_device_nodes() {
+    let plugin = plugin(json!({}));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    let ids = advertised_ids(&plugin);
+
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![v1beta1::ContainerAllocateRequest {
+        devices_ids: vec![ids["/dev/ttyACM1"].clone(), ids["/dev/ttyACM0"].clone()],
+      }],
+    };
+    let response = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap();
+
+    assert_eq!(response.container_responses.len(), 1);
+    let specs = response.container_responses[0]
+      .devices
+      .iter()
+      .map(|d| (&*d.host_path, &*d.container_path, &*d.permissions))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      specs,
+      vec![
+        ("/dev/ttyACM1", "/dev/ttyACM1", "rwm"),
+        ("/dev/ttyACM0", "/dev/ttyACM0", "rwm"),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn allocate_uses_subsystem_default_permissions() {
+    let config: Config = serde_json::from_value(json!({
+      "devices": [],
+      "permissions": { "subsystems": { "iio": "r" } },
+      "deviceClasses": [
+        { "name": "sensor", "subsystem": "iio", "target": "sensor", "selector": {} },
+        { "name": "conbee2", "subsystem": "tty", "target": "conbee2", "selector": {} },
+        {
+          "name": "writable-sensor",
+          "subsystem": "iio",
+          "target": "sensor",
+          "selector": {},
+          "permissions": "rw",
+        },
+      ],
+    }))
+    .unwrap();
+
+    let mut permissions = Vec::new();
+    for class in config.device_classes() {
+      let resource_name = class.resource_name(None);
+      let plugin = DevicePlugin::new(class.clone(), resource_name);
+      plugin.reconcile(devices_at(&["/dev/device0"]));
+
+      let request = v1beta1::AllocateRequest {
+        container_requests: vec![v1beta1::ContainerAllocateRequest {
+          devices_ids: advertised_ids(&plugin).into_values().collect(),
+        }],
+      };
+      let response = v1beta1::DevicePlugin::allocate(&plugin, request)
+        .await
+        .unwrap();
+      permissions.push(
+        response.container_responses[0].devices[0]
+          .permissions
+          .clone(),
+      );
+    }
+
+    assert_eq!(permissions, vec!["r", "rwm", "rw"]);
+  }
+
+  #[tokio::test]
+  async fn allocate_annotates_a_recorded_lease() {
+    let plugin = plugin(json!({}));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    let ids = advertised_ids(&plugin);
+    let (acm0, acm1) = (&ids["/dev/ttyACM0"], &ids["/dev/ttyACM1"]);
+
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![
+        v1beta1::ContainerAllocateRequest {
+          devices_ids: vec![acm0.clone()],
+        },
+        v1beta1::ContainerAllocateRequest {
+          devices_ids: vec![acm1.clone()],
+        },
+      ],
+    };
+    let response = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap();
+
+    let leases = response
+      .container_responses
+      .iter()
+      .map(|c| c.annotations[LEASE_ANNOTATION].clone())
+      .collect::<Vec<_>>();
+    assert_ne!(leases[0], leases[1]);
+    assert_eq!(
+      plugin.state.allocations.lease_of(acm0),
+      Some(leases[0].clone())
+    );
+    assert_eq!(
+      plugin.state.allocations.lease_of(acm1),
+      Some(leases[1].clone())
+    );
+
+    // allocating a device again moves it to the new lease
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![v1beta1::ContainerAllocateRequest {
+        devices_ids: vec![acm0.clone()],
+      }],
+    };
+    let response = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap();
+    let lease = &response.container_responses[0].annotations[LEASE_ANNOTATION];
+    assert_ne!(lease, &leases[0]);
+    assert_eq!(
+      plugin.state.allocations.lease_of(acm0).as_ref(),
+      Some(lease)
+    );
+    assert_eq!(
+      plugin.state.allocations.lease_of(acm1),
+      Some(leases[1].clone())
+    );
+  }
+
+  #[tokio::test]
+  async fn allocate_annotates_the_class_tenant() {
+    let allocate_tenant = |plugin: DevicePlugin| async move {
+      plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+      let ids = advertised_ids(&plugin);
+      let request = v1beta1::AllocateRequest {
+        container_requests: vec![v1beta1::ContainerAllocateRequest {
+          devices_ids: vec![ids["/dev/ttyACM0"].clone()],
+        }],
+      };
+      let response = v1beta1::DevicePlugin::allocate(&plugin, request)
+        .await
+        .unwrap();
+      response.container_responses[0].annotations[TENANT_ANNOTATION].clone()
+    };
+
+    assert_eq!(allocate_tenant(plugin(json!({}))).await, "conbee2");
+    assert_eq!(
+      allocate_tenant(plugin(json!({ "tenant": "team-zigbee" }))).await,
+      "team-zigbee"
+    );
+  }
+
+  #[tokio::test]
+  async fn allocate_hook_gets_the_allocation_as_json() {
+    let record = std::env::temp_dir().join(format!(
+      "udev-device-manager-allocate-hook-{}.json",
+      std::process::id()
+    ));
+    let plugin = plugin(json!({
+      "allocateHook": { "command": ["sh", "-c", format!("cat > {}", record.display())] },
+    }));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let id = advertised_ids(&plugin)["/dev/ttyACM0"].clone();
+
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![v1beta1::ContainerAllocateRequest {
+        devices_ids: vec![id.clone()],
+      }],
+    };
+    let response = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap();
+    let lease = &response.container_responses[0].annotations[LEASE_ANNOTATION];
+
+    let input: serde_json::Value =
+      serde_json::from_slice(&std::fs::read(&record).unwrap()).unwrap();
+    std::fs::remove_file(&record).unwrap();
+    assert_eq!(
+      input,
+      json!({
+        "resource": "udev/tty/conbee2",
+        "lease": lease,
+        "devices": [{ "id": id, "devnode": "/dev/ttyACM0", "containerPath": "/dev/ttyACM0" }],
+      })
+    );
+  }
+
+  #[tokio::test]
+  async fn failing_allocate_hook_refuses_the_allocation() {
+    let plugin = plugin(json!({
+      "allocateHook": { "command": ["sh", "-c", "exit 3"] },
+    }));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let ids = advertised_ids(&plugin);
+
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![v1beta1::ContainerAllocateRequest {
+        devices_ids: vec![ids["/dev/ttyACM0"].clone()],
+      }],
+    };
+    let status = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap_err();
+    assert_eq!(
+      status.code(),
+      kubelet_deviceplugin_proto::tonic::Code::FailedPrecondition
+    );
+    assert!(status.message().contains("exit"), "{}", status.message());
+    assert!(plugin.state.allocations.leased().is_empty());
+  }
+
+  #[tokio::test]
+  async fn prestart_resets_each_requested_device() {
+    let log = std::env::temp_dir().join(format!(
+      "udev-device-manager-reset-{}.log",
+      std::process::id()
+    ));
+    let plugin = plugin(json!({
+      "preStartReset": true,
+      "resetCommand": ["sh", "-c", format!("echo ${{devnode}} >> {}", log.display())],
+    }));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    let ids = advertised_ids(&plugin);
+
+    let request = v1beta1::PreStartContainerRequest {
+      devices_ids: vec![ids["/dev/ttyACM1"].clone(), ids["/dev/ttyACM0"].clone()],
+    };
+    v1beta1::ContainerPrestart::prestart_container(&plugin, request)
+      .await
+      .unwrap();
+
+    let reset = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+    assert_eq!(reset, "/dev/ttyACM1\n/dev/ttyACM0\n");
+  }
+
+  #[tokio::test]
+  async fn failing_reset_fails_the_prestart() {
+    let plugin = plugin(json!({
+      "preStartReset": true,
+      "resetCommand": ["false"],
+    }));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let ids = advertised_ids(&plugin);
+
+    let request = v1beta1::PreStartContainerRequest {
+      devices_ids: vec![ids["/dev/ttyACM0"].clone()],
+    };
+    let status = v1beta1::ContainerPrestart::prestart_container(&plugin, request)
+      .await
+      .unwrap_err();
+    assert_eq!(
+      status.code(),
+      kubelet_deviceplugin_proto::tonic::Code::FailedPrecondition
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+  async fn concurrent_allocates_never_exceed_device_slots() {
+    let device_type = serde_json::from_value::<DeviceType>(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "access": 2,
+      "labels": {},
+      "selector": {},
+    }))
+    .unwrap();
+    let device = UdevDevice::from_parts("tty", "/sys/devices/shared", "/dev/ttyACM0", vec![]);
+    let mut registry = DeviceRegistry::new();
+    registry.update(UdevEvent::Add(device.clone()));
+    let mut device_types = DeviceTypeRegistry::new(&[device_type]);
+    device_types.reconcile(&registry);
+
+    let plugin = plugin(json!({}));
+    plugin.reconcile(device_types.distributor().get_device_types(|_| true));
+    let mut ids = plugin
+      .devices()
+      .devices
+      .iter()
+      .map(|d| d.id().to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(ids.len(), 2);
+
+    // the kubelet may still ask for a slot the device had before its access was lowered
+    ids.push(DeviceIdScheme::Indexed.device_id(&device.id(), 2, 3));
+
+    let tasks = (0..16).map(|task| {
+      let plugin = plugin.clone();
+      let ids = ids.clone();
+      tokio::spawn(async move {
+        let mut granted = 0;
+        for i in 0..48 {
+          let devices_ids = vec![ids[(task + i) % ids.len()].clone()];
+          let request = v1beta1::AllocateRequest {
+            container_requests: vec![v1beta1::ContainerAllocateRequest { devices_ids }],
+          };
+          match v1beta1::DevicePlugin::allocate(&plugin, request).await {
+            Ok(_) => granted += 1,
+            Err(status) => assert_eq!(
+              status.code(),
+              kubelet_deviceplugin_proto::tonic::Code::NotFound
+            ),
           }
+        }
+        granted
+      })
+    });
+    let mut granted = 0;
+    for task in futures::future::join_all(tasks).await {
+      granted += task.unwrap();
+    }
+
+    // every third request asks for the slot that isn't advertised
+    assert_eq!(granted, 16 * 48 * 2 / 3);
+    let allocations = &plugin.state.allocations;
+    assert_eq!(allocations.leased_slots("/sys/devices/shared"), 2);
+    assert_eq!(allocations.lease_of(&ids[2]), None);
+  }
+
+  #[tokio::test]
+  async fn shared_slots_are_tracked_across_allocations() {
+    let device_type = serde_json::from_value::<DeviceType>(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "access": 2,
+      "labels": {},
+      "selector": {},
+    }))
+    .unwrap();
+    let mut registry = DeviceRegistry::new();
+    registry.update(UdevEvent::Add(UdevDevice::from_parts(
+      "tty",
+      "/sys/devices/shared",
+      "/dev/ttyACM0",
+      vec![],
+    )));
+    let mut device_types = DeviceTypeRegistry::new(&[device_type]);
+    device_types.reconcile(&registry);
+
+    let plugin = plugin(json!({}));
+    plugin.reconcile(device_types.distributor().get_device_types(|_| true));
+    let ids = plugin
+      .devices()
+      .devices
+      .iter()
+      .map(|d| d.id().to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(plugin.leased_count(), 0);
+
+    let allocate = |id: &String| v1beta1::AllocateRequest {
+      container_requests: vec![v1beta1::ContainerAllocateRequest {
+        devices_ids: vec![id.clone()],
+      }],
+    };
+    v1beta1::DevicePlugin::allocate(&plugin, allocate(&ids[0]))
+      .await
+      .unwrap();
+    assert_eq!(plugin.leased_count(), 1);
+
+    // the free slot is preferred over the leased one
+    let request = v1beta1::PreferredAllocationRequest {
+      container_requests: vec![v1beta1::ContainerPreferredAllocationRequest {
+        available_device_ids: ids.clone(),
+        must_include_device_ids: vec![],
+        allocation_size: 1,
+      }],
+    };
+    let response = v1beta1::PreferredAllocation::get_preferred_allocation(&plugin, request)
+      .await
+      .unwrap();
+    assert_eq!(
+      response.container_responses[0].device_ids,
+      vec![ids[1].clone()]
+    );
+
+    v1beta1::DevicePlugin::allocate(&plugin, allocate(&ids[1]))
+      .await
+      .unwrap();
+    assert_eq!(plugin.leased_count(), 2);
+
+    // reallocating a slot replaces its lease rather than adding one
+    v1beta1::DevicePlugin::allocate(&plugin, allocate(&ids[0]))
+      .await
+      .unwrap();
+    assert_eq!(plugin.leased_count(), 2);
+    assert_eq!(
+      plugin.state.allocations.leased_slots("/sys/devices/shared"),
+      2
+    );
+  }
+
+  #[tokio::test]
+  async fn allocate_injects_mounts_and_envs() {
+    let plugin = plugin(json!({
+      "envs": { "SERIAL_PORT": "${DEVNODE}" },
+      "mounts": [{ "containerPath": "/opt/zigbee", "hostPath": "/usr/share/zigbee", "readOnly": true }],
+    }));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let ids = advertised_ids(&plugin);
+
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![v1beta1::ContainerAllocateRequest {
+        devices_ids: vec![ids["/dev/ttyACM0"].clone()],
+      }],
+    };
+    let response = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap();
+
+    let container = &response.container_responses[0];
+    assert_eq!(container.envs["SERIAL_PORT"], "/dev/ttyACM0");
+    assert_eq!(container.mounts.len(), 1);
+    assert_eq!(container.mounts[0].container_path, "/opt/zigbee");
+    assert_eq!(container.mounts[0].host_path, "/usr/share/zigbee");
+    assert!(container.mounts[0].read_only);
+  }
+
+  #[tokio::test]
+  async fn allocate_unknown_device_is_not_found() {
+    let plugin = plugin(json!({}));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![v1beta1::ContainerAllocateRequest {
+        devices_ids: vec!["removed".into()],
+      }],
+    };
+    let status = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap_err();
+    assert_eq!(
+      status.code(),
+      kubelet_deviceplugin_proto::tonic::Code::NotFound
+    );
+  }
+
+  #[tokio::test]
+  async fn allocate_just_removed_device_is_not_found() {
+    let plugin = plugin(json!({}));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    let ids = advertised_ids(&plugin);
+    let (kept, removed) = (&ids["/dev/ttyACM0"], &ids["/dev/ttyACM1"]);
+
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![
+        v1beta1::ContainerAllocateRequest {
+          devices_ids: vec![kept.clone()],
+        },
+        v1beta1::ContainerAllocateRequest {
+          devices_ids: vec![removed.clone(), kept.clone()],
         },
+      ],
+    };
+    let status = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap_err();
+
+    assert_eq!(
+      status.code(),
+      kubelet_deviceplugin_proto::tonic::Code::NotFound
+    );
+    assert!(
+      status.message().contains(removed.as_str()),
+      "{}",
+      status.message()
+    );
+    assert!(
+      !status.message().contains(kept.as_str()),
+      "{}",
+      status.message()
+    );
+  }
+
+  #[tokio::test]
+  async fn maintenance_advertises_nothing_until_disabled() {
+    let plugin = plugin(json!({}));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    let ids = advertised_ids(&plugin);
+    assert_eq!(plugin.device_count(), 2);
+
+    plugin.set_maintenance(true);
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    assert_eq!(plugin.device_count(), 0);
+
+    let request = v1beta1::AllocateRequest {
+      container_requests: vec![v1beta1::ContainerAllocateRequest {
+        devices_ids: vec![ids["/dev/ttyACM0"].clone()],
+      }],
+    };
+    let status = v1beta1::DevicePlugin::allocate(&plugin, request)
+      .await
+      .unwrap_err();
+    assert_eq!(
+      status.code(),
+      kubelet_deviceplugin_proto::tonic::Code::Unavailable
+    );
+
+    plugin.set_maintenance(false);
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    assert_eq!(advertised_ids(&plugin), ids);
+  }
+
+  #[test]
+  fn expected_count_exactly_one() {
+    let plugin = plugin(json!({ "expectedCount": { "exactly": 1 } }));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    assert!(plugin.has_expected_count());
+    assert_eq!(plugin.device_count(), 1);
+
+    // too many devices still get advertised, but the plugin isn't ready
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    assert!(!plugin.has_expected_count());
+    assert_eq!(plugin.device_count(), 2);
+
+    plugin.reconcile(devices_at(&["/dev/ttyACM1"]));
+    assert!(plugin.has_expected_count());
+  }
+
+  #[test]
+  fn unexpected_count_can_advertise_nothing() {
+    let plugin = plugin(json!({
+      "expectedCount": { "exactly": 1 },
+      "onUnexpectedCount": "advertiseNone",
+    }));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    assert!(!plugin.has_expected_count());
+    assert_eq!(plugin.device_count(), 0);
+
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    assert!(plugin.has_expected_count());
+    assert_eq!(plugin.device_count(), 1);
+  }
+
+  #[tokio::test]
+  async fn initial_list_waits_for_reconcile() {
+    let plugin = plugin(json!({ "initialListTimeout": 5 }));
+    let list = {
+      let plugin = plugin.clone();
+      tokio::spawn(async move {
+        let mut stream = v1beta1::DevicePlugin::list_and_watch(&plugin)
+          .await
+          .unwrap();
+        stream.next().await.unwrap().unwrap()
+      })
+    };
+
+    time::sleep(Duration::from_millis(50)).await;
+    plugin.reconcile(device_types());
+
+    let first = list.await.unwrap();
+    assert_eq!(first.devices.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn list_and_watch_coalesces_quick_reconciles() {
+    let plugin = plugin(json!({}));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let mut stream = v1beta1::DevicePlugin::list_and_watch(&plugin)
+      .await
+      .unwrap();
+    let first = stream.next().await.unwrap().unwrap();
+    assert_eq!(first.devices.len(), 1);
+
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    plugin.reconcile(devices_at(&[
+      "/dev/ttyACM0",
+      "/dev/ttyACM1",
+      "/dev/ttyACM2",
+    ]));
+    let next = stream.next().await.unwrap().unwrap();
+    assert_eq!(next.devices.len(), 3);
+
+    let pending = time::timeout(Duration::from_millis(50), stream.next()).await;
+    assert!(pending.is_err(), "the intermediate device list was sent");
+  }
+
+  /// Requests reconciles of the plugin from another thread when a reconcile logs skipping a
+  /// duplicate device, so while that reconcile is still running, and counts those runs
+  struct ReconcileDuringRun {
+    plugin: DevicePlugin,
+    device_types: Vec<DeviceTypeHandle>,
+    requests: usize,
+    runs: Arc<AtomicUsize>,
+  }
+
+  impl<S: tracing::Subscriber> Layer<S> for ReconcileDuringRun {
+    fn on_event(&self, event: &tracing::Event<'_>, _: layer::Context<'_, S>) {
+      if event
+        .metadata()
+        .fields()
+        .field("device_type.owner")
+        .is_none()
+      {
+        return;
+      }
+
+      if self.runs.fetch_add(1, Ordering::SeqCst) == 0 {
+        let plugin = self.plugin.clone();
+        let device_types = self.device_types.clone();
+        let requests = self.requests;
+        std::thread::spawn(move || {
+          for _ in 0..requests {
+            plugin.reconcile(device_types.clone());
+          }
+        })
+        .join()
+        .unwrap();
+      }
+    }
+  }
+
+  #[test]
+  fn reconciles_requested_during_a_run_coalesce() {
+    let plugin = plugin(json!({}));
+    // both device types match the same device, so every run skips it for the second one
+    let device_types = device_types_named(&["conbee2", "zigbee"]);
+    let runs = Arc::new(AtomicUsize::new(0));
+    let subscriber = tracing_subscriber::registry().with(ReconcileDuringRun {
+      plugin: plugin.clone(),
+      device_types: device_types.clone(),
+      requests: 10,
+      runs: runs.clone(),
+    });
+
+    tracing::subscriber::with_default(subscriber, || plugin.reconcile(device_types));
+
+    // the requests made during the first run were handled by a single run after it
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+    assert_eq!(plugin.device_count(), 1);
+  }
+
+  #[tokio::test]
+  async fn list_and_watch_repeats_the_device_list_as_heartbeat() {
+    let plugin = plugin(json!({ "heartbeatInterval": 1 }));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let mut stream = v1beta1::DevicePlugin::list_and_watch(&plugin)
+      .await
+      .unwrap();
+    let first = stream.next().await.unwrap().unwrap();
+
+    let pending = time::timeout(Duration::from_millis(500), stream.next()).await;
+    assert!(pending.is_err(), "the heartbeat came early");
+
+    let heartbeat = time::timeout(Duration::from_secs(2), stream.next())
+      .await
+      .expect("no heartbeat was sent")
+      .unwrap()
+      .unwrap();
+    assert_eq!(heartbeat, first);
+  }
+
+  #[tokio::test]
+  async fn list_and_watch_skips_unchanged_device_lists() {
+    let plugin = plugin(json!({}));
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let mut stream = v1beta1::DevicePlugin::list_and_watch(&plugin)
+      .await
+      .unwrap();
+    let first = stream.next().await.unwrap().unwrap();
+
+    // new device handles, but they render to the same device list
+    plugin.reconcile(devices_at(&["/dev/ttyACM0"]));
+    let pending = time::timeout(Duration::from_millis(50), stream.next()).await;
+    assert!(pending.is_err(), "an unchanged device list was sent");
+
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    let next = time::timeout(Duration::from_secs(1), stream.next())
+      .await
+      .unwrap()
+      .unwrap()
+      .unwrap();
+    assert_eq!(next.devices.len(), 2);
+    assert_eq!(next.devices[0], first.devices[0]);
+  }
+
+  #[test]
+  fn devices_matched_by_several_types_are_advertised_once() {
+    let deduped = plugin(json!({}));
+    deduped.reconcile(device_types_named(&["conbee2", "serial"]));
+    assert_eq!(deduped.device_count(), 1);
+
+    let duplicated = plugin(json!({ "allowDuplicates": true }));
+    duplicated.reconcile(device_types_named(&["conbee2", "serial"]));
+    assert_eq!(duplicated.device_count(), 2);
+  }
+
+  #[test]
+  fn manual_devices_are_advertised_without_udev() {
+    let config: Config = toml::from_str(
+      r#"
+        [[devices]]
+        name = "virtual-accel"
+        subsystem = "accel"
+        labels = { type = "accel" }
+        selector = { matchAttributes = { model = "sim" } }
+
+        [[deviceClasses]]
+        name = "accel"
+        subsystem = "accel"
+        target = "accel"
+        selector = { matchLabels = { type = "accel" } }
+
+        [[manualDevices]]
+        id = "accel0"
+        subsystem = "accel"
+        devnode = "/dev/null"
+        attributes = { model = "sim" }
+      "#,
+    )
+    .unwrap();
+
+    let mut devices = DeviceRegistry::new();
+    devices.add_manual_devices(config.manual_devices());
+    let mut device_types = DeviceTypeRegistry::new(config.device_types());
+    device_types.reconcile(&devices);
+
+    let class = config.device_classes()[0].clone();
+    let resource_name = class.resource_name(None);
+    let plugin = DevicePlugin::new(class, resource_name);
+    plugin.reconcile(device_types.distributor().get_device_types(|_| true));
+
+    assert_eq!(plugin.device_count(), 1);
+    assert_eq!(
+      plugin.devices().devices[0].config().syspath(),
+      "/sys/devices/manual/accel0"
+    );
+  }
+
+  #[tokio::test]
+  async fn preferred_allocation_keeps_numa_alignment() {
+    let plugin = plugin(json!({ "preferNumaAlignment": true }));
+    plugin.reconcile(numa_device_types(&[
+      ("/sys/devices/a", "0"),
+      ("/sys/devices/b", "0"),
+      ("/sys/devices/c", "0"),
+      ("/sys/devices/d", "1"),
+      ("/sys/devices/e", "1"),
+    ]));
+
+    let ids_on = |node| {
+      let state = plugin.devices();
+      let mut ids = state
+        .devices
+        .iter()
+        .filter(|d| d.numa_node() == Some(node))
+        .map(|d| d.id().to_string())
+        .collect::<Vec<_>>();
+      ids.sort();
+      ids
+    };
+    let (node0, node1) = (ids_on(0), ids_on(1));
+    assert_eq!((node0.len(), node1.len()), (3, 2));
+
+    let request = v1beta1::PreferredAllocationRequest {
+      container_requests: vec![v1beta1::ContainerPreferredAllocationRequest {
+        available_device_ids: node0.iter().chain(&node1).cloned().collect(),
+        must_include_device_ids: vec![node1[0].clone()],
+        allocation_size: 2,
+      }],
+    };
+    let response = v1beta1::PreferredAllocation::get_preferred_allocation(&plugin, request)
+      .await
+      .unwrap();
+
+    let mut chosen = response.container_responses[0].device_ids.clone();
+    chosen.sort();
+    assert_eq!(chosen, node1);
+  }
+
+  fn container_request(
+    available: &[&str],
+    must_include: &[&str],
+    size: i32,
+  ) -> v1beta1::ContainerPreferredAllocationRequest {
+    v1beta1::ContainerPreferredAllocationRequest {
+      available_device_ids: available.iter().map(|id| id.to_string()).collect(),
+      must_include_device_ids: must_include.iter().map(|id| id.to_string()).collect(),
+      allocation_size: size,
+    }
+  }
+
+  #[test]
+  fn numa_aligned_packs_two_numa_nodes() {
+    // a0..a2 are on node 0, b0 and b1 on node 1
+    let numa_node = |id: &str| match &id[..1] {
+      "a" => Some(0),
+      "b" => Some(1),
+      _ => None,
+    };
+    let available = ["b0", "a0", "b1", "a1", "a2"];
+    let sorted = |mut ids: Vec<String>| {
+      ids.sort();
+      ids
+    };
+
+    // the node with the most available devices fills first
+    let chosen = numa_aligned(&container_request(&available, &[], 2), numa_node, |_| false);
+    assert_eq!(sorted(chosen), vec!["a0", "a1"]);
+    let chosen = numa_aligned(&container_request(&available, &[], 3), numa_node, |_| false);
+    assert_eq!(sorted(chosen), vec!["a0", "a1", "a2"]);
+
+    // must-include devices pull their node to the front
+    let chosen = numa_aligned(
+      &container_request(&available, &["b1"], 2),
+      numa_node,
+      |_| false,
+    );
+    assert_eq!(chosen, vec!["b1", "b0"]);
+
+    // and spill over onto the fullest other node once theirs is exhausted
+    let chosen = numa_aligned(
+      &container_request(&available, &["b1"], 4),
+      numa_node,
+      |_| false,
+    );
+    assert_eq!(chosen, vec!["b1", "b0", "a0", "a1"]);
+
+    // must-include devices are kept even when they exceed the allocation size
+    let chosen = numa_aligned(
+      &container_request(&available, &["a0", "b0"], 1),
+      numa_node,
+      |_| false,
+    );
+    assert_eq!(chosen, vec!["a0", "b0"]);
+  }
+
+  #[tokio::test]
+  async fn preferred_allocation_is_logged() {
+    let plugin = plugin(json!({ "preferNumaAlignment": true }));
+    plugin.reconcile(numa_device_types(&[
+      ("/sys/devices/a", "0"),
+      ("/sys/devices/b", "1"),
+      ("/sys/devices/c", "1"),
+    ]));
+
+    let ids = plugin
+      .devices()
+      .devices
+      .iter()
+      .map(|d| d.id().to_string())
+      .collect::<Vec<_>>();
+    let request = v1beta1::PreferredAllocationRequest {
+      container_requests: vec![v1beta1::ContainerPreferredAllocationRequest {
+        available_device_ids: ids.clone(),
+        must_include_device_ids: vec![],
+        allocation_size: 2,
+      }],
+    };
+
+    let buffer = Buffer::default();
+    let guard = tracing::subscriber::set_default(buffer.subscriber(Level::DEBUG));
+    v1beta1::PreferredAllocation::get_preferred_allocation(&plugin, request)
+      .await
+      .unwrap();
+    drop(guard);
+
+    let output = buffer.contents();
+    let line = output
+      .lines()
+      .find(|line| line.contains("preferred allocation"))
+      .unwrap_or_else(|| panic!("no preferred allocation event in {}", output));
+    assert!(
+      line.contains(&format!("resource={:?}", plugin.resource_name())),
+      "{}",
+      line
+    );
+    assert!(line.contains(&format!("available={:?}", ids)), "{}", line);
+    assert!(line.contains("must_include=[]"), "{}", line);
+    assert!(line.contains("allocation_size=2"), "{}", line);
+    assert!(line.contains("numa_nodes={1}"), "{}", line);
+  }
+
+  #[cfg(feature = "otel")]
+  mod otel {
+    use super::*;
+    use opentelemetry::{
+      sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        trace::TracerProvider,
+      },
+      trace::TracerProvider as _,
+      Key, Value,
+    };
+    use std::sync::Mutex;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    #[derive(Debug, Clone, Default)]
+    struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    #[async_trait]
+    impl SpanExporter for InMemoryExporter {
+      async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        self.0.lock().unwrap().extend(batch);
+        Ok(())
       }
     }
+
+    #[test]
+    fn allocate_span_is_exported_with_resource() {
+      let exporter = InMemoryExporter::default();
+      let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+      let tracer = provider.get_tracer("test", None);
+      let subscriber = Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+      let request = v1beta1::AllocateRequest {
+        container_requests: vec![v1beta1::ContainerAllocateRequest {
+          devices_ids: vec!["dev-0".into()],
+        }],
+      };
+
+      tracing::subscriber::with_default(subscriber, || {
+        let _guard = allocate_span("udev/usb/yubikey", &request).entered();
+      });
+      // spans are exported on a background thread, shutting down waits for it
+      drop(provider);
+
+      let spans = exporter.0.lock().unwrap();
+      let span = spans
+        .iter()
+        .find(|s| s.name == "allocate")
+        .expect("allocate span exported");
+      assert_eq!(
+        span.attributes.get(&Key::new("resource")),
+        Some(&Value::from("udev/usb/yubikey"))
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn update_config_swaps_selector_in_place() {
+    let device_types = [("a", "1cf1"), ("b", "0403")]
+      .iter()
+      .map(|(model, vendor)| {
+        serde_json::from_value::<DeviceType>(json!({
+          "name": model,
+          "subsystem": "tty",
+          "labels": { "model": model },
+          "selector": { "matchAttributes": { "idVendor": vendor } },
+        }))
+        .unwrap()
+      })
+      .collect::<Vec<_>>();
+
+    let mut devices = DeviceRegistry::new();
+    for (devnode, vendor) in &[("/dev/ttyACM0", "1cf1"), ("/dev/ttyUSB0", "0403")] {
+      let syspath = format!("/sys/devices{}", devnode);
+      devices.update(UdevEvent::Add(UdevDevice::from_parts(
+        "tty",
+        &syspath,
+        devnode,
+        vec![("idVendor", *vendor)],
+      )));
+    }
+
+    let mut registry = DeviceTypeRegistry::new(&device_types);
+    registry.reconcile(&devices);
+    let all = registry.distributor().get_device_types(|_| true);
+
+    let class = |model: &str| -> DeviceClass {
+      serde_json::from_value(json!({
+        "name": "conbee2",
+        "subsystem": "tty",
+        "target": "conbee2",
+        "selector": { "matchLabels": { "model": model } },
+      }))
+      .unwrap()
+    };
+    let reconcile = |plugin: &DevicePlugin| {
+      let config = plugin.config();
+      plugin.reconcile(
+        all
+          .iter()
+          .filter(|ty| config.match_with(ty.config()).is_match())
+          .cloned()
+          .collect(),
+      );
+    };
+
+    let plugin = DevicePlugin::new(class("a"), "udev/tty/conbee2".into());
+    reconcile(&plugin);
+    let mut stream = v1beta1::DevicePlugin::list_and_watch(&plugin)
+      .await
+      .unwrap();
+    let first = stream.next().await.unwrap().unwrap();
+    assert_eq!(first.devices.len(), 1);
+    assert!(advertised_ids(&plugin).contains_key("/dev/ttyACM0"));
+
+    plugin.update_config(class("b")).unwrap();
+    reconcile(&plugin);
+    assert_eq!(plugin.resource_name(), "udev/tty/conbee2");
+    assert_eq!(
+      advertised_ids(&plugin).keys().collect::<Vec<_>>(),
+      vec!["/dev/ttyUSB0"]
+    );
+
+    // the open stream is kept and sees the new devices
+    let next = time::timeout(Duration::from_secs(1), stream.next())
+      .await
+      .unwrap()
+      .unwrap()
+      .unwrap();
+    assert_eq!(next.devices.len(), 1);
+    assert_ne!(next.devices[0].id, first.devices[0].id);
+
+    let mut renamed = serde_json::to_value(class("b")).unwrap();
+    renamed["name"] = json!("zigbee");
+    let renamed: DeviceClass = serde_json::from_value(renamed).unwrap();
+    assert!(plugin.update_config(renamed).is_err());
+    assert_eq!(plugin.config().name(), "conbee2");
+  }
+
+  #[test]
+  fn current_list_and_watch_reflects_reconcile() {
+    let plugin = plugin(json!({}));
+    assert!(plugin.current_list_and_watch().devices.is_empty());
+
+    plugin.reconcile(devices_at(&["/dev/ttyACM0", "/dev/ttyACM1"]));
+    let ids = plugin
+      .current_list_and_watch()
+      .devices
+      .into_iter()
+      .map(|d| d.id)
+      .collect::<BTreeSet<_>>();
+    assert_eq!(ids, advertised_ids(&plugin).into_values().collect());
+    assert_eq!(ids.len(), 2);
+  }
+
+  #[test]
+  fn unauthorized_devices_are_unhealthy_until_authorized() {
+    let device_type = serde_json::from_value::<DeviceType>(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "unauthorized": "unhealthy",
+      "labels": {},
+      "selector": {},
+    }))
+    .unwrap();
+    let device = |authorized| {
+      UdevDevice::from_parts(
+        "tty",
+        "/sys/devices/a",
+        "/dev/ttyACM0",
+        vec![("authorized", authorized)],
+      )
+    };
+    let healthy = |plugin: &DevicePlugin| {
+      plugin
+        .current_list_and_watch()
+        .devices
+        .iter()
+        .map(|d| matches!(d.health, v1beta1::DeviceHealth::Healthy))
+        .collect::<Vec<_>>()
+    };
+
+    let mut devices = DeviceRegistry::new();
+    devices.update(UdevEvent::Add(device("0")));
+    let mut registry = DeviceTypeRegistry::new(&[device_type]);
+    registry.reconcile(&devices);
+
+    let plugin = plugin(json!({}));
+    plugin.reconcile(registry.distributor().get_device_types(|_| true));
+    assert_eq!(healthy(&plugin), vec![false]);
+
+    devices.update(UdevEvent::Change(device("1")));
+    registry.reconcile(&devices);
+    plugin.reconcile(registry.distributor().get_device_types(|_| true));
+    assert_eq!(healthy(&plugin), vec![true]);
+  }
+
+  #[tokio::test]
+  async fn leases_are_released_when_slots_turn_unhealthy_or_go_away() {
+    let device_type = serde_json::from_value::<DeviceType>(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "unauthorized": "unhealthy",
+      "labels": {},
+      "selector": {},
+    }))
+    .unwrap();
+    let device = |authorized| {
+      UdevDevice::from_parts(
+        "tty",
+        "/sys/devices/a",
+        "/dev/ttyACM0",
+        vec![("authorized", authorized)],
+      )
+    };
+
+    let mut devices = DeviceRegistry::new();
+    devices.update(UdevEvent::Add(device("1")));
+    let mut registry = DeviceTypeRegistry::new(&[device_type]);
+    registry.reconcile(&devices);
+
+    let plugin = plugin(json!({}));
+    let allocate = |plugin: DevicePlugin| async move {
+      let devices_ids = plugin
+        .devices()
+        .devices
+        .iter()
+        .map(|d| d.id().to_string())
+        .collect();
+      let request = v1beta1::AllocateRequest {
+        container_requests: vec![v1beta1::ContainerAllocateRequest { devices_ids }],
+      };
+      v1beta1::DevicePlugin::allocate(&plugin, request)
+        .await
+        .unwrap();
+    };
+
+    plugin.reconcile(registry.distributor().get_device_types(|_| true));
+    allocate(plugin.clone()).await;
+    assert_eq!(plugin.leased_count(), 1);
+
+    devices.update(UdevEvent::Change(device("0")));
+    registry.reconcile(&devices);
+    plugin.reconcile(registry.distributor().get_device_types(|_| true));
+    assert_eq!(plugin.leased_count(), 0);
+    assert!(plugin.state.allocations.leased().is_empty());
+
+    devices.update(UdevEvent::Change(device("1")));
+    registry.reconcile(&devices);
+    plugin.reconcile(registry.distributor().get_device_types(|_| true));
+    allocate(plugin.clone()).await;
+    assert_eq!(plugin.leased_count(), 1);
+
+    devices.update(UdevEvent::Remove(device("1")));
+    registry.reconcile(&devices);
+    plugin.reconcile(registry.distributor().get_device_types(|_| true));
+    assert!(plugin.state.allocations.leased().is_empty());
   }
 }
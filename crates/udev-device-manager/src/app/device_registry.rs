@@ -1,15 +1,21 @@
 use crate::{
-  config::InternedString,
-  udev::{UdevDevice, UdevEvent},
+  config::{InternedString, ManualDevice},
+  udev::{DeviceScanner, UdevDevice, UdevEvent},
 };
 use color_eyre::Result;
-use std::{collections::BTreeMap, convert::TryFrom};
-use tokio_udev::Enumerator;
+use std::collections::{BTreeMap, BTreeSet};
 use tracing::{event, Level};
 
 #[derive(Debug, Default)]
 pub struct DeviceRegistry {
   devices: BTreeMap<InternedString, UdevDevice>,
+
+  /// Devices udev reported as removed, kept around (and reported unhealthy) until the next
+  /// reconcile so the kubelet sees them degrade before they disappear
+  removed: BTreeSet<InternedString>,
+
+  /// Devices declared in the config rather than found by udev
+  manual: BTreeSet<InternedString>,
 }
 
 impl DeviceRegistry {
@@ -17,27 +23,69 @@ impl DeviceRegistry {
     Self::default()
   }
 
-  pub fn scan_devices(&mut self) -> Result<()> {
+  pub fn scan_devices(&mut self, scanner: &DeviceScanner) -> Result<()> {
     event!(target: "udev-device-manager", Level::DEBUG, "gathering udev devices");
-    let devices: BTreeMap<_, _> = Enumerator::new()?
-      .scan_devices()?
-      .filter_map(|d| UdevDevice::try_from(d).ok())
-      .map(|d| (d.syspath(), d))
-      .collect();
+    let devices: BTreeMap<_, _> = scanner.scan()?.map(|d| (d.syspath(), d)).collect();
     event!(target: "udev-device-manager", Level::DEBUG, devices.len = devices.len(), "gathered {} udev devices", devices.len());
 
     self.devices = devices;
+    self.removed.clear();
+    self.manual.clear();
     Ok(())
   }
 
+  /// Registers devices declared in the config, as if udev had found them. Needs redoing after
+  /// every scan, which only keeps what udev reports.
+  pub fn add_manual_devices(&mut self, devices: &[ManualDevice]) {
+    for device in devices {
+      let device = UdevDevice::from(device);
+      self.removed.remove(&device.syspath());
+      self.manual.insert(device.syspath());
+      self.devices.insert(device.syspath(), device);
+    }
+  }
+
+  /// Events turning the known devices into a fresh scan, standing in for the udev monitor
+  /// where it's unavailable. Manual devices aren't scanned, so they're left alone.
+  pub fn diff(&self, scanned: impl IntoIterator<Item = UdevDevice>) -> Vec<UdevEvent> {
+    let mut scanned: BTreeMap<_, _> = scanned.into_iter().map(|d| (d.syspath(), d)).collect();
+    let mut events = Vec::new();
+
+    for (syspath, known) in &self.devices {
+      if self.manual.contains(syspath) {
+        continue;
+      }
+
+      let removed = self.removed.contains(syspath);
+      match scanned.remove(syspath) {
+        None if removed => (),
+        None => events.push(UdevEvent::Remove(known.clone())),
+        Some(device) if removed => events.push(UdevEvent::Add(device)),
+        Some(device)
+          if device.devnode() != known.devnode() || device.attributes() != known.attributes() =>
+        {
+          events.push(UdevEvent::Change(device))
+        }
+        Some(_) => (),
+      }
+    }
+
+    events.extend(scanned.into_values().map(UdevEvent::Add));
+    events
+  }
+
   pub fn update(&mut self, event: UdevEvent) {
     match event {
       UdevEvent::Add(device) | UdevEvent::Change(device) => {
+        self.removed.remove(&device.syspath());
         self.devices.insert(device.syspath(), device);
       }
 
       UdevEvent::Remove(device) => {
-        self.devices.remove(&device.syspath());
+        if self.devices.contains_key(&device.syspath()) {
+          event!(target: "udev-device-manager", Level::DEBUG, device.syspath = %device.syspath(), device.devnode = %device.devnode(), "device removed");
+          self.removed.insert(device.syspath());
+        }
       }
 
       UdevEvent::Bind(device) => {
@@ -60,4 +108,66 @@ impl DeviceRegistry {
   ) -> impl Iterator<Item = UdevDevice> + 'f {
     self.devices.values().filter(move |d| f(*d)).cloned()
   }
+
+  /// Number of known devices, including the ones reported as removed
+  pub fn device_count(&self) -> usize {
+    self.devices.len()
+  }
+
+  /// Whether udev reported the device as removed since the last reconcile
+  pub fn is_removed(&self, device: &UdevDevice) -> bool {
+    self.removed.contains(&device.syspath())
+  }
+
+  /// Drops the devices reported as removed, so the next reconcile stops advertising them.
+  /// Returns whether there were any.
+  pub fn forget_removed(&mut self) -> bool {
+    let removed = std::mem::take(&mut self.removed);
+    for syspath in &removed {
+      self.devices.remove(syspath);
+    }
+
+    !removed.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn diff_against_a_rescan() {
+    let device = |syspath: &str, product: &str| {
+      UdevDevice::from_parts("tty", syspath, "/dev/ttyACM0", vec![("product", product)])
+    };
+
+    let mut registry = DeviceRegistry::new();
+    registry.update(UdevEvent::Add(device("/sys/devices/kept", "a")));
+    registry.update(UdevEvent::Add(device("/sys/devices/changed", "a")));
+    registry.update(UdevEvent::Add(device("/sys/devices/unplugged", "a")));
+    registry.add_manual_devices(
+      &serde_json::from_value::<Vec<ManualDevice>>(serde_json::json!([
+        { "id": "relay", "subsystem": "tty", "devnode": "/dev/ttyS9" },
+      ]))
+      .unwrap(),
+    );
+
+    let events = registry.diff(vec![
+      device("/sys/devices/kept", "a"),
+      device("/sys/devices/changed", "b"),
+      device("/sys/devices/plugged", "a"),
+    ]);
+    let events = events
+      .iter()
+      .map(|e| (e.kind(), e.device().syspath().to_string()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      events,
+      vec![
+        ("change", "/sys/devices/changed".to_string()),
+        ("remove", "/sys/devices/unplugged".to_string()),
+        ("add", "/sys/devices/plugged".to_string()),
+      ]
+    );
+  }
 }
@@ -1,17 +1,70 @@
 use super::DeviceRegistry;
 use crate::{
-  config::{DeviceType, InternedString},
+  config::{DeviceType, DeviceTypeLabels, InternedString, UnauthorizedDevices},
   udev::UdevDevice,
 };
-use arc_swap::{ArcSwap, ArcSwapAny};
+use arc_swap::{ArcSwap, ArcSwapAny, ArcSwapOption};
 use kubelet_deviceplugin_proto::v1beta1;
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+  collections::BTreeMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
 use tracing::{event, Level};
 
+/// Longest device id the kubelet accepts, it silently drops devices with longer ones
+const MAX_DEVICE_ID_LEN: usize = 63;
+
+/// Shortens an over-long device id to a prefix of it followed by a hash of the full id, which
+/// keeps it stable across reconciles and unique among ids sharing the prefix. Returns `None`
+/// when the id already fits.
+fn shortened_device_id(id: &str) -> Option<String> {
+  if id.len() <= MAX_DEVICE_ID_LEN {
+    return None;
+  }
+
+  let hash = base64::encode(seahash::hash(id.as_bytes()).to_le_bytes());
+  let max_prefix = MAX_DEVICE_ID_LEN - hash.len() - 1;
+  let prefix_len = id
+    .char_indices()
+    .map(|(index, c)| index + c.len_utf8())
+    .take_while(|end| *end <= max_prefix)
+    .last()
+    .unwrap_or(0);
+
+  Some(format!("{}-{}", &id[..prefix_len], hash))
+}
+
+/// Lets operators know the device is advertised under a shortened id, logged once per device
+fn warn_shortened(config: &DeviceType, device: &UdevDevice, id: InternedString) {
+  event!(
+    target: "udev-device-manager",
+    Level::WARN,
+    device_type.name = %config.name(),
+    device.syspath = %device.syspath(),
+    device.id = %id,
+    "device id is longer than the kubelet accepts ({} chars), shortened it",
+    MAX_DEVICE_ID_LEN
+  );
+}
+
 #[derive(Debug)]
 struct DeviceState {
   device: ArcSwapAny<UdevDevice>,
   id: InternedString,
+  unauthorized: UnauthorizedDevices,
+
+  /// Set when udev reported the device as removed, it's then advertised as unhealthy until
+  /// it's dropped on the next reconcile
+  removed: AtomicBool,
+
+  /// Set while the health check of the device type fails for the device
+  check_failed: AtomicBool,
+
+  /// The device as last advertised to the kubelet, cleared whenever the udev device changes
+  advertised: ArcSwapOption<v1beta1::Device>,
 }
 
 #[derive(Debug, Clone)]
@@ -23,17 +76,30 @@ impl DeviceHandle {
     &*self.0
   }
 
-  pub fn new(device: UdevDevice, index: usize) -> Self {
-    let id = InternedString::new(format!("{}:{}", device.id(), index));
-
+  pub fn new(device: UdevDevice, id: InternedString, unauthorized: UnauthorizedDevices) -> Self {
     Self(Arc::new(DeviceState {
       device: ArcSwapAny::new(device),
       id,
+      unauthorized,
+      removed: AtomicBool::new(false),
+      check_failed: AtomicBool::new(false),
+      advertised: ArcSwapOption::empty(),
     }))
   }
 
-  pub fn update(&self, config: UdevDevice) {
-    self.state().device.store(config)
+  pub fn update(&self, device: UdevDevice) {
+    let state = self.state();
+    if !state.device.load().ptr_eq(&device) {
+      state.device.store(device);
+      state.advertised.store(None);
+    }
+  }
+
+  pub fn set_removed(&self, removed: bool) {
+    let state = self.state();
+    if state.removed.swap(removed, Ordering::AcqRel) != removed {
+      state.advertised.store(None);
+    }
   }
 
   pub fn config(&self) -> UdevDevice {
@@ -43,6 +109,65 @@ impl DeviceHandle {
   pub fn id(&self) -> InternedString {
     self.state().id
   }
+
+  pub fn numa_node(&self) -> Option<i64> {
+    self.state().device.load().numa_node()
+  }
+
+  /// Records the outcome of the latest health check, returns whether it differs from the
+  /// previous one
+  pub fn set_check_failed(&self, failed: bool) -> bool {
+    let state = self.state();
+    let changed = state.check_failed.swap(failed, Ordering::AcqRel) != failed;
+    if changed {
+      state.advertised.store(None);
+    }
+
+    changed
+  }
+
+  /// Unhealthy devices are advertised, but not allocated by the kubelet
+  pub fn healthy(&self) -> bool {
+    let state = self.state();
+    if state.removed.load(Ordering::Acquire) || state.check_failed.load(Ordering::Acquire) {
+      return false;
+    }
+
+    let unauthorized = state.device.load().authorized() == Some(false);
+    !(unauthorized && state.unauthorized == UnauthorizedDevices::Unhealthy)
+  }
+
+  /// The device as advertised to the kubelet. This is cached, and only rebuilt when the
+  /// underlying udev device changes.
+  pub fn advertised(&self) -> Arc<v1beta1::Device> {
+    let state = self.state();
+    if let Some(device) = state.advertised.load_full() {
+      return device;
+    }
+
+    let udev_device = self.config();
+    let healthy = self.healthy();
+    let topology = udev_device.numa_node().map(|id| v1beta1::TopologyInfo {
+      nodes: vec![v1beta1::NumaNode { id }],
+    });
+    let device = Arc::new(v1beta1::Device {
+      id: self.id().into(),
+      health: match healthy {
+        true => v1beta1::DeviceHealth::Healthy,
+        false => v1beta1::DeviceHealth::Unhealthy,
+      },
+      topology,
+    });
+
+    // the device may have changed while this was built, after its cache was cleared; don't
+    // leave the stale device cached then
+    state.advertised.store(Some(device.clone()));
+    if self.healthy() != healthy || !state.device.load().ptr_eq(&udev_device) {
+      state.advertised.store(None);
+    }
+
+    device
+  }
 }
 
 impl PartialEq for DeviceHandle {
@@ -54,11 +179,7 @@ impl PartialEq for DeviceHandle {
 
 impl<'a> From<&'a DeviceHandle> for v1beta1::Device {
   fn from(device: &'a DeviceHandle) -> Self {
-    v1beta1::Device {
-      id: device.id().into(),
-      health: v1beta1::DeviceHealth::Healthy,
-      topology: None,
-    }
+    (*device.advertised()).clone()
   }
 }
 
@@ -84,14 +205,29 @@ impl DeviceTypeHandle {
     &*self.0
   }
 
-  fn config(&self) -> &DeviceType {
+  pub fn config(&self) -> &DeviceType {
     &self.inner().config
   }
 
   fn reconcile(&self, registry: &DeviceRegistry) {
     let config = self.config();
     let devices = registry
-      .find(|d| config.match_with(d).is_match())
+      .find(|d| {
+        let result = config.match_with(d);
+        // devices of other subsystems are never meant for the device type
+        if result.is_mismatch() && d.subsystem() == config.subsystem() {
+          event!(
+            target: "udev-device-manager",
+            Level::DEBUG,
+            device_type.name = %config.name(),
+            device.syspath = %d.syspath(),
+            mismatches = %result,
+            "device excluded from device type"
+          );
+        }
+
+        result.is_match()
+      })
       .collect::<Vec<_>>();
 
     event!(
@@ -103,10 +239,39 @@ impl DeviceTypeHandle {
       "device type matches {} devices",
       devices.len());
 
+    // reuse the existing handles, so their cached state survives the reconcile
+    let existing = self.inner().devices.load();
+    let existing = existing
+      .iter()
+      .map(|handle| (handle.id(), handle))
+      .collect::<BTreeMap<_, _>>();
+
+    let scheme = config.id_scheme();
+    let count: usize = config.access().into();
     let devices = devices
       .into_iter()
-      .flat_map(|device| {
-        (0usize..config.access().into()).map(move |index| DeviceHandle::new(device.clone(), index))
+      .flat_map(|device| (0..count).map(move |index| (device.clone(), index)))
+      .map(|(device, index)| {
+        let removed = registry.is_removed(&device);
+        let id = scheme.device_id(&device.id(), index, count);
+        let shortened = shortened_device_id(&id);
+        let id = InternedString::new(shortened.as_deref().unwrap_or(&id));
+        let handle = match existing.get(&id) {
+          Some(handle) => {
+            handle.update(device);
+            (*handle).clone()
+          }
+          None => {
+            if shortened.is_some() {
+              warn_shortened(config, &device, id);
+            }
+
+            DeviceHandle::new(device, id, config.unauthorized())
+          }
+        };
+
+        handle.set_removed(removed);
+        handle
       })
       .collect::<Vec<_>>();
     let devices = Arc::new(devices);
@@ -114,6 +279,34 @@ impl DeviceTypeHandle {
     self.inner().devices.store(devices);
   }
 
+  /// The device type as handed to device classes. Templated labels are expanded per device,
+  /// splitting the devices up into one device type per distinct set of labels.
+  fn concrete(&self) -> Vec<DeviceTypeHandle> {
+    let config = self.config();
+    if !config.labels().is_templated() {
+      return vec![self.clone()];
+    }
+
+    let mut groups: Vec<(DeviceTypeLabels, Vec<DeviceHandle>)> = Vec::new();
+    for device in self.devices() {
+      let labels = config.labels().expand(&device.config());
+      match groups.iter_mut().find(|(group, _)| *group == labels) {
+        Some((_, devices)) => devices.push(device),
+        None => groups.push((labels, vec![device])),
+      }
+    }
+
+    groups
+      .into_iter()
+      .map(|(labels, devices)| {
+        DeviceTypeHandle(Arc::new(Inner {
+          config: config.with_labels(labels),
+          devices: ArcSwap::from_pointee(devices),
+        }))
+      })
+      .collect()
+  }
+
   pub fn devices(&self) -> impl IntoIterator<Item = DeviceHandle> {
     self
       .inner()
@@ -135,21 +328,50 @@ impl DeviceTypeRegistry {
   pub fn new(devices: &[DeviceType]) -> Self {
     let device_types = devices
       .iter()
+      .filter(|d| d.enabled())
       .map(|d| (d.name(), DeviceTypeHandle::new(d.clone())))
       .collect();
 
     DeviceTypeRegistry { device_types }
   }
 
+  /// Applies a new set of device types, keeping the handles (and with them the devices and
+  /// their health) of the unchanged ones. Returns the number of device types added, removed
+  /// or changed.
+  pub fn reload(&mut self, devices: &[DeviceType]) -> usize {
+    let mut previous = std::mem::take(&mut self.device_types);
+    let mut changes = 0;
+    for device_type in devices.iter().filter(|d| d.enabled()) {
+      let handle = match previous.remove(&device_type.name()) {
+        Some(handle) if handle.config() == device_type => handle,
+        _ => {
+          changes += 1;
+          DeviceTypeHandle::new(device_type.clone())
+        }
+      };
+      self.device_types.insert(device_type.name(), handle);
+    }
+
+    changes + previous.len()
+  }
+
   pub fn reconcile(&self, registry: &DeviceRegistry) {
     for device in self.device_types.values() {
       device.reconcile(registry);
     }
   }
 
-  pub(super) fn distributor<'a>(&'a mut self) -> Distributor<'a> {
+  pub fn device_types(&self) -> impl Iterator<Item = &DeviceTypeHandle> {
+    self.device_types.values()
+  }
+
+  pub(super) fn distributor(&mut self) -> Distributor {
     Distributor {
-      types: self.device_types.values().collect(),
+      types: self
+        .device_types
+        .values()
+        .flat_map(DeviceTypeHandle::concrete)
+        .collect(),
     }
   }
 }
@@ -158,20 +380,350 @@ pub trait DeviceTypeDistributor {
   fn get_device_types(&mut self, f: impl FnMut(&DeviceType) -> bool) -> Vec<DeviceTypeHandle>;
 }
 
-pub(super) struct Distributor<'a> {
-  types: Vec<&'a DeviceTypeHandle>,
+pub(super) struct Distributor {
+  types: Vec<DeviceTypeHandle>,
 }
 
-impl<'a> Distributor<'a> {
-  pub fn remaining(self) -> Vec<&'a DeviceTypeHandle> {
+impl Distributor {
+  pub fn remaining(self) -> Vec<DeviceTypeHandle> {
     self.types
   }
 }
 
-impl<'a> DeviceTypeDistributor for Distributor<'a> {
+impl DeviceTypeDistributor for Distributor {
   fn get_device_types(&mut self, mut f: impl FnMut(&DeviceType) -> bool) -> Vec<DeviceTypeHandle> {
-    let (hits, misses) = self.types.iter().partition(|d| f(d.config()));
-    self.types = hits;
-    misses.into_iter().map(|h| (*h).clone()).collect()
+    let (hits, misses) = self.types.drain(..).partition(|d| f(d.config()));
+    self.types = misses;
+    hits
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    config::{DeviceAccess, DeviceIdScheme},
+    test_log::logged,
+    udev::UdevEvent,
+  };
+  use serde_json::json;
+  use std::{collections::BTreeSet, num::NonZeroU8};
+
+  fn serial_device(syspath: &str, vendor: &str) -> UdevDevice {
+    UdevDevice::from_parts("tty", syspath, "/dev/ttyACM0", vec![("idVendor", vendor)])
+  }
+
+  fn advertised(handle: &DeviceTypeHandle) -> Vec<Arc<v1beta1::Device>> {
+    handle
+      .devices()
+      .into_iter()
+      .map(|d| d.advertised())
+      .collect()
+  }
+
+  #[test]
+  fn advertised_devices_are_cached_across_reconciles() {
+    let device_type: DeviceType = serde_json::from_value(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "labels": {},
+      "selector": { "matchAttributes": { "idVendor": "1cf1" } },
+    }))
+    .unwrap();
+
+    let handle = DeviceTypeHandle::new(device_type);
+    let mut registry = DeviceRegistry::new();
+    registry.update(UdevEvent::Add(serial_device("/sys/devices/a", "1cf1")));
+    handle.reconcile(&registry);
+    let before = advertised(&handle);
+    assert_eq!(before.len(), 1);
+
+    // an unrelated device showing up doesn't rebuild the advertised device
+    registry.update(UdevEvent::Add(serial_device("/sys/devices/b", "0403")));
+    handle.reconcile(&registry);
+    let after = advertised(&handle);
+    assert_eq!(after.len(), 1);
+    assert!(Arc::ptr_eq(&before[0], &after[0]));
+
+    // a change to the device itself does
+    registry.update(UdevEvent::Change(serial_device("/sys/devices/a", "1cf1")));
+    handle.reconcile(&registry);
+    let changed = advertised(&handle);
+    assert_eq!(changed.len(), 1);
+    assert!(!Arc::ptr_eq(&before[0], &changed[0]));
+    assert_eq!(before[0].id, changed[0].id);
+  }
+
+  #[test]
+  fn advertises_numa_node_topology() {
+    let numa_nodes = |attributes: Vec<(&str, &str)>| {
+      let device = UdevDevice::from_parts(
+        "accel",
+        "/sys/devices/pci0000:80/0000:80:01.0/accel/accel0",
+        "/dev/accel0",
+        attributes,
+      );
+      let handle = DeviceHandle::new(
+        device,
+        InternedString::new("accel0"),
+        UnauthorizedDevices::default(),
+      );
+      v1beta1::Device::from(&handle)
+        .topology
+        .map(|t| t.nodes.iter().map(|n| n.id).collect::<Vec<_>>())
+    };
+
+    assert_eq!(numa_nodes(vec![("numa_node", "1")]), Some(vec![1]));
+    assert_eq!(numa_nodes(vec![("numa_node", "-1")]), None);
+    assert_eq!(numa_nodes(vec![("numa_node", "")]), None);
+    assert_eq!(numa_nodes(vec![]), None);
+  }
+
+  fn ids(handle: &DeviceTypeHandle) -> Vec<String> {
+    handle
+      .devices()
+      .into_iter()
+      .map(|d| d.id().to_string())
+      .collect()
+  }
+
+  #[test]
+  fn compact_ids_only_suffixed_when_shared() {
+    let device = serial_device("/sys/devices/a", "1cf1");
+    let mut registry = DeviceRegistry::new();
+    registry.update(UdevEvent::Add(device.clone()));
+
+    let exclusive = DeviceTypeHandle::new(
+      DeviceType::new("conbee2", "tty").with_id_scheme(DeviceIdScheme::Compact),
+    );
+    exclusive.reconcile(&registry);
+    assert_eq!(ids(&exclusive), vec![device.id().to_string()]);
+
+    let shared = DeviceTypeHandle::new(
+      DeviceType::new("conbee2", "tty")
+        .with_id_scheme(DeviceIdScheme::Compact)
+        .with_access(DeviceAccess::AtMost(NonZeroU8::new(2).unwrap())),
+    );
+    shared.reconcile(&registry);
+    let before = ids(&shared);
+    assert_eq!(
+      before,
+      vec![format!("{}:0", device.id()), format!("{}:1", device.id())]
+    );
+
+    registry.update(UdevEvent::Change(serial_device("/sys/devices/a", "1cf1")));
+    shared.reconcile(&registry);
+    assert_eq!(ids(&shared), before);
+  }
+
+  #[test]
+  fn shared_devices_are_advertised_many_times() {
+    let mut registry = DeviceRegistry::new();
+    registry.update(UdevEvent::Add(serial_device("/sys/devices/a", "1cf1")));
+    registry.update(UdevEvent::Add(serial_device("/sys/devices/b", "1cf1")));
+
+    let handle =
+      DeviceTypeHandle::new(DeviceType::new("kvm", "tty").with_access(DeviceAccess::Shared));
+    handle.reconcile(&registry);
+
+    let count = usize::from(DeviceAccess::Shared);
+    assert!(count > 255);
+    let ids = ids(&handle);
+    assert_eq!(ids.len(), 2 * count);
+    assert_eq!(ids.iter().collect::<BTreeSet<_>>().len(), ids.len());
+  }
+
+  #[test]
+  fn reload_keeps_unchanged_device_types() {
+    let kept = DeviceType::new("kept", "tty");
+    let changed = DeviceType::new("changed", "tty");
+    let mut registry = DeviceTypeRegistry::new(&[
+      kept.clone(),
+      changed.clone(),
+      DeviceType::new("removed", "tty"),
+    ]);
+    let handle = |registry: &DeviceTypeRegistry, name: &str| {
+      registry
+        .device_types
+        .get(name)
+        .map(|handle| handle.0.clone())
+    };
+    let before = handle(&registry, "kept").unwrap();
+
+    let changes = registry.reload(&[
+      kept,
+      changed.with_access(DeviceAccess::Shared),
+      DeviceType::new("added", "tty"),
+    ]);
+    assert_eq!(changes, 3);
+    assert!(Arc::ptr_eq(&before, &handle(&registry, "kept").unwrap()));
+    assert!(handle(&registry, "added").is_some());
+    assert!(handle(&registry, "removed").is_none());
+  }
+
+  #[test]
+  fn templated_labels_split_device_types_per_device() {
+    let device_type: DeviceType = serde_json::from_value(json!({
+      "name": "serial",
+      "subsystem": "tty",
+      "labels": { "type": "serial", "vendor": "${attr:idVendor}" },
+      "selector": {},
+    }))
+    .unwrap();
+
+    let mut devices = DeviceRegistry::new();
+    devices.update(UdevEvent::Add(serial_device("/sys/devices/a", "1cf1")));
+    devices.update(UdevEvent::Add(serial_device("/sys/devices/b", "0403")));
+    devices.update(UdevEvent::Add(serial_device("/sys/devices/c", "1cf1")));
+    devices.update(UdevEvent::Add(UdevDevice::from_parts(
+      "tty",
+      "/sys/devices/d",
+      "/dev/ttyS0",
+      vec![],
+    )));
+
+    let mut registry = DeviceTypeRegistry::new(&[device_type]);
+    registry.reconcile(&devices);
+    let mut distributor = registry.distributor();
+
+    let conbee = distributor
+      .get_device_types(|ty| ty.labels().get("vendor") == Some(InternedString::new("1cf1")));
+    assert_eq!(conbee.len(), 1);
+    assert_eq!(conbee[0].config().name(), InternedString::new("serial"));
+    assert_eq!(
+      conbee[0].config().labels().get("type"),
+      Some(InternedString::new("serial"))
+    );
+    assert_eq!(conbee[0].devices().into_iter().count(), 2);
+
+    // the device without the attribute falls back to an empty label
+    let mut vendors = distributor
+      .remaining()
+      .iter()
+      .map(|ty| ty.config().labels().get("vendor").unwrap().to_string())
+      .collect::<Vec<_>>();
+    vendors.sort();
+    assert_eq!(vendors, vec!["", "0403"]);
+  }
+
+  #[test]
+  fn removed_devices_are_unhealthy_before_vanishing() {
+    let handle = DeviceTypeHandle::new(DeviceType::new("conbee2", "tty"));
+    let mut registry = DeviceRegistry::new();
+    registry.update(UdevEvent::Add(serial_device("/sys/devices/a", "1cf1")));
+    handle.reconcile(&registry);
+    registry.forget_removed();
+    let healthy = advertised(&handle);
+    assert_eq!(healthy.len(), 1);
+    assert!(matches!(healthy[0].health, v1beta1::DeviceHealth::Healthy));
+
+    registry.update(UdevEvent::Remove(serial_device("/sys/devices/a", "1cf1")));
+    handle.reconcile(&registry);
+    registry.forget_removed();
+    let unhealthy = advertised(&handle);
+    assert_eq!(unhealthy.len(), 1);
+    assert_eq!(unhealthy[0].id, healthy[0].id);
+    assert!(matches!(
+      unhealthy[0].health,
+      v1beta1::DeviceHealth::Unhealthy
+    ));
+
+    handle.reconcile(&registry);
+    assert!(advertised(&handle).is_empty());
+
+    // a device coming back before the reconcile is healthy again
+    registry.update(UdevEvent::Add(serial_device("/sys/devices/a", "1cf1")));
+    registry.update(UdevEvent::Remove(serial_device("/sys/devices/a", "1cf1")));
+    registry.update(UdevEvent::Add(serial_device("/sys/devices/a", "1cf1")));
+    handle.reconcile(&registry);
+    let readded = advertised(&handle);
+    assert_eq!(readded.len(), 1);
+    assert!(matches!(readded[0].health, v1beta1::DeviceHealth::Healthy));
+  }
+
+  #[test]
+  fn disabled_device_types_match_nothing() {
+    let device_type: DeviceType = serde_json::from_value(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "enabled": false,
+      "labels": {},
+      "selector": { "matchAttributes": { "idVendor": "1cf1" } },
+    }))
+    .unwrap();
+
+    let mut devices = DeviceRegistry::new();
+    devices.update(UdevEvent::Add(serial_device("/sys/devices/a", "1cf1")));
+
+    let mut registry = DeviceTypeRegistry::new(&[device_type]);
+    registry.reconcile(&devices);
+    assert!(registry.distributor().get_device_types(|_| true).is_empty());
+  }
+
+  #[test]
+  fn over_length_ids_are_shortened() {
+    assert_eq!(shortened_device_id("aHR0cHM6Ly9:0"), None);
+
+    let long = format!("serial-{}", "0123456789".repeat(7));
+    let shortened = shortened_device_id(&long).unwrap();
+    assert!(shortened.len() <= MAX_DEVICE_ID_LEN, "{}", shortened);
+    assert!(shortened.starts_with("serial-0123"), "{}", shortened);
+    assert_eq!(shortened_device_id(&long), Some(shortened.clone()));
+
+    // ids sharing the kept prefix still get distinct ids
+    let other = format!("{}:1", long);
+    assert_ne!(shortened_device_id(&other).unwrap(), shortened);
+    assert_eq!(shortened_device_id(&"é".repeat(40)).unwrap().len(), 63);
+  }
+
+  #[test]
+  fn shortened_ids_are_warned_about() {
+    let device_type: DeviceType = serde_json::from_value(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "labels": {},
+      "selector": {},
+    }))
+    .unwrap();
+    let long = format!("serial-{}", "0123456789".repeat(7));
+    let mut devices = DeviceRegistry::new();
+    devices.update(UdevEvent::Add(
+      serial_device("/sys/devices/a", "1cf1").with_id(&long),
+    ));
+
+    let registry = DeviceTypeRegistry::new(&[device_type]);
+    let output = logged(|| registry.reconcile(&devices));
+    let handle = registry.device_types().next().unwrap();
+    let ids = handle
+      .devices()
+      .into_iter()
+      .map(|device| device.id())
+      .collect::<Vec<_>>();
+    assert_eq!(ids.len(), 1);
+    assert_eq!(
+      ids[0].to_string(),
+      shortened_device_id(&format!("{}:0", long)).unwrap()
+    );
+
+    assert!(output.contains("WARN"), "{}", output);
+    assert!(output.contains("device_type.name=conbee2"), "{}", output);
+    assert!(
+      output.contains("device.syspath=/sys/devices/a"),
+      "{}",
+      output
+    );
+    assert!(
+      output.contains(&format!("device.id={}", ids[0])),
+      "{}",
+      output
+    );
+    assert!(output.contains("(63 chars)"), "{}", output);
+
+    // the device keeps its shortened id, and is only warned about once
+    let output = logged(|| registry.reconcile(&devices));
+    assert!(!output.contains("shortened"), "{}", output);
+    let handle = registry.device_types().next().unwrap();
+    let device = handle.devices().into_iter().next().unwrap();
+    assert_eq!(device.id(), ids[0]);
   }
 }
@@ -0,0 +1,283 @@
+use super::{
+  args::LogFormat, DeviceRegistry, DeviceTypeDistributor, DeviceTypeHandle, DeviceTypeRegistry,
+};
+use crate::{
+  config::{Config, ConfigFormat},
+  udev::DeviceScanner,
+};
+use color_eyre::Result;
+use serde::Serialize;
+use std::{fmt, path::Path};
+
+/// What a reconcile would advertise for the current devices, without starting any device
+/// plugins
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunReport {
+  /// Likely mistakes in the config, which is applied regardless
+  pub warnings: Vec<String>,
+
+  pub device_types: Vec<DeviceTypeReport>,
+}
+
+/// A device type with its matched devices. Device types with templated labels show up once
+/// per distinct set of labels, as they're distributed to the device classes that way.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceTypeReport {
+  pub name: String,
+  pub subsystem: String,
+  pub devices: Vec<DeviceReport>,
+
+  /// Device class claiming the device type, if any
+  pub device_class: Option<String>,
+
+  /// Resource the devices would be advertised under
+  pub resource: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceReport {
+  pub id: String,
+  pub syspath: String,
+  pub devnode: String,
+}
+
+impl DryRunReport {
+  /// Matches the devices against the config the same way a reconcile does
+  pub fn new(config: &Config, devices: &DeviceRegistry) -> Self {
+    let mut registry = DeviceTypeRegistry::new(config.device_types());
+    registry.reconcile(devices);
+    let mut distributor = registry.distributor();
+
+    // device classes take their device types in name order, like the class registry does
+    let mut device_classes = config
+      .device_classes()
+      .iter()
+      .filter(|class| class.enabled())
+      .collect::<Vec<_>>();
+    device_classes.sort_by_key(|class| class.name());
+
+    let mut device_types = Vec::new();
+    for class in device_classes {
+      for device_type in distributor.get_device_types(|ty| class.match_with(ty).is_match()) {
+        let resource = class.resource_name_for(device_type.config());
+        device_types.push(DeviceTypeReport::new(
+          &device_type,
+          Some(class.name().to_string()),
+          resource,
+        ));
+      }
+    }
+
+    for device_type in distributor.remaining() {
+      device_types.push(DeviceTypeReport::new(&device_type, None, None));
+    }
+
+    let warnings = config.lint().iter().map(ToString::to_string).collect();
+    Self {
+      warnings,
+      device_types,
+    }
+  }
+}
+
+impl DeviceTypeReport {
+  fn new(
+    device_type: &DeviceTypeHandle,
+    device_class: Option<String>,
+    resource: Option<String>,
+  ) -> Self {
+    let devices = device_type
+      .devices()
+      .into_iter()
+      .map(|handle| {
+        let device = handle.config();
+        DeviceReport {
+          id: handle.id().to_string(),
+          syspath: device.syspath().to_string(),
+          devnode: device.devnode().to_string(),
+        }
+      })
+      .collect();
+
+    Self {
+      name: device_type.config().name().to_string(),
+      subsystem: device_type.config().subsystem().to_string(),
+      devices,
+      device_class,
+      resource,
+    }
+  }
+}
+
+impl fmt::Display for DryRunReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for warning in &self.warnings {
+      writeln!(f, "warning: {}", warning)?;
+    }
+
+    for device_type in &self.device_types {
+      write!(
+        f,
+        "device type {} ({}): {} devices, ",
+        device_type.name,
+        device_type.subsystem,
+        device_type.devices.len()
+      )?;
+      match (&device_type.device_class, &device_type.resource) {
+        (Some(class), Some(resource)) => writeln!(f, "advertised by {} as {}", class, resource)?,
+        (Some(class), None) => writeln!(f, "claimed by {} but not advertised", class)?,
+        (None, _) => writeln!(f, "not claimed by any device class")?,
+      }
+
+      for device in &device_type.devices {
+        writeln!(f, "  {} {} ({})", device.id, device.syspath, device.devnode)?;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// Scans the devices, prints what would be advertised for them and returns
+pub async fn run(config_file: &Path, format: ConfigFormat, log_format: LogFormat) -> Result<()> {
+  let config = Config::read(config_file, format).await?;
+  let mut devices = DeviceRegistry::new();
+  devices.scan_devices(&DeviceScanner::new(&config))?;
+  devices.add_manual_devices(config.manual_devices());
+
+  let report = DryRunReport::new(&config, &devices);
+  match log_format {
+    LogFormat::Pretty => print!("{}", report),
+    LogFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::udev::{UdevDevice, UdevEvent};
+
+  #[test]
+  fn report_lists_matches_and_claims() {
+    let config: Config = toml::from_str(
+      r#"
+        [[devices]]
+        name = "conbee2"
+        subsystem = "tty"
+        labels = { type = "conbee2" }
+        selector = { matchAttributes = { idVendor = "1cf1" } }
+
+        [[devices]]
+        name = "ftdi"
+        subsystem = "tty"
+        labels = { type = "ftdi" }
+        selector = { matchAttributes = { idVendor = "0403" } }
+
+        [[deviceClasses]]
+        name = "zigbee"
+        subsystem = "tty"
+        target = "zigbee"
+        selector = { matchLabels = { type = "conbee2" } }
+      "#,
+    )
+    .unwrap();
+
+    let mut devices = DeviceRegistry::new();
+    for (syspath, devnode, vendor) in &[
+      ("/sys/devices/a", "/dev/ttyACM0", "1cf1"),
+      ("/sys/devices/b", "/dev/ttyUSB0", "0403"),
+      ("/sys/devices/c", "/dev/ttyS0", "8086"),
+    ] {
+      devices.update(UdevEvent::Add(UdevDevice::from_parts(
+        "tty",
+        syspath,
+        devnode,
+        vec![("idVendor", *vendor)],
+      )));
+    }
+
+    let report = DryRunReport::new(&config, &devices);
+    let summary = report
+      .device_types
+      .iter()
+      .map(|ty| {
+        let devnodes = ty
+          .devices
+          .iter()
+          .map(|d| d.devnode.as_str())
+          .collect::<Vec<_>>();
+        (ty.name.as_str(), devnodes, ty.device_class.as_deref())
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      summary,
+      vec![
+        ("conbee2", vec!["/dev/ttyACM0"], Some("zigbee")),
+        ("ftdi", vec!["/dev/ttyUSB0"], None),
+      ]
+    );
+    assert_eq!(
+      report.device_types[0].resource.as_deref(),
+      Some("udev/tty/zigbee")
+    );
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["deviceTypes"][0]["deviceClass"], "zigbee");
+    assert_eq!(
+      json["deviceTypes"][0]["devices"][0]["syspath"],
+      "/sys/devices/a"
+    );
+
+    let text = report.to_string();
+    assert!(
+      text.starts_with(
+        "device type conbee2 (tty): 1 devices, advertised by zigbee as udev/tty/zigbee\n"
+      ),
+      "{}",
+      text
+    );
+    assert!(text.contains("ftdi (tty): 1 devices, not claimed by any device class"));
+  }
+
+  #[test]
+  fn report_includes_config_warnings() {
+    let config: Config = toml::from_str(
+      r#"
+        [[devices]]
+        name = "conbee2"
+        subsystem = "tty"
+        labels = { type = "conbee2" }
+        selector = {}
+
+        [[deviceClasses]]
+        name = "zigbee"
+        subsystem = "tty"
+        target = "zigbee"
+        selector = { matchLabels = { type = "conbee2" }, exclude = { matchLabels = { vendorr = "dresden" } } }
+      "#,
+    )
+    .unwrap();
+
+    let report = DryRunReport::new(&config, &DeviceRegistry::new());
+    assert_eq!(
+      report.warnings,
+      vec!["Device class 'zigbee' selects on label 'vendorr', which no device type sets"]
+    );
+    assert!(
+      report.to_string().starts_with(
+        "warning: Device class 'zigbee' selects on label 'vendorr', which no device type sets\n"
+      ),
+      "{}",
+      report
+    );
+    assert_eq!(
+      serde_json::to_value(&report).unwrap()["warnings"][0],
+      report.warnings[0]
+    );
+  }
+}
@@ -0,0 +1,248 @@
+use super::{command, DeviceHandle, DeviceTypeHandle, DeviceTypeRegistry};
+use crate::config::InternedString;
+use async_trait::async_trait;
+use futures::{future, stream, StreamExt};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{sync::Notify, task::JoinHandle, time};
+use tracing::{event, Level};
+
+/// Runs the health check commands of device types
+#[async_trait]
+pub trait HealthCheckRunner: Send + Sync + 'static {
+  /// Runs `command`, returning whether it succeeded within `timeout`
+  async fn run(&self, command: &[String], timeout: Duration) -> bool;
+}
+
+/// Runs health checks as child processes, killing them once they time out
+#[derive(Debug, Default)]
+pub struct CommandRunner;
+
+#[async_trait]
+impl HealthCheckRunner for CommandRunner {
+  async fn run(&self, command: &[String], timeout: Duration) -> bool {
+    match command::exec("health check", command, &[], timeout).await {
+      Ok(()) => true,
+      Err(e) => {
+        // a failing check just means an unhealthy device, which is logged when it changes
+        event!(target: "udev-device-manager", Level::DEBUG, "{}", e);
+        false
+      }
+    }
+  }
+}
+
+/// Runs the health check of a device type once for each of its devices, at most `concurrency`
+/// at a time. Returns the number of devices whose health changed.
+pub async fn check_devices(
+  runner: &dyn HealthCheckRunner,
+  device_type: &DeviceTypeHandle,
+  concurrency: usize,
+) -> usize {
+  let config = device_type.config();
+  let check = match config.health_check() {
+    Some(check) => check,
+    None => return 0,
+  };
+
+  // shared devices have a handle per slot, but are only checked once
+  let mut devices: BTreeMap<InternedString, Vec<DeviceHandle>> = BTreeMap::new();
+  for handle in device_type.devices() {
+    devices
+      .entry(handle.config().syspath())
+      .or_default()
+      .push(handle);
+  }
+
+  stream::iter(devices.into_values())
+    .map(|handles| async move {
+      let device = handles[0].config();
+      let command = check.command_for(&device);
+      let healthy = runner.run(&command, check.timeout()).await;
+      // every handle needs updating, so this mustn't stop at the first change
+      let mut changed = false;
+      for handle in &handles {
+        changed |= handle.set_check_failed(!healthy);
+      }
+
+      match (changed, healthy) {
+        (false, _) => (),
+        (true, true) => event!(
+          target: "udev-device-manager",
+          Level::INFO,
+          device_type.name = %config.name(),
+          device.syspath = %device.syspath(),
+          "device health check passes again"
+        ),
+        (true, false) => event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          device_type.name = %config.name(),
+          device.syspath = %device.syspath(),
+          "device health check failed, advertising it as unhealthy"
+        ),
+      }
+
+      changed
+    })
+    .buffer_unordered(concurrency.max(1))
+    .fold(0, |transitions, changed| {
+      future::ready(transitions + changed as usize)
+    })
+    .await
+}
+
+/// Periodically runs the health checks of all device types, each on its own interval
+pub struct HealthChecks {
+  runner: Arc<dyn HealthCheckRunner>,
+  concurrency: usize,
+  transitions: Arc<Notify>,
+  tasks: Vec<JoinHandle<()>>,
+}
+
+impl HealthChecks {
+  pub fn new(runner: Arc<dyn HealthCheckRunner>, concurrency: usize) -> Self {
+    Self {
+      runner,
+      concurrency,
+      transitions: Arc::new(Notify::new()),
+      tasks: Vec::new(),
+    }
+  }
+
+  /// Notified whenever a health check changed the health of a device
+  pub fn transitions(&self) -> Arc<Notify> {
+    self.transitions.clone()
+  }
+
+  /// Replaces the running checks with those of the given device types
+  pub fn restart(&mut self, device_types: &DeviceTypeRegistry) {
+    self.stop();
+
+    for device_type in device_types.device_types() {
+      let period = match device_type.config().health_check() {
+        Some(check) => check.interval(),
+        None => continue,
+      };
+
+      let device_type = device_type.clone();
+      let runner = self.runner.clone();
+      let transitions = self.transitions.clone();
+      let concurrency = self.concurrency;
+      self.tasks.push(tokio::spawn(async move {
+        let mut interval = time::interval_at(time::Instant::now() + period, period);
+        loop {
+          interval.tick().await;
+          if check_devices(&*runner, &device_type, concurrency).await > 0 {
+            transitions.notify_one();
+          }
+        }
+      }));
+    }
+  }
+
+  fn stop(&mut self) {
+    for task in self.tasks.drain(..) {
+      task.abort();
+    }
+  }
+}
+
+impl Drop for HealthChecks {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    app::device_registry::DeviceRegistry,
+    config::DeviceType,
+    udev::{UdevDevice, UdevEvent},
+  };
+  use kubelet_deviceplugin_proto::v1beta1;
+  use serde_json::json;
+  use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  };
+
+  #[derive(Default)]
+  struct MockRunner {
+    failing: AtomicBool,
+    commands: Mutex<Vec<Vec<String>>>,
+  }
+
+  #[async_trait]
+  impl HealthCheckRunner for MockRunner {
+    async fn run(&self, command: &[String], _timeout: Duration) -> bool {
+      self.commands.lock().unwrap().push(command.to_vec());
+      !self.failing.load(Ordering::SeqCst)
+    }
+  }
+
+  #[tokio::test]
+  async fn command_runner_passes_only_successful_commands() {
+    let command = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    let timeout = Duration::from_secs(5);
+
+    assert!(CommandRunner.run(&command(&["true"]), timeout).await);
+    assert!(!CommandRunner.run(&command(&["false"]), timeout).await);
+    assert!(!CommandRunner.run(&command(&[]), timeout).await);
+    assert!(
+      !CommandRunner
+        .run(&command(&["sleep", "5"]), Duration::from_millis(50))
+        .await
+    );
+  }
+
+  #[tokio::test]
+  async fn failing_health_check_flips_device_unhealthy() {
+    let device_type: DeviceType = serde_json::from_value(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "labels": {},
+      "selector": {},
+      "healthCheck": { "command": ["probe", "${devnode}"] },
+    }))
+    .unwrap();
+
+    let mut devices = DeviceRegistry::new();
+    devices.update(UdevEvent::Add(UdevDevice::from_parts(
+      "tty",
+      "/sys/devices/a",
+      "/dev/ttyACM0",
+      vec![],
+    )));
+    let registry = DeviceTypeRegistry::new(&[device_type]);
+    registry.reconcile(&devices);
+    let device_type = registry.device_types().next().unwrap();
+    let advertised = || {
+      device_type
+        .devices()
+        .into_iter()
+        .map(|d| d.advertised().health.clone())
+        .collect::<Vec<_>>()
+    };
+
+    let runner = MockRunner::default();
+    assert_eq!(check_devices(&runner, device_type, 4).await, 0);
+    assert_eq!(advertised(), vec![v1beta1::DeviceHealth::Healthy]);
+    assert_eq!(
+      *runner.commands.lock().unwrap(),
+      vec![vec!["probe".to_string(), "/dev/ttyACM0".to_string()]]
+    );
+
+    runner.failing.store(true, Ordering::SeqCst);
+    assert_eq!(check_devices(&runner, device_type, 4).await, 1);
+    assert_eq!(advertised(), vec![v1beta1::DeviceHealth::Unhealthy]);
+
+    // only transitions count
+    assert_eq!(check_devices(&runner, device_type, 4).await, 0);
+
+    runner.failing.store(false, Ordering::SeqCst);
+    assert_eq!(check_devices(&runner, device_type, 4).await, 1);
+    assert_eq!(advertised(), vec![v1beta1::DeviceHealth::Healthy]);
+  }
+}
@@ -0,0 +1,56 @@
+use hyper::{
+  service::{make_service_fn, service_fn},
+  Body, Request, Response, Server,
+};
+use std::{convert::Infallible, future, net::SocketAddr, sync::Arc};
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+/// Minimal HTTP server answering every request with `respond`, until dropped
+pub struct HttpServer {
+  addr: SocketAddr,
+  task: JoinHandle<()>,
+}
+
+impl HttpServer {
+  /// Binds `addr` and serves in the background. `name` is only used to log failures.
+  pub fn start<F>(name: &'static str, addr: SocketAddr, respond: F) -> Result<Self, hyper::Error>
+  where
+    F: Fn(&Request<Body>) -> Response<Body> + Send + Sync + 'static,
+  {
+    let respond = Arc::new(respond);
+    let make_service = make_service_fn(move |_| {
+      let respond = respond.clone();
+      future::ready(Ok::<_, Infallible>(service_fn(move |request| {
+        future::ready(Ok::<_, Infallible>(respond(&request)))
+      })))
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    let addr = server.local_addr();
+    let task = tokio::spawn(async move {
+      if let Err(e) = server.await {
+        event!(
+          target: "udev-device-manager",
+          Level::ERROR,
+          "{} server failed: {}",
+          name,
+          e
+        );
+      }
+    });
+
+    Ok(Self { addr, task })
+  }
+
+  /// Address the server listens on
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+}
+
+impl Drop for HttpServer {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
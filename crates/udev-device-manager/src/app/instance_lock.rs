@@ -0,0 +1,104 @@
+use std::{
+  fs::{File, OpenOptions},
+  io::{self, Read, Seek, SeekFrom, Write},
+  os::unix::io::AsRawFd,
+  path::PathBuf,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum InstanceLockError {
+  #[error(
+    "Another udev-device-manager instance{} already holds '{}', only one may run per node",
+    .holder.map(|pid| format!(" (pid {})", pid)).unwrap_or_default(),
+    .path.display()
+  )]
+  AlreadyRunning { path: PathBuf, holder: Option<u32> },
+
+  #[error("Failed to lock '{}': {1}", .0.display())]
+  Io(PathBuf, #[source] io::Error),
+}
+
+/// Node-wide lock held for the lifetime of the manager, so a second instance accidentally
+/// deployed on the same node exits instead of fighting over the plugin sockets.
+///
+/// Uses an advisory `flock` on a regular file, the kernel releases it when the holder exits,
+/// so a crashed instance never leaves a stale lock behind. The file must live outside the
+/// device plugin directory, which the kubelet empties when it restarts - a recreated file
+/// could be locked by a second instance while the first still holds the deleted one.
+#[derive(Debug)]
+pub struct InstanceLock {
+  _file: File,
+}
+
+impl InstanceLock {
+  /// Takes the lock at `path`, failing fast if another instance holds it.
+  pub fn acquire(path: impl Into<PathBuf>) -> Result<Self, InstanceLockError> {
+    let path = path.into();
+    let io_error = |e| InstanceLockError::Io(path.clone(), e);
+
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      // the running holder's pid is reported if the lock is taken, so only clear it once held
+      .truncate(false)
+      .open(&path)
+      .map_err(io_error)?;
+
+    // SAFETY: the descriptor is owned by `file`, which outlives the call
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if result != 0 {
+      let error = io::Error::last_os_error();
+      return match error.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Err(InstanceLockError::AlreadyRunning {
+          holder: read_holder(&mut file),
+          path: path.clone(),
+        }),
+        _ => Err(io_error(error)),
+      };
+    }
+
+    write_holder(&mut file).map_err(io_error)?;
+    Ok(Self { _file: file })
+  }
+}
+
+fn read_holder(file: &mut File) -> Option<u32> {
+  let mut contents = String::new();
+  file.read_to_string(&mut contents).ok()?;
+  contents.trim().parse().ok()
+}
+
+fn write_holder(file: &mut File) -> io::Result<()> {
+  file.set_len(0)?;
+  file.seek(SeekFrom::Start(0))?;
+  write!(file, "{}", std::process::id())?;
+  file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn second_instance_fails_fast() {
+    let dir = std::env::temp_dir().join(format!("udev-device-manager-lock-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let lock_file = dir.join("udev-device-manager.lock");
+
+    let first = InstanceLock::acquire(&lock_file).unwrap();
+    match InstanceLock::acquire(&lock_file) {
+      Err(InstanceLockError::AlreadyRunning { path, holder }) => {
+        assert_eq!(path, lock_file);
+        assert_eq!(holder, Some(std::process::id()));
+      }
+      other => panic!("expected the lock to be held, got {:?}", other),
+    }
+
+    drop(first);
+    assert!(InstanceLock::acquire(&lock_file).is_ok());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,400 @@
+use super::{args::LogFormat, dry_run::DryRunReport, DeviceRegistry};
+use crate::{
+  config::{Config, ConfigFormat},
+  udev::{UdevDevice, UdevEvent},
+};
+use color_eyre::{
+  eyre::{eyre, WrapErr},
+  Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, path::Path};
+
+/// Synthetic devices to match a config against, along with what each is expected to land in
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MatchFixture {
+  pub devices: Vec<FixtureDevice>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FixtureDevice {
+  pub subsystem: String,
+
+  /// Device type within the subsystem, e.g. `disk` or `partition` for `block`
+  #[serde(default)]
+  pub devtype: Option<String>,
+
+  #[serde(default)]
+  pub devnode: String,
+
+  /// Defaults to a syspath derived from the position of the device in the fixture
+  #[serde(default)]
+  pub syspath: Option<String>,
+
+  #[serde(default)]
+  pub attributes: BTreeMap<String, String>,
+
+  #[serde(default)]
+  pub expect: Expectation,
+}
+
+/// Assertions on a fixture device. Unset lists aren't checked, set ones have to match exactly,
+/// in any order.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Expectation {
+  /// Device types matching the device
+  pub device_types: Option<Vec<String>>,
+
+  /// Device classes claiming the device
+  pub device_classes: Option<Vec<String>>,
+}
+
+/// Where each fixture device landed, and which of its assertions failed
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchTestReport {
+  pub devices: Vec<DeviceMatches>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceMatches {
+  pub syspath: String,
+  pub devnode: String,
+  pub subsystem: String,
+  pub device_types: Vec<String>,
+  pub device_classes: Vec<String>,
+  pub failures: Vec<String>,
+}
+
+impl FixtureDevice {
+  fn to_device(&self, index: usize) -> UdevDevice {
+    let syspath = match &self.syspath {
+      Some(syspath) => syspath.clone(),
+      None => format!("/sys/devices/fixture/{}", index),
+    };
+
+    let mut builder = UdevDevice::builder(&self.subsystem, &syspath).devnode(&self.devnode);
+    if let Some(devtype) = &self.devtype {
+      builder = builder.devtype(devtype);
+    }
+
+    self
+      .attributes
+      .iter()
+      .fold(builder, |builder, (name, value)| {
+        builder.attribute(name, value)
+      })
+      .build()
+  }
+}
+
+impl MatchTestReport {
+  /// Matches the fixture devices against the config the same way a dry run matches real ones
+  pub fn new(config: &Config, fixture: &MatchFixture) -> Self {
+    let devices = fixture
+      .devices
+      .iter()
+      .enumerate()
+      .map(|(index, device)| device.to_device(index))
+      .collect::<Vec<_>>();
+
+    let mut registry = DeviceRegistry::new();
+    for device in &devices {
+      registry.update(UdevEvent::Add(device.clone()));
+    }
+
+    let mut matches = BTreeMap::<String, (Vec<String>, Vec<String>)>::new();
+    for device_type in DryRunReport::new(config, &registry).device_types {
+      for device in &device_type.devices {
+        let (device_types, device_classes) = matches.entry(device.syspath.clone()).or_default();
+        device_types.push(device_type.name.clone());
+        device_classes.extend(device_type.device_class.clone());
+      }
+    }
+
+    let devices = fixture
+      .devices
+      .iter()
+      .zip(&devices)
+      .map(|(expected, device)| {
+        let (mut device_types, mut device_classes) = matches
+          .remove(device.syspath().as_str())
+          .unwrap_or_default();
+        sort_unique(&mut device_types);
+        sort_unique(&mut device_classes);
+
+        let mut failures = Vec::new();
+        check(
+          "device types",
+          &expected.expect.device_types,
+          &device_types,
+          &mut failures,
+        );
+        check(
+          "device classes",
+          &expected.expect.device_classes,
+          &device_classes,
+          &mut failures,
+        );
+
+        DeviceMatches {
+          syspath: device.syspath().to_string(),
+          devnode: device.devnode().to_string(),
+          subsystem: device.subsystem().to_string(),
+          device_types,
+          device_classes,
+          failures,
+        }
+      })
+      .collect();
+
+    Self { devices }
+  }
+
+  /// Number of devices with failed assertions
+  pub fn failed(&self) -> usize {
+    self
+      .devices
+      .iter()
+      .filter(|device| !device.failures.is_empty())
+      .count()
+  }
+}
+
+fn sort_unique(names: &mut Vec<String>) {
+  names.sort();
+  names.dedup();
+}
+
+/// Records a failure unless `actual` has exactly the expected names, if any are expected
+fn check(
+  what: &str,
+  expected: &Option<Vec<String>>,
+  actual: &[String],
+  failures: &mut Vec<String>,
+) {
+  let mut expected = match expected {
+    Some(expected) => expected.clone(),
+    None => return,
+  };
+  sort_unique(&mut expected);
+
+  if expected != actual {
+    failures.push(format!(
+      "expected {} {:?}, got {:?}",
+      what, expected, actual
+    ));
+  }
+}
+
+impl fmt::Display for MatchTestReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let list = |names: &[String]| match names.is_empty() {
+      true => "none".to_owned(),
+      false => names.join(", "),
+    };
+
+    for device in &self.devices {
+      writeln!(
+        f,
+        "{} {} ({}): device types {}, device classes {}",
+        device.syspath,
+        device.devnode,
+        device.subsystem,
+        list(&device.device_types),
+        list(&device.device_classes)
+      )?;
+
+      for failure in &device.failures {
+        writeln!(f, "  FAILED: {}", failure)?;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// Matches the devices of a fixture file against the config, prints where each landed and
+/// fails if any of the fixture's assertions do
+pub async fn run(
+  config_file: &Path,
+  format: ConfigFormat,
+  fixture_file: &Path,
+  log_format: LogFormat,
+) -> Result<()> {
+  let config = Config::read(config_file, format).await?;
+  let fixture = tokio::fs::read(fixture_file).await.wrap_err_with(|| {
+    format!(
+      "Failed to read match test fixture {}",
+      fixture_file.display()
+    )
+  })?;
+  let fixture: MatchFixture = serde_json::from_slice(&fixture)
+    .wrap_err_with(|| format!("Invalid match test fixture {}", fixture_file.display()))?;
+
+  let report = MatchTestReport::new(&config, &fixture);
+  match log_format {
+    LogFormat::Pretty => print!("{}", report),
+    LogFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+  }
+
+  match report.failed() {
+    0 => Ok(()),
+    failed => Err(eyre!(
+      "{} of {} fixture devices failed their assertions",
+      failed,
+      report.devices.len()
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn config() -> Config {
+    toml::from_str(
+      r#"
+        [[devices]]
+        name = "conbee2"
+        subsystem = "tty"
+        labels = { type = "conbee2" }
+        selector = { matchAttributes = { idVendor = "1cf1" } }
+
+        [[devices]]
+        name = "ftdi"
+        subsystem = "tty"
+        labels = { type = "ftdi" }
+        selector = { matchAttributes = { idVendor = "0403" } }
+
+        [[deviceClasses]]
+        name = "zigbee"
+        subsystem = "tty"
+        target = "zigbee"
+        selector = { matchLabels = { type = "conbee2" } }
+      "#,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn fixture_devices_are_matched_and_checked() {
+    let fixture: MatchFixture = serde_json::from_value(json!({
+      "devices": [
+        {
+          "subsystem": "tty",
+          "devnode": "/dev/ttyACM0",
+          "attributes": { "idVendor": "1cf1" },
+          "expect": { "deviceTypes": ["conbee2"], "deviceClasses": ["zigbee"] },
+        },
+        {
+          "subsystem": "tty",
+          "devnode": "/dev/ttyUSB0",
+          "attributes": { "idVendor": "0403" },
+          "expect": { "deviceClasses": ["zigbee"] },
+        },
+        {
+          "subsystem": "tty",
+          "devnode": "/dev/ttyS0",
+          "expect": { "deviceTypes": [] },
+        },
+      ],
+    }))
+    .unwrap();
+
+    let report = MatchTestReport::new(&config(), &fixture);
+    let summary = report
+      .devices
+      .iter()
+      .map(|d| {
+        (
+          d.devnode.as_str(),
+          d.device_types.clone(),
+          d.device_classes.clone(),
+          d.failures.len(),
+        )
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      summary,
+      vec![
+        (
+          "/dev/ttyACM0",
+          vec!["conbee2".to_owned()],
+          vec!["zigbee".to_owned()],
+          0
+        ),
+        ("/dev/ttyUSB0", vec!["ftdi".to_owned()], vec![], 1),
+        ("/dev/ttyS0", vec![], vec![], 0),
+      ]
+    );
+    assert_eq!(report.failed(), 1);
+    assert_eq!(
+      report.devices[1].failures,
+      vec![r#"expected device classes ["zigbee"], got []"#]
+    );
+
+    let text = report.to_string();
+    assert!(
+      text.starts_with(
+        "/sys/devices/fixture/0 /dev/ttyACM0 (tty): device types conbee2, device classes zigbee\n"
+      ),
+      "{}",
+      text
+    );
+    assert!(
+      text.contains("  FAILED: expected device classes"),
+      "{}",
+      text
+    );
+  }
+
+  #[test]
+  fn fixture_devices_can_have_a_devtype() {
+    let config: Config = toml::from_str(
+      r#"
+        deviceClasses = []
+
+        [[devices]]
+        name = "disk"
+        subsystem = "block"
+        labels = { type = "disk" }
+        selector = { matchExpressions = [{ key = "@devtype", operator = "In", values = ["disk"] }] }
+      "#,
+    )
+    .unwrap();
+    let fixture: MatchFixture = serde_json::from_value(json!({
+      "devices": [
+        {
+          "subsystem": "block",
+          "devtype": "disk",
+          "devnode": "/dev/sda",
+          "expect": { "deviceTypes": ["disk"] },
+        },
+        {
+          "subsystem": "block",
+          "devtype": "partition",
+          "devnode": "/dev/sda1",
+          "expect": { "deviceTypes": [] },
+        },
+      ],
+    }))
+    .unwrap();
+
+    let report = MatchTestReport::new(&config, &fixture);
+    assert_eq!(report.failed(), 0, "{}", report);
+  }
+
+  #[test]
+  fn misspelled_assertions_are_rejected() {
+    let fixture = serde_json::from_value::<MatchFixture>(json!({
+      "devices": [{ "subsystem": "tty", "expect": { "deviceType": ["conbee2"] } }],
+    }));
+    assert!(fixture.is_err());
+  }
+}
@@ -0,0 +1,131 @@
+use super::{device_class::AdvertisedResource, http::HttpServer};
+use crate::{config::ConfigMetrics, udev::UdevEvent};
+use hyper::{header::CONTENT_TYPE, Body, Request, Response, StatusCode};
+use prometheus::{
+  Encoder, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::net::SocketAddr;
+
+/// Metrics of the manager, in a registry of its own
+pub struct Metrics {
+  registry: Registry,
+  config: ConfigMetrics,
+  udev_devices: IntGauge,
+  device_types: IntGauge,
+  class_matched_devices: IntGaugeVec,
+  class_leased_devices: IntGaugeVec,
+  reconciles: IntCounter,
+  udev_events: IntCounterVec,
+}
+
+impl Metrics {
+  pub fn new() -> prometheus::Result<Self> {
+    let registry = Registry::new();
+    let config = ConfigMetrics::new(&registry)?;
+    let udev_devices = IntGauge::new("udev_devices_total", "Devices known to udev")?;
+    let device_types = IntGauge::new("device_types_total", "Enabled device types")?;
+    let class_matched_devices = IntGaugeVec::new(
+      Opts::new(
+        "device_class_matched_devices",
+        "Devices advertised by a device class, over all its resources",
+      ),
+      &["class"],
+    )?;
+    let class_leased_devices = IntGaugeVec::new(
+      Opts::new(
+        "device_class_leased_devices",
+        "Advertised devices of a device class held by an allocation, over all its resources",
+      ),
+      &["class"],
+    )?;
+    let reconciles = IntCounter::new("reconciles_total", "Reconciles run")?;
+    let udev_events = IntCounterVec::new(
+      Opts::new("udev_events_total", "Udev events received, by type"),
+      &["type"],
+    )?;
+
+    registry.register(Box::new(udev_devices.clone()))?;
+    registry.register(Box::new(device_types.clone()))?;
+    registry.register(Box::new(class_matched_devices.clone()))?;
+    registry.register(Box::new(class_leased_devices.clone()))?;
+    registry.register(Box::new(reconciles.clone()))?;
+    registry.register(Box::new(udev_events.clone()))?;
+
+    Ok(Self {
+      registry,
+      config,
+      udev_devices,
+      device_types,
+      class_matched_devices,
+      class_leased_devices,
+      reconciles,
+      udev_events,
+    })
+  }
+
+  pub fn registry(&self) -> &Registry {
+    &self.registry
+  }
+
+  /// Config reload metrics, recorded by whatever reads the config
+  pub fn config(&self) -> ConfigMetrics {
+    self.config.clone()
+  }
+
+  /// Records the outcome of a reconcile
+  pub fn reconciled(
+    &self,
+    udev_devices: usize,
+    device_types: usize,
+    resources: &[AdvertisedResource],
+  ) {
+    self.reconciles.inc();
+    self.udev_devices.set(udev_devices as i64);
+    self.device_types.set(device_types as i64);
+
+    // classes that are gone shouldn't keep reporting their last count
+    self.class_matched_devices.reset();
+    self.class_leased_devices.reset();
+    for resource in resources {
+      self
+        .class_matched_devices
+        .with_label_values(&[&resource.device_class])
+        .add(resource.device_count as i64);
+      self
+        .class_leased_devices
+        .with_label_values(&[&resource.device_class])
+        .add(resource.leased_count as i64);
+    }
+  }
+
+  pub fn udev_event(&self, event: &UdevEvent) {
+    self.udev_events.with_label_values(&[event.kind()]).inc();
+  }
+}
+
+/// Starts an HTTP server serving metrics in the Prometheus text format on `/metrics`
+pub fn serve(addr: SocketAddr, registry: Registry) -> Result<HttpServer, hyper::Error> {
+  HttpServer::start("Metrics", addr, move |request| respond(&registry, request))
+}
+
+fn respond(registry: &Registry, request: &Request<Body>) -> Response<Body> {
+  if request.uri().path() != "/metrics" {
+    return Response::builder()
+      .status(StatusCode::NOT_FOUND)
+      .body(Body::empty())
+      .unwrap();
+  }
+
+  let encoder = TextEncoder::new();
+  let mut buffer = Vec::new();
+  match encoder.encode(&registry.gather(), &mut buffer) {
+    Ok(()) => Response::builder()
+      .header(CONTENT_TYPE, encoder.format_type())
+      .body(buffer.into())
+      .unwrap(),
+    Err(e) => Response::builder()
+      .status(StatusCode::INTERNAL_SERVER_ERROR)
+      .body(e.to_string().into())
+      .unwrap(),
+  }
+}
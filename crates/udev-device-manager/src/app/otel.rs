@@ -0,0 +1,52 @@
+//! Optional export of the tracing spans to an OpenTelemetry collector over OTLP.
+//! Only available with the `otel` feature, without it the layer is a no-op.
+
+use super::args::Args;
+use color_eyre::Result;
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+#[cfg(feature = "otel")]
+pub fn layer<S>(args: &Args) -> Result<Option<impl Layer<S>>>
+where
+  S: Subscriber + for<'span> LookupSpan<'span>,
+{
+  use color_eyre::eyre::WrapErr;
+  use opentelemetry::{
+    sdk::{trace, Resource},
+    KeyValue,
+  };
+
+  let endpoint = match &args.otlp_endpoint {
+    None => return Ok(None),
+    Some(endpoint) => endpoint,
+  };
+
+  let tracer = opentelemetry_otlp::new_pipeline()
+    .with_endpoint(endpoint)
+    .with_trace_config(
+      trace::config().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        env!("CARGO_PKG_NAME"),
+      )])),
+    )
+    .with_tonic()
+    .install_batch(opentelemetry::runtime::Tokio)
+    .wrap_err("Failed to install OTLP exporter")?;
+
+  Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn layer<S>(_args: &Args) -> Result<Option<impl Layer<S>>>
+where
+  S: Subscriber + for<'span> LookupSpan<'span>,
+{
+  Ok(None::<tracing_subscriber::layer::Identity>)
+}
+
+/// Flushes any spans still pending export.
+pub fn shutdown() {
+  #[cfg(feature = "otel")]
+  opentelemetry::global::shutdown_tracer_provider();
+}
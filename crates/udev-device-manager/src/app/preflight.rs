@@ -0,0 +1,117 @@
+use crate::{
+  config::{Config, ConfigError, ConfigFormat},
+  utils::AggregateErrorExt,
+};
+use color_eyre::Result;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::{fs, io};
+use tokio_udev::Enumerator;
+use tracing::{event, Level};
+
+#[derive(Debug, Error)]
+pub enum PreflightError {
+  #[error("Device plugin directory '{}' is not writable: {1}", .0.display())]
+  PluginDirNotWritable(PathBuf, #[source] io::Error),
+
+  #[error("Failed to enumerate udev devices: {0}")]
+  Udev(#[source] io::Error),
+
+  #[error("Failed to read config file '{}': {1}", .0.display())]
+  Config(PathBuf, #[source] ConfigError),
+}
+
+/// Startup self-check, verifying everything the manager needs up front so problems are
+/// reported together instead of surfacing as obscure errors later on.
+#[derive(Debug, Clone)]
+pub struct Preflight {
+  plugin_dir: PathBuf,
+  kubelet_socket: PathBuf,
+  config_file: PathBuf,
+  config_format: ConfigFormat,
+}
+
+impl Preflight {
+  pub fn new(
+    plugin_dir: impl Into<PathBuf>,
+    kubelet_socket: impl Into<PathBuf>,
+    config_file: impl Into<PathBuf>,
+    config_format: ConfigFormat,
+  ) -> Self {
+    Self {
+      plugin_dir: plugin_dir.into(),
+      kubelet_socket: kubelet_socket.into(),
+      config_file: config_file.into(),
+      config_format,
+    }
+  }
+
+  /// Runs all checks, failing with a single report listing every problem found.
+  pub async fn run(&self) -> Result<()> {
+    self.check().await.into_iter().map(Err).collect_errors()
+  }
+
+  /// Runs all checks, returning every problem found.
+  pub async fn check(&self) -> Vec<PreflightError> {
+    let mut problems = Vec::new();
+
+    if let Err(e) = check_writable(&self.plugin_dir).await {
+      problems.push(PreflightError::PluginDirNotWritable(
+        self.plugin_dir.clone(),
+        e,
+      ));
+    }
+
+    // the kubelet may simply not be up yet, registration retries once it is
+    if fs::metadata(&self.kubelet_socket).await.is_err() {
+      event!(
+        target: "udev-device-manager",
+        Level::WARN,
+        socket = %self.kubelet_socket.display(),
+        "kubelet socket not found"
+      );
+    }
+
+    if let Err(e) = Enumerator::new().and_then(|mut e| e.scan_devices().map(|_| ())) {
+      problems.push(PreflightError::Udev(e));
+    }
+
+    if let Err(e) = Config::read(&self.config_file, self.config_format).await {
+      problems.push(PreflightError::Config(self.config_file.clone(), e));
+    }
+
+    problems
+  }
+}
+
+async fn check_writable(dir: &Path) -> io::Result<()> {
+  let probe = dir.join(format!(".preflight-{}", std::process::id()));
+  fs::write(&probe, b"").await?;
+  fs::remove_file(&probe).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn aggregates_all_problems() {
+    let missing = std::env::temp_dir().join("udev-device-manager-preflight-missing");
+    let preflight = Preflight::new(
+      missing.join("device-plugins"),
+      missing.join("device-plugins/kubelet.sock"),
+      missing.join("config.toml"),
+      ConfigFormat::Auto,
+    );
+
+    let problems = preflight.check().await;
+    assert!(problems
+      .iter()
+      .any(|p| matches!(p, PreflightError::PluginDirNotWritable(..))));
+    assert!(problems
+      .iter()
+      .any(|p| matches!(p, PreflightError::Config(..))));
+
+    assert!(preflight.run().await.is_err());
+  }
+}
@@ -0,0 +1,71 @@
+use super::http::HttpServer;
+use hyper::{Body, Request, Response, StatusCode};
+use std::{
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+use tracing::{event, Level};
+
+/// Liveness and readiness of the manager, as reported to Kubernetes
+#[derive(Debug, Default)]
+pub struct Probes {
+  live: AtomicBool,
+  ready: AtomicBool,
+}
+
+impl Probes {
+  /// Marks the main loop as running
+  pub fn set_live(&self, live: bool) {
+    self.live.store(live, Ordering::SeqCst);
+  }
+
+  /// Marks the config as loaded and the devices as reconciled
+  pub fn set_ready(&self, ready: bool) {
+    if self.ready.swap(ready, Ordering::SeqCst) != ready {
+      event!(
+        target: "udev-device-manager",
+        Level::INFO,
+        ready,
+        "readiness changed"
+      );
+    }
+  }
+
+  fn is_live(&self) -> bool {
+    self.live.load(Ordering::SeqCst)
+  }
+
+  fn is_ready(&self) -> bool {
+    self.ready.load(Ordering::SeqCst)
+  }
+}
+
+/// Starts an HTTP server for Kubernetes probes, serving `/livez` and `/readyz`
+pub fn serve(addr: SocketAddr, probes: Arc<Probes>) -> Result<HttpServer, hyper::Error> {
+  HttpServer::start("Probe", addr, move |request| respond(&probes, request))
+}
+
+fn respond(probes: &Probes, request: &Request<Body>) -> Response<Body> {
+  let passing = match request.uri().path() {
+    "/livez" => probes.is_live(),
+    "/readyz" => probes.is_ready(),
+    _ => {
+      return Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::empty())
+        .unwrap()
+    }
+  };
+
+  let (status, body) = match passing {
+    true => (StatusCode::OK, "ok"),
+    false => (StatusCode::SERVICE_UNAVAILABLE, "not ok"),
+  };
+  Response::builder()
+    .status(status)
+    .body(body.into())
+    .unwrap()
+}
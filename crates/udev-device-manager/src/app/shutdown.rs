@@ -0,0 +1,55 @@
+use crate::signals::Signal;
+use color_eyre::Report;
+use std::fmt;
+
+/// Why the manager stopped, logged once on exit to ease post-mortems.
+///
+/// Failures carry their reason as error context (`.context(ShutdownReason::..)`), so it
+/// can be recovered from the final report with [`ShutdownReason::of`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+  /// A termination signal was received
+  Signal(Signal),
+
+  /// The signal stream ended unexpectedly
+  SignalStreamClosed,
+
+  /// The config file watcher ended unexpectedly
+  ConfigWatcherClosed,
+
+  /// The udev event stream ended or failed
+  UdevStreamError,
+
+  /// Applying a reloaded config (or a SIGHUP restart) failed
+  ReloadFailed,
+
+  /// The startup self-check failed
+  PreflightFailed,
+
+  /// Any other error
+  Error,
+}
+
+impl ShutdownReason {
+  /// The reason attached to a failure, or [`ShutdownReason::Error`] if it carries none.
+  pub fn of(report: &Report) -> Self {
+    report
+      .downcast_ref::<Self>()
+      .copied()
+      .unwrap_or(Self::Error)
+  }
+}
+
+impl fmt::Display for ShutdownReason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Signal(signal) => write!(f, "received {}", signal),
+      Self::SignalStreamClosed => f.write_str("signal stream closed"),
+      Self::ConfigWatcherClosed => f.write_str("config watcher closed"),
+      Self::UdevStreamError => f.write_str("udev stream error"),
+      Self::ReloadFailed => f.write_str("reload failed"),
+      Self::PreflightFailed => f.write_str("preflight checks failed"),
+      Self::Error => f.write_str("error"),
+    }
+  }
+}
@@ -1,31 +1,82 @@
 mod device_class;
 mod device_type;
+mod diff;
+mod manual_device;
+mod metrics;
 mod parse;
+mod permissions;
+mod schema;
 mod selector;
 mod string;
+mod template;
+mod validate;
 mod watch;
 
 use futures::Stream;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{fmt, path::Path, sync::Arc};
+use std::{collections::BTreeSet, fmt, path::Path, sync::Arc};
+use tokio::fs;
 
-pub use device_class::{DeviceClass, DeviceTypeSelector};
-pub use device_type::{DeviceAccess, DeviceType, DeviceTypeLabels};
+pub use device_class::{AllocateHook, DeviceClass, DeviceTypeSelector, UnexpectedCount};
+pub use device_type::{
+  DeviceAccess, DeviceIdScheme, DeviceType, DeviceTypeLabels, UnauthorizedDevices,
+};
+pub use diff::ConfigDiff;
+pub use manual_device::ManualDevice;
+pub use metrics::ConfigMetrics;
 pub use parse::{ConfigError, ConfigFormat, FormatError};
-pub use selector::{MatchResult, Mismatch};
+pub use permissions::{DevicePermissions, PermissionDefaults};
+pub use schema::config_schema;
+pub use selector::{MatchResult, Mismatch, SelectorRequirement, SelectorValueRequirement};
 pub use string::InternedString;
+pub use validate::ConfigValidationError;
 pub use watch::ConfigWatcherError;
 
 mod inner {
   use super::*;
 
-  #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+  #[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
   #[serde(rename_all = "camelCase")]
   pub(super) struct Config {
-    #[serde(rename = "devices")]
-    pub(super) device_typess: Vec<DeviceType>,
+    #[serde(
+      default,
+      rename = "devices",
+      alias = "deviceTypes",
+      alias = "device_types"
+    )]
+    pub(super) device_types: Vec<DeviceType>,
 
+    #[serde(default, alias = "device_classes")]
     pub(super) device_classes: Vec<DeviceClass>,
+
+    /// Devices served as if udev found them
+    #[serde(
+      default,
+      alias = "manual_devices",
+      skip_serializing_if = "Vec::is_empty"
+    )]
+    pub(super) manual_devices: Vec<ManualDevice>,
+
+    /// Advertise no devices at all, while keeping the plugins registered
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(super) maintenance: bool,
+
+    /// Device permissions of classes which don't set their own
+    #[serde(default, skip_serializing_if = "PermissionDefaults::is_default")]
+    pub(super) permissions: PermissionDefaults,
+  }
+
+  impl Config {
+    /// Appends the entries of a config read after this one. Maintenance mode is on if any
+    /// config turns it on, and permission defaults set by the later config win.
+    pub(super) fn merge(&mut self, other: Config) {
+      self.device_types.extend(other.device_types);
+      self.device_classes.extend(other.device_classes);
+      self.manual_devices.extend(other.manual_devices);
+      self.maintenance |= other.maintenance;
+      self.permissions.merge(other.permissions);
+    }
   }
 }
 
@@ -37,17 +88,48 @@ pub struct Config {
 impl Config {
   /// Device types
   pub fn device_types(&self) -> &[DeviceType] {
-    &self.inner.device_typess
+    &self.inner.device_types
   }
 
   /// Device classes (handlers)
   pub fn device_classes(&self) -> &[DeviceClass] {
     &self.inner.device_classes
   }
+
+  /// Devices declared in the config, registered alongside the ones found through udev
+  pub fn manual_devices(&self) -> &[ManualDevice] {
+    &self.inner.manual_devices
+  }
+
+  /// Subsystems of the enabled device types - the only devices the config cares about
+  pub fn subsystems(&self) -> BTreeSet<InternedString> {
+    self
+      .device_types()
+      .iter()
+      .filter(|ty| ty.enabled())
+      .map(|ty| ty.subsystem())
+      .collect()
+  }
+
+  /// Maintenance mode, used to drain a node's devices (e.g. ahead of a reboot). All plugins
+  /// stay registered, but advertise zero devices and refuse allocations.
+  pub fn maintenance(&self) -> bool {
+    self.inner.maintenance
+  }
+
+  /// What changed from this config to `new`
+  pub fn diff(&self, new: &Config) -> ConfigDiff {
+    ConfigDiff::new(self, new)
+  }
 }
 
 impl From<inner::Config> for Config {
-  fn from(inner: inner::Config) -> Self {
+  fn from(mut inner: inner::Config) -> Self {
+    for class in &mut inner.device_classes {
+      let permissions = inner.permissions.for_subsystem(class.subsystem());
+      *class = class.with_default_permissions(permissions);
+    }
+
     Self {
       inner: Arc::new(inner),
     }
@@ -78,15 +160,43 @@ impl<'de> Deserialize<'de> for Config {
   }
 }
 
+/// Serde default for the `enabled` flag of device types and classes
+fn default_enabled() -> bool {
+  true
+}
+
+/// Only disabled entries need the `enabled` flag serialized
+fn is_enabled(enabled: &bool) -> bool {
+  *enabled
+}
+
 impl Config {
-  pub async fn read(file: impl AsRef<Path>, format: ConfigFormat) -> Result<Config, ConfigError> {
-    parse::read_config(file, format).await
+  /// Reads a config file, or a directory of them (see [`Config::read_dir`])
+  pub async fn read(path: impl AsRef<Path>, format: ConfigFormat) -> Result<Config, ConfigError> {
+    let path = path.as_ref();
+    match fs::metadata(path).await?.is_dir() {
+      true => Self::read_dir(path, format).await,
+      false => parse::read_config(path, format).await,
+    }
+  }
+
+  /// Reads every `*.json`, `*.yaml` and `*.toml` file in `dir` in lexical order, merged into
+  /// one config. Files are parsed by their extension, so `format` must be auto. Validation
+  /// happens on the merged config, so e.g. two files defining the same device type are
+  /// rejected.
+  pub async fn read_dir(
+    dir: impl AsRef<Path>,
+    format: ConfigFormat,
+  ) -> Result<Config, ConfigError> {
+    parse::read_config_dir(dir, format).await
   }
 
+  /// Watches the config file for changes, recording each reload in `metrics`
   pub fn watch(
     file: impl AsRef<Path>,
     format: ConfigFormat,
+    metrics: ConfigMetrics,
   ) -> Result<impl Stream<Item = Result<Config, ConfigError>>, ConfigWatcherError> {
-    watch::watch(file, format)
+    watch::watch(file, format, metrics)
   }
 }
@@ -1,15 +1,33 @@
+mod allocate_hook;
+mod expected_count;
+mod mount;
+mod resource_name;
 mod selector;
+mod socket_mode;
 
-use super::{DeviceType, InternedString, MatchResult};
+use super::{template, DevicePermissions, DeviceType, InternedString, MatchResult};
+use crate::udev::UdevDevice;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
-use std::{fmt, sync::Arc};
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt, iter,
+  sync::Arc,
+  time::Duration,
+};
 
+pub use allocate_hook::AllocateHook;
+pub use expected_count::{ExpectedCount, UnexpectedCount};
+pub use mount::MountSpec;
+pub use resource_name::ResourceName;
 pub use selector::DeviceTypeSelector;
+pub use socket_mode::SocketMode;
 
 mod inner {
   use super::*;
 
-  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+  #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+  #[serde(rename_all = "camelCase")]
   pub(super) struct DeviceClass {
     /// Device class subsystem
     pub subsystem: InternedString,
@@ -20,8 +38,145 @@ mod inner {
     /// Device class target
     pub target: InternedString,
 
+    /// Disabled device classes are parsed, but advertise nothing
+    #[serde(
+      default = "crate::config::default_enabled",
+      skip_serializing_if = "crate::config::is_enabled"
+    )]
+    pub enabled: bool,
+
     /// Selector to match against device groups
     pub selector: DeviceTypeSelector,
+
+    /// Resource name advertised to the kubelet, instead of `udev/{subsystem}/{name}`
+    #[serde(
+      default,
+      alias = "resource_name",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub resource_name: Option<ResourceName>,
+
+    /// Label to group matched device types by - one resource is advertised per distinct value
+    #[serde(default, alias = "group_by", skip_serializing_if = "Option::is_none")]
+    pub group_by: Option<InternedString>,
+
+    /// Template for the path allocated devices get inside the container
+    #[serde(
+      default,
+      alias = "container_path",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub container_path: Option<InternedString>,
+
+    /// Annotations added to containers allocated devices of this class
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<InternedString, InternedString>,
+
+    /// Tenant allocations of this class are attributed to, defaults to the class target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<InternedString>,
+
+    /// Environment variables set in containers allocated devices of this class. Values support
+    /// `${devnode}`, `${syspath}` and `${attr:NAME}`, expanding to the values of the allocated
+    /// devices, comma separated; `${DEVNODE}` is kept as an alias of `${devnode}`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub envs: BTreeMap<InternedString, InternedString>,
+
+    /// Host paths mounted into containers allocated devices of this class
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<MountSpec>,
+
+    /// Command run for every container allocated devices of this class
+    #[serde(
+      default,
+      alias = "allocate_hook",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub allocate_hook: Option<AllocateHook>,
+
+    /// Have the kubelet call PreStartContainer, resetting devices before each container start
+    #[serde(
+      default,
+      alias = "pre_start_reset",
+      skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub pre_start_reset: bool,
+
+    /// Command resetting a single device, run for each device on PreStartContainer
+    #[serde(
+      default,
+      alias = "reset_command",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub reset_command: Option<Vec<InternedString>>,
+
+    /// Advertise a device once per matching device type, instead of once per class
+    #[serde(
+      default,
+      alias = "allow_duplicates",
+      skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub allow_duplicates: bool,
+
+    /// Seconds the first ListAndWatch response waits for the initial reconcile
+    #[serde(
+      default,
+      alias = "initial_list_timeout",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub initial_list_timeout: Option<u64>,
+
+    /// Seconds after which ListAndWatch streams send the current device list again
+    #[serde(
+      default,
+      alias = "heartbeat_interval",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub heartbeat_interval: Option<u64>,
+
+    /// Answer GetPreferredAllocation, keeping allocations on as few NUMA nodes as possible
+    #[serde(
+      default,
+      alias = "prefer_numa_alignment",
+      skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub prefer_numa_alignment: bool,
+
+    /// Number of devices each resource of the class is expected to advertise
+    #[serde(
+      default,
+      alias = "expected_count",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub expected_count: Option<ExpectedCount>,
+
+    /// What to do while a resource doesn't advertise the expected number of devices
+    #[serde(default, alias = "on_unexpected_count")]
+    pub on_unexpected_count: UnexpectedCount,
+
+    /// Cgroup permissions on allocated devices, overriding the subsystem default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<DevicePermissions>,
+
+    /// Permissions used when the class sets none, resolved from the config's defaults
+    #[serde(skip)]
+    pub default_permissions: DevicePermissions,
+
+    /// Mode of the plugin sockets, as an octal string like "0660"
+    #[serde(
+      default,
+      alias = "socket_mode",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub socket_mode: Option<SocketMode>,
+
+    /// Allow a socketMode letting anyone on the node connect to the plugin sockets
+    #[serde(
+      default,
+      alias = "allow_world_writable_socket",
+      skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub allow_world_writable_socket: bool,
   }
 }
 
@@ -46,11 +201,240 @@ impl DeviceClass {
     self.inner.target
   }
 
+  /// Whether the device class is started and advertises resources
+  pub fn enabled(&self) -> bool {
+    self.inner.enabled
+  }
+
   /// Selector for filtering out udev devices
   pub fn selector(&self) -> &DeviceTypeSelector {
     &self.inner.selector
   }
 
+  /// Label to group matched device types by
+  pub fn group_by(&self) -> Option<InternedString> {
+    self.inner.group_by
+  }
+
+  /// Whether a device matched by several device types of the class is advertised once per
+  /// device type. By default it's only advertised for the first one.
+  pub fn allow_duplicates(&self) -> bool {
+    self.inner.allow_duplicates
+  }
+
+  /// How long the first ListAndWatch response is held back waiting for the initial reconcile,
+  /// so the kubelet isn't briefly shown an empty device list. Not held back when unset.
+  pub fn initial_list_timeout(&self) -> Option<Duration> {
+    self.inner.initial_list_timeout.map(Duration::from_secs)
+  }
+
+  /// How often ListAndWatch streams repeat the current device list when nothing changed. The
+  /// write fails on a connection the kubelet dropped without closing it, ending the stream
+  /// instead of leaving it to linger. Never repeated when unset.
+  pub fn heartbeat_interval(&self) -> Option<Duration> {
+    self
+      .inner
+      .heartbeat_interval
+      .filter(|secs| *secs > 0)
+      .map(Duration::from_secs)
+  }
+
+  /// Whether the plugin tells the kubelet which devices to prefer, packing allocations onto
+  /// the fewest NUMA nodes so the topology manager can align them with CPUs and memory
+  pub fn prefer_numa_alignment(&self) -> bool {
+    self.inner.prefer_numa_alignment
+  }
+
+  /// Number of devices each resource of the class is expected to advertise, if constrained
+  pub fn expected_count(&self) -> Option<ExpectedCount> {
+    self.inner.expected_count
+  }
+
+  /// What a resource does while it doesn't advertise the expected number of devices
+  pub fn on_unexpected_count(&self) -> UnexpectedCount {
+    self.inner.on_unexpected_count
+  }
+
+  /// Command allocations have to pass, if any
+  pub fn allocate_hook(&self) -> Option<&AllocateHook> {
+    self.inner.allocate_hook.as_ref()
+  }
+
+  /// Whether the plugin asks the kubelet for a PreStartContainer call before each container
+  /// start, to reset the devices the container was allocated
+  pub fn pre_start_reset(&self) -> bool {
+    self.inner.pre_start_reset
+  }
+
+  /// Whether the class configures a command to reset its devices with
+  pub fn has_reset_command(&self) -> bool {
+    self
+      .inner
+      .reset_command
+      .as_ref()
+      .is_some_and(|command| !command.is_empty())
+  }
+
+  /// Command resetting `device`, advertised as `id`, if the class configures one. Every
+  /// argument is a template supporting `${id}`, `${devnode}`, `${syspath}` and `${attr:NAME}`.
+  pub fn reset_command(&self, device: &UdevDevice, id: &str) -> Option<Vec<String>> {
+    let command = self.inner.reset_command.as_ref()?;
+    let expand = |arg: &InternedString| {
+      template::expand(arg, |key| match key {
+        "id" => Some(id.to_string()),
+        "devnode" => Some(device.devnode().to_string()),
+        "syspath" => Some(device.syspath().to_string()),
+        _ => key
+          .strip_prefix("attr:")
+          .and_then(|name| device.attribute(name))
+          .and_then(|value| value.as_option())
+          .map(|value| value.to_string()),
+      })
+    };
+
+    Some(command.iter().map(expand).collect())
+  }
+
+  /// Cgroup permissions containers get on allocated devices
+  pub fn permissions(&self) -> DevicePermissions {
+    self
+      .inner
+      .permissions
+      .unwrap_or(self.inner.default_permissions)
+  }
+
+  /// Permissions the plugin sockets are created with, left to the umask when unset
+  pub fn socket_mode(&self) -> Option<SocketMode> {
+    self.inner.socket_mode
+  }
+
+  /// Whether the class explicitly allows a world-writable `socketMode`
+  pub fn allow_world_writable_socket(&self) -> bool {
+    self.inner.allow_world_writable_socket
+  }
+
+  /// The class with the permissions it falls back to when it doesn't set any
+  pub(super) fn with_default_permissions(&self, permissions: DevicePermissions) -> Self {
+    let mut inner = (*self.inner).clone();
+    inner.default_permissions = permissions;
+    inner.into()
+  }
+
+  /// Resource name advertised to the kubelet, optionally for a single group. Defaults to
+  /// `udev/{subsystem}/{name}`, unless the class configures its own.
+  pub fn resource_name(&self, group: Option<InternedString>) -> String {
+    let base = match self.inner.resource_name {
+      Some(name) => name.to_string(),
+      None => format!("udev/{}/{}", self.subsystem(), self.name()),
+    };
+
+    match group {
+      None => base,
+      Some(group) => format!("{}-{}", base, resource_name::sanitize(&group)),
+    }
+  }
+
+  /// Configured resource name, overriding the default
+  pub fn resource_name_override(&self) -> Option<ResourceName> {
+    self.inner.resource_name
+  }
+
+  /// Resource name a matching device type is advertised under. None for grouped classes if
+  /// the device type lacks the label, or its value has nothing left once sanitized.
+  pub fn resource_name_for(&self, device_type: &DeviceType) -> Option<String> {
+    match self.group_by() {
+      None => Some(self.resource_name(None)),
+      Some(label) => device_type
+        .labels()
+        .get(&label)
+        .filter(|group| !resource_name::sanitize(group).is_empty())
+        .map(|group| self.resource_name(Some(group))),
+    }
+  }
+
+  /// Path an allocated device is exposed at inside the container. Defaults to the host devnode,
+  /// otherwise expands the configured template, which supports `${index}` (the position of the
+  /// device in the allocation), `${devnode}` and `${attr:NAME}`.
+  pub fn container_path(&self, device: &UdevDevice, index: usize) -> String {
+    match self.inner.container_path {
+      None => device.devnode().to_string(),
+      Some(path) => template::expand(&path, |key| match key {
+        "index" => Some(index.to_string()),
+        "devnode" => Some(device.devnode().to_string()),
+        _ => key
+          .strip_prefix("attr:")
+          .and_then(|name| device.attribute(name))
+          .and_then(|value| value.as_option())
+          .map(|value| value.to_string()),
+      }),
+    }
+  }
+
+  /// Annotations for a container allocated devices of the given device types. The device type
+  /// annotations are merged first, so the class wins when both set the same key.
+  pub fn annotations_for<'a>(
+    &'a self,
+    device_types: impl IntoIterator<Item = &'a DeviceType>,
+  ) -> HashMap<String, String> {
+    merge(
+      device_types.into_iter().map(DeviceType::annotations),
+      &self.inner.annotations,
+    )
+  }
+
+  /// Tenant allocations of the class are attributed to, for accounting on shared nodes
+  pub fn tenant(&self) -> InternedString {
+    self.inner.tenant.unwrap_or(self.inner.target)
+  }
+
+  /// Environment variables for a container allocated the given devices of the given device
+  /// types, merged the same way as [`DeviceClass::annotations_for`]. `${devnode}` (or
+  /// `${DEVNODE}`), `${syspath}` and `${attr:NAME}` in a value expand to the values of the
+  /// allocated devices, comma separated; devices without the attribute contribute an empty value.
+  pub fn envs_for<'a>(
+    &'a self,
+    device_types: impl IntoIterator<Item = &'a DeviceType>,
+    devices: &[UdevDevice],
+  ) -> HashMap<String, String> {
+    let joined = |value: &dyn Fn(&UdevDevice) -> String| {
+      devices.iter().map(value).collect::<Vec<_>>().join(",")
+    };
+
+    let mut envs = merge(
+      device_types.into_iter().map(DeviceType::envs),
+      &self.inner.envs,
+    );
+    for value in envs.values_mut() {
+      *value = template::expand(value, |key| match key {
+        "devnode" | "DEVNODE" => Some(joined(&|device| device.devnode().to_string())),
+        "syspath" => Some(joined(&|device| device.syspath().to_string())),
+        _ => match key.strip_prefix("attr:") {
+          Some(name) => Some(joined(&|device| {
+            device
+              .attribute(name)
+              .and_then(|value| value.as_option())
+              .map(|value| value.to_string())
+              .unwrap_or_default()
+          })),
+          // other placeholders are left for the container to interpret
+          None => Some(format!("${{{}}}", key)),
+        },
+      });
+    }
+
+    envs
+  }
+
+  /// The configured container path template, if any
+  pub fn container_path_template(&self) -> Option<InternedString> {
+    self.inner.container_path
+  }
+
+  /// Host paths mounted into containers allocated devices of this class
+  pub fn mounts(&self) -> &[MountSpec] {
+    &self.inner.mounts
+  }
+
   pub fn match_with(&self, device_type: &DeviceType) -> MatchResult {
     let mut result = MatchResult::Matches;
 
@@ -67,10 +451,29 @@ impl DeviceClass {
     let labels = device_type.labels();
     result += self.selector().match_with(&|name| labels.get(name));
 
+    if let Some(label) = self.group_by() {
+      let group = labels.get(&label);
+      if group.is_none() {
+        result += MatchResult::expected_any(label, group);
+      }
+    }
+
     result
   }
 }
 
+/// Merges the maps in order, later maps overriding earlier ones.
+fn merge<'a>(
+  device_types: impl Iterator<Item = &'a BTreeMap<InternedString, InternedString>>,
+  class: &'a BTreeMap<InternedString, InternedString>,
+) -> HashMap<String, String> {
+  device_types
+    .chain(iter::once(class))
+    .flat_map(|values| values.iter())
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+}
+
 impl From<inner::DeviceClass> for DeviceClass {
   fn from(inner: inner::DeviceClass) -> Self {
     Self {
@@ -102,3 +505,266 @@ impl<'de> Deserialize<'de> for DeviceClass {
     <inner::DeviceClass as Deserialize>::deserialize(deserializer).map(Self::from)
   }
 }
+
+impl JsonSchema for DeviceClass {
+  fn schema_name() -> String {
+    stringify!(DeviceClass).into()
+  }
+
+  fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+    <inner::DeviceClass as JsonSchema>::json_schema(gen)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use std::collections::BTreeSet;
+
+  fn device_type(name: &str, model: Option<&str>) -> DeviceType {
+    let mut labels = json!({ "vendor": "nvidia" });
+    if let Some(model) = model {
+      labels["model"] = json!(model);
+    }
+
+    serde_json::from_value(json!({
+      "name": name,
+      "subsystem": "drm",
+      "labels": labels,
+      "selector": {},
+    }))
+    .unwrap()
+  }
+
+  #[test]
+  fn group_by_label_value() {
+    let class: DeviceClass = serde_json::from_value(json!({
+      "name": "gpu",
+      "subsystem": "drm",
+      "target": "/dev/dri/card#",
+      "groupBy": "model",
+      "selector": { "matchLabels": { "vendor": "nvidia" } },
+    }))
+    .unwrap();
+
+    let device_types = [
+      device_type("gpu-a", Some("a100")),
+      device_type("gpu-b", Some("t4")),
+      device_type("gpu-c", None),
+      device_type("gpu-d", Some("?")),
+    ];
+
+    let resource_names = device_types
+      .iter()
+      .filter(|ty| class.match_with(ty).is_match())
+      .filter_map(|ty| class.resource_name_for(ty))
+      .collect::<BTreeSet<_>>();
+
+    assert_eq!(
+      resource_names,
+      ["udev/drm/gpu-a100", "udev/drm/gpu-t4"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<BTreeSet<_>>()
+    );
+  }
+
+  #[test]
+  fn resource_name_defaults_and_overrides() {
+    let class = |resource_name: Option<&str>| {
+      let mut class = json!({
+        "name": "gpu",
+        "subsystem": "drm",
+        "target": "/dev/dri/card#",
+        "selector": {},
+      });
+      if let Some(name) = resource_name {
+        class["resourceName"] = json!(name);
+      }
+      serde_json::from_value::<DeviceClass>(class)
+    };
+
+    let default = class(None).unwrap();
+    assert_eq!(default.resource_name(None), "udev/drm/gpu");
+
+    let custom = class(Some("example.com/gpu")).unwrap();
+    assert_eq!(custom.resource_name(None), "example.com/gpu");
+    assert_eq!(
+      custom.resource_name(Some(InternedString::new("a100"))),
+      "example.com/gpu-a100"
+    );
+    assert_eq!(
+      custom.resource_name(Some(InternedString::new("Tesla T4"))),
+      "example.com/gpu-tesla-t4"
+    );
+
+    for invalid in &["Example.com/gpu", "example.com/my gpu", "gpu"] {
+      assert!(class(Some(invalid)).is_err(), "{} was accepted", invalid);
+    }
+  }
+
+  #[test]
+  fn numeric_selector_matches_typed_labels() {
+    let class: DeviceClass = toml::from_str(
+      r#"
+        name = "tpu"
+        subsystem = "accel"
+        target = "/dev/accel#"
+
+        [selector]
+        matchExpressions = [
+          { key = "cores", operator = "Gt", values = 4 },
+          { key = "fast", operator = "IsTrue" },
+        ]
+      "#,
+    )
+    .unwrap();
+
+    let device_type = |cores: &str| -> DeviceType {
+      toml::from_str(&format!(
+        r#"
+          name = "tpu"
+          subsystem = "accel"
+          labels = {{ cores = {}, fast = true }}
+          selector = {{}}
+        "#,
+        cores
+      ))
+      .unwrap()
+    };
+
+    assert!(class.match_with(&device_type("8")).is_match());
+    assert!(class.match_with(&device_type("2")).is_mismatch());
+    assert!(class.match_with(&device_type("\"many\"")).is_mismatch());
+  }
+
+  #[test]
+  fn container_path_template() {
+    let device = UdevDevice::from_parts(
+      "accel",
+      "/sys/devices/pci0000:00/accel/accel3",
+      "/dev/accel3",
+      vec![("dev", "261:3")],
+    );
+
+    let class: DeviceClass = serde_json::from_value(json!({
+      "name": "accel",
+      "subsystem": "accel",
+      "target": "/dev/accel#",
+      "selector": {},
+    }))
+    .unwrap();
+    assert_eq!(class.container_path(&device, 1), "/dev/accel3");
+
+    let class: DeviceClass = serde_json::from_value(json!({
+      "name": "accel",
+      "subsystem": "accel",
+      "target": "/dev/accel#",
+      "containerPath": "/dev/accel${index}",
+      "selector": {},
+    }))
+    .unwrap();
+    assert_eq!(class.container_path(&device, 0), "/dev/accel0");
+    assert_eq!(class.container_path(&device, 1), "/dev/accel1");
+    assert_eq!(device.devnode(), "/dev/accel3");
+  }
+
+  #[test]
+  fn class_annotations_override_device_type_annotations() {
+    let device_type: DeviceType = serde_json::from_value(json!({
+      "name": "gpu-a",
+      "subsystem": "drm",
+      "labels": { "vendor": "nvidia" },
+      "selector": {},
+      "annotations": { "gpu.memory": "16GB", "gpu.vendor": "nvidia" },
+      "envs": { "GPU_MEMORY": "16GB" },
+    }))
+    .unwrap();
+
+    let class: DeviceClass = serde_json::from_value(json!({
+      "name": "gpu",
+      "subsystem": "drm",
+      "target": "/dev/dri/card#",
+      "selector": {},
+      "annotations": { "gpu.vendor": "any" },
+    }))
+    .unwrap();
+
+    let annotations = class.annotations_for(vec![&device_type]);
+    assert_eq!(annotations.len(), 2);
+    assert_eq!(annotations["gpu.memory"], "16GB");
+    assert_eq!(annotations["gpu.vendor"], "any");
+
+    let envs = class.envs_for(vec![&device_type], &[]);
+    assert_eq!(envs["GPU_MEMORY"], "16GB");
+  }
+
+  #[test]
+  fn mounts_and_envs_round_trip() {
+    let config = json!({
+      "name": "gpu",
+      "subsystem": "drm",
+      "target": "/dev/dri/card#",
+      "selector": {},
+      "envs": { "GPU_DEVICE": "${DEVNODE}", "LD_PATH": "${HOME}/lib" },
+      "mounts": [
+        { "containerPath": "/usr/lib/gpu", "hostPath": "/opt/gpu/lib", "readOnly": true },
+        { "containerPath": "/var/run/gpu", "hostPath": "/var/run/gpu" },
+      ],
+    });
+
+    let class: DeviceClass = serde_json::from_value(config.clone()).unwrap();
+    let serialized = serde_json::to_value(&class).unwrap();
+    assert_eq!(serialized["mounts"], config["mounts"]);
+    assert_eq!(serialized["envs"], config["envs"]);
+    assert_eq!(
+      class.mounts()[0],
+      MountSpec {
+        container_path: InternedString::new("/usr/lib/gpu"),
+        host_path: InternedString::new("/opt/gpu/lib"),
+        read_only: true,
+      }
+    );
+
+    let devices = [
+      UdevDevice::from_parts("drm", "/sys/devices/card0", "/dev/dri/card0", vec![]),
+      UdevDevice::from_parts("drm", "/sys/devices/card1", "/dev/dri/card1", vec![]),
+    ];
+    let envs = class.envs_for(vec![], &devices);
+    assert_eq!(envs["GPU_DEVICE"], "/dev/dri/card0,/dev/dri/card1");
+    assert_eq!(envs["LD_PATH"], "${HOME}/lib");
+  }
+
+  #[test]
+  fn envs_expand_device_placeholders() {
+    let class: DeviceClass = serde_json::from_value(json!({
+      "name": "serial",
+      "subsystem": "tty",
+      "target": "serial",
+      "selector": {},
+      "envs": {
+        "DEVICES": "${devnode}",
+        "SYSPATHS": "${syspath}",
+        "SERIALS": "${attr:serial}",
+        "TERM": "${TERM}",
+      },
+    }))
+    .unwrap();
+
+    let devices = [
+      UdevDevice::from_parts(
+        "tty",
+        "/sys/devices/a",
+        "/dev/ttyACM0",
+        vec![("serial", "A1")],
+      ),
+      UdevDevice::from_parts("tty", "/sys/devices/b", "/dev/ttyACM1", vec![]),
+    ];
+    let envs = class.envs_for(vec![], &devices);
+    assert_eq!(envs["DEVICES"], "/dev/ttyACM0,/dev/ttyACM1");
+    assert_eq!(envs["SYSPATHS"], "/sys/devices/a,/sys/devices/b");
+    assert_eq!(envs["SERIALS"], "A1,");
+    assert_eq!(envs["TERM"], "${TERM}");
+  }
+}
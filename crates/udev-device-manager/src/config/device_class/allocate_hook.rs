@@ -0,0 +1,41 @@
+use crate::config::InternedString;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+fn default_timeout() -> u64 {
+  10
+}
+
+/// A command run for every container allocated devices of a class, getting the allocation as
+/// JSON on stdin. The allocation fails if the command fails or times out, so sites can do
+/// their own device setup (e.g. `chmod` the device) as part of allocating it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocateHook {
+  /// Command and its arguments
+  command: Vec<InternedString>,
+
+  /// Seconds the command may take before it's killed and the allocation fails
+  #[serde(default = "default_timeout")]
+  timeout: u64,
+}
+
+impl AllocateHook {
+  pub fn command(&self) -> Vec<String> {
+    self.command.iter().map(|arg| arg.to_string()).collect()
+  }
+
+  /// Whether there's no program to run, which can't be told from a failing hook at runtime
+  pub fn is_empty(&self) -> bool {
+    self
+      .command
+      .first()
+      .is_none_or(|program| program.trim().is_empty())
+  }
+
+  /// Time the command may take
+  pub fn timeout(&self) -> Duration {
+    Duration::from_secs(self.timeout)
+  }
+}
@@ -0,0 +1,103 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Number of devices a device class is expected to advertise, per resource, e.g.
+/// `{ exactly: 1 }` for a singleton. Catches selectors silently matching too much or too
+/// little.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ExpectedCount {
+  /// Exactly this many devices
+  Exactly(usize),
+
+  /// No fewer than this many devices
+  #[serde(alias = "at_least")]
+  AtLeast(usize),
+
+  /// No more than this many devices
+  #[serde(alias = "at_most")]
+  AtMost(usize),
+}
+
+impl ExpectedCount {
+  /// Whether `count` devices satisfy the constraint
+  pub fn allows(&self, count: usize) -> bool {
+    match *self {
+      ExpectedCount::Exactly(n) => count == n,
+      ExpectedCount::AtLeast(n) => count >= n,
+      ExpectedCount::AtMost(n) => count <= n,
+    }
+  }
+}
+
+impl fmt::Display for ExpectedCount {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ExpectedCount::Exactly(n) => write!(f, "exactly {}", n),
+      ExpectedCount::AtLeast(n) => write!(f, "at least {}", n),
+      ExpectedCount::AtMost(n) => write!(f, "at most {}", n),
+    }
+  }
+}
+
+/// What a device class does while it doesn't match the expected number of devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum UnexpectedCount {
+  /// Keep advertising the matched devices, but report the resource as not ready
+  #[default]
+  Unready,
+
+  /// Advertise no devices at all, and report the resource as not ready
+  #[serde(alias = "advertise_none")]
+  AdvertiseNone,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_test::{assert_de_tokens, assert_tokens, Token};
+
+  #[test]
+  fn expected_count_serde() {
+    assert_tokens(
+      &ExpectedCount::Exactly(1),
+      &[
+        Token::NewtypeVariant {
+          name: "ExpectedCount",
+          variant: "exactly",
+        },
+        Token::U64(1),
+      ],
+    );
+    assert_de_tokens(
+      &ExpectedCount::AtLeast(2),
+      &[
+        Token::Enum {
+          name: "ExpectedCount",
+        },
+        Token::Str("at_least"),
+        Token::U64(2),
+      ],
+    );
+    assert_tokens(
+      &UnexpectedCount::AdvertiseNone,
+      &[Token::UnitVariant {
+        name: "UnexpectedCount",
+        variant: "advertiseNone",
+      }],
+    );
+  }
+
+  #[test]
+  fn exactly_one() {
+    let expected = ExpectedCount::Exactly(1);
+    assert!(expected.allows(1));
+    assert!(!expected.allows(0));
+    assert!(!expected.allows(2));
+    assert!(ExpectedCount::AtLeast(1).allows(2));
+    assert!(!ExpectedCount::AtMost(1).allows(2));
+    assert_eq!(expected.to_string(), "exactly 1");
+  }
+}
@@ -0,0 +1,25 @@
+use crate::config::InternedString;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A host path mounted into containers allocated devices of a class, e.g. the user space
+/// libraries or tools belonging to a device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MountSpec {
+  /// Path of the mount within the container
+  #[serde(alias = "container_path")]
+  pub container_path: InternedString,
+
+  /// Path of the mount on the host
+  #[serde(alias = "host_path")]
+  pub host_path: InternedString,
+
+  /// Mount read-only
+  #[serde(
+    default,
+    alias = "read_only",
+    skip_serializing_if = "std::ops::Not::not"
+  )]
+  pub read_only: bool,
+}
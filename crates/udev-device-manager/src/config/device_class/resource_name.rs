@@ -0,0 +1,155 @@
+use crate::config::{schema::schema_from_json, InternedString};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{convert::TryFrom, fmt};
+
+const MAX_DOMAIN_LEN: usize = 253;
+const MAX_NAME_LEN: usize = 63;
+
+/// Extended resource name a device class is advertised to the kubelet as, in `domain/name`
+/// form, e.g. `example.com/serial`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "InternedString", try_from = "InternedString")]
+pub struct ResourceName(InternedString);
+
+impl ResourceName {
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+/// Why `name` isn't a legal extended resource name, if it isn't
+fn check(name: &str) -> Result<(), &'static str> {
+  let (domain, name) = match name.find('/') {
+    Some(slash) => (&name[..slash], &name[slash + 1..]),
+    None => return Err("expected the form domain/name"),
+  };
+
+  let lowercase_alphanumeric = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+  let valid_part = |part: &str, separators: &[char]| {
+    part.starts_with(lowercase_alphanumeric)
+      && part.ends_with(lowercase_alphanumeric)
+      && part
+        .chars()
+        .all(|c| lowercase_alphanumeric(c) || separators.contains(&c))
+  };
+
+  if domain.is_empty() || domain.len() > MAX_DOMAIN_LEN {
+    Err("the domain must have 1 to 253 characters")
+  } else if !domain.split('.').all(|label| valid_part(label, &['-'])) {
+    Err("the domain must be lowercase DNS labels separated by dots")
+  } else if domain == "kubernetes.io" || domain.ends_with(".kubernetes.io") {
+    Err("the kubernetes.io domain is reserved")
+  } else if name.is_empty() || name.len() > MAX_NAME_LEN {
+    Err("the name must have 1 to 63 characters")
+  } else if !valid_part(name, &['-', '_', '.']) {
+    Err("the name must be lowercase alphanumerics, '-', '_' or '.', starting and ending with an alphanumeric")
+  } else {
+    Ok(())
+  }
+}
+
+/// Makes a label value usable in a resource name, by lowercasing it and replacing the
+/// characters a name can't contain with '-'
+pub(super) fn sanitize(value: &str) -> String {
+  let sanitized = value
+    .chars()
+    .map(|c| match c.to_ascii_lowercase() {
+      c @ ('a'..='z' | '0'..='9' | '-' | '_' | '.') => c,
+      _ => '-',
+    })
+    .collect::<String>();
+
+  sanitized
+    .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+    .into()
+}
+
+impl TryFrom<InternedString> for ResourceName {
+  type Error = String;
+
+  fn try_from(value: InternedString) -> Result<Self, Self::Error> {
+    match check(&value) {
+      Ok(()) => Ok(ResourceName(value)),
+      Err(reason) => Err(format!(
+        "invalid resource name {:?}: {}",
+        value.as_str(),
+        reason
+      )),
+    }
+  }
+}
+
+impl From<ResourceName> for InternedString {
+  fn from(name: ResourceName) -> Self {
+    name.0
+  }
+}
+
+impl fmt::Display for ResourceName {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl JsonSchema for ResourceName {
+  fn schema_name() -> String {
+    stringify!(ResourceName).into()
+  }
+
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    // doesn't check lengths or the reserved domain, which the config parser rejects
+    schema_from_json(json!({
+      "type": "string",
+      "pattern": "^[a-z0-9]([-a-z0-9]*[a-z0-9])?(\\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*/[a-z0-9]([-_.a-z0-9]*[a-z0-9])?$",
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_test::{assert_de_tokens_error, assert_tokens, Token};
+
+  #[test]
+  fn resource_name_serde() {
+    assert_tokens(
+      &ResourceName(InternedString::new_static("example.com/serial")),
+      &[Token::Str("example.com/serial")],
+    );
+    assert_de_tokens_error::<ResourceName>(
+      &[Token::Str("Example.com/serial")],
+      "invalid resource name \"Example.com/serial\": the domain must be lowercase DNS labels separated by dots",
+    );
+  }
+
+  #[test]
+  fn legal_extended_resource_names() {
+    let valid = |name: &str| check(name).is_ok();
+
+    assert!(valid("example.com/serial"));
+    assert!(valid("udev/tty_acm.0"));
+    assert!(valid("my-org.example.com/zigbee-2"));
+
+    assert!(!valid("serial"));
+    assert!(!valid("example.com/Serial"));
+    assert!(!valid("example.com/my serial"));
+    assert!(!valid("example.com/serial/0"));
+    assert!(!valid("example..com/serial"));
+    assert!(!valid("-example.com/serial"));
+    assert!(!valid("example.com/serial-"));
+    assert!(!valid("example.com/"));
+    assert!(!valid("/serial"));
+    assert!(!valid("kubernetes.io/serial"));
+    assert!(!valid(&format!("example.com/{}", "a".repeat(64))));
+  }
+
+  #[test]
+  fn label_values_are_sanitized() {
+    assert_eq!(sanitize("a100"), "a100");
+    assert_eq!(sanitize("A100 80GB"), "a100-80gb");
+    assert_eq!(sanitize("  RTX/4090 "), "rtx-4090");
+    assert_eq!(sanitize("--"), "");
+  }
+}
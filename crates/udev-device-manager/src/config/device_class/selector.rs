@@ -2,20 +2,93 @@ use crate::config::{
   selector::{Selector, SelectorType},
   InternedString, MatchResult,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub struct DeviceTypeSelector {
   #[serde(flatten)]
   selector: Selector<Self>,
+
+  /// Device types matching this are dropped, even if matched by the selector above
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  exclude: Option<Selector<Self>>,
 }
 
 impl DeviceTypeSelector {
   pub fn match_with(&self, get_value: &impl Fn(&str) -> Option<InternedString>) -> MatchResult {
-    self.selector.match_with(get_value)
+    let mut result = self.selector.match_with(get_value);
+
+    // an empty exclude selector would match everything, treat it as excluding nothing instead
+    if let Some(exclude) = self.exclude.as_ref().filter(|e| !e.is_empty()) {
+      if exclude.match_with(get_value).is_match() {
+        result += MatchResult::excluded(InternedString::new_static("exclude"));
+      }
+    }
+
+    result
+  }
+
+  /// Every label key the selector or its exclude has a requirement on
+  pub fn keys(&self) -> impl Iterator<Item = InternedString> + '_ {
+    let exclude = self.exclude.iter().flat_map(|exclude| exclude.keys());
+    self.selector.keys().chain(exclude)
   }
 }
 
 impl SelectorType for DeviceTypeSelector {
   const FLAT_KEYS_NAME: Option<&'static str> = Some("matchLabels");
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use std::collections::BTreeMap;
+
+  fn labels(values: &[(&str, &str)]) -> BTreeMap<InternedString, InternedString> {
+    values
+      .iter()
+      .map(|(k, v)| (InternedString::new(k), InternedString::new(v)))
+      .collect()
+  }
+
+  #[test]
+  fn exclude_drops_included_device_types() {
+    let selector: DeviceTypeSelector = serde_json::from_value(json!({
+      "matchLabels": { "vendor": "nvidia" },
+      "exclude": {
+        "matchExpressions": [{ "key": "model", "operator": "In", "values": ["t4"] }],
+      },
+    }))
+    .unwrap();
+
+    let a100 = labels(&[("vendor", "nvidia"), ("model", "a100")]);
+    let t4 = labels(&[("vendor", "nvidia"), ("model", "t4")]);
+    let other = labels(&[("vendor", "amd"), ("model", "mi100")]);
+
+    assert!(selector
+      .match_with(&|name| a100.get(name).copied())
+      .is_match());
+    assert!(selector
+      .match_with(&|name| t4.get(name).copied())
+      .is_mismatch());
+    assert!(selector
+      .match_with(&|name| other.get(name).copied())
+      .is_mismatch());
+  }
+
+  #[test]
+  fn empty_exclude_excludes_nothing() {
+    let selector: DeviceTypeSelector = serde_json::from_value(json!({
+      "matchLabels": { "vendor": "nvidia" },
+      "exclude": {},
+    }))
+    .unwrap();
+
+    let a100 = labels(&[("vendor", "nvidia"), ("model", "a100")]);
+    assert!(selector
+      .match_with(&|name| a100.get(name).copied())
+      .is_match());
+  }
+}
@@ -0,0 +1,111 @@
+use crate::config::{schema::schema_from_json, InternedString};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{convert::TryFrom, fmt};
+
+/// Permissions of the plugin socket of a device class, written as an octal string like
+/// `"0660"` since JSON and YAML have no octal numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "InternedString", try_from = "InternedString")]
+pub struct SocketMode(u32);
+
+impl SocketMode {
+  pub fn bits(self) -> u32 {
+    self.0
+  }
+
+  /// Whether anyone on the node may connect to the socket
+  pub fn is_world_writable(self) -> bool {
+    self.0 & 0o002 != 0
+  }
+}
+
+impl TryFrom<InternedString> for SocketMode {
+  type Error = String;
+
+  fn try_from(value: InternedString) -> Result<Self, Self::Error> {
+    let digits = value.strip_prefix("0o").unwrap_or(&value);
+    // unlike `from_str_radix`, a leading sign isn't a mode
+    let octal = !digits.is_empty() && digits.bytes().all(|digit| matches!(digit, b'0'..=b'7'));
+    match u32::from_str_radix(digits, 8) {
+      Ok(mode) if octal && mode <= 0o777 => Ok(SocketMode(mode)),
+      Ok(_) if octal => Err(format!(
+        "invalid socket mode {:?}: only permission bits (up to 0777) can be set",
+        value.as_str()
+      )),
+      _ => Err(format!(
+        "invalid socket mode {:?}: expected an octal mode like \"0660\"",
+        value.as_str()
+      )),
+    }
+  }
+}
+
+impl From<SocketMode> for InternedString {
+  fn from(mode: SocketMode) -> Self {
+    InternedString::new(mode.to_string())
+  }
+}
+
+impl fmt::Display for SocketMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:04o}", self.0)
+  }
+}
+
+/// What the schema accepts, the same modes parsing does
+const PATTERN: &str = "^(0o)?0*[0-7]{1,3}$";
+
+impl JsonSchema for SocketMode {
+  fn schema_name() -> String {
+    stringify!(SocketMode).into()
+  }
+
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    schema_from_json(json!({
+      "type": "string",
+      "pattern": PATTERN,
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_test::{assert_de_tokens, assert_de_tokens_error, assert_tokens, Token};
+
+  #[test]
+  fn socket_mode_serde() {
+    assert_tokens(&SocketMode(0o660), &[Token::Str("0660")]);
+    assert_de_tokens(&SocketMode(0o600), &[Token::Str("600")]);
+    assert_de_tokens(&SocketMode(0o640), &[Token::Str("0o640")]);
+
+    assert_de_tokens_error::<SocketMode>(
+      &[Token::Str("1777")],
+      "invalid socket mode \"1777\": only permission bits (up to 0777) can be set",
+    );
+    assert_de_tokens_error::<SocketMode>(
+      &[Token::Str("rw-rw----")],
+      "invalid socket mode \"rw-rw----\": expected an octal mode like \"0660\"",
+    );
+  }
+
+  #[test]
+  fn schema_pattern_agrees_with_parsing() {
+    let pattern = regex::Regex::new(PATTERN).unwrap();
+    for mode in &[
+      "0660", "660", "0o660", "00660", "0o0660", "7", "0777", "1777", "+660", "0o", "", "0x1b6",
+    ] {
+      let parsed = SocketMode::try_from(InternedString::new(mode)).is_ok();
+      assert_eq!(pattern.is_match(mode), parsed, "{:?}", mode);
+    }
+  }
+
+  #[test]
+  fn world_writable_modes() {
+    assert!(!SocketMode(0o660).is_world_writable());
+    assert!(!SocketMode(0o664).is_world_writable());
+    assert!(SocketMode(0o666).is_world_writable());
+  }
+}
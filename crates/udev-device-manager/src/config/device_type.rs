@@ -1,21 +1,29 @@
 mod access;
+mod authorization;
+mod health_check;
+mod id_scheme;
 mod labels;
 mod selector;
 
 use crate::udev::UdevDevice;
 
 use super::{InternedString, MatchResult};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
-use std::{fmt, sync::Arc};
+use std::{collections::BTreeMap, fmt, sync::Arc};
 
 pub use access::DeviceAccess;
+pub use authorization::UnauthorizedDevices;
+pub use health_check::HealthCheck;
+pub use id_scheme::DeviceIdScheme;
 pub use labels::DeviceTypeLabels;
 pub use selector::UdevSelector;
 
 mod inner {
   use super::*;
 
-  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+  #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+  #[serde(rename_all = "camelCase")]
   pub(super) struct DeviceType {
     /// Device group name - must be unique
     pub(super) name: InternedString,
@@ -23,15 +31,63 @@ mod inner {
     /// Device subsystem
     pub(super) subsystem: InternedString,
 
+    /// Disabled device types are parsed, but never matched or advertised
+    #[serde(
+      default = "crate::config::default_enabled",
+      skip_serializing_if = "crate::config::is_enabled"
+    )]
+    pub(super) enabled: bool,
+
     /// Device access rules
     #[serde(default)]
     pub(super) access: DeviceAccess,
 
+    /// How advertised device IDs are derived from the udev devices
+    #[serde(default, alias = "id_scheme")]
+    pub(super) id_scheme: DeviceIdScheme,
+
+    /// How devices which aren't authorized are treated
+    #[serde(default)]
+    pub(super) unauthorized: UnauthorizedDevices,
+
     /// Device labels
     pub(super) labels: DeviceTypeLabels,
 
     /// Selector for filtering out udev devices
     pub(super) selector: UdevSelector,
+
+    /// Selector the direct parent device has to match as well, against its own attributes
+    /// rather than those merged from the whole hierarchy
+    #[serde(
+      default,
+      alias = "parent_selector",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub(super) parent_selector: Option<UdevSelector>,
+
+    /// Attributes holding paths, which are matched by their basename
+    #[serde(
+      default,
+      alias = "path_attributes",
+      skip_serializing_if = "Vec::is_empty"
+    )]
+    pub(super) path_attributes: Vec<InternedString>,
+
+    /// Annotations added to containers allocated devices of this type
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) annotations: BTreeMap<InternedString, InternedString>,
+
+    /// Environment variables set in containers allocated devices of this type
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) envs: BTreeMap<InternedString, InternedString>,
+
+    /// Command periodically checking the health of each device
+    #[serde(
+      default,
+      alias = "health_check",
+      skip_serializing_if = "Option::is_none"
+    )]
+    pub(super) health_check: Option<HealthCheck>,
   }
 }
 
@@ -41,6 +97,57 @@ pub struct DeviceType {
 }
 
 impl DeviceType {
+  /// Creates a device type without labels, using the default access rules and a selector
+  /// that matches every device in the subsystem.
+  pub fn new(name: impl Into<InternedString>, subsystem: impl Into<InternedString>) -> Self {
+    Self::from(inner::DeviceType {
+      name: name.into(),
+      subsystem: subsystem.into(),
+      enabled: true,
+      access: DeviceAccess::default(),
+      id_scheme: DeviceIdScheme::default(),
+      unauthorized: UnauthorizedDevices::default(),
+      labels: DeviceTypeLabels::default(),
+      selector: UdevSelector::default(),
+      parent_selector: None,
+      path_attributes: Vec::new(),
+      annotations: BTreeMap::new(),
+      envs: BTreeMap::new(),
+      health_check: None,
+    })
+  }
+
+  /// Copy of this device type using the given selector
+  pub fn with_selector(&self, selector: UdevSelector) -> Self {
+    self.with(|inner| inner.selector = selector)
+  }
+
+  /// Copy of this device type using the given labels
+  pub fn with_labels(&self, labels: DeviceTypeLabels) -> Self {
+    self.with(|inner| inner.labels = labels)
+  }
+
+  /// Copy of this device type using the given access rules
+  pub fn with_access(&self, access: DeviceAccess) -> Self {
+    self.with(|inner| inner.access = access)
+  }
+
+  /// Copy of this device type using the given ID scheme
+  pub fn with_id_scheme(&self, id_scheme: DeviceIdScheme) -> Self {
+    self.with(|inner| inner.id_scheme = id_scheme)
+  }
+
+  /// Copy of this device type, enabled or disabled
+  pub fn with_enabled(&self, enabled: bool) -> Self {
+    self.with(|inner| inner.enabled = enabled)
+  }
+
+  fn with(&self, f: impl FnOnce(&mut inner::DeviceType)) -> Self {
+    let mut inner = (*self.inner).clone();
+    f(&mut inner);
+    Self::from(inner)
+  }
+
   /// Device group name - must be unique
   pub fn name(&self) -> InternedString {
     self.inner.name
@@ -51,11 +158,26 @@ impl DeviceType {
     self.inner.subsystem
   }
 
+  /// Whether the device type takes part in reconciles
+  pub fn enabled(&self) -> bool {
+    self.inner.enabled
+  }
+
   /// Device access rules
   pub fn access(&self) -> DeviceAccess {
     self.inner.access
   }
 
+  /// How advertised device IDs are derived from the udev devices
+  pub fn id_scheme(&self) -> DeviceIdScheme {
+    self.inner.id_scheme
+  }
+
+  /// How devices which aren't authorized are treated
+  pub fn unauthorized(&self) -> UnauthorizedDevices {
+    self.inner.unauthorized
+  }
+
   /// Device labels
   pub fn labels(&self) -> &DeviceTypeLabels {
     &self.inner.labels
@@ -66,6 +188,31 @@ impl DeviceType {
     &self.inner.selector
   }
 
+  /// Selector matched against the own attributes of the direct parent device
+  pub fn parent_selector(&self) -> Option<&UdevSelector> {
+    self.inner.parent_selector.as_ref()
+  }
+
+  /// Attributes holding paths, which are matched by their basename
+  pub fn path_attributes(&self) -> &[InternedString] {
+    &self.inner.path_attributes
+  }
+
+  /// Annotations added to containers allocated devices of this type
+  pub fn annotations(&self) -> &BTreeMap<InternedString, InternedString> {
+    &self.inner.annotations
+  }
+
+  /// Environment variables set in containers allocated devices of this type
+  pub fn envs(&self) -> &BTreeMap<InternedString, InternedString> {
+    &self.inner.envs
+  }
+
+  /// Command periodically checking the health of each device, if any
+  pub fn health_check(&self) -> Option<&HealthCheck> {
+    self.inner.health_check.as_ref()
+  }
+
   pub fn match_with(&self, device: &UdevDevice) -> MatchResult {
     let mut result = MatchResult::Matches;
 
@@ -79,14 +226,74 @@ impl DeviceType {
       );
     }
 
-    result += self
-      .selector()
-      .match_with(&|name| device.attribute(name).and_then(|v| v.as_option()));
+    let path_attributes = self.path_attributes();
+    let normalize = |name: &str, value: InternedString| {
+      if path_attributes.iter().any(|a| a == name) {
+        basename(value)
+      } else {
+        value
+      }
+    };
+
+    result += self.selector().match_with(&|parent_subsystem, name| {
+      if parent_subsystem.is_none() {
+        if let Some(value) = device_key(device, name) {
+          return value;
+        }
+      }
+
+      let value = match parent_subsystem {
+        None => device.attribute(name),
+        Some(subsystem) => device.parent_attribute(subsystem, name),
+      };
+      Some(normalize(name, value.and_then(|v| v.as_option())?))
+    });
+
+    if let Some(parent_selector) = self.parent_selector() {
+      result += parent_selector.match_with(&|parent_subsystem, name| {
+        let value = match parent_subsystem {
+          None => device
+            .parent_attributes()
+            .and_then(|a| a.get(name).copied()),
+          Some(subsystem) => device.parent_attribute(subsystem, name),
+        };
+        Some(normalize(name, value.and_then(|v| v.as_option())?))
+      });
+    }
+
+    if self.unauthorized() == UnauthorizedDevices::Exclude && device.authorized() == Some(false) {
+      let field = InternedString::new_static("authorized");
+      let actual = device.attribute(&field).and_then(|v| v.as_option());
+      result += MatchResult::expected_bool(field, true, actual);
+    }
 
     result
   }
 }
 
+/// Resolves the reserved selector keys naming the device itself rather than one of its
+/// attributes, `None` if `name` isn't one of them
+fn device_key(device: &UdevDevice, name: &str) -> Option<Option<InternedString>> {
+  let value = match name {
+    "@subsystem" => Some(device.subsystem()),
+    "@devtype" => device.devtype(),
+    "@devnode" => Some(device.devnode()).filter(|devnode| !devnode.is_empty()),
+    "@syspath" => Some(device.syspath()),
+    _ => return None,
+  };
+
+  Some(value)
+}
+
+/// Reduces a path like `../../bus/pci/drivers/nvidia` to its last segment.
+fn basename(value: InternedString) -> InternedString {
+  let trimmed = value.trim_end_matches('/');
+  match trimmed.rfind('/') {
+    Some(index) => InternedString::new(&trimmed[index + 1..]),
+    None => value,
+  }
+}
+
 impl From<inner::DeviceType> for DeviceType {
   fn from(inner: inner::DeviceType) -> Self {
     Self {
@@ -118,3 +325,331 @@ impl<'de> Deserialize<'de> for DeviceType {
     <inner::DeviceType as Deserialize>::deserialize(deserializer).map(Self::from)
   }
 }
+
+impl JsonSchema for DeviceType {
+  fn schema_name() -> String {
+    stringify!(DeviceType).into()
+  }
+
+  fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+    <inner::DeviceType as JsonSchema>::json_schema(gen)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::{SelectorRequirement, SelectorValueRequirement};
+  use std::{iter::FromIterator, num::NonZeroU8};
+
+  const CONFIG: &str = r#"
+    name = "conbee2"
+    subsystem = "tty"
+    access = 2
+
+    [labels]
+    type = "conbee2"
+
+    [selector]
+    matchAttributes = { idVendor = "1cf1", idProduct = "0030" }
+    matchExpressions = [{ key = "serial", operator = "Exists" }]
+  "#;
+
+  #[test]
+  fn programmatic_device_type_matches_parsed() {
+    let parsed: DeviceType = toml::from_str(CONFIG).unwrap();
+
+    let built = DeviceType::new("conbee2", "tty")
+      .with_access(DeviceAccess::AtMost(NonZeroU8::new(2).unwrap()))
+      .with_labels(DeviceTypeLabels::from_iter(vec![("type", "conbee2")]))
+      .with_selector(
+        UdevSelector::default()
+          .with_attribute("idVendor", "1cf1")
+          .with_attribute("idProduct", "0030")
+          .with_expression(SelectorRequirement {
+            key: InternedString::new_static("serial"),
+            parent_subsystem: None,
+            value_requirement: SelectorValueRequirement::Exists,
+          }),
+      );
+
+    assert_eq!(built, parsed);
+    assert_eq!(
+      serde_json::to_value(&built).unwrap(),
+      serde_json::to_value(&parsed).unwrap()
+    );
+  }
+
+  #[test]
+  fn path_attributes_match_by_basename() {
+    let device = UdevDevice::from_parts(
+      "drm",
+      "/sys/devices/pci0000:00/0000:00:02.0/drm/card0",
+      "/dev/dri/card0",
+      vec![("driver", "../../../bus/pci/drivers/nvidia")],
+    );
+
+    let verbatim: DeviceType = serde_json::from_value(serde_json::json!({
+      "name": "gpu",
+      "subsystem": "drm",
+      "labels": {},
+      "selector": { "matchAttributes": { "driver": "nvidia" } },
+    }))
+    .unwrap();
+    assert!(verbatim.match_with(&device).is_mismatch());
+
+    let normalized: DeviceType = serde_json::from_value(serde_json::json!({
+      "name": "gpu",
+      "subsystem": "drm",
+      "labels": {},
+      "selector": { "matchAttributes": { "driver": "nvidia" } },
+      "pathAttributes": ["driver"],
+    }))
+    .unwrap();
+    assert!(normalized.match_with(&device).is_match());
+  }
+
+  #[test]
+  fn selector_needs_every_attribute_and_expression() {
+    let device_type: DeviceType = toml::from_str(CONFIG).unwrap();
+    let conbee = || {
+      UdevDevice::builder("tty", "/sys/devices/usb1/1-1/tty/ttyACM0")
+        .devnode("/dev/ttyACM0")
+        .attribute("idVendor", "1cf1")
+        .attribute("idProduct", "0030")
+    };
+
+    assert!(device_type
+      .match_with(&conbee().attribute("serial", "DE2132").build())
+      .is_match());
+    assert!(device_type.match_with(&conbee().build()).is_mismatch());
+    assert!(device_type
+      .match_with(&conbee().attribute("serial", "").build())
+      .is_mismatch());
+    assert!(device_type
+      .match_with(
+        &conbee()
+          .attribute("serial", "DE2132")
+          .attribute("idProduct", "0031")
+          .build()
+      )
+      .is_mismatch());
+
+    // ids are derived from the syspath, like for devices from udev
+    assert_eq!(conbee().build().id(), conbee().devnode("").build().id());
+    assert_ne!(
+      conbee().build().id(),
+      UdevDevice::builder("tty", "/sys/devices/usb1/1-2/tty/ttyACM1")
+        .build()
+        .id()
+    );
+  }
+
+  #[test]
+  fn reserved_keys_match_the_device_itself() {
+    let device_type: DeviceType = toml::from_str(
+      r#"
+        name = "disks"
+        subsystem = "block"
+        labels = {}
+
+        [selector]
+        matchAttributes = { "@devnode" = "/dev/sda" }
+        matchExpressions = [
+          { key = "@devtype", operator = "In", values = ["disk"] },
+          { key = "@syspath", operator = "Glob", values = ["/sys/devices/pci*"] },
+        ]
+      "#,
+    )
+    .unwrap();
+    let sda =
+      || UdevDevice::builder("block", "/sys/devices/pci0000:00/block/sda").devnode("/dev/sda");
+
+    assert!(device_type
+      .match_with(&sda().devtype("disk").build())
+      .is_match());
+    assert!(device_type
+      .match_with(&sda().devtype("partition").build())
+      .is_mismatch());
+    assert!(device_type.match_with(&sda().build()).is_mismatch());
+
+    // attributes can't stand in for the device's own values
+    assert!(device_type
+      .match_with(&sda().attribute("@devtype", "disk").build())
+      .is_mismatch());
+  }
+
+  #[test]
+  fn enabled_defaults_to_true() {
+    let parsed: DeviceType = toml::from_str(CONFIG).unwrap();
+    assert!(parsed.enabled());
+    assert!(serde_json::to_value(&parsed)
+      .unwrap()
+      .get("enabled")
+      .is_none());
+
+    let disabled: DeviceType = toml::from_str(&format!("enabled = false\n{}", CONFIG)).unwrap();
+    assert!(!disabled.enabled());
+    assert_eq!(disabled, parsed.with_enabled(false));
+    assert_eq!(
+      serde_json::to_value(&disabled).unwrap()["enabled"],
+      serde_json::json!(false)
+    );
+  }
+
+  #[test]
+  fn with_methods_leave_original_untouched() {
+    let original = DeviceType::new("conbee2", "tty");
+    let changed =
+      original.with_selector(UdevSelector::default().with_attribute("idVendor", "1cf1"));
+
+    assert_ne!(original, changed);
+    assert_eq!(original.selector(), &UdevSelector::default());
+    assert_eq!(changed.name(), original.name());
+  }
+
+  #[test]
+  fn requirements_can_read_parent_attributes() {
+    // the device shadows the vendor of its usb parent in the flattened attributes
+    let device = UdevDevice::from_parts(
+      "tty",
+      "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0/tty/ttyACM0",
+      "/dev/ttyACM0",
+      vec![("idVendor", "0403")],
+    )
+    .with_parent("usb", vec![("idVendor", "1cf1")])
+    .with_parent("usb", vec![("idVendor", "1d6b")]);
+
+    let device_type = |parent_subsystem: Option<&str>| {
+      DeviceType::new("conbee2", "tty").with_selector(UdevSelector::default().with_expression(
+        SelectorRequirement {
+          key: InternedString::new_static("idVendor"),
+          parent_subsystem: parent_subsystem.map(InternedString::new),
+          value_requirement: SelectorValueRequirement::In(smallvec::smallvec![
+            InternedString::new_static("1cf1")
+          ]),
+        },
+      ))
+    };
+
+    assert!(device_type(None).match_with(&device).is_mismatch());
+    assert!(device_type(Some("usb")).match_with(&device).is_match());
+    assert!(device_type(Some("pci")).match_with(&device).is_mismatch());
+
+    let parsed: SelectorRequirement = serde_json::from_value(serde_json::json!({
+      "key": "idVendor",
+      "parentSubsystem": "usb",
+      "operator": "In",
+      "values": ["1cf1"],
+    }))
+    .unwrap();
+    assert_eq!(
+      parsed.parent_subsystem,
+      Some(InternedString::new_static("usb"))
+    );
+  }
+
+  #[test]
+  fn parent_selector_matches_the_parent_attributes() {
+    // a usb interface, whose vendor is only known by its parent usb device
+    let interface = |vendor| {
+      UdevDevice::from_parts(
+        "usb",
+        "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0",
+        "/dev/bus/usb/001/002",
+        vec![("bInterfaceClass", "02")],
+      )
+      .with_parent("usb", vec![("idVendor", vendor), ("bDeviceClass", "02")])
+      .with_parent("usb", vec![("idVendor", "1d6b")])
+    };
+
+    let device_type: DeviceType = serde_json::from_value(serde_json::json!({
+      "name": "cdc-acm",
+      "subsystem": "usb",
+      "labels": {},
+      "selector": { "matchAttributes": { "bInterfaceClass": "02" } },
+      "parentSelector": { "matchAttributes": { "idVendor": "1cf1" } },
+    }))
+    .unwrap();
+
+    assert!(device_type.match_with(&interface("1cf1")).is_match());
+    assert!(device_type.match_with(&interface("0403")).is_mismatch());
+
+    // only the direct parent is looked at, not the root hub above it
+    let hub: DeviceType = serde_json::from_value(serde_json::json!({
+      "name": "root-hub",
+      "subsystem": "usb",
+      "labels": {},
+      "selector": {},
+      "parentSelector": { "matchAttributes": { "idVendor": "1d6b" } },
+    }))
+    .unwrap();
+    assert!(hub.match_with(&interface("1cf1")).is_mismatch());
+
+    // without a parent there's nothing to match
+    let orphan = UdevDevice::from_parts(
+      "usb",
+      "/sys/devices/a",
+      "/dev/a",
+      vec![("bInterfaceClass", "02")],
+    );
+    assert!(device_type.match_with(&orphan).is_mismatch());
+  }
+
+  #[test]
+  fn modalias_glob_matches() {
+    let device = UdevDevice::from_parts(
+      "tty",
+      "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0/tty/ttyACM0",
+      "/dev/ttyACM0",
+      vec![(
+        "modalias",
+        "usb:v1D6Bp0002d0504dc09dsc00dp03ic09isc00ip00in00",
+      )],
+    );
+
+    let device_type: DeviceType = toml::from_str(
+      r#"
+        name = "linux-foundation"
+        subsystem = "tty"
+
+        [labels]
+
+        [selector]
+        matchExpressions = [{ key = "modalias", operator = "Glob", values = ["usb:v1D6B*"] }]
+      "#,
+    )
+    .unwrap();
+    assert!(device_type.match_with(&device).is_match());
+
+    let other = device_type.with_selector(UdevSelector::default().with_expression(
+      SelectorRequirement {
+        key: InternedString::new_static("modalias"),
+        parent_subsystem: None,
+        value_requirement: SelectorValueRequirement::Glob(smallvec::smallvec![
+          InternedString::new_static("usb:v1CF1*")
+        ]),
+      },
+    ));
+    assert!(other.match_with(&device).is_mismatch());
+  }
+
+  #[test]
+  fn unauthorized_devices_can_be_excluded() {
+    let device = |attributes| UdevDevice::from_parts("usb", "/sys/devices/a", "/dev/a", attributes);
+    let excluding = DeviceType::new("usb", "usb").with(|inner| {
+      inner.unauthorized = UnauthorizedDevices::Exclude;
+    });
+
+    assert!(excluding
+      .match_with(&device(vec![("authorized", "0")]))
+      .is_mismatch());
+    assert!(excluding
+      .match_with(&device(vec![("authorized", "1")]))
+      .is_match());
+    assert!(excluding.match_with(&device(vec![])).is_match());
+    assert!(DeviceType::new("usb", "usb")
+      .match_with(&device(vec![("authorized", "0")]))
+      .is_match());
+  }
+}
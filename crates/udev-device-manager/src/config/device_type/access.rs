@@ -1,11 +1,18 @@
+use crate::config::schema::schema_from_json;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{
   de::{self, Unexpected, Visitor},
   Deserialize, Serialize,
 };
-use std::{fmt, num::NonZeroU8};
+use serde_json::json;
+use std::{convert::TryFrom, fmt, num::NonZeroU8};
 
 const EXCLUSIVE: &str = "exclusive";
-// const SHARED: &str = "shared";
+const SHARED: &str = "shared";
+
+/// Number of times a [`DeviceAccess::Shared`] device is advertised. The v1beta1 API has no
+/// notion of an unbounded device, so this just has to exceed the pods a node can run.
+const SHARED_DEVICE_COUNT: usize = 256;
 
 /// Rules for how many pods can access a single devices at a time.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -13,25 +20,18 @@ pub enum DeviceAccess {
   /// Exclusive access - one device per pod
   Exclusive,
 
-  // /// Shared access - this device can be claimed by an unbound number of pods at once.
-  // Shared,
+  /// Shared access - this device can be claimed by an unbound number of pods at once.
+  Shared,
+
   /// Shared access with an upper bound of pods that can access the pod at once.
   AtMost(NonZeroU8),
 }
 
-impl From<DeviceAccess> for u8 {
-  fn from(access: DeviceAccess) -> Self {
-    match access {
-      DeviceAccess::Exclusive => 1,
-      DeviceAccess::AtMost(n) => n.get(),
-    }
-  }
-}
-
 impl From<DeviceAccess> for usize {
   fn from(access: DeviceAccess) -> Self {
     match access {
       DeviceAccess::Exclusive => 1,
+      DeviceAccess::Shared => SHARED_DEVICE_COUNT,
       DeviceAccess::AtMost(n) => n.get() as usize,
     }
   }
@@ -51,7 +51,7 @@ impl Serialize for DeviceAccess {
   {
     match self {
       DeviceAccess::Exclusive => serializer.serialize_str(EXCLUSIVE),
-      // DeviceAccess::Shared => serializer.serialize_str(SHARED),
+      DeviceAccess::Shared => serializer.serialize_str(SHARED),
       DeviceAccess::AtMost(v) => serializer.serialize_u8(v.get()),
     }
   }
@@ -62,15 +62,10 @@ impl<'de> Visitor<'de> for DeviceAccessVisitor {
   type Value = DeviceAccess;
 
   fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    // write!(
-    //   f,
-    //   "'{}', '{}', or a positive number between 1 and 255, both inclusive",
-    //   EXCLUSIVE, SHARED
-    // )
     write!(
       f,
-      "'{}' or a positive number between 1 and 255, both inclusive",
-      EXCLUSIVE
+      "'{}', '{}', or a positive number between 1 and 255, both inclusive",
+      EXCLUSIVE, SHARED
     )
   }
 
@@ -85,13 +80,33 @@ impl<'de> Visitor<'de> for DeviceAccessVisitor {
     }
   }
 
+  fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+  where
+    E: de::Error,
+  {
+    match u8::try_from(v) {
+      Ok(v) => self.visit_u8(v),
+      Err(_) => Err(E::invalid_value(Unexpected::Unsigned(v), &self)),
+    }
+  }
+
+  fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+  where
+    E: de::Error,
+  {
+    match u8::try_from(v) {
+      Ok(v) => self.visit_u8(v),
+      Err(_) => Err(E::invalid_value(Unexpected::Signed(v), &self)),
+    }
+  }
+
   fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
   where
     E: de::Error,
   {
     match v {
       EXCLUSIVE => Ok(DeviceAccess::Exclusive),
-      // SHARED => Ok(DeviceAccess::Shared),
+      SHARED => Ok(DeviceAccess::Shared),
       _ => Err(E::invalid_value(Unexpected::Str(v), &self)),
     }
   }
@@ -106,6 +121,21 @@ impl<'de> Deserialize<'de> for DeviceAccess {
   }
 }
 
+impl JsonSchema for DeviceAccess {
+  fn schema_name() -> String {
+    stringify!(DeviceAccess).into()
+  }
+
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    schema_from_json(json!({
+      "oneOf": [
+        { "type": "string", "enum": [EXCLUSIVE, SHARED] },
+        { "type": "integer", "minimum": 1, "maximum": 255 },
+      ],
+    }))
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -116,10 +146,10 @@ mod tests {
     assert_tokens(&DeviceAccess::Exclusive, &[Token::Str(EXCLUSIVE)]);
   }
 
-  // #[test]
-  // fn shared_serde() {
-  //   assert_tokens(&DeviceAccess::Shared, &[Token::Str(SHARED)]);
-  // }
+  #[test]
+  fn shared_serde() {
+    assert_tokens(&DeviceAccess::Shared, &[Token::Str(SHARED)]);
+  }
 
   #[test]
   fn atmost_serde() {
@@ -0,0 +1,19 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How devices are treated while their `authorized` attribute is `0`, e.g. USB devices
+/// USBGuard hasn't allowed yet. Re-evaluated whenever the device changes, so devices become
+/// available once they're authorized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum UnauthorizedDevices {
+  /// Advertise them like any other device
+  #[default]
+  Advertise,
+
+  /// Advertise them as unhealthy, so they aren't allocated
+  Unhealthy,
+
+  /// Don't match them at all
+  Exclude,
+}
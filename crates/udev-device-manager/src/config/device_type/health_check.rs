@@ -0,0 +1,80 @@
+use crate::{
+  config::{template, InternedString},
+  udev::UdevDevice,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+fn default_interval() -> u64 {
+  30
+}
+
+fn default_timeout() -> u64 {
+  10
+}
+
+/// A command periodically run for every device of a type, which is advertised as unhealthy
+/// while the command fails. Catches devices which are present, but not working.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthCheck {
+  /// Command and its arguments, `${devnode}` is replaced by the devnode of the checked device
+  command: Vec<InternedString>,
+
+  /// Seconds between checks
+  #[serde(default = "default_interval")]
+  interval: u64,
+
+  /// Seconds a check may take before it's killed and counted as failed
+  #[serde(default = "default_timeout")]
+  timeout: u64,
+}
+
+impl HealthCheck {
+  /// The command checking `device`, with its placeholders expanded
+  pub fn command_for(&self, device: &UdevDevice) -> Vec<String> {
+    self
+      .command
+      .iter()
+      .map(|arg| {
+        template::expand(arg, |key| match key {
+          "devnode" => Some(device.devnode().to_string()),
+          _ => None,
+        })
+      })
+      .collect()
+  }
+
+  /// Time between checks
+  pub fn interval(&self) -> Duration {
+    Duration::from_secs(self.interval.max(1))
+  }
+
+  /// Time a check may take
+  pub fn timeout(&self) -> Duration {
+    Duration::from_secs(self.timeout)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn expands_the_devnode() {
+    let check: HealthCheck = serde_json::from_value(json!({
+      "command": ["/usr/bin/probe", "--device", "${devnode}"],
+    }))
+    .unwrap();
+    let device = UdevDevice::from_parts("tty", "/sys/devices/a", "/dev/ttyACM0", vec![]);
+
+    assert_eq!(
+      check.command_for(&device),
+      vec!["/usr/bin/probe", "--device", "/dev/ttyACM0"]
+    );
+    assert_eq!(check.interval(), Duration::from_secs(30));
+    assert_eq!(check.timeout(), Duration::from_secs(10));
+  }
+}
@@ -0,0 +1,26 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How the IDs of the devices advertised for a udev device are derived. The v1beta1 API only
+/// knows discrete devices, so a device shared by up to N pods is advertised N times.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DeviceIdScheme {
+  /// Every advertised device gets its index appended, `<id>:0` to `<id>:N-1`
+  #[default]
+  Indexed,
+
+  /// Same as `Indexed`, except a device advertised only once uses the bare device ID
+  Compact,
+}
+
+impl DeviceIdScheme {
+  /// ID of the `index`th out of `count` devices advertised for the device with the given ID.
+  /// Only depends on its inputs, so IDs are stable across reconciles.
+  pub fn device_id(self, device_id: &str, index: usize, count: usize) -> String {
+    match self {
+      DeviceIdScheme::Compact if count == 1 => device_id.to_owned(),
+      _ => format!("{}:{}", device_id, index),
+    }
+  }
+}
@@ -1,8 +1,13 @@
-use crate::config::InternedString;
+use crate::{
+  config::{schema::schema_from_json, string::deserialize_scalar, template, InternedString},
+  udev::UdevDevice,
+};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fmt, sync::Arc};
+use serde_json::json;
+use std::{collections::BTreeMap, fmt, iter::FromIterator, sync::Arc};
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Default, PartialEq)]
 pub struct DeviceTypeLabels {
   values: Arc<BTreeMap<InternedString, InternedString>>,
 }
@@ -11,6 +16,69 @@ impl DeviceTypeLabels {
   pub fn get(&self, name: &str) -> Option<InternedString> {
     self.values.get(name).cloned()
   }
+
+  /// The label value as an integer, see [`InternedString::as_i64`]
+  pub fn get_i64(&self, name: &str) -> Option<i64> {
+    self.get(name)?.as_i64()
+  }
+
+  /// The label value as a boolean, see [`InternedString::as_bool`]
+  pub fn get_bool(&self, name: &str) -> Option<bool> {
+    self.get(name)?.as_bool()
+  }
+
+  /// Names of the labels, which unlike their values are never templated
+  pub fn keys(&self) -> impl Iterator<Item = InternedString> + '_ {
+    self.values.keys().copied()
+  }
+
+  /// Whether any of the values has `${..}` placeholders, which are expanded per device
+  pub fn is_templated(&self) -> bool {
+    self.values.values().any(|value| value.contains("${"))
+  }
+
+  /// The labels of a single device, with `${attr:NAME}` expanded to one of its udev attributes
+  /// and `${devnode}` to its device node. Placeholders that don't resolve expand to nothing.
+  pub fn expand(&self, device: &UdevDevice) -> Self {
+    self
+      .values
+      .iter()
+      .map(|(name, value)| {
+        let value = template::expand(value, |key| match key {
+          "devnode" => Some(device.devnode().to_string()),
+          _ => key
+            .strip_prefix("attr:")
+            .and_then(|name| device.attribute(name))
+            .and_then(|value| value.as_option())
+            .map(|value| value.to_string()),
+        });
+
+        (*name, InternedString::new(value))
+      })
+      .collect()
+  }
+}
+
+/// Label values may be written as numbers or booleans, they're stored in their string form
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct LabelValue(#[serde(deserialize_with = "deserialize_scalar")] InternedString);
+
+impl<K, V> FromIterator<(K, V)> for DeviceTypeLabels
+where
+  K: Into<InternedString>,
+  V: Into<InternedString>,
+{
+  fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+    let values = iter
+      .into_iter()
+      .map(|(k, v)| (k.into(), v.into()))
+      .collect();
+
+    DeviceTypeLabels {
+      values: Arc::new(values),
+    }
+  }
 }
 
 impl fmt::Debug for DeviceTypeLabels {
@@ -33,10 +101,78 @@ impl<'de> Deserialize<'de> for DeviceTypeLabels {
   where
     D: serde::Deserializer<'de>,
   {
-    <BTreeMap<InternedString, InternedString> as Deserialize<'de>>::deserialize(deserializer).map(
+    <BTreeMap<InternedString, LabelValue> as Deserialize<'de>>::deserialize(deserializer).map(
       |values| DeviceTypeLabels {
-        values: Arc::new(values),
+        values: Arc::new(values.into_iter().map(|(k, v)| (k, v.0)).collect()),
       },
     )
   }
 }
+
+impl JsonSchema for DeviceTypeLabels {
+  fn schema_name() -> String {
+    stringify!(DeviceTypeLabels).into()
+  }
+
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    schema_from_json(json!({
+      "type": "object",
+      "additionalProperties": { "type": ["string", "number", "boolean"] },
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn typed_values_are_stringified() {
+    let labels: DeviceTypeLabels = toml::from_str(
+      r#"
+        type = "tpu"
+        cores = 4
+        fast = true
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(labels.get("cores"), Some(InternedString::new("4")));
+    assert_eq!(labels.get_i64("cores"), Some(4));
+    assert_eq!(labels.get_bool("fast"), Some(true));
+    assert_eq!(labels.get_i64("type"), None);
+    assert_eq!(
+      serde_json::to_value(&labels).unwrap(),
+      serde_json::json!({ "type": "tpu", "cores": "4", "fast": "true" })
+    );
+  }
+
+  #[test]
+  fn templated_values_are_expanded_per_device() {
+    let labels = DeviceTypeLabels::from_iter(vec![
+      ("type", "conbee2"),
+      ("vendor", "${attr:idVendor}"),
+      ("serial", "usb-${attr:serial}"),
+      ("node", "${devnode}"),
+    ]);
+    assert!(labels.is_templated());
+    assert!(!DeviceTypeLabels::from_iter(vec![("type", "conbee2")]).is_templated());
+
+    let device = UdevDevice::from_parts(
+      "tty",
+      "/sys/devices/a",
+      "/dev/ttyACM0",
+      vec![("idVendor", "1cf1")],
+    );
+    let expanded = labels.expand(&device);
+    assert_eq!(expanded.get("type"), Some(InternedString::new("conbee2")));
+    assert_eq!(expanded.get("vendor"), Some(InternedString::new("1cf1")));
+    assert_eq!(
+      expanded.get("node"),
+      Some(InternedString::new("/dev/ttyACM0"))
+    );
+    // the device has no serial attribute
+    assert_eq!(expanded.get("serial"), Some(InternedString::new("usb-")));
+    assert!(!expanded.is_templated());
+  }
+}
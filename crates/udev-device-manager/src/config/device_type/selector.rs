@@ -1,18 +1,40 @@
 use crate::config::{
-  selector::{MatchResult, Selector, SelectorType},
+  selector::{MatchResult, Selector, SelectorRequirement, SelectorType},
   InternedString,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub struct UdevSelector {
   #[serde(flatten)]
   selector: Selector<Self>,
 }
 
 impl UdevSelector {
-  pub fn match_with(&self, get_value: &impl Fn(&str) -> Option<InternedString>) -> MatchResult {
-    self.selector.match_with(get_value)
+  /// Requires the attribute `name` to have exactly `value`
+  pub fn with_attribute(
+    mut self,
+    name: impl Into<InternedString>,
+    value: impl Into<InternedString>,
+  ) -> Self {
+    self.selector.insert_flat(name.into(), value.into());
+    self
+  }
+
+  /// Adds an expression requirement
+  pub fn with_expression(mut self, requirement: SelectorRequirement) -> Self {
+    self.selector.push_expression(requirement);
+    self
+  }
+
+  /// Matches the attributes returned by `get_value`, which is passed the parent subsystem a
+  /// requirement reads its attribute from, if any
+  pub fn match_with(
+    &self,
+    get_value: &impl Fn(Option<InternedString>, &str) -> Option<InternedString>,
+  ) -> MatchResult<'_> {
+    self.selector.match_with_scoped(get_value)
   }
 }
 
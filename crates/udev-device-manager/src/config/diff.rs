@@ -0,0 +1,187 @@
+use super::{Config, InternedString};
+use std::{collections::BTreeMap, fmt};
+
+/// Names of the entries of one kind added, removed and changed between two configs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryDiff {
+  pub added: Vec<InternedString>,
+  pub removed: Vec<InternedString>,
+  pub changed: Vec<InternedString>,
+}
+
+impl EntryDiff {
+  fn new<'a, T: PartialEq + 'a>(
+    old: impl IntoIterator<Item = (InternedString, &'a T)>,
+    new: impl IntoIterator<Item = (InternedString, &'a T)>,
+  ) -> Self {
+    let mut old: BTreeMap<_, _> = old.into_iter().collect();
+    let mut diff = Self::default();
+
+    for (name, entry) in new {
+      match old.remove(&name) {
+        None => diff.added.push(name),
+        Some(previous) if previous != entry => diff.changed.push(name),
+        Some(_) => (),
+      }
+    }
+    diff.removed.extend(old.into_keys());
+
+    diff
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+  }
+}
+
+impl fmt::Display for EntryDiff {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let changes = [
+      ("+", &self.added),
+      ("-", &self.removed),
+      ("~", &self.changed),
+    ];
+    let mut first = true;
+    for (sign, names) in changes.iter() {
+      for name in names.iter() {
+        if !first {
+          f.write_str(" ")?;
+        }
+        write!(f, "{}{}", sign, name)?;
+        first = false;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// What changed between two configs, by device type and class name. Changes outside of those
+/// (e.g. maintenance mode) only show up as [`ConfigDiff::other_changed`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+  pub device_types: EntryDiff,
+  pub device_classes: EntryDiff,
+
+  /// Whether anything besides the device types and classes changed
+  pub other_changed: bool,
+}
+
+impl ConfigDiff {
+  pub(super) fn new(old: &Config, new: &Config) -> Self {
+    let device_types = EntryDiff::new(
+      old.device_types().iter().map(|ty| (ty.name(), ty)),
+      new.device_types().iter().map(|ty| (ty.name(), ty)),
+    );
+    let device_classes = EntryDiff::new(
+      old
+        .device_classes()
+        .iter()
+        .map(|class| (class.name(), class)),
+      new
+        .device_classes()
+        .iter()
+        .map(|class| (class.name(), class)),
+    );
+    let other_changed = old.manual_devices() != new.manual_devices()
+      || old.maintenance() != new.maintenance()
+      || old.inner.permissions != new.inner.permissions;
+
+    Self {
+      device_types,
+      device_classes,
+      other_changed,
+    }
+  }
+
+  /// Whether the configs are the same
+  pub fn is_empty(&self) -> bool {
+    self.device_types.is_empty() && self.device_classes.is_empty() && !self.other_changed
+  }
+}
+
+impl fmt::Display for ConfigDiff {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.is_empty() {
+      return f.write_str("no changes");
+    }
+
+    let mut parts = Vec::new();
+    if !self.device_types.is_empty() {
+      parts.push(format!("device types {}", self.device_types));
+    }
+    if !self.device_classes.is_empty() {
+      parts.push(format!("device classes {}", self.device_classes));
+    }
+    if self.other_changed {
+      parts.push("other settings changed".to_owned());
+    }
+
+    f.write_str(&parts.join("; "))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn config(device_types: &[(&str, &str)], device_classes: &[(&str, &str)]) -> Config {
+    let device_types = device_types
+      .iter()
+      .map(|(name, subsystem)| {
+        json!({ "name": name, "subsystem": subsystem, "labels": {}, "selector": {} })
+      })
+      .collect::<Vec<_>>();
+    let device_classes = device_classes
+      .iter()
+      .map(|(name, target)| {
+        json!({ "name": name, "subsystem": "tty", "target": target, "selector": {} })
+      })
+      .collect::<Vec<_>>();
+
+    serde_json::from_value(json!({
+      "devices": device_types,
+      "deviceClasses": device_classes,
+    }))
+    .unwrap()
+  }
+
+  #[test]
+  fn reports_added_removed_and_changed_entries() {
+    let old = config(
+      &[("conbee2", "tty"), ("zwave", "tty"), ("gpu", "drm")],
+      &[("zigbee", "zigbee"), ("legacy", "legacy")],
+    );
+    let new = config(
+      &[("conbee2", "tty"), ("zwave", "usb"), ("coral", "usb")],
+      &[("zigbee", "zigbee-2"), ("coral", "coral")],
+    );
+
+    let diff = old.diff(&new);
+    assert_eq!(
+      diff.device_types,
+      EntryDiff {
+        added: vec!["coral".into()],
+        removed: vec!["gpu".into()],
+        changed: vec!["zwave".into()],
+      }
+    );
+    assert_eq!(
+      diff.device_classes,
+      EntryDiff {
+        added: vec!["coral".into()],
+        removed: vec!["legacy".into()],
+        changed: vec!["zigbee".into()],
+      }
+    );
+    assert!(!diff.other_changed);
+    assert_eq!(
+      diff.to_string(),
+      "device types +coral -gpu ~zwave; device classes +coral -legacy ~zigbee"
+    );
+
+    assert!(old.diff(&old.clone()).is_empty());
+    assert_eq!(old.diff(&old).to_string(), "no changes");
+  }
+}
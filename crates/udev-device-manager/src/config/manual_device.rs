@@ -0,0 +1,32 @@
+use crate::config::InternedString;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A device declared in the config instead of discovered through udev, for virtual devices,
+/// simulations and running the whole pipeline on nodes without the hardware
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ManualDevice {
+  /// Unique id of the device, its syspath is derived from it
+  pub id: InternedString,
+
+  /// Subsystem the device pretends to be in
+  pub subsystem: InternedString,
+
+  /// Device node handed to containers, if any
+  #[serde(default, skip_serializing_if = "InternedString::is_empty")]
+  pub devnode: InternedString,
+
+  /// Attributes device type selectors match against
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub attributes: BTreeMap<InternedString, InternedString>,
+}
+
+impl ManualDevice {
+  /// Syspath the device is registered under, outside of where udev devices live so the two
+  /// can't collide
+  pub fn syspath(&self) -> String {
+    format!("/sys/devices/manual/{}", self.id)
+  }
+}
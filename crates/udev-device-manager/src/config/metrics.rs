@@ -0,0 +1,79 @@
+use super::{Config, ConfigError};
+use prometheus::{IntCounterVec, IntGauge, Opts, Registry};
+
+/// Metrics about config loads, to catch broken config rollouts (e.g. of a ConfigMap)
+#[derive(Clone)]
+pub struct ConfigMetrics {
+  reloads: IntCounterVec,
+  generation: IntGauge,
+}
+
+impl ConfigMetrics {
+  pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+    let reloads = IntCounterVec::new(
+      Opts::new(
+        "udevdm_config_reload_total",
+        "Config loads, by whether the config could be read",
+      ),
+      &["result"],
+    )?;
+    let generation = IntGauge::new(
+      "udevdm_config_generation",
+      "Number of configs successfully loaded so far",
+    )?;
+
+    registry.register(Box::new(reloads.clone()))?;
+    registry.register(Box::new(generation.clone()))?;
+    Ok(Self {
+      reloads,
+      generation,
+    })
+  }
+
+  /// Records the outcome of reading the config. Failed reads leave the generation as is.
+  pub fn record(&self, result: &Result<Config, ConfigError>) {
+    match result {
+      Ok(_) => {
+        self.reloads.with_label_values(&["ok"]).inc();
+        self.generation.inc();
+      }
+      Err(_) => self.reloads.with_label_values(&["error"]).inc(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::ConfigFormat;
+
+  #[tokio::test]
+  async fn reloads_are_counted_by_result() {
+    let metrics = ConfigMetrics::new(&Registry::new()).unwrap();
+    let file = std::env::temp_dir().join(format!(
+      "udev-device-manager-reload-metrics-{}.toml",
+      std::process::id()
+    ));
+    let reload = |content: &'static str| {
+      let file = file.clone();
+      let metrics = metrics.clone();
+      async move {
+        tokio::fs::write(&file, content).await.unwrap();
+        metrics.record(&Config::read(&file, ConfigFormat::Toml).await);
+      }
+    };
+    let reloads = |result: &str| metrics.reloads.with_label_values(&[result]).get();
+
+    reload("devices = []\ndeviceClasses = []\n").await;
+    assert_eq!(reloads("ok"), 1);
+    assert_eq!(reloads("error"), 0);
+    assert_eq!(metrics.generation.get(), 1);
+
+    reload("devices = [\n").await;
+    assert_eq!(reloads("ok"), 1);
+    assert_eq!(reloads("error"), 1);
+    assert_eq!(metrics.generation.get(), 1);
+
+    let _ = tokio::fs::remove_file(&file).await;
+  }
+}
@@ -1,6 +1,7 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use super::Config;
+use super::{inner, validate::join_errors, Config, ConfigValidationError, InternedString};
+use serde::de::DeserializeOwned;
 use thiserror::Error;
 use tokio::{fs, io};
 use tracing::{event, Level};
@@ -15,15 +16,28 @@ pub enum ConfigFormat {
 
 #[derive(Debug, Error)]
 pub enum ConfigError {
-  #[error("Invalid config file extension when using auto format: {0}")]
-  InvalidExtension(String),
-
-  #[error("Config file does not have a file extension, and format is set to auto")]
-  MissingExtension,
+  /// The config source and format can't be used together, e.g. a file without extension
+  /// and the auto format
+  #[error("Unsupported config source: {detail}")]
+  UnsupportedSource { detail: String },
 
   #[error("Failed to parse config file")]
   ParseError(#[from] FormatError),
 
+  #[error("Failed to serialize config")]
+  SerializeError(#[source] FormatError),
+
+  #[error("Invalid config: {}", join_errors(.0))]
+  Validation(Vec<ConfigValidationError>),
+
+  #[error("Device class '{class}' has an invalid {field} {path:?}: {reason}")]
+  InvalidPath {
+    class: InternedString,
+    field: String,
+    path: InternedString,
+    reason: &'static str,
+  },
+
   #[error(transparent)]
   Io(#[from] io::Error),
 }
@@ -38,52 +52,205 @@ pub enum FormatError {
 
   #[error(transparent)]
   TomlError(#[from] toml::de::Error),
+
+  #[error(transparent)]
+  TomlSerializeError(#[from] toml::ser::Error),
 }
 
 trait ConfigParser {
-  fn parse_config(content: &[u8]) -> Result<Config, FormatError>;
+  fn parse_config<T: DeserializeOwned>(content: &[u8]) -> Result<T, FormatError>;
 }
 
 struct Json;
 impl ConfigParser for Json {
-  fn parse_config(content: &[u8]) -> Result<Config, FormatError> {
+  fn parse_config<T: DeserializeOwned>(content: &[u8]) -> Result<T, FormatError> {
     Ok(serde_json::from_slice(content)?)
   }
 }
 
 struct Yaml;
 impl ConfigParser for Yaml {
-  fn parse_config(content: &[u8]) -> Result<Config, FormatError> {
+  fn parse_config<T: DeserializeOwned>(content: &[u8]) -> Result<T, FormatError> {
     Ok(serde_yaml::from_slice(content)?)
   }
 }
 
 struct Toml;
 impl ConfigParser for Toml {
-  fn parse_config(content: &[u8]) -> Result<Config, FormatError> {
+  fn parse_config<T: DeserializeOwned>(content: &[u8]) -> Result<T, FormatError> {
     Ok(toml::from_slice(content)?)
   }
 }
 
+impl ConfigError {
+  fn unsupported_source(detail: impl Into<String>) -> Self {
+    ConfigError::UnsupportedSource {
+      detail: detail.into(),
+    }
+  }
+}
+
+impl Config {
+  /// The config written out in `format`, for `--convert`. Keys are written in their canonical
+  /// spelling, so this also normalizes a hand-edited config.
+  pub fn to_format(&self, format: ConfigFormat) -> Result<String, ConfigError> {
+    let output = match format {
+      ConfigFormat::Json => serde_json::to_string_pretty(self).map_err(FormatError::from),
+      ConfigFormat::Yaml => serde_yaml::to_string(self).map_err(FormatError::from),
+      // TOML needs plain values ahead of tables, which serializing a `Value` takes care of
+      ConfigFormat::Toml => toml::Value::try_from(self)
+        .and_then(|value| toml::to_string_pretty(&value))
+        .map_err(FormatError::from),
+      ConfigFormat::Auto => {
+        return Err(ConfigError::unsupported_source(
+          "the format to write a config in can't be auto",
+        ))
+      }
+    };
+
+    let mut output = output.map_err(ConfigError::SerializeError)?;
+    if !output.ends_with('\n') {
+      output.push('\n');
+    }
+
+    Ok(output)
+  }
+}
+
+/// Format of a config file by its extension
+fn file_format(file: &Path) -> Result<ConfigFormat, ConfigError> {
+  match file.extension().map(|e| e.to_str()) {
+    Some(Some("toml")) => Ok(ConfigFormat::Toml),
+    Some(Some("yaml")) | Some(Some("yml")) => Ok(ConfigFormat::Yaml),
+    Some(Some("json")) => Ok(ConfigFormat::Json),
+    Some(other) => Err(ConfigError::unsupported_source(format!(
+      "unknown extension {:?} of {:?}, set the config format explicitly",
+      other.unwrap_or_default(),
+      file
+    ))),
+    None => Err(ConfigError::unsupported_source(format!(
+      "{:?} has no extension to detect the format from (e.g. stdin), set the config format explicitly",
+      file
+    ))),
+  }
+}
+
+fn validate(config: Config) -> Result<Config, ConfigError> {
+  match config.validate() {
+    Ok(()) => Ok(config),
+    Err(errors) => Err(ConfigError::Validation(errors)),
+  }
+}
+
+/// Paths handed to the kubelet on allocate must be absolute, or the pod is rejected with an
+/// opaque error long after the config was loaded.
+fn check_paths(config: Config) -> Result<Config, ConfigError> {
+  for class in config.device_classes() {
+    let invalid = |field: String, path: InternedString, reason| ConfigError::InvalidPath {
+      class: class.name(),
+      field,
+      path,
+      reason,
+    };
+
+    let mut paths = Vec::new();
+    if let Some(path) = class.container_path_template() {
+      // templates may start with a placeholder like `${devnode}`, which expands to a path
+      if !path.starts_with("${") {
+        paths.push(("containerPath".to_string(), path));
+      }
+    }
+    for (index, mount) in class.mounts().iter().enumerate() {
+      paths.push((
+        format!("mounts[{}].containerPath", index),
+        mount.container_path,
+      ));
+      paths.push((format!("mounts[{}].hostPath", index), mount.host_path));
+    }
+
+    for (field, path) in paths {
+      if path.is_empty() {
+        return Err(invalid(field, path, "path is empty"));
+      }
+      if !Path::new(&*path).is_absolute() {
+        return Err(invalid(field, path, "path is not absolute"));
+      }
+    }
+  }
+
+  Ok(config)
+}
+
 pub(super) async fn read_config(
   file: impl AsRef<Path>,
   format: ConfigFormat,
 ) -> Result<Config, ConfigError> {
   let file = file.as_ref();
+  finish(read_file(file, format).await)
+}
+
+/// Reads every config file in `dir` (by extension, skipping hidden ones) in lexical order,
+/// concatenating their device types, device classes and manual devices
+pub(super) async fn read_config_dir(
+  dir: impl AsRef<Path>,
+  format: ConfigFormat,
+) -> Result<Config, ConfigError> {
+  finish(merge_dir(dir.as_ref(), format).await)
+}
+
+async fn read_file(file: &Path, format: ConfigFormat) -> Result<inner::Config, ConfigError> {
   let content = fs::read(file).await?;
 
-  let result = match format {
+  let format = match format {
+    ConfigFormat::Auto => file_format(file)?,
+    format => format,
+  };
+
+  match format {
     ConfigFormat::Json => Ok(Json::parse_config(&content)?),
     ConfigFormat::Yaml => Ok(Yaml::parse_config(&content)?),
     ConfigFormat::Toml => Ok(Toml::parse_config(&content)?),
-    ConfigFormat::Auto => match file.extension().and_then(|e| e.to_str()) {
-      Some("toml") => Ok(Toml::parse_config(&content)?),
-      Some("yaml") | Some("yml") => Ok(Yaml::parse_config(&content)?),
-      Some("json") => Ok(Json::parse_config(&content)?),
-      Some(other) => Err(ConfigError::InvalidExtension(other.into())),
-      None => Err(ConfigError::MissingExtension),
-    },
-  };
+    ConfigFormat::Auto => Err(ConfigError::unsupported_source(format!(
+      "the format of {:?} was left to detect, set the config format explicitly",
+      file
+    ))),
+  }
+}
+
+async fn merge_dir(dir: &Path, format: ConfigFormat) -> Result<inner::Config, ConfigError> {
+  if format != ConfigFormat::Auto {
+    return Err(ConfigError::unsupported_source(format!(
+      "{:?} is a directory, whose files are read by their extension - the config format must be auto",
+      dir
+    )));
+  }
+
+  let mut files: Vec<(PathBuf, ConfigFormat)> = Vec::new();
+  let mut entries = fs::read_dir(dir).await?;
+  while let Some(entry) = entries.next_entry().await? {
+    let path = entry.path();
+    let hidden = entry.file_name().to_string_lossy().starts_with('.');
+    if let Ok(format) = file_format(&path) {
+      if !hidden && entry.file_type().await?.is_file() {
+        files.push((path, format));
+      }
+    }
+  }
+  files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+  let mut config = inner::Config::default();
+  for (file, format) in files {
+    config.merge(read_file(&file, format).await?);
+  }
+
+  Ok(config)
+}
+
+fn finish(result: Result<inner::Config, ConfigError>) -> Result<Config, ConfigError> {
+  let result = result
+    .map(Config::from)
+    .and_then(validate)
+    .and_then(check_paths);
 
   match result {
     Ok(config) => {
@@ -96,3 +263,373 @@ pub(super) async fn read_config(
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DEVICE_TYPES: &str = r#"
+    [[devices]]
+    name = "conbee2"
+    subsystem = "tty"
+    labels = { type = "conbee2" }
+    selector = { matchAttributes = { idVendor = "1cf1" } }
+  "#;
+
+  async fn read(name: &str, device_classes: &str) -> Result<Config, ConfigError> {
+    let file = std::env::temp_dir().join(format!("udev-device-manager-{}.toml", name));
+    fs::write(&file, format!("{}{}", DEVICE_TYPES, device_classes))
+      .await
+      .unwrap();
+
+    let result = read_config(&file, ConfigFormat::Auto).await;
+    let _ = fs::remove_file(&file).await;
+    result
+  }
+
+  #[tokio::test]
+  async fn class_matching_a_device_type_loads() {
+    let config = read(
+      "satisfiable",
+      r#"
+        [[deviceClasses]]
+        name = "conbee2"
+        subsystem = "tty"
+        target = "conbee2"
+        selector = { matchLabels = { type = "conbee2" } }
+      "#,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(config.device_classes().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn class_matching_templated_labels_loads() {
+    let config = read(
+      "templated",
+      r#"
+        [[devices]]
+        name = "serial"
+        subsystem = "tty"
+        labels = { vendor = "${attr:idVendor}" }
+        selector = {}
+
+        [[deviceClasses]]
+        name = "conbee2"
+        subsystem = "tty"
+        target = "conbee2"
+        selector = { matchLabels = { vendor = "1cf1" } }
+      "#,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(config.device_classes().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn class_matching_no_device_type_is_rejected() {
+    let error = read(
+      "unsatisfiable",
+      r#"
+        [[deviceClasses]]
+        name = "gpu"
+        subsystem = "drm"
+        target = "/dev/dri/card#"
+        selector = {}
+      "#,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+      matches!(&error, ConfigError::Validation(errors) if *errors == [ConfigValidationError::UnsatisfiableClass(InternedString::new("gpu"))]),
+      "{:?}",
+      error
+    );
+  }
+
+  #[tokio::test]
+  async fn relative_mount_container_path_is_rejected() {
+    let error = read(
+      "relative-mount",
+      r#"
+        [[deviceClasses]]
+        name = "conbee2"
+        subsystem = "tty"
+        target = "conbee2"
+        selector = {}
+        mounts = [{ containerPath = "opt/zigbee", hostPath = "/usr/share/zigbee" }]
+      "#,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+      matches!(&error, ConfigError::InvalidPath { field, .. } if field == "mounts[0].containerPath"),
+      "{:?}",
+      error
+    );
+  }
+
+  #[tokio::test]
+  async fn empty_mount_host_path_is_rejected() {
+    let error = read(
+      "empty-mount",
+      r#"
+        [[deviceClasses]]
+        name = "conbee2"
+        subsystem = "tty"
+        target = "conbee2"
+        selector = {}
+        mounts = [{ containerPath = "/opt/zigbee", hostPath = "" }]
+      "#,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+      matches!(&error, ConfigError::InvalidPath { field, reason, .. } if field == "mounts[0].hostPath" && *reason == "path is empty"),
+      "{:?}",
+      error
+    );
+  }
+
+  async fn read_dir(name: &str, files: &[(&str, &str)]) -> Result<Config, ConfigError> {
+    let dir = std::env::temp_dir().join(format!(
+      "udev-device-manager-{}-{}.d",
+      name,
+      std::process::id()
+    ));
+    fs::create_dir_all(&dir).await.unwrap();
+    for (file, content) in files {
+      fs::write(dir.join(file), content).await.unwrap();
+    }
+
+    let result = Config::read(&dir, ConfigFormat::Auto).await;
+    let _ = fs::remove_dir_all(&dir).await;
+    result
+  }
+
+  #[tokio::test]
+  async fn config_dir_files_are_merged_in_order() {
+    let config = read_dir(
+      "merged",
+      &[
+        ("20-classes.json", r#"{ "deviceClasses": [{ "name": "conbee2", "subsystem": "tty", "target": "conbee2", "selector": { "matchLabels": { "type": "conbee2" } } }] }"#),
+        ("10-devices.toml", DEVICE_TYPES),
+        ("30-serial.yaml", "devices:\n  - name: serial\n    subsystem: tty\n    labels: {}\n    selector: {}\n"),
+        ("README.md", "not a config file"),
+        (".40-editor.toml.swp", "not a config file either"),
+      ],
+    )
+    .await
+    .unwrap();
+
+    let device_types = config
+      .device_types()
+      .iter()
+      .map(|ty| ty.name().to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(device_types, vec!["conbee2", "serial"]);
+    assert_eq!(config.device_classes().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn config_dir_needs_the_auto_format() {
+    let dir = std::env::temp_dir().join(format!(
+      "udev-device-manager-format-{}.d",
+      std::process::id()
+    ));
+    fs::create_dir_all(&dir).await.unwrap();
+    let error = Config::read(&dir, ConfigFormat::Toml).await.unwrap_err();
+    let _ = fs::remove_dir_all(&dir).await;
+
+    assert!(
+      matches!(&error, ConfigError::UnsupportedSource { .. }),
+      "{:?}",
+      error
+    );
+  }
+
+  #[tokio::test]
+  async fn auto_format_needs_a_known_extension() {
+    for extension in &["", ".ini"] {
+      let file = std::env::temp_dir().join(format!(
+        "udev-device-manager-config-{}{}",
+        std::process::id(),
+        extension
+      ));
+      fs::write(&file, DEVICE_TYPES).await.unwrap();
+      let error = read_config(&file, ConfigFormat::Auto).await.unwrap_err();
+
+      // an explicit format reads the same file fine
+      let config = read_config(&file, ConfigFormat::Toml).await;
+      let _ = fs::remove_file(&file).await;
+
+      assert!(
+        matches!(&error, ConfigError::UnsupportedSource { detail } if detail.contains("set the config format explicitly")),
+        "{:?}",
+        error
+      );
+      assert!(config.is_ok(), "{:?}", config);
+    }
+  }
+
+  #[tokio::test]
+  async fn config_dir_name_collisions_are_rejected() {
+    let error = read_dir(
+      "collision",
+      &[("a.toml", DEVICE_TYPES), ("b.toml", DEVICE_TYPES)],
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+      matches!(&error, ConfigError::Validation(errors) if *errors == [ConfigValidationError::DuplicateDeviceType(InternedString::new("conbee2"))]),
+      "{:?}",
+      error
+    );
+  }
+
+  #[test]
+  fn snake_case_keys_parse_like_camel_case_keys() {
+    let camel_case: Config = toml::from_str(
+      r#"
+        [[devices]]
+        name = "conbee2"
+        subsystem = "tty"
+        idScheme = "compact"
+        pathAttributes = ["driver"]
+        labels = { type = "conbee2" }
+        selector = { matchAttributes = { idVendor = "1cf1" }, matchExpressions = [] }
+
+        [[deviceClasses]]
+        name = "conbee2"
+        subsystem = "tty"
+        target = "conbee2"
+        groupBy = "type"
+        containerPath = "/dev/zigbee"
+        allowDuplicates = true
+        initialListTimeout = 5
+        heartbeatInterval = 60
+        preferNumaAlignment = true
+        selector = { matchLabels = { type = "conbee2" } }
+        mounts = [{ containerPath = "/opt/zigbee", hostPath = "/usr/share/zigbee", readOnly = true }]
+      "#,
+    )
+    .unwrap();
+
+    let snake_case: Config = toml::from_str(
+      r#"
+        [[device_types]]
+        name = "conbee2"
+        subsystem = "tty"
+        id_scheme = "compact"
+        path_attributes = ["driver"]
+        labels = { type = "conbee2" }
+        selector = { match_attributes = { idVendor = "1cf1" }, match_expressions = [] }
+
+        [[device_classes]]
+        name = "conbee2"
+        subsystem = "tty"
+        target = "conbee2"
+        group_by = "type"
+        container_path = "/dev/zigbee"
+        allow_duplicates = true
+        initial_list_timeout = 5
+        heartbeat_interval = 60
+        prefer_numa_alignment = true
+        selector = { match_labels = { type = "conbee2" } }
+        mounts = [{ container_path = "/opt/zigbee", host_path = "/usr/share/zigbee", read_only = true }]
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(camel_case, snake_case);
+
+    // the canonical spelling is what gets written back
+    let written = serde_json::to_value(&snake_case).unwrap();
+    assert!(written.get("devices").is_some());
+    assert!(written["deviceClasses"][0]
+      .get("preferNumaAlignment")
+      .is_some());
+  }
+
+  #[test]
+  fn conversions_round_trip() {
+    let yaml = r#"
+      devices:
+        - name: conbee2
+          subsystem: tty
+          access: 2
+          idScheme: compact
+          labels: { type: conbee2, port: "${attr:devpath}" }
+          selector:
+            matchAttributes: { idVendor: "1cf1" }
+            matchExpressions:
+              - { key: serial, operator: Exists }
+              - { key: idProduct, operator: In, values: ["0030", "0031"] }
+        - name: gpu
+          subsystem: drm
+          access: shared
+          enabled: false
+          labels: { type: gpu }
+          selector: {}
+      deviceClasses:
+        - name: zigbee
+          subsystem: tty
+          target: zigbee
+          groupBy: port
+          containerPath: /dev/zigbee
+          heartbeatInterval: 60
+          envs: { ZIGBEE_PORT: "${DEVNODE}" }
+          selector:
+            matchLabels: { type: conbee2 }
+            exclude:
+              matchExpressions: [{ key: port, operator: DoesNotExist }]
+          mounts: [{ containerPath: /opt/zigbee, hostPath: /usr/share/zigbee, readOnly: true }]
+      manualDevices:
+        - id: virtual0
+          subsystem: tty
+          devnode: /dev/ttyV0
+          attributes: { idVendor: "1cf1" }
+      maintenance: true
+    "#;
+
+    let parse = |format: ConfigFormat, content: &str| -> Config {
+      match format {
+        ConfigFormat::Json => Json::parse_config(content.as_bytes()),
+        ConfigFormat::Yaml => Yaml::parse_config(content.as_bytes()),
+        ConfigFormat::Toml => Toml::parse_config(content.as_bytes()),
+        ConfigFormat::Auto => unreachable!(),
+      }
+      .unwrap_or_else(|e| panic!("{:?} didn't parse back: {}\n{}", format, e, content))
+    };
+
+    let original = parse(ConfigFormat::Yaml, yaml);
+    let mut config = original.clone();
+    for &format in &[ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+      let written = config.to_format(format).unwrap();
+      config = parse(format, &written);
+      assert_eq!(config, original, "changed by a {:?} round trip", format);
+    }
+
+    assert!(original.to_format(ConfigFormat::Auto).is_err());
+  }
+
+  #[test]
+  fn device_types_key_is_an_alias_of_devices() {
+    let devices: Config = toml::from_str(&format!("deviceClasses = []\n{}", DEVICE_TYPES)).unwrap();
+    let device_types: Config = toml::from_str(&format!(
+      "deviceClasses = []\n{}",
+      DEVICE_TYPES.replace("[[devices]]", "[[deviceTypes]]")
+    ))
+    .unwrap();
+
+    assert_eq!(devices, device_types);
+    assert_eq!(device_types.device_types().len(), 1);
+  }
+}
@@ -0,0 +1,135 @@
+use super::{schema::schema_from_json, InternedString};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeMap, convert::TryFrom, fmt};
+
+const DEFAULT_PERMISSIONS: &str = "rwm";
+
+/// Cgroup permissions containers get on allocated devices - any of `r` (read), `w` (write)
+/// and `m` (mknod), e.g. `rwm`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "InternedString", try_from = "InternedString")]
+pub struct DevicePermissions(InternedString);
+
+impl DevicePermissions {
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl Default for DevicePermissions {
+  fn default() -> Self {
+    DevicePermissions(InternedString::new_static(DEFAULT_PERMISSIONS))
+  }
+}
+
+impl TryFrom<InternedString> for DevicePermissions {
+  type Error = String;
+
+  fn try_from(value: InternedString) -> Result<Self, Self::Error> {
+    // each of the permissions at most once
+    let permissions = value.as_str();
+    let valid = !permissions.is_empty()
+      && permissions
+        .char_indices()
+        .all(|(i, c)| DEFAULT_PERMISSIONS.contains(c) && !permissions[..i].contains(c));
+
+    match valid {
+      true => Ok(DevicePermissions(value)),
+      false => Err(format!(
+        "invalid device permissions {:?}, expected a combination of 'r', 'w' and 'm'",
+        permissions
+      )),
+    }
+  }
+}
+
+impl From<DevicePermissions> for InternedString {
+  fn from(permissions: DevicePermissions) -> Self {
+    permissions.0
+  }
+}
+
+impl fmt::Display for DevicePermissions {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl JsonSchema for DevicePermissions {
+  fn schema_name() -> String {
+    stringify!(DevicePermissions).into()
+  }
+
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    // doesn't catch repeated permissions, which the config parser rejects
+    schema_from_json(json!({ "type": "string", "pattern": "^[rwm]{1,3}$" }))
+  }
+}
+
+/// Device permissions used by classes which don't set their own, by the class subsystem
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PermissionDefaults {
+  /// Permissions for subsystems without an entry of their own
+  #[serde(default, skip_serializing_if = "is_default_permissions")]
+  pub default: DevicePermissions,
+
+  /// Permissions by subsystem
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub subsystems: BTreeMap<InternedString, DevicePermissions>,
+}
+
+impl PermissionDefaults {
+  /// Default permissions for the devices of a subsystem
+  pub fn for_subsystem(&self, subsystem: InternedString) -> DevicePermissions {
+    self
+      .subsystems
+      .get(&subsystem)
+      .copied()
+      .unwrap_or(self.default)
+  }
+
+  /// Applies the defaults set by `other` on top of these
+  pub(super) fn merge(&mut self, other: PermissionDefaults) {
+    if !is_default_permissions(&other.default) {
+      self.default = other.default;
+    }
+    self.subsystems.extend(other.subsystems);
+  }
+
+  pub(super) fn is_default(&self) -> bool {
+    *self == Self::default()
+  }
+}
+
+fn is_default_permissions(permissions: &DevicePermissions) -> bool {
+  *permissions == DevicePermissions::default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_test::{assert_de_tokens_error, assert_tokens, Token};
+
+  #[test]
+  fn permissions_serde() {
+    assert_tokens(
+      &DevicePermissions(InternedString::new_static("r")),
+      &[Token::Str("r")],
+    );
+    assert_de_tokens_error::<DevicePermissions>(
+      &[Token::Str("rx")],
+      "invalid device permissions \"rx\", expected a combination of 'r', 'w' and 'm'",
+    );
+    assert_de_tokens_error::<DevicePermissions>(
+      &[Token::Str("")],
+      "invalid device permissions \"\", expected a combination of 'r', 'w' and 'm'",
+    );
+    assert_de_tokens_error::<DevicePermissions>(
+      &[Token::Str("rr")],
+      "invalid device permissions \"rr\", expected a combination of 'r', 'w' and 'm'",
+    );
+  }
+}
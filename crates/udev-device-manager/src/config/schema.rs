@@ -0,0 +1,95 @@
+use super::inner;
+use schemars::{
+  gen::SchemaSettings,
+  schema::{RootSchema, Schema},
+};
+
+/// JSON Schema of the config file, for validating configs and editor completion
+pub fn config_schema() -> RootSchema {
+  SchemaSettings::draft07()
+    .into_generator()
+    .into_root_schema_for::<inner::Config>()
+}
+
+/// Schema of a type whose serde impls are hand-written, given in its JSON form
+pub(super) fn schema_from_json(schema: serde_json::Value) -> Schema {
+  serde_json::from_value(schema).expect("hand-written schemas are valid")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use jsonschema::JSONSchema;
+  use serde_json::json;
+
+  fn validate(config: &serde_json::Value) -> Result<(), Vec<String>> {
+    let schema = serde_json::to_value(config_schema()).unwrap();
+    let schema = JSONSchema::compile(&schema).expect("generated schema compiles");
+    schema
+      .validate(config)
+      .map_err(|errors| errors.map(|e| e.to_string()).collect())
+  }
+
+  #[test]
+  fn sample_config_is_valid() {
+    let sample: serde_json::Value =
+      toml::from_str(include_str!("../../sample_config.toml")).unwrap();
+    assert_eq!(validate(&sample), Ok(()));
+  }
+
+  #[test]
+  fn custom_serde_types_are_described() {
+    let config = |device_type: serde_json::Value| {
+      json!({
+        "devices": [device_type],
+        "deviceClasses": [],
+      })
+    };
+
+    let valid = config(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "access": 4,
+      "labels": { "type": "conbee2", "cores": 4, "fast": true },
+      "selector": {
+        "matchAttributes": { "idVendor": "1cf1" },
+        "matchExpressions": [
+          { "key": "serial", "operator": "In", "values": ["DE2422340"] },
+          { "key": "busnum", "operator": "Gt", "values": 2 },
+          { "key": "product", "operator": "Matches", "values": "^ConBee" },
+          { "key": "authorized", "operator": "IsTrue" },
+        ],
+      },
+    }));
+    assert_eq!(validate(&valid), Ok(()));
+
+    let invalid_access = config(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "access": 0,
+      "labels": {},
+      "selector": {},
+    }));
+    assert!(validate(&invalid_access).is_err());
+
+    let invalid_operator = config(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "labels": {},
+      "selector": {
+        "matchExpressions": [{ "key": "serial", "operator": "Like", "values": ["DE*"] }],
+      },
+    }));
+    assert!(validate(&invalid_operator).is_err());
+
+    let missing_values = config(json!({
+      "name": "conbee2",
+      "subsystem": "tty",
+      "labels": {},
+      "selector": {
+        "matchExpressions": [{ "key": "serial", "operator": "In" }],
+      },
+    }));
+    assert!(validate(&missing_values).is_err());
+  }
+}
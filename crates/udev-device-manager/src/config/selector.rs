@@ -1,11 +1,14 @@
-use super::InternedString;
+use super::{schema::schema_from_json, string::deserialize_scalar, InternedString};
+use regex::Regex;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{
   de::{Error, MapAccess, Visitor},
   ser::SerializeStruct,
   Deserialize, Deserializer, Serialize, Serializer,
 };
+use serde_json::json;
 use smallvec::{smallvec, SmallVec};
-use std::{collections::BTreeMap, fmt, marker::PhantomData, ops};
+use std::{cmp::Ordering, collections::BTreeMap, fmt, marker::PhantomData, ops, sync::Arc};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "operator", content = "values")]
@@ -21,6 +24,29 @@ pub enum SelectorValueRequirement {
 
   /// Require that a value does not exist
   DoesNotExist,
+
+  /// Require that the value matches one of a set of glob patterns, where `*` matches any
+  /// run of characters and `?` matches a single character (e.g. `usb:v1D6B*` on `modalias`)
+  Glob(SmallVec<[InternedString; 2]>),
+
+  /// Require that the value matches a regular expression. Unanchored, so `^` and `$` are
+  /// needed to match the whole value.
+  Matches(SelectorRegex),
+
+  /// Require that the value doesn't match a regular expression
+  DoesNotMatch(SelectorRegex),
+
+  /// Require that the value is a number greater than the given one
+  Gt(#[serde(deserialize_with = "deserialize_scalar")] InternedString),
+
+  /// Require that the value is a number less than the given one
+  Lt(#[serde(deserialize_with = "deserialize_scalar")] InternedString),
+
+  /// Require that the value is a boolean that is true
+  IsTrue,
+
+  /// Require that the value is a boolean that is false
+  IsFalse,
 }
 
 impl SelectorValueRequirement {
@@ -29,6 +55,13 @@ impl SelectorValueRequirement {
       (None, Self::DoesNotExist | Self::NotIn(_)) => MatchResult::Matches,
       (None, Self::In(vs)) => MatchResult::expected_one_of(field, vs, value),
       (None, Self::Exists) => MatchResult::expected_any(field, value),
+      (None, Self::Glob(ps)) => MatchResult::expected_matching(field, ps, value),
+      (None, Self::Matches(r)) => MatchResult::expected_regex(field, r, value),
+      (None, Self::DoesNotMatch(_)) => MatchResult::Matches,
+      (None, Self::Gt(v)) => MatchResult::expected_greater_than(field, *v, value),
+      (None, Self::Lt(v)) => MatchResult::expected_less_than(field, *v, value),
+      (None, Self::IsTrue) => MatchResult::expected_bool(field, true, value),
+      (None, Self::IsFalse) => MatchResult::expected_bool(field, false, value),
       (Some(_), Self::Exists) => MatchResult::Matches,
       (Some(_), Self::DoesNotExist) => MatchResult::expected_none(field, value),
       (Some(v), Self::In(vs)) => {
@@ -45,20 +78,211 @@ impl SelectorValueRequirement {
           MatchResult::expected_none_of(field, vs, value)
         }
       }
+      (Some(v), Self::Glob(ps)) => {
+        if ps.iter().any(|p| glob_match(p, &v)) {
+          MatchResult::Matches
+        } else {
+          MatchResult::expected_matching(field, ps, value)
+        }
+      }
+      (Some(v), Self::Matches(r)) => {
+        if r.is_match(&v) {
+          MatchResult::Matches
+        } else {
+          MatchResult::expected_regex(field, r, value)
+        }
+      }
+      (Some(v), Self::DoesNotMatch(r)) => {
+        if !r.is_match(&v) {
+          MatchResult::Matches
+        } else {
+          MatchResult::expected_not_regex(field, r, value)
+        }
+      }
+      (Some(v), Self::Gt(bound)) => match compare_numeric(&v, bound) {
+        Some(Ordering::Greater) => MatchResult::Matches,
+        _ => MatchResult::expected_greater_than(field, *bound, value),
+      },
+      (Some(v), Self::Lt(bound)) => match compare_numeric(&v, bound) {
+        Some(Ordering::Less) => MatchResult::Matches,
+        _ => MatchResult::expected_less_than(field, *bound, value),
+      },
+      (Some(v), Self::IsTrue) => match v.as_bool() {
+        Some(true) => MatchResult::Matches,
+        _ => MatchResult::expected_bool(field, true, value),
+      },
+      (Some(v), Self::IsFalse) => match v.as_bool() {
+        Some(false) => MatchResult::Matches,
+        _ => MatchResult::expected_bool(field, false, value),
+      },
+    }
+  }
+}
+
+/// A regular expression in a selector, compiled once when the config is parsed
+#[derive(Clone)]
+pub struct SelectorRegex {
+  source: InternedString,
+  regex: Arc<Regex>,
+}
+
+impl SelectorRegex {
+  pub fn new(source: impl Into<InternedString>) -> Result<Self, regex::Error> {
+    let source = source.into();
+    let regex = Regex::new(&source)?;
+    Ok(Self {
+      source,
+      regex: Arc::new(regex),
+    })
+  }
+
+  pub fn as_str(&self) -> &str {
+    &self.source
+  }
+
+  pub fn is_match(&self, value: &str) -> bool {
+    self.regex.is_match(value)
+  }
+}
+
+impl PartialEq for SelectorRegex {
+  fn eq(&self, other: &Self) -> bool {
+    self.source == other.source
+  }
+}
+
+impl fmt::Debug for SelectorRegex {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(self.as_str(), f)
+  }
+}
+
+impl Serialize for SelectorRegex {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    self.source.serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for SelectorRegex {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let source = InternedString::deserialize(deserializer)?;
+    Self::new(source)
+      .map_err(|error| D::Error::custom(format!("invalid regex {:?}: {}", &*source, error)))
+  }
+}
+
+/// Compares two values numerically - as integers where both are, so large ones keep their
+/// precision, and as floats otherwise. `None` if either isn't a number.
+fn compare_numeric(value: &InternedString, bound: &InternedString) -> Option<Ordering> {
+  match (value.as_i64(), bound.as_i64()) {
+    (Some(value), Some(bound)) => Some(value.cmp(&bound)),
+    _ => value.as_f64()?.partial_cmp(&bound.as_f64()?),
+  }
+}
+
+/// Matches `value` against a glob `pattern` supporting `*` and `?`.
+fn glob_match(pattern: &str, value: &str) -> bool {
+  let pattern = pattern.chars().collect::<SmallVec<[char; 32]>>();
+  let value = value.chars().collect::<SmallVec<[char; 64]>>();
+
+  let (mut p, mut v) = (0, 0);
+  // position of the last `*` in the pattern, and the value position it was tried at
+  let mut backtrack = None;
+
+  while v < value.len() {
+    match pattern.get(p) {
+      Some('*') => {
+        backtrack = Some((p, v));
+        p += 1;
+      }
+      Some('?') => {
+        p += 1;
+        v += 1;
+      }
+      Some(c) if *c == value[v] => {
+        p += 1;
+        v += 1;
+      }
+      _ => match backtrack {
+        // let the last `*` swallow one more character
+        Some((star, at)) => {
+          backtrack = Some((star, at + 1));
+          p = star + 1;
+          v = at + 1;
+        }
+        None => return false,
+      },
     }
   }
+
+  pattern[p..].iter().all(|c| *c == '*')
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SelectorRequirement {
-  /// The attribute key that the selector applies to.
+  /// The attribute key that the selector applies to. For udev attribute selectors, `@subsystem`,
+  /// `@devtype`, `@devnode` and `@syspath` refer to the device itself instead.
   pub key: InternedString,
 
+  /// Read the key from the nearest parent device in this subsystem having it, instead of from
+  /// the whole device hierarchy. Only applies to udev attribute selectors.
+  #[serde(
+    default,
+    rename = "parentSubsystem",
+    alias = "parent_subsystem",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub parent_subsystem: Option<InternedString>,
+
   /// Represents a key's relationship to a set of values.
   #[serde(flatten)]
   pub value_requirement: SelectorValueRequirement,
 }
 
+impl JsonSchema for SelectorRequirement {
+  fn schema_name() -> String {
+    stringify!(SelectorRequirement).into()
+  }
+
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    // the shape of `values` depends on the operator
+    let operator = |operators: &[&str], values: Option<serde_json::Value>| match values {
+      None => json!({ "properties": { "operator": { "enum": operators } } }),
+      Some(values) => json!({
+        "required": ["values"],
+        "properties": { "operator": { "enum": operators }, "values": values },
+      }),
+    };
+
+    schema_from_json(json!({
+      "type": "object",
+      "required": ["key", "operator"],
+      "properties": {
+        "key": { "type": "string" },
+        "parentSubsystem": { "type": "string" },
+      },
+      "oneOf": [
+        operator(
+          &["In", "NotIn", "Glob"],
+          Some(json!({ "type": "array", "items": { "type": "string" } })),
+        ),
+        operator(
+          &["Matches", "DoesNotMatch"],
+          Some(json!({ "type": "string", "format": "regex" })),
+        ),
+        operator(&["Gt", "Lt"], Some(json!({ "type": ["string", "number"] }))),
+        operator(&["Exists", "DoesNotExist", "IsTrue", "IsFalse"], None),
+      ],
+    }))
+  }
+}
+
 #[derive(Clone, Debug, Copy)]
 pub struct Mismatch<'a> {
   field: InternedString,
@@ -72,7 +296,14 @@ pub enum ExpectedValue<'a> {
   None,
   OneOf(&'a SmallVec<[InternedString; 2]>),
   NoneOf(&'a SmallVec<[InternedString; 2]>),
+  Matching(&'a SmallVec<[InternedString; 2]>),
+  MatchingRegex(&'a SelectorRegex),
+  NotMatchingRegex(&'a SelectorRegex),
+  GreaterThan(InternedString),
+  LessThan(InternedString),
+  Bool(bool),
   Value(InternedString),
+  NotExcluded,
 }
 
 #[derive(Clone, Debug)]
@@ -91,6 +322,71 @@ impl<'a> MatchResult<'a> {
   }
 }
 
+impl<'a> fmt::Display for Mismatch<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: expected {}, got ", self.field, self.expected_value)?;
+    match self.actual_value {
+      Some(value) => write!(f, "{:?}", value.as_str()),
+      None => f.write_str("nothing"),
+    }
+  }
+}
+
+impl<'a> fmt::Display for ExpectedValue<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let list = |values: &SmallVec<[InternedString; 2]>| {
+      let values = values
+        .iter()
+        .map(|v| format!("{:?}", v.as_str()))
+        .collect::<Vec<_>>();
+      format!("[{}]", values.join(", "))
+    };
+
+    match self {
+      ExpectedValue::Any => f.write_str("any value"),
+      ExpectedValue::None => f.write_str("no value"),
+      ExpectedValue::OneOf(values) => write!(f, "one of {}", list(values)),
+      ExpectedValue::NoneOf(values) => write!(f, "none of {}", list(values)),
+      ExpectedValue::Matching(patterns) => write!(f, "a match of {}", list(patterns)),
+      ExpectedValue::MatchingRegex(regex) => write!(f, "a match of /{}/", regex.as_str()),
+      ExpectedValue::NotMatchingRegex(regex) => write!(f, "no match of /{}/", regex.as_str()),
+      ExpectedValue::GreaterThan(bound) => write!(f, "more than {}", bound),
+      ExpectedValue::LessThan(bound) => write!(f, "less than {}", bound),
+      ExpectedValue::Bool(value) => write!(f, "{}", value),
+      ExpectedValue::Value(value) => write!(f, "{:?}", value.as_str()),
+      ExpectedValue::NotExcluded => f.write_str("not to be excluded"),
+    }
+  }
+}
+
+impl<'a> MatchResult<'a> {
+  /// Every requirement that wasn't met, none for a match
+  pub fn mismatches(&self) -> &[Mismatch<'a>] {
+    match self {
+      MatchResult::Matches => &[],
+      MatchResult::Mismatch(mismatches) => mismatches.as_slice(),
+    }
+  }
+}
+
+/// Renders the mismatches separated by `; `, e.g. for a `mismatches` tracing field
+impl<'a> fmt::Display for MatchResult<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.is_match() {
+      return f.write_str("matches");
+    }
+
+    for (i, mismatch) in self.mismatches().iter().enumerate() {
+      if i > 0 {
+        f.write_str("; ")?;
+      }
+      write!(f, "{}", mismatch)?;
+    }
+
+    Ok(())
+  }
+}
+
 impl<'a> ops::AddAssign for MatchResult<'a> {
   fn add_assign(&mut self, rhs: Self) {
     match self {
@@ -128,6 +424,74 @@ impl<'a> MatchResult<'a> {
     }])
   }
 
+  pub fn expected_matching(
+    field: InternedString,
+    patterns: &'a SmallVec<[InternedString; 2]>,
+    actual: Option<InternedString>,
+  ) -> Self {
+    Self::Mismatch(smallvec![Mismatch {
+      field,
+      expected_value: ExpectedValue::Matching(patterns),
+      actual_value: actual,
+    }])
+  }
+
+  pub fn expected_regex(
+    field: InternedString,
+    regex: &'a SelectorRegex,
+    actual: Option<InternedString>,
+  ) -> Self {
+    Self::Mismatch(smallvec![Mismatch {
+      field,
+      expected_value: ExpectedValue::MatchingRegex(regex),
+      actual_value: actual,
+    }])
+  }
+
+  pub fn expected_not_regex(
+    field: InternedString,
+    regex: &'a SelectorRegex,
+    actual: Option<InternedString>,
+  ) -> Self {
+    Self::Mismatch(smallvec![Mismatch {
+      field,
+      expected_value: ExpectedValue::NotMatchingRegex(regex),
+      actual_value: actual,
+    }])
+  }
+
+  pub fn expected_greater_than(
+    field: InternedString,
+    bound: InternedString,
+    actual: Option<InternedString>,
+  ) -> Self {
+    Self::Mismatch(smallvec![Mismatch {
+      field,
+      expected_value: ExpectedValue::GreaterThan(bound),
+      actual_value: actual,
+    }])
+  }
+
+  pub fn expected_less_than(
+    field: InternedString,
+    bound: InternedString,
+    actual: Option<InternedString>,
+  ) -> Self {
+    Self::Mismatch(smallvec![Mismatch {
+      field,
+      expected_value: ExpectedValue::LessThan(bound),
+      actual_value: actual,
+    }])
+  }
+
+  pub fn expected_bool(field: InternedString, value: bool, actual: Option<InternedString>) -> Self {
+    Self::Mismatch(smallvec![Mismatch {
+      field,
+      expected_value: ExpectedValue::Bool(value),
+      actual_value: actual,
+    }])
+  }
+
   pub fn expected_any(field: InternedString, actual: Option<InternedString>) -> Self {
     Self::Mismatch(smallvec![Mismatch {
       field,
@@ -144,6 +508,14 @@ impl<'a> MatchResult<'a> {
     }])
   }
 
+  pub fn excluded(field: InternedString) -> Self {
+    Self::Mismatch(smallvec![Mismatch {
+      field,
+      expected_value: ExpectedValue::NotExcluded,
+      actual_value: None,
+    }])
+  }
+
   pub fn expected_value(
     field: InternedString,
     value: InternedString,
@@ -158,10 +530,13 @@ impl<'a> MatchResult<'a> {
 }
 
 impl SelectorRequirement {
-  pub fn match_with(&self, get_value: &impl Fn(&str) -> Option<InternedString>) -> MatchResult {
+  pub fn match_with(
+    &self,
+    get_value: &impl Fn(Option<InternedString>, &str) -> Option<InternedString>,
+  ) -> MatchResult<'_> {
     self
       .value_requirement
-      .match_with(get_value(&*self.key), self.key)
+      .match_with(get_value(self.parent_subsystem, &*self.key), self.key)
   }
 }
 
@@ -176,12 +551,60 @@ pub struct Selector<T: SelectorType> {
   marker: PhantomData<T>,
 }
 
+impl<T: SelectorType> Default for Selector<T> {
+  fn default() -> Self {
+    Self {
+      flat: None,
+      expressions: None,
+      marker: PhantomData,
+    }
+  }
+}
+
 impl<T: SelectorType> Selector<T> {
+  /// Every key the selector has a requirement on
+  pub fn keys(&self) -> impl Iterator<Item = InternedString> + '_ {
+    let flat = self.flat.iter().flat_map(|flat| flat.keys().copied());
+    let expressions = self.expressions.iter().flatten().map(|expr| expr.key);
+    flat.chain(expressions)
+  }
+
+  /// Adds a requirement that `key` has exactly `value`
+  pub fn insert_flat(&mut self, key: InternedString, value: InternedString) {
+    self
+      .flat
+      .get_or_insert_with(Default::default)
+      .insert(key, value);
+  }
+
+  /// Adds an expression requirement
+  pub fn push_expression(&mut self, requirement: SelectorRequirement) {
+    self
+      .expressions
+      .get_or_insert_with(Default::default)
+      .push(requirement);
+  }
+
+  /// Whether the selector has no requirements at all (and thus matches anything)
+  pub fn is_empty(&self) -> bool {
+    self.flat.as_ref().is_none_or(|f| f.is_empty())
+      && self.expressions.as_ref().is_none_or(|e| e.is_empty())
+  }
+
   pub fn match_with(&self, get_value: &impl Fn(&str) -> Option<InternedString>) -> MatchResult {
+    self.match_with_scoped(&|_, name| get_value(name))
+  }
+
+  /// Like [`Selector::match_with`], except the value lookup also gets the parent subsystem a
+  /// requirement is scoped to, if any
+  pub fn match_with_scoped(
+    &self,
+    get_value: &impl Fn(Option<InternedString>, &str) -> Option<InternedString>,
+  ) -> MatchResult<'_> {
     let mut result = MatchResult::Matches;
 
     for (name, value) in self.flat.iter().flatten() {
-      let actual_value = get_value(&*name);
+      let actual_value = get_value(None, &*name);
       if actual_value != Some(*value) {
         result += MatchResult::expected_value(*name, *value, actual_value);
       }
@@ -207,6 +630,27 @@ mod ser_de {
   use super::*;
 
   const MATCH_EXPRESSIONS_KEY: &str = "matchExpressions";
+  const MATCH_EXPRESSIONS_ALIAS: &str = "match_expressions";
+  const FLAT_KEYS_FALLBACK_NAME: &str = "matchKeys";
+
+  /// The snake_case spelling of a camelCase key
+  fn snake_case(key: &str) -> String {
+    let mut snake_case = String::with_capacity(key.len() + 2);
+    for c in key.chars() {
+      if c.is_ascii_uppercase() {
+        snake_case.push('_');
+      }
+
+      snake_case.push(c.to_ascii_lowercase());
+    }
+
+    snake_case
+  }
+
+  /// Whether a field name is the given camelCase key, or its snake_case spelling
+  fn is_key(v: &[u8], key: &str) -> bool {
+    v == key.as_bytes() || v == snake_case(key).as_bytes()
+  }
 
   impl<T: SelectorType> Serialize for Selector<T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -269,22 +713,16 @@ mod ser_de {
     where
       E: Error,
     {
-      if v == MATCH_EXPRESSIONS_KEY {
-        Ok(Field::Expressions)
-      } else if Some(v) == T::FLAT_KEYS_NAME {
-        Ok(Field::Flat)
-      } else {
-        Ok(Field::Ignore(PhantomData))
-      }
+      self.visit_bytes(v.as_bytes())
     }
 
     fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
     where
       E: Error,
     {
-      if v == MATCH_EXPRESSIONS_KEY.as_bytes() {
+      if is_key(v, MATCH_EXPRESSIONS_KEY) {
         Ok(Field::Expressions)
-      } else if Some(v) == T::FLAT_KEYS_NAME.map(|v| v.as_bytes()) {
+      } else if matches!(T::FLAT_KEYS_NAME, Some(key) if is_key(v, key)) {
         Ok(Field::Flat)
       } else {
         Ok(Field::Ignore(PhantomData))
@@ -359,9 +797,10 @@ mod ser_de {
         match key {
           Field::Flat => {
             if flat.is_some() {
-              // SAFETY: we know that FLAT_KEYS_NAME is not none here, cause we got a Field::Flat
+              // NOTE: Field::Flat is only produced when FLAT_KEYS_NAME is set, the fallback
+              // is never used in practice.
               return Err(<A::Error as Error>::duplicate_field(
-                T::FLAT_KEYS_NAME.unwrap(),
+                T::FLAT_KEYS_NAME.unwrap_or(FLAT_KEYS_FALLBACK_NAME),
               ));
             }
 
@@ -369,10 +808,7 @@ mod ser_de {
           }
           Field::Expressions => {
             if expressions.is_some() {
-              // SAFETY: we know that FLAT_KEYS_NAME is not none here, cause we got a Field::Flat
-              return Err(<A::Error as Error>::duplicate_field(
-                T::FLAT_KEYS_NAME.unwrap(),
-              ));
+              return Err(<A::Error as Error>::duplicate_field(MATCH_EXPRESSIONS_KEY));
             }
 
             expressions = Some(map.next_value()?);
@@ -397,12 +833,20 @@ mod ser_de {
   static FIELD_CACHES: Lazy<Mutex<HashMap<&'static str, &'static [&'static str]>>> =
     Lazy::new(Default::default);
 
+  /// Field names passed to the deserializer, which includes the snake_case spellings as a
+  /// flattened selector is only handed the fields listed here
   fn get_field_names(flat_field_name: &'static str) -> &'static [&'static str] {
     let mut lock = FIELD_CACHES.lock().unwrap();
     match lock.entry(flat_field_name) {
       Entry::Occupied(v) => *v.get(),
       Entry::Vacant(entry) => {
-        let arr = [flat_field_name, MATCH_EXPRESSIONS_KEY];
+        let flat_field_alias: &'static str = Box::leak(snake_case(flat_field_name).into());
+        let arr = [
+          flat_field_name,
+          flat_field_alias,
+          MATCH_EXPRESSIONS_KEY,
+          MATCH_EXPRESSIONS_ALIAS,
+        ];
         let boxed: Box<[&'static str]> = Box::new(arr);
         let leaked: &'static [&'static str] = Box::leak(boxed);
         entry.insert(leaked);
@@ -424,8 +868,40 @@ mod ser_de {
         let fields = get_field_names(flat_field_name);
         deserializer.deserialize_struct("Selector", fields, visitor)
       } else {
-        deserializer.deserialize_struct("Selector", &[MATCH_EXPRESSIONS_KEY], visitor)
+        deserializer.deserialize_struct(
+          "Selector",
+          &[MATCH_EXPRESSIONS_KEY, MATCH_EXPRESSIONS_ALIAS],
+          visitor,
+        )
+      }
+    }
+  }
+
+  impl<T: SelectorType> JsonSchema for Selector<T> {
+    fn schema_name() -> String {
+      stringify!(Selector).into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+      let mut properties = serde_json::Map::new();
+      if let Some(flat_keys_name) = T::FLAT_KEYS_NAME {
+        properties.insert(
+          flat_keys_name.into(),
+          json!({ "type": "object", "additionalProperties": { "type": "string" } }),
+        );
       }
+
+      properties.insert(
+        MATCH_EXPRESSIONS_KEY.into(),
+        json!({ "type": "array", "items": gen.subschema_for::<SelectorRequirement>() }),
+      );
+
+      schema_from_json(json!({ "type": "object", "properties": properties }))
+    }
+
+    // the flat keys differ per selector type, so it's inlined instead of shared by reference
+    fn is_referenceable() -> bool {
+      false
     }
   }
 }
@@ -433,9 +909,25 @@ mod ser_de {
 #[cfg(test)]
 mod tests {
   use super::*;
-  use serde_test::{assert_tokens, Token};
+  use serde_test::{
+    assert_de_tokens, assert_de_tokens_error, assert_ser_tokens, assert_tokens, Token,
+  };
   use smallvec::smallvec;
 
+  #[derive(Debug, PartialEq)]
+  struct ExpressionsOnly;
+
+  impl SelectorType for ExpressionsOnly {
+    const FLAT_KEYS_NAME: Option<&'static str> = None;
+  }
+
+  #[derive(Debug, PartialEq)]
+  struct WithFlatKeys;
+
+  impl SelectorType for WithFlatKeys {
+    const FLAT_KEYS_NAME: Option<&'static str> = Some("matchLabels");
+  }
+
   #[derive(Debug, Serialize, Deserialize, PartialEq)]
   struct LabelsSelector {
     #[serde(flatten)]
@@ -460,10 +952,12 @@ mod tests {
           expressions: Some(vec![
             SelectorRequirement {
               key: InternedString::new_static("idVendor"),
+              parent_subsystem: None,
               value_requirement: SelectorValueRequirement::Exists,
             },
             SelectorRequirement {
               key: InternedString::new_static("idProduct"),
+              parent_subsystem: None,
               value_requirement: SelectorValueRequirement::NotIn(smallvec![
                 InternedString::new_static("0030"),
                 InternedString::new_static("DE2422340"),
@@ -510,4 +1004,221 @@ mod tests {
       ],
     )
   }
+
+  #[test]
+  fn duplicate_expressions_without_flat_keys() {
+    assert_de_tokens_error::<Selector<ExpressionsOnly>>(
+      &[
+        Token::Map { len: None },
+        Token::Str("matchExpressions"),
+        Token::Some,
+        Token::Seq { len: Some(0) },
+        Token::SeqEnd,
+        Token::Str("matchExpressions"),
+      ],
+      "duplicate field `matchExpressions`",
+    )
+  }
+
+  #[test]
+  fn duplicate_expressions_with_flat_keys() {
+    assert_de_tokens_error::<Selector<WithFlatKeys>>(
+      &[
+        Token::Map { len: None },
+        Token::Str("matchExpressions"),
+        Token::Some,
+        Token::Seq { len: Some(0) },
+        Token::SeqEnd,
+        Token::Str("matchExpressions"),
+      ],
+      "duplicate field `matchExpressions`",
+    )
+  }
+
+  #[test]
+  fn duplicate_flat_keys() {
+    assert_de_tokens_error::<Selector<WithFlatKeys>>(
+      &[
+        Token::Map { len: None },
+        Token::Str("matchLabels"),
+        Token::Some,
+        Token::Map { len: Some(0) },
+        Token::MapEnd,
+        Token::Str("matchLabels"),
+      ],
+      "duplicate field `matchLabels`",
+    )
+  }
+
+  #[test]
+  fn glob_patterns() {
+    assert!(glob_match("usb:v1D6B*", "usb:v1D6Bp0002d0504dc09"));
+    assert!(glob_match("usb:v????p0002*", "usb:v1D6Bp0002d0504dc09"));
+    assert!(glob_match(
+      "*ic09*",
+      "usb:v1D6Bp0002d0504dc09dsc00dp03ic09isc00"
+    ));
+    assert!(glob_match("*", ""));
+    assert!(!glob_match("usb:v1D6B*", "usb:v1CF1p0030"));
+    assert!(!glob_match("usb:v1D6B", "usb:v1D6Bp0002"));
+    assert!(!glob_match("?", ""));
+  }
+
+  #[test]
+  fn numeric_requirement_serde() {
+    let requirement = SelectorRequirement {
+      key: InternedString::new_static("speed"),
+      parent_subsystem: None,
+      value_requirement: SelectorValueRequirement::Gt(InternedString::new_static("1000")),
+    };
+
+    // the flattened operator serializes as a unit variant, but is read from any string
+    let tokens = |operator| {
+      [
+        Token::Map { len: None },
+        Token::Str("key"),
+        Token::Str("speed"),
+        Token::Str("operator"),
+        operator,
+        Token::Str("values"),
+        Token::Str("1000"),
+        Token::MapEnd,
+      ]
+    };
+    assert_ser_tokens(
+      &requirement,
+      &tokens(Token::UnitVariant {
+        name: "SelectorValueRequirement",
+        variant: "Gt",
+      }),
+    );
+    assert_de_tokens(&requirement, &tokens(Token::Str("Gt")));
+
+    assert_de_tokens(
+      &SelectorRequirement {
+        key: InternedString::new_static("load"),
+        parent_subsystem: None,
+        value_requirement: SelectorValueRequirement::Lt(InternedString::new_static("0.5")),
+      },
+      &[
+        Token::Map { len: None },
+        Token::Str("key"),
+        Token::Str("load"),
+        Token::Str("operator"),
+        Token::Str("Lt"),
+        Token::Str("values"),
+        Token::F64(0.5),
+        Token::MapEnd,
+      ],
+    );
+  }
+
+  #[test]
+  fn numeric_requirements() {
+    let field = InternedString::new_static("speed");
+    let matches = |requirement: &SelectorValueRequirement, value: &str| {
+      requirement
+        .match_with(Some(InternedString::new(value)), field)
+        .is_match()
+    };
+
+    let gt = SelectorValueRequirement::Gt(InternedString::new_static("1000"));
+    assert!(matches(&gt, "10000"));
+    assert!(matches(&gt, "1000.5"));
+    assert!(matches(&gt, "0x400"));
+    assert!(!matches(&gt, "1000"));
+    assert!(!matches(&gt, "999.9"));
+
+    let lt = SelectorValueRequirement::Lt(InternedString::new_static("2.5"));
+    assert!(matches(&lt, "2"));
+    assert!(matches(&lt, "-3.75"));
+    assert!(!matches(&lt, "2.5"));
+
+    // non-numeric values on either side fail the match, without erroring
+    assert!(!matches(&gt, "fast"));
+    assert!(!matches(&gt, ""));
+    assert!(!matches(
+      &SelectorValueRequirement::Lt(InternedString::new_static("slow")),
+      "1"
+    ));
+    assert!(!gt.match_with(None, field).is_match());
+  }
+
+  #[test]
+  fn regex_requirements() {
+    let requirement = |operator: &str| {
+      serde_json::from_value::<SelectorRequirement>(serde_json::json!({
+        "key": "devpath",
+        "operator": operator,
+        "values": "^/devices/pci0000:00/.*/tty/ttyACM[0-9]+$",
+      }))
+      .unwrap()
+    };
+    let matches = |requirement: &SelectorRequirement, devpath: &str| {
+      requirement
+        .value_requirement
+        .match_with(Some(InternedString::new(devpath)), requirement.key)
+        .is_match()
+    };
+
+    let acm = "/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/tty/ttyACM0";
+    let usb = "/devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3:1.0/ttyUSB0/tty/ttyUSB0";
+
+    let positive = requirement("Matches");
+    assert!(matches(&positive, acm));
+    assert!(!matches(&positive, usb));
+    assert!(!positive
+      .value_requirement
+      .match_with(None, positive.key)
+      .is_match());
+
+    let negative = requirement("DoesNotMatch");
+    assert!(!matches(&negative, acm));
+    assert!(matches(&negative, usb));
+    assert!(negative
+      .value_requirement
+      .match_with(None, negative.key)
+      .is_match());
+
+    assert_eq!(
+      serde_json::to_value(&positive).unwrap()["values"],
+      "^/devices/pci0000:00/.*/tty/ttyACM[0-9]+$"
+    );
+  }
+
+  #[test]
+  fn invalid_regex_is_rejected() {
+    let error = serde_json::from_value::<SelectorRequirement>(serde_json::json!({
+      "key": "id_model",
+      "operator": "Matches",
+      "values": "ConBee(",
+    }))
+    .unwrap_err();
+
+    assert!(
+      error.to_string().contains("invalid regex \"ConBee(\""),
+      "{}",
+      error
+    );
+  }
+
+  #[test]
+  fn mismatches_render_expected_and_actual_values() {
+    let vendors: SmallVec<[InternedString; 2]> = smallvec!["1cf1".into(), "0403".into()];
+    let mut result = MatchResult::expected_one_of("idVendor".into(), &vendors, Some("dead".into()));
+    result += MatchResult::expected_any("serial".into(), None);
+    result += MatchResult::expected_greater_than("busnum".into(), "2".into(), Some("1".into()));
+
+    assert_eq!(
+      result.mismatches()[0].to_string(),
+      "idVendor: expected one of [\"1cf1\", \"0403\"], got \"dead\""
+    );
+    assert_eq!(
+      result.to_string(),
+      "idVendor: expected one of [\"1cf1\", \"0403\"], got \"dead\"; \
+       serial: expected any value, got nothing; \
+       busnum: expected more than 2, got \"1\""
+    );
+    assert_eq!(MatchResult::Matches.to_string(), "matches");
+  }
 }
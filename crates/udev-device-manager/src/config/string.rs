@@ -1,10 +1,17 @@
 use lasso::{Spur, ThreadedRodeo};
 use once_cell::sync::Lazy;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use std::{borrow::Borrow, cmp::Ordering, ffi::OsStr, fmt, hash, ops::Deref, sync::Arc};
 
-pub(crate) static STRING_INTERNER: Lazy<Arc<ThreadedRodeo>> =
-  Lazy::new(|| Arc::new(Default::default()));
+/// The one interner all [`InternedString`]s are created by. Kept private, as the keys of
+/// different interners can't be compared.
+static STRING_INTERNER: Lazy<Arc<ThreadedRodeo>> = Lazy::new(|| Arc::new(Default::default()));
 
+/// A string interned in the process wide interner.
+///
+/// Since every instance comes from the same interner, which hands out one key per distinct
+/// text, equal keys mean equal strings and vice versa. Equality is thus a key compare, while
+/// ordering and hashing go by the text so they agree with [`str`] (see [`Borrow`]).
 #[repr(transparent)]
 #[derive(Clone, Copy)]
 pub struct InternedString(Spur);
@@ -21,6 +28,13 @@ impl InternedString {
     InternedString(STRING_INTERNER.get_or_intern_static(text))
   }
 
+  /// Whether both were handed out the same symbol, which with a single interner is the same as
+  /// having the same text. This is exactly what `==` does; ordering and hashing go by the text.
+  #[inline(always)]
+  pub fn same_symbol(&self, other: &InternedString) -> bool {
+    self.0 == other.0
+  }
+
   #[inline(always)]
   pub fn as_str(&self) -> &str {
     &*self
@@ -42,6 +56,43 @@ impl InternedString {
   pub fn is_empty(&self) -> bool {
     self.as_str().is_empty()
   }
+
+  /// The value as an integer, accepting decimal and `0x` prefixed hexadecimal
+  pub fn as_i64(&self) -> Option<i64> {
+    let value = self.as_str().trim();
+    match value
+      .strip_prefix("0x")
+      .or_else(|| value.strip_prefix("0X"))
+    {
+      Some(hex) => i64::from_str_radix(hex, 16).ok(),
+      None => value.parse().ok(),
+    }
+  }
+
+  /// The value as a (finite) float, accepting anything [`as_i64`](Self::as_i64) does too
+  pub fn as_f64(&self) -> Option<f64> {
+    self.as_i64().map(|v| v as f64).or_else(|| {
+      self
+        .as_str()
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+    })
+  }
+
+  /// The value as a boolean, accepting `true`/`false`, `yes`/`no`, `on`/`off` and `1`/`0`
+  pub fn as_bool(&self) -> Option<bool> {
+    let value = self.as_str().trim();
+    let is = |s: &str| value.eq_ignore_ascii_case(s);
+    if is("true") || is("yes") || is("on") || is("1") {
+      Some(true)
+    } else if is("false") || is("no") || is("off") || is("0") {
+      Some(false)
+    } else {
+      None
+    }
+  }
 }
 
 impl Default for InternedString {
@@ -60,7 +111,8 @@ impl Deref for InternedString {
 
 impl PartialEq<InternedString> for InternedString {
   fn eq(&self, other: &InternedString) -> bool {
-    self.0 == other.0 || self.as_str() == other.as_str()
+    // only sound as long as there's a single interner, see `STRING_INTERNER`
+    self.same_symbol(other)
   }
 }
 
@@ -223,6 +275,82 @@ mod serde {
       deserializer.deserialize_str(InternedStringVisitor)
     }
   }
+
+  struct ScalarVisitor;
+  impl<'de> Visitor<'de> for ScalarVisitor {
+    type Value = InternedString;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+      formatter.write_str("a string, number or boolean")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
+    where
+      E: Error,
+    {
+      Ok(InternedString::new(v.to_string()))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+    where
+      E: Error,
+    {
+      Ok(InternedString::new(v.to_string()))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    where
+      E: Error,
+    {
+      Ok(InternedString::new(v.to_string()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+    where
+      E: Error,
+    {
+      Ok(InternedString::new(v.to_string()))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+      E: Error,
+    {
+      InternedStringVisitor.visit_str(v)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+      E: Error,
+    {
+      InternedStringVisitor.visit_bytes(v)
+    }
+  }
+
+  /// Deserializes any scalar into its string form, so typed config values (like `count = 4`
+  /// or `fast = true`) end up as the same strings the coercions on [`InternedString`] read.
+  pub(crate) fn deserialize_scalar<'de, D>(deserializer: D) -> Result<InternedString, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_any(ScalarVisitor)
+  }
+}
+
+pub(crate) use self::serde::deserialize_scalar;
+
+impl JsonSchema for InternedString {
+  fn schema_name() -> String {
+    String::schema_name()
+  }
+
+  fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+    String::json_schema(gen)
+  }
+
+  fn is_referenceable() -> bool {
+    false
+  }
 }
 
 #[cfg(test)]
@@ -234,4 +362,64 @@ mod tests {
   fn interned_str_serde() {
     assert_tokens(&InternedString::new_static("foo"), &[Token::Str("foo")]);
   }
+
+  #[test]
+  fn equality_across_interning_paths() {
+    use std::collections::{hash_map::DefaultHasher, BTreeSet};
+    use std::hash::{Hash, Hasher};
+
+    let hash = |value: &dyn Fn(&mut DefaultHasher)| {
+      let mut hasher = DefaultHasher::new();
+      value(&mut hasher);
+      hasher.finish()
+    };
+
+    let dynamic = InternedString::new(String::from("interning-paths"));
+    let fixed = InternedString::new_static("interning-paths");
+    let converted = InternedString::from("interning-paths".to_string());
+    assert_eq!(dynamic, fixed);
+    assert_eq!(fixed, converted);
+    assert!(dynamic.0 == fixed.0 && fixed.0 == converted.0);
+    assert_eq!(
+      hash(&|h| dynamic.hash(h)),
+      hash(&|h| "interning-paths".hash(h))
+    );
+
+    let other = InternedString::new_static("interning-paths-2");
+    assert_ne!(dynamic, other);
+    assert!(dynamic < other);
+
+    // lookups by `str` find entries inserted under either path
+    let set: BTreeSet<_> = vec![dynamic, other].into_iter().collect();
+    assert!(set.contains("interning-paths"));
+    assert!(set.contains(&fixed));
+  }
+
+  #[test]
+  fn coercions() {
+    assert_eq!(InternedString::new("42").as_i64(), Some(42));
+    assert_eq!(InternedString::new("0x1cf1").as_i64(), Some(0x1cf1));
+    assert_eq!(InternedString::new("1cf1").as_i64(), None);
+    assert_eq!(InternedString::new("1.5").as_f64(), Some(1.5));
+    assert_eq!(InternedString::new("0x10").as_f64(), Some(16.0));
+    assert_eq!(InternedString::new("NaN").as_f64(), None);
+    assert_eq!(InternedString::new("Yes").as_bool(), Some(true));
+    assert_eq!(InternedString::new("0").as_bool(), Some(false));
+    assert_eq!(InternedString::new("maybe").as_bool(), None);
+  }
+
+  #[test]
+  fn static_and_dynamic_interning_share_symbols() {
+    let fixed = InternedString::new_static("shared-symbol");
+    let dynamic = InternedString::new(String::from("shared-symbol"));
+    assert_eq!(fixed, dynamic);
+    assert!(fixed.same_symbol(&dynamic));
+    assert!(!fixed.same_symbol(&InternedString::new_static("shared-symbol-2")));
+
+    // ordering and display go by the text, not by interning order
+    let later = InternedString::new("another-shared-symbol");
+    assert!(later < fixed);
+    assert_eq!(fixed.to_string(), "shared-symbol");
+    assert_eq!(format!("{}", dynamic), "shared-symbol");
+  }
 }
@@ -0,0 +1,59 @@
+/// Expands `${name}` placeholders in `template` using `resolve`. Placeholders that don't
+/// resolve expand to an empty string, and an unterminated `${` is kept verbatim.
+pub fn expand(template: &str, resolve: impl Fn(&str) -> Option<String>) -> String {
+  let mut result = String::with_capacity(template.len());
+  let mut rest = template;
+
+  while let Some(start) = rest.find("${") {
+    let (head, tail) = rest.split_at(start);
+    result.push_str(head);
+
+    match tail[2..].find('}') {
+      None => {
+        rest = tail;
+        break;
+      }
+      Some(end) => {
+        let key = &tail[2..2 + end];
+        if let Some(value) = resolve(key) {
+          result.push_str(&value);
+        }
+
+        rest = &tail[2 + end + 1..];
+      }
+    }
+  }
+
+  // either everything is consumed, or rest starts with an unterminated placeholder
+  result.push_str(rest);
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn resolve(key: &str) -> Option<String> {
+    match key {
+      "index" => Some("3".into()),
+      "attr:idVendor" => Some("1cf1".into()),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn expands_placeholders() {
+    assert_eq!(expand("/dev/accel${index}", resolve), "/dev/accel3");
+    assert_eq!(
+      expand("${attr:idVendor}-${index}", resolve),
+      "1cf1-3".to_string()
+    );
+    assert_eq!(expand("no placeholders", resolve), "no placeholders");
+  }
+
+  #[test]
+  fn missing_and_unterminated_placeholders() {
+    assert_eq!(expand("/dev/${unknown}x", resolve), "/dev/x");
+    assert_eq!(expand("/dev/${index", resolve), "/dev/${index");
+  }
+}
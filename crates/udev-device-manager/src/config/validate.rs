@@ -0,0 +1,533 @@
+use super::{Config, InternedString};
+use std::{
+  collections::{btree_map::Entry, BTreeMap, BTreeSet},
+  iter,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ConfigValidationError {
+  #[error("Device type '{0}' is defined more than once")]
+  DuplicateDeviceType(InternedString),
+
+  #[error("Device class '{0}' is defined more than once")]
+  DuplicateDeviceClass(InternedString),
+
+  #[error("Manual device '{0}' is defined more than once")]
+  DuplicateManualDevice(InternedString),
+
+  #[error("The {kind} '{name}' has an empty subsystem")]
+  EmptySubsystem {
+    kind: &'static str,
+    name: InternedString,
+  },
+
+  #[error("Device class '{0}' can't match any of the configured device types")]
+  UnsatisfiableClass(InternedString),
+
+  #[error("Device classes '{first}' and '{second}' both advertise resource '{resource}'")]
+  DuplicateResourceName {
+    resource: String,
+    first: InternedString,
+    second: InternedString,
+  },
+
+  #[error("Device class '{0}' has a world-writable socketMode without allowWorldWritableSocket")]
+  WorldWritableSocket(InternedString),
+
+  #[error("Device class '{0}' has an allocateHook without a command")]
+  EmptyAllocateHook(InternedString),
+
+  #[error("Device class '{0}' enables preStartReset without a resetCommand")]
+  MissingResetCommand(InternedString),
+}
+
+/// Likely mistakes in a config which is still valid, e.g. requirements that can only ever
+/// pass (like `DoesNotExist`) on a misspelled label
+#[derive(Debug, Error, PartialEq)]
+pub enum ConfigWarning {
+  #[error("Device class '{class}' selects on label '{label}', which no device type sets")]
+  UnknownLabel {
+    class: InternedString,
+    label: InternedString,
+  },
+
+  #[error("Device class '{class}' groups by label '{label}', which no device type sets")]
+  UnknownGroupLabel {
+    class: InternedString,
+    label: InternedString,
+  },
+}
+
+impl Config {
+  /// Checks the config for mistakes that parse fine, returning all of them at once
+  pub fn validate(&self) -> Result<(), Vec<ConfigValidationError>> {
+    let mut errors = Vec::new();
+
+    let mut names = BTreeSet::new();
+    for ty in self.device_types() {
+      if !names.insert(ty.name()) {
+        errors.push(ConfigValidationError::DuplicateDeviceType(ty.name()));
+      }
+      if ty.subsystem().trim().is_empty() {
+        errors.push(ConfigValidationError::EmptySubsystem {
+          kind: "device type",
+          name: ty.name(),
+        });
+      }
+    }
+
+    let mut names = BTreeSet::new();
+    for class in self.device_classes() {
+      if !names.insert(class.name()) {
+        errors.push(ConfigValidationError::DuplicateDeviceClass(class.name()));
+      }
+      if class.subsystem().trim().is_empty() {
+        errors.push(ConfigValidationError::EmptySubsystem {
+          kind: "device class",
+          name: class.name(),
+        });
+      }
+      let world_writable = class
+        .socket_mode()
+        .is_some_and(|mode| mode.is_world_writable());
+      if world_writable && !class.allow_world_writable_socket() {
+        errors.push(ConfigValidationError::WorldWritableSocket(class.name()));
+      }
+      if class.allocate_hook().is_some_and(|hook| hook.is_empty()) {
+        errors.push(ConfigValidationError::EmptyAllocateHook(class.name()));
+      }
+      if class.pre_start_reset() && !class.has_reset_command() {
+        errors.push(ConfigValidationError::MissingResetCommand(class.name()));
+      }
+    }
+
+    let mut ids = BTreeSet::new();
+    for device in self.manual_devices() {
+      if !ids.insert(device.id) {
+        errors.push(ConfigValidationError::DuplicateManualDevice(device.id));
+      }
+      if device.subsystem.trim().is_empty() {
+        errors.push(ConfigValidationError::EmptySubsystem {
+          kind: "manual device",
+          name: device.id,
+        });
+      }
+    }
+
+    errors.extend(
+      self
+        .unsatisfiable_classes()
+        .map(ConfigValidationError::UnsatisfiableClass),
+    );
+    errors.extend(self.duplicate_resource_names());
+
+    match errors.is_empty() {
+      true => Ok(()),
+      false => Err(errors),
+    }
+  }
+
+  /// Checks the config for likely mistakes that don't make it invalid, returning all of them
+  pub fn lint(&self) -> Vec<ConfigWarning> {
+    let labels = self
+      .device_types()
+      .iter()
+      .flat_map(|ty| ty.labels().keys())
+      .collect::<BTreeSet<_>>();
+
+    let mut warnings = Vec::new();
+    for class in self.device_classes() {
+      let unknown = class
+        .selector()
+        .keys()
+        .filter(|key| !labels.contains(key))
+        .collect::<BTreeSet<_>>();
+      warnings.extend(
+        unknown
+          .into_iter()
+          .map(|label| ConfigWarning::UnknownLabel {
+            class: class.name(),
+            label,
+          }),
+      );
+      if let Some(label) = class.group_by().filter(|label| !labels.contains(label)) {
+        warnings.push(ConfigWarning::UnknownGroupLabel {
+          class: class.name(),
+          label,
+        });
+      }
+    }
+
+    warnings
+  }
+
+  /// The kubelet only accepts one registration per resource name, so two classes advertising
+  /// the same one can't both run. Grouped classes advertise one resource per group of the
+  /// device types they match; groups from templated labels are only known per device.
+  fn duplicate_resource_names(&self) -> Vec<ConfigValidationError> {
+    let device_types = self
+      .device_types()
+      .iter()
+      .filter(|ty| ty.enabled() && !ty.labels().is_templated())
+      .collect::<Vec<_>>();
+
+    let mut resources = BTreeMap::new();
+    let mut errors = Vec::new();
+    for class in self.device_classes().iter().filter(|class| class.enabled()) {
+      let advertised: BTreeSet<_> = match class.group_by() {
+        None => iter::once(class.resource_name(None)).collect(),
+        Some(_) => device_types
+          .iter()
+          .filter(|ty| class.match_with(ty).is_match())
+          .filter_map(|ty| class.resource_name_for(ty))
+          .collect(),
+      };
+
+      for resource in advertised {
+        match resources.entry(resource) {
+          Entry::Vacant(entry) => {
+            entry.insert(class.name());
+          }
+          // a class defined twice is reported as such
+          Entry::Occupied(entry) if *entry.get() == class.name() => {}
+          Entry::Occupied(entry) => errors.push(ConfigValidationError::DuplicateResourceName {
+            resource: entry.key().clone(),
+            first: *entry.get(),
+            second: class.name(),
+          }),
+        }
+      }
+    }
+
+    errors
+  }
+
+  /// Device type labels are static, so a class that matches none of the declared device
+  /// types can never advertise anything - that's a config error rather than a lack of devices.
+  fn unsatisfiable_classes(&self) -> impl Iterator<Item = InternedString> + '_ {
+    let device_types = self
+      .device_types()
+      .iter()
+      .filter(|ty| ty.enabled())
+      .collect::<Vec<_>>();
+
+    self
+      .device_classes()
+      .iter()
+      .filter(|class| class.enabled())
+      .filter(move |class| {
+        // templated labels are only known once expanded for a device
+        !device_types.iter().any(|ty| {
+          class.match_with(ty).is_match()
+            || (ty.labels().is_templated() && ty.subsystem() == class.subsystem())
+        })
+      })
+      .map(|class| class.name())
+  }
+}
+
+/// Joins validation errors into a single line, for [`ConfigError`](super::ConfigError)
+pub(super) fn join_errors(errors: &[ConfigValidationError]) -> String {
+  errors
+    .iter()
+    .map(ToString::to_string)
+    .collect::<Vec<_>>()
+    .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DEVICE_TYPE: &str = r#"
+    [[devices]]
+    name = "conbee2"
+    subsystem = "tty"
+    labels = { type = "conbee2" }
+    selector = {}
+  "#;
+
+  const DEVICE_CLASS: &str = r#"
+    [[deviceClasses]]
+    name = "zigbee"
+    subsystem = "tty"
+    target = "/dev/ttyACM#"
+    selector = { matchLabels = { type = "conbee2" } }
+  "#;
+
+  fn validate(config: &str) -> Result<(), Vec<ConfigValidationError>> {
+    toml::from_str::<Config>(config).unwrap().validate()
+  }
+
+  #[test]
+  fn valid_config_passes() {
+    assert_eq!(
+      validate(&format!("{}{}", DEVICE_TYPE, DEVICE_CLASS)),
+      Ok(())
+    );
+    assert_eq!(validate(include_str!("../../sample_config.toml")), Ok(()));
+  }
+
+  #[test]
+  fn world_writable_sockets_need_to_be_allowed() {
+    let class = |options: &str| format!("{}{}{}", DEVICE_TYPE, DEVICE_CLASS, options);
+
+    assert_eq!(validate(&class(r#"socketMode = "0660""#)), Ok(()));
+    assert_eq!(
+      validate(&class(r#"socketMode = "0666""#)),
+      Err(vec![ConfigValidationError::WorldWritableSocket(
+        InternedString::new("zigbee")
+      )])
+    );
+    assert_eq!(
+      validate(&class(
+        "socketMode = \"0666\"\n    allowWorldWritableSocket = true"
+      )),
+      Ok(())
+    );
+  }
+
+  #[test]
+  fn allocate_hooks_need_a_command() {
+    let class = |hook: &str| format!("{}{}    allocateHook = {}", DEVICE_TYPE, DEVICE_CLASS, hook);
+
+    assert_eq!(validate(&class(r#"{ command = ["true"] }"#)), Ok(()));
+    for hook in &[r#"{ command = [] }"#, r#"{ command = [" ", "-x"] }"#] {
+      assert_eq!(
+        validate(&class(hook)),
+        Err(vec![ConfigValidationError::EmptyAllocateHook(
+          InternedString::new("zigbee")
+        )])
+      );
+    }
+  }
+
+  #[test]
+  fn pre_start_resets_need_a_reset_command() {
+    let class = |options: &str| format!("{}{}{}", DEVICE_TYPE, DEVICE_CLASS, options);
+
+    assert_eq!(
+      validate(&class(
+        "preStartReset = true\n    resetCommand = [\"usbreset\", \"${devnode}\"]"
+      )),
+      Ok(())
+    );
+    assert_eq!(validate(&class(r#"resetCommand = ["usbreset"]"#)), Ok(()));
+    for options in &[
+      "preStartReset = true",
+      "preStartReset = true\n    resetCommand = []",
+    ] {
+      assert_eq!(
+        validate(&class(options)),
+        Err(vec![ConfigValidationError::MissingResetCommand(
+          InternedString::new("zigbee")
+        )])
+      );
+    }
+  }
+
+  #[test]
+  fn misspelled_label_keys_are_flagged() {
+    let config: Config = toml::from_str(&format!(
+      "{}{}",
+      DEVICE_TYPE,
+      r#"
+        [[deviceClasses]]
+        name = "zigbee"
+        subsystem = "tty"
+        target = "/dev/ttyACM#"
+        selector = { matchLabels = { type = "conbee2" }, exclude = { matchExpressions = [
+          { key = "vendorr", operator = "In", values = ["dresden"] },
+        ] } }
+      "#
+    ))
+    .unwrap();
+
+    // the exclude never matches, so the class is satisfiable and the config valid
+    assert_eq!(config.validate(), Ok(()));
+    assert_eq!(
+      config.lint(),
+      vec![ConfigWarning::UnknownLabel {
+        class: InternedString::new("zigbee"),
+        label: InternedString::new("vendorr"),
+      }]
+    );
+
+    let config: Config = toml::from_str(&format!("{}{}", DEVICE_TYPE, DEVICE_CLASS)).unwrap();
+    assert_eq!(config.lint(), vec![]);
+
+    let config: Config = toml::from_str(&format!(
+      "{}{}    groupBy = \"modle\"",
+      DEVICE_TYPE, DEVICE_CLASS
+    ))
+    .unwrap();
+    assert_eq!(
+      config.lint(),
+      vec![ConfigWarning::UnknownGroupLabel {
+        class: InternedString::new("zigbee"),
+        label: InternedString::new("modle"),
+      }]
+    );
+  }
+
+  #[test]
+  fn duplicate_device_types_are_rejected() {
+    assert_eq!(
+      validate(&format!(
+        "deviceClasses = []\n{}{}",
+        DEVICE_TYPE, DEVICE_TYPE
+      )),
+      Err(vec![ConfigValidationError::DuplicateDeviceType(
+        InternedString::new("conbee2")
+      )])
+    );
+  }
+
+  #[test]
+  fn duplicate_device_classes_are_rejected() {
+    assert_eq!(
+      validate(&format!("{}{}{}", DEVICE_TYPE, DEVICE_CLASS, DEVICE_CLASS)),
+      Err(vec![ConfigValidationError::DuplicateDeviceClass(
+        InternedString::new("zigbee")
+      )])
+    );
+  }
+
+  #[test]
+  fn duplicate_manual_devices_are_rejected() {
+    let manual_device = r#"
+      [[manualDevices]]
+      id = "virtual0"
+      subsystem = "tty"
+    "#;
+
+    assert_eq!(
+      validate(&format!(
+        "{}{}{}{}",
+        DEVICE_TYPE, DEVICE_CLASS, manual_device, manual_device
+      )),
+      Err(vec![ConfigValidationError::DuplicateManualDevice(
+        InternedString::new("virtual0")
+      )])
+    );
+  }
+
+  #[test]
+  fn empty_subsystems_are_rejected() {
+    let config = format!("{}{}", DEVICE_TYPE, DEVICE_CLASS).replace("\"tty\"", "\" \"");
+
+    assert_eq!(
+      validate(&config),
+      Err(vec![
+        ConfigValidationError::EmptySubsystem {
+          kind: "device type",
+          name: InternedString::new("conbee2"),
+        },
+        ConfigValidationError::EmptySubsystem {
+          kind: "device class",
+          name: InternedString::new("zigbee"),
+        },
+      ])
+    );
+  }
+
+  #[test]
+  fn classes_must_match_a_device_type() {
+    assert_eq!(
+      validate(&format!(
+        "{}{}",
+        DEVICE_TYPE,
+        DEVICE_CLASS.replace("type = \"conbee2\"", "type = \"gpu\"")
+      )),
+      Err(vec![ConfigValidationError::UnsatisfiableClass(
+        InternedString::new("zigbee")
+      )])
+    );
+  }
+
+  #[test]
+  fn colliding_resource_names_are_rejected() {
+    let class = |name: &str, options: &str| {
+      format!(
+        r#"
+          [[deviceClasses]]
+          name = "{}"
+          subsystem = "tty"
+          target = "/dev/ttyACM#"
+          selector = {{ matchLabels = {{ type = "conbee2" }} }}
+          {}
+        "#,
+        name, options
+      )
+    };
+    let duplicate = |resource: &str| {
+      Err(vec![ConfigValidationError::DuplicateResourceName {
+        resource: resource.to_owned(),
+        first: InternedString::new("zigbee"),
+        second: InternedString::new("zwave"),
+      }])
+    };
+
+    let radio = r#"resourceName = "example.com/radio""#;
+    assert_eq!(
+      validate(&format!(
+        "{}{}{}",
+        DEVICE_TYPE,
+        class("zigbee", radio),
+        class("zwave", radio)
+      )),
+      duplicate("example.com/radio")
+    );
+
+    // a disabled class doesn't advertise anything to collide with
+    assert_eq!(
+      validate(&format!(
+        "{}{}{}",
+        DEVICE_TYPE,
+        class("zigbee", radio),
+        class("zwave", &format!("{}\n          enabled = false", radio))
+      )),
+      Ok(())
+    );
+
+    // grouping by type gives example.com/radio-conbee2
+    let grouped = format!("{}\n          groupBy = \"type\"", radio);
+    assert_eq!(
+      validate(&format!(
+        "{}{}{}",
+        DEVICE_TYPE,
+        class("zigbee", r#"resourceName = "example.com/radio-conbee2""#),
+        class("zwave", &grouped)
+      )),
+      duplicate("example.com/radio-conbee2")
+    );
+    assert_eq!(
+      validate(&format!(
+        "{}{}{}",
+        DEVICE_TYPE,
+        class("zigbee", &grouped),
+        class("zwave", r#"resourceName = "example.com/zwave""#)
+      )),
+      Ok(())
+    );
+  }
+
+  #[test]
+  fn all_errors_are_collected() {
+    let errors = validate(&format!(
+      "{}{}{}",
+      DEVICE_TYPE,
+      DEVICE_TYPE,
+      DEVICE_CLASS.replace("type = \"conbee2\"", "type = \"gpu\"")
+    ))
+    .unwrap_err();
+
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+      join_errors(&errors),
+      "Device type 'conbee2' is defined more than once; \
+       Device class 'zigbee' can't match any of the configured device types"
+    );
+  }
+}
@@ -1,16 +1,18 @@
-use super::{Config, ConfigError, ConfigFormat};
+use super::{Config, ConfigError, ConfigFormat, ConfigMetrics};
 use async_stream::stream;
 use futures::{Stream, StreamExt};
 use notify::{DebouncedEvent, RecursiveMode, Watcher as WatcherTrait};
 use pin_project::pin_project;
 use std::{
-  path::Path,
+  os::unix::fs::MetadataExt,
+  path::{Path, PathBuf},
   pin::Pin,
   task::{Context, Poll},
-  time::Duration,
+  time::{Duration, SystemTime},
 };
 use thiserror::Error;
 use tokio::{io, sync::mpsc::UnboundedReceiver};
+use tracing::{event, Level};
 
 #[pin_project]
 struct Watcher {
@@ -67,18 +69,103 @@ pub enum ConfigWatcherError {
   Io(#[from] io::Error),
 }
 
+/// How long file events are debounced before the config is read again
+const DEBOUNCE: Duration = Duration::from_secs(30);
+
+/// The file a config path currently resolves to, following symlinks. Changes when the file is
+/// replaced by an atomic rename or a symlink swap, even though nothing was written to the path.
+#[derive(Debug, PartialEq, Eq)]
+struct FileIdentity {
+  target: PathBuf,
+  inode: u64,
+  modified: Option<SystemTime>,
+  len: u64,
+}
+
+impl FileIdentity {
+  fn of(path: &Path) -> Option<Self> {
+    let target = std::fs::canonicalize(path).ok()?;
+    let metadata = std::fs::metadata(&target).ok()?;
+
+    Some(Self {
+      target,
+      inode: metadata.ino(),
+      modified: metadata.modified().ok(),
+      len: metadata.len(),
+    })
+  }
+}
+
 pub fn watch(
   file: impl AsRef<Path>,
   format: ConfigFormat,
+  metrics: ConfigMetrics,
+) -> Result<impl Stream<Item = Result<Config, ConfigError>>, ConfigWatcherError> {
+  watch_with_delay(file, format, metrics, DEBOUNCE)
+}
+
+fn watch_with_delay(
+  file: impl AsRef<Path>,
+  format: ConfigFormat,
+  metrics: ConfigMetrics,
+  delay: Duration,
 ) -> Result<impl Stream<Item = Result<Config, ConfigError>>, ConfigWatcherError> {
   let file = file.as_ref().to_owned();
-  let mut watcher = Watcher::new(Duration::from_secs(30))?;
-  watcher.watch(&file, RecursiveMode::NonRecursive)?;
+  let is_dir = file.is_dir();
+
+  // a config file is watched through its directory, as a watch on the file itself would be
+  // left on the old inode once the file is replaced by a rename (e.g. by editors, or the
+  // `..data` symlink swap of a Kubernetes ConfigMap volume)
+  let watched = match file.parent() {
+    _ if is_dir => file.clone(),
+    Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+    _ => PathBuf::from("."),
+  };
+  let mut watcher = Watcher::new(delay)?;
+  watcher.watch(&watched, RecursiveMode::NonRecursive)?;
+  let mut identity = FileIdentity::of(&file);
 
   Ok(stream! {
     while let Some(event) = watcher.next().await {
-      if let DebouncedEvent::Write(_) = event {
-        yield Config::read(&file, format).await;
+      let changed = match event {
+        // the watched directory was replaced, so the watch has to move on to the new one
+        DebouncedEvent::Remove(ref path) | DebouncedEvent::Rename(ref path, _)
+          if *path == watched =>
+        {
+          if let Err(e) = watcher.watch(&watched, RecursiveMode::NonRecursive) {
+            event!(
+              target: "udev-device-manager",
+              Level::WARN,
+              path = %watched.display(),
+              "failed to watch config again: {}",
+              e
+            );
+          }
+
+          true
+        }
+        DebouncedEvent::Rescan => true,
+
+        // in a config directory, adding or removing a file changes the config too
+        DebouncedEvent::Write(_)
+        | DebouncedEvent::Create(_)
+        | DebouncedEvent::Remove(_)
+        | DebouncedEvent::Rename(_, _) if is_dir => true,
+        DebouncedEvent::Write(ref path) if path.file_name() == file.file_name() => true,
+
+        // other changes in the directory only matter if the path now resolves to another file
+        DebouncedEvent::Write(_)
+        | DebouncedEvent::Create(_)
+        | DebouncedEvent::Remove(_)
+        | DebouncedEvent::Rename(_, _) => FileIdentity::of(&file) != identity,
+        _ => false,
+      };
+
+      if changed {
+        identity = FileIdentity::of(&file);
+        let config = Config::read(&file, format).await;
+        metrics.record(&config);
+        yield config;
       }
     }
   })
@@ -141,3 +228,93 @@ pub fn watch(
 //     }
 //   }
 // }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::pin_mut;
+  use prometheus::Registry;
+  use tokio::time;
+
+  const CONFIG: &str = "devices = []\ndeviceClasses = []\n";
+
+  /// Waits for the next reloaded config
+  async fn next_config(
+    configs: impl Stream<Item = Result<Config, ConfigError>>,
+  ) -> Result<Config, ConfigError> {
+    pin_mut!(configs);
+    time::timeout(Duration::from_secs(10), configs.next())
+      .await
+      .expect("config reload")
+      .expect("config stream")
+  }
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+      "udev-device-manager-watch-{}-{}",
+      name,
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[tokio::test]
+  async fn atomic_rename_reloads_the_config() {
+    let dir = temp_dir("rename");
+    let file = dir.join("config.toml");
+    std::fs::write(&file, CONFIG).unwrap();
+
+    let metrics = ConfigMetrics::new(&Registry::new()).unwrap();
+    let configs = watch_with_delay(
+      &file,
+      ConfigFormat::Auto,
+      metrics,
+      Duration::from_millis(100),
+    )
+    .unwrap();
+
+    let tmp = dir.join(".config.toml.tmp");
+    std::fs::write(&tmp, format!("{}maintenance = true\n", CONFIG)).unwrap();
+    std::fs::rename(&tmp, &file).unwrap();
+
+    let config = next_config(configs).await.unwrap();
+    assert!(config.maintenance());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn configmap_symlink_swap_reloads_the_config() {
+    use std::os::unix::fs::symlink;
+
+    // laid out like a ConfigMap volume: config.toml -> ..data/config.toml, ..data -> ..v1
+    let dir = temp_dir("configmap");
+    for (version, content) in &[
+      ("..v1", CONFIG.to_owned()),
+      ("..v2", format!("{}maintenance = true\n", CONFIG)),
+    ] {
+      std::fs::create_dir(dir.join(version)).unwrap();
+      std::fs::write(dir.join(version).join("config.toml"), content).unwrap();
+    }
+    symlink("..v1", dir.join("..data")).unwrap();
+    symlink("..data/config.toml", dir.join("config.toml")).unwrap();
+
+    let file = dir.join("config.toml");
+    let metrics = ConfigMetrics::new(&Registry::new()).unwrap();
+    let configs = watch_with_delay(
+      &file,
+      ConfigFormat::Auto,
+      metrics,
+      Duration::from_millis(100),
+    )
+    .unwrap();
+
+    symlink("..v2", dir.join("..data_tmp")).unwrap();
+    std::fs::rename(dir.join("..data_tmp"), dir.join("..data")).unwrap();
+
+    let config = next_config(configs).await.unwrap();
+    assert!(config.maintenance());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
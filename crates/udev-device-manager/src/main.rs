@@ -1,6 +1,8 @@
 mod app;
 mod config;
 mod signals;
+#[cfg(test)]
+mod test_log;
 mod udev;
 mod utils;
 
@@ -12,6 +12,7 @@ macro_rules! define_signals {
     }
   ) => {
     #[repr(i32)]
+    #[derive(Clone, Copy, PartialEq, Eq)]
     pub enum $name {
       $($case = ::signal_hook::consts::$val,)+
     }
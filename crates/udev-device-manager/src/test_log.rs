@@ -0,0 +1,46 @@
+//! Capturing of the events logged by tests, shared by the tests asserting on them.
+
+use std::{
+  io,
+  sync::{Arc, Mutex},
+};
+use tracing::{Level, Subscriber};
+
+/// In-memory log output of a subscriber writing to it
+#[derive(Clone, Default)]
+pub struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+  /// A subscriber formatting events up to `level` into the buffer, without colors
+  pub fn subscriber(&self, level: Level) -> impl Subscriber + Send + Sync + 'static {
+    let writer = self.clone();
+    tracing_subscriber::fmt()
+      .with_writer(move || writer.clone())
+      .with_max_level(level)
+      .with_ansi(false)
+      .finish()
+  }
+
+  /// Everything logged so far
+  pub fn contents(&self) -> String {
+    String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+  }
+}
+
+impl io::Write for Buffer {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.lock().unwrap().extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Runs `f`, returning the events up to INFO it logged on the current thread
+pub fn logged(f: impl FnOnce()) -> String {
+  let buffer = Buffer::default();
+  tracing::subscriber::with_default(buffer.subscriber(Level::INFO), f);
+  buffer.contents()
+}
@@ -1,17 +1,28 @@
 mod device;
 mod event_stream;
+mod scanner;
 
+use crate::config::InternedString;
 use event_stream::UdevEventStreamBuilder;
 use futures::Stream;
 
-pub use device::{UdevDevice, UdevDeviceError};
-pub use event_stream::{UdevBuilderError, UdevEvent};
+pub use device::UdevDevice;
+pub use event_stream::{UdevBuilderError, UdevEvent, UdevStreamError};
+pub use scanner::DeviceScanner;
 
 pub struct Udev;
 
 impl Udev {
+  /// Watches the events of devices in the given subsystems. Without any subsystems no events
+  /// are passed on, rather than the events of every device
   pub async fn watch(
-  ) -> Result<impl Stream<Item = Result<UdevEvent, UdevDeviceError>>, UdevBuilderError> {
-    UdevEventStreamBuilder::new()?.listen().await
+    subsystems: Vec<InternedString>,
+  ) -> Result<impl Stream<Item = Result<UdevEvent, UdevStreamError>>, UdevBuilderError> {
+    let mut builder = UdevEventStreamBuilder::new()?;
+    for subsystem in subsystems {
+      builder = builder.match_subsystem(subsystem).await?;
+    }
+
+    builder.listen().await
   }
 }
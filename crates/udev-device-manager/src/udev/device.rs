@@ -1,4 +1,4 @@
-use crate::config::InternedString;
+use crate::config::{InternedString, ManualDevice};
 use arc_swap::RefCnt;
 use std::{
   collections::BTreeMap, convert::TryFrom, ffi::OsString, fmt, io, path::PathBuf, sync::Arc,
@@ -42,7 +42,7 @@ impl Iterator for UdevHierarchy {
   }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum AttributeValue {
   None,
   Invalid,
@@ -68,13 +68,23 @@ impl AttributeValue {
   }
 }
 
-#[derive(Debug)]
+/// Attributes of a single parent device in the hierarchy
+#[derive(Debug, Clone)]
+struct Parent {
+  subsystem: Option<InternedString>,
+  attributes: BTreeMap<InternedString, AttributeValue>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Inner {
   id: InternedString,
   subsystem: InternedString,
+  devtype: Option<InternedString>,
   syspath: InternedString,
   devnode: InternedString,
+  numa_node: Option<i64>,
   attributes: BTreeMap<InternedString, AttributeValue>,
+  parents: Vec<Parent>,
 }
 
 #[derive(Clone)]
@@ -89,6 +99,11 @@ impl UdevDevice {
     self.0.subsystem
   }
 
+  /// Device type within the subsystem (e.g. `disk` or `partition` for `block`), if any
+  pub fn devtype(&self) -> Option<InternedString> {
+    self.0.devtype
+  }
+
   pub fn syspath(&self) -> InternedString {
     self.0.syspath
   }
@@ -104,6 +119,156 @@ impl UdevDevice {
   pub fn attributes(&self) -> &BTreeMap<InternedString, AttributeValue> {
     &self.0.attributes
   }
+
+  /// Own attributes of the direct parent device, if the device has a parent
+  pub fn parent_attributes(&self) -> Option<&BTreeMap<InternedString, AttributeValue>> {
+    self.0.parents.first().map(|parent| &parent.attributes)
+  }
+
+  /// Attribute of the nearest parent device in `subsystem` having it. Unlike
+  /// [`UdevDevice::attribute`], which looks at the whole hierarchy, this tells apart e.g. the
+  /// `idVendor` of a USB device from one of the device itself.
+  pub fn parent_attribute(&self, subsystem: InternedString, name: &str) -> Option<AttributeValue> {
+    self
+      .0
+      .parents
+      .iter()
+      .filter(|parent| parent.subsystem == Some(subsystem))
+      .find_map(|parent| parent.attributes.get(name).copied())
+  }
+
+  /// NUMA node the device is attached to, from the closest `numa_node` attribute in its
+  /// hierarchy (usually the PCI parent)
+  pub fn numa_node(&self) -> Option<i64> {
+    self.0.numa_node
+  }
+
+  /// Whether the device (or the closest device in its hierarchy having the attribute) is
+  /// authorized, `None` if there's no `authorized` attribute
+  pub fn authorized(&self) -> Option<bool> {
+    self
+      .attribute("authorized")
+      .and_then(|v| v.as_option())
+      .and_then(|v| v.as_bool())
+  }
+
+  /// Whether both values refer to the same captured udev device (and not just an equal one)
+  pub fn ptr_eq(&self, other: &UdevDevice) -> bool {
+    Arc::ptr_eq(&self.0, &other.0)
+  }
+
+  /// Starts building a device that doesn't come from udev, e.g. a manual device or one for a
+  /// test. Its id is derived from the syspath, the same way it is for devices from udev.
+  pub fn builder(subsystem: &str, syspath: &str) -> UdevDeviceBuilder {
+    UdevDeviceBuilder {
+      subsystem: subsystem.intern(),
+      devtype: None,
+      syspath: syspath.intern(),
+      devnode: InternedString::default(),
+      attributes: BTreeMap::new(),
+    }
+  }
+
+  /// Builds a device that doesn't come from udev, empty attribute values count as unset
+  pub(crate) fn from_parts<'a>(
+    subsystem: &str,
+    syspath: &str,
+    devnode: &str,
+    attributes: impl IntoIterator<Item = (&'a str, &'a str)>,
+  ) -> Self {
+    Self::builder(subsystem, syspath)
+      .devnode(devnode)
+      .attributes(attributes)
+      .build()
+  }
+
+  /// Adds a parent above the current topmost one. Its attributes only show up in the flattened
+  /// attributes where no closer device has them.
+  #[cfg(test)]
+  pub(crate) fn with_parent<'a>(
+    self,
+    subsystem: &str,
+    attributes: impl IntoIterator<Item = (&'a str, &'a str)>,
+  ) -> Self {
+    let parent = Self::from_parts(subsystem, "", "", attributes);
+    let mut inner = Arc::try_unwrap(self.0).unwrap_or_else(|inner| (*inner).clone());
+    for (name, value) in parent.attributes() {
+      inner.attributes.entry(*name).or_insert(*value);
+    }
+    inner.numa_node = numa_node(&inner.attributes);
+    inner.parents.push(Parent {
+      subsystem: Some(subsystem.intern()),
+      attributes: parent.attributes().clone(),
+    });
+
+    UdevDevice(Arc::new(inner))
+  }
+
+  /// Replaces the id derived from the syspath, standing in for ids no syspath hashes to
+  #[cfg(test)]
+  pub(crate) fn with_id(self, id: &str) -> Self {
+    let mut inner = Arc::try_unwrap(self.0).unwrap_or_else(|inner| (*inner).clone());
+    inner.id = id.intern();
+
+    UdevDevice(Arc::new(inner))
+  }
+}
+
+/// Builder for devices that don't come from udev, see [`UdevDevice::builder`]
+#[derive(Debug, Clone)]
+pub struct UdevDeviceBuilder {
+  subsystem: InternedString,
+  devtype: Option<InternedString>,
+  syspath: InternedString,
+  devnode: InternedString,
+  attributes: BTreeMap<InternedString, AttributeValue>,
+}
+
+impl UdevDeviceBuilder {
+  pub fn devnode(mut self, devnode: &str) -> Self {
+    self.devnode = devnode.intern();
+    self
+  }
+
+  pub fn devtype(mut self, devtype: &str) -> Self {
+    self.devtype = Some(devtype.intern());
+    self
+  }
+
+  /// Sets an attribute, an empty value counts as unset
+  pub fn attribute(self, name: &str, value: &str) -> Self {
+    self.attributes(std::iter::once((name, value)))
+  }
+
+  /// Sets several attributes, empty values count as unset
+  pub fn attributes<'a>(
+    mut self,
+    attributes: impl IntoIterator<Item = (&'a str, &'a str)>,
+  ) -> Self {
+    for (name, value) in attributes {
+      let value = match value {
+        "" => AttributeValue::None,
+        v => AttributeValue::Value(v.intern()),
+      };
+
+      self.attributes.insert(name.intern(), value);
+    }
+
+    self
+  }
+
+  pub fn build(self) -> UdevDevice {
+    UdevDevice(Arc::new(Inner {
+      id: device_id(&self.syspath),
+      subsystem: self.subsystem,
+      devtype: self.devtype,
+      syspath: self.syspath,
+      devnode: self.devnode,
+      numa_node: numa_node(&self.attributes),
+      attributes: self.attributes,
+      parents: Vec::new(),
+    }))
+  }
 }
 
 impl fmt::Debug for UdevDevice {
@@ -112,6 +277,20 @@ impl fmt::Debug for UdevDevice {
   }
 }
 
+impl<'a> From<&'a ManualDevice> for UdevDevice {
+  fn from(device: &'a ManualDevice) -> Self {
+    UdevDevice::from_parts(
+      &device.subsystem,
+      &device.syspath(),
+      &device.devnode,
+      device
+        .attributes
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str())),
+    )
+  }
+}
+
 // SAFETY: Just forwards all calls to the inner Arc
 unsafe impl RefCnt for UdevDevice {
   type Base = Inner;
@@ -188,6 +367,22 @@ impl UdevDeviceError {
   }
 }
 
+/// The kernel reports `-1` when the platform has no NUMA information
+fn numa_node(attributes: &BTreeMap<InternedString, AttributeValue>) -> Option<i64> {
+  attributes
+    .get("numa_node")
+    .and_then(|v| v.as_option())
+    .and_then(|v| v.as_i64())
+    .filter(|node| *node >= 0)
+}
+
+fn device_id(syspath: &str) -> InternedString {
+  let id_hash = seahash::hash(syspath.as_bytes());
+  let id_hash_bytes = id_hash.to_le_bytes();
+  let id_string = base64::encode(id_hash_bytes);
+  id_string.intern()
+}
+
 impl<'a> TryFrom<tokio_udev::Device> for UdevDevice {
   type Error = UdevDeviceError;
 
@@ -198,6 +393,11 @@ impl<'a> TryFrom<tokio_udev::Device> for UdevDevice {
       .to_str()
       .ok_or_else(|| UdevDeviceError::invalid_subsystem(value.subsystem().unwrap()))?
       .intern();
+    // a devtype that isn't a valid string is treated like a missing one
+    let devtype = value
+      .devtype()
+      .and_then(|devtype| devtype.to_str())
+      .map(|devtype| devtype.intern());
     let syspath = value
       .syspath()
       .to_str()
@@ -211,7 +411,9 @@ impl<'a> TryFrom<tokio_udev::Device> for UdevDevice {
       .intern();
 
     let mut attributes = BTreeMap::new();
-    for device in value.hierarchy() {
+    let mut parents = Vec::new();
+    for (level, device) in value.hierarchy().enumerate() {
+      let mut level_attributes = BTreeMap::new();
       for attribute in device.attributes() {
         let name = attribute
           .name()
@@ -227,20 +429,32 @@ impl<'a> TryFrom<tokio_udev::Device> for UdevDevice {
           };
 
           attributes.entry(name).or_insert(value);
+          level_attributes.insert(name, value);
         }
       }
+
+      // the device itself is covered by `subsystem` and the flattened attributes
+      if level > 0 {
+        let subsystem = device
+          .subsystem()
+          .and_then(|subsystem| subsystem.to_str())
+          .map(|subsystem| subsystem.intern());
+        parents.push(Parent {
+          subsystem,
+          attributes: level_attributes,
+        });
+      }
     }
 
-    let id_hash = seahash::hash(syspath.as_bytes());
-    let id_hash_bytes = id_hash.to_le_bytes();
-    let id_string = base64::encode(&id_hash_bytes);
-    let id = id_string.intern();
     let inner = Inner {
-      id,
+      id: device_id(&syspath),
       subsystem,
+      devtype,
       syspath,
       devnode,
+      numa_node: numa_node(&attributes),
       attributes,
+      parents,
     };
     Ok(UdevDevice(Arc::new(inner)))
   }
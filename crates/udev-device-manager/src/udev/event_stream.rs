@@ -1,12 +1,13 @@
-use super::{UdevDevice, UdevDeviceError};
+use super::UdevDevice;
 use crate::config::InternedString;
 use futures::{Stream, StreamExt};
 use pin_project::pin_project;
 use std::{
   convert::{TryFrom, TryInto},
-  io,
+  fmt, io,
   pin::Pin,
   task::{Context, Poll},
+  time::Duration,
 };
 use thiserror::Error;
 use tokio::{
@@ -17,8 +18,10 @@ use tokio::{
     oneshot::{self, error::RecvError},
   },
   task::{JoinError, LocalSet},
+  time,
 };
-use tokio_udev::AsyncMonitorSocket;
+use tokio_udev::{AsyncMonitorSocket, MonitorBuilder};
+use tracing::{event, Level};
 
 #[derive(Clone, Debug)]
 pub enum UdevEvent {
@@ -31,6 +34,18 @@ pub enum UdevEvent {
 }
 
 impl UdevEvent {
+  /// Name of the event type, e.g. `add`
+  pub fn kind(&self) -> &'static str {
+    match self {
+      UdevEvent::Add(_) => "add",
+      UdevEvent::Change(_) => "change",
+      UdevEvent::Remove(_) => "remove",
+      UdevEvent::Bind(_) => "bind",
+      UdevEvent::Unbind(_) => "unbind",
+      UdevEvent::Unknown(_) => "unknown",
+    }
+  }
+
   pub fn device(&self) -> &UdevDevice {
     match self {
       UdevEvent::Add(device)
@@ -81,10 +96,39 @@ pub enum UdevBuilderError {
   #[error(transparent)]
   Io(#[from] io::Error),
 
+  /// Devices can still be enumerated, but the netlink monitor can't be set up, usually for
+  /// lack of `CAP_NET_ADMIN` or a seccomp profile blocking netlink sockets
+  #[error("udev devices can be enumerated, but not monitored: {0}")]
+  MonitorUnavailable(#[source] io::Error),
+
   #[error(transparent)]
   Join(#[from] JoinError),
 }
 
+/// An error on the udev event stream
+#[derive(Debug, Error)]
+pub enum UdevStreamError {
+  /// The monitor socket failed and was set up again with the same filters. Events may have
+  /// been missed in between, but the stream goes on.
+  #[error("udev monitor failed and was reconnected: {0}")]
+  Reconnected(#[source] io::Error),
+
+  /// The monitor socket failed and couldn't be set up again, so the stream ends
+  #[error("udev monitor failed ({error}) and could not be reconnected: {reconnect}")]
+  Fatal {
+    error: io::Error,
+    #[source]
+    reconnect: io::Error,
+  },
+}
+
+impl UdevStreamError {
+  /// Whether the stream goes on after the error
+  pub fn is_recoverable(&self) -> bool {
+    matches!(self, Self::Reconnected(_))
+  }
+}
+
 impl<T> From<SendError<T>> for UdevBuilderError {
   fn from(_: SendError<T>) -> Self {
     Self::SendError
@@ -97,6 +141,66 @@ impl From<RecvError> for UdevBuilderError {
   }
 }
 
+/// How often a failed monitor socket is set up again before the stream gives up
+const RECONNECT_ATTEMPTS: u32 = 5;
+
+/// Delay before the first reconnect attempt, doubled after every failed one
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// A filter applied to the monitor, kept so a reconnected monitor can be set up the same way
+#[derive(Debug, Clone, Copy)]
+enum Filter {
+  Subsystem(InternedString),
+  SubsystemDevtype(InternedString, InternedString),
+  Tag(InternedString),
+}
+
+impl Filter {
+  /// Subsystem the filter restricts events to, if any
+  fn subsystem(self) -> Option<InternedString> {
+    match self {
+      Filter::Subsystem(subsystem) | Filter::SubsystemDevtype(subsystem, _) => Some(subsystem),
+      Filter::Tag(_) => None,
+    }
+  }
+
+  /// Whether an event passes the subsystem filters, like libudev checks the events it receives.
+  /// Device types and tags are left to the monitor socket, as devices don't keep them. Unlike
+  /// the monitor, which passes everything without any filter, no filters pass no events,
+  /// matching [`DeviceScanner`](super::DeviceScanner).
+  fn passes(filters: &[Filter], event: &UdevEvent) -> bool {
+    let mut subsystems = filters
+      .iter()
+      .filter_map(|filter| filter.subsystem())
+      .peekable();
+    match subsystems.peek() {
+      None => !filters.is_empty(),
+      Some(_) => subsystems.any(|subsystem| subsystem == event.device().subsystem()),
+    }
+  }
+
+  fn apply(self, builder: MonitorBuilder) -> io::Result<MonitorBuilder> {
+    match self {
+      Filter::Subsystem(subsystem) => builder.match_subsystem(subsystem),
+      Filter::SubsystemDevtype(subsystem, devtype) => {
+        builder.match_subsystem_devtype(subsystem, devtype)
+      }
+      Filter::Tag(tag) => builder.match_tag(tag),
+    }
+  }
+}
+
+/// Sets up a new monitor socket listening with the given filters
+fn connect(filters: &[Filter]) -> io::Result<AsyncMonitorSocket> {
+  let builder = filters
+    .iter()
+    .try_fold(MonitorBuilder::new()?, |builder, filter| {
+      filter.apply(builder)
+    })?;
+
+  AsyncMonitorSocket::new(builder.listen()?)
+}
+
 enum BuilderCommand {
   /// Adds a filter that matches events for devices with the given subsystem.
   MatchSubsystem(
@@ -125,7 +229,7 @@ enum BuilderCommand {
     oneshot::Sender<
       Result<
         (
-          Receiver<Result<UdevEvent, UdevDeviceError>>,
+          Receiver<Result<UdevEvent, UdevStreamError>>,
           oneshot::Sender<()>,
         ),
         UdevBuilderError,
@@ -148,55 +252,58 @@ impl UdevEventStreamBuilder {
     Ok(Self { sender })
   }
 
-  // /// Adds a filter that matches events for devices with the given subsystem.
-  // pub async fn match_subsystem(self, subsystem: InternedString) -> Result<Self, UdevBuilderError> {
-  //   let (sender, receiver) = oneshot::channel();
-  //   self
-  //     .sender
-  //     .send(BuilderCommand::MatchSubsystem(subsystem, sender))
-  //     .await?;
-  //   receiver.await?;
-  //   Ok(self)
-  // }
-
-  // /// Adds a filter that matches events for devices with the given subsystem and device type.
-  // pub async fn match_subsystem_devtype(
-  //   self,
-  //   subsystem: InternedString,
-  //   devtype: InternedString,
-  // ) -> Result<Self, UdevBuilderError> {
-  //   let (sender, receiver) = oneshot::channel();
-  //   self
-  //     .sender
-  //     .send(BuilderCommand::MatchSubsystemDevtype(
-  //       subsystem, devtype, sender,
-  //     ))
-  //     .await?;
-  //   receiver.await?;
-  //   Ok(self)
-  // }
-
-  // /// Adds a filter that matches events for devices with the given tag.
-  // pub async fn match_tag(self, tag: InternedString) -> Result<Self, UdevBuilderError> {
-  //   let (sender, receiver) = oneshot::channel();
-  //   self
-  //     .sender
-  //     .send(BuilderCommand::MatchTag(tag, sender))
-  //     .await?;
-  //   receiver.await?;
-  //   Ok(self)
-  // }
-
-  // /// Removes all filters currently set on the monitor.
-  // pub async fn clear_filters(self) -> Result<Self, UdevBuilderError> {
-  //   let (sender, receiver) = oneshot::channel();
-  //   self
-  //     .sender
-  //     .send(BuilderCommand::ClearFilters(sender))
-  //     .await?;
-  //   receiver.await?;
-  //   Ok(self)
-  // }
+  /// Adds a filter that matches events for devices with the given subsystem.
+  pub async fn match_subsystem(self, subsystem: InternedString) -> Result<Self, UdevBuilderError> {
+    let (sender, receiver) = oneshot::channel();
+    self
+      .sender
+      .send(BuilderCommand::MatchSubsystem(subsystem, sender))
+      .await?;
+    receiver.await??;
+    Ok(self)
+  }
+
+  /// Adds a filter that matches events for devices with the given subsystem and device type.
+  #[allow(dead_code)]
+  pub async fn match_subsystem_devtype(
+    self,
+    subsystem: InternedString,
+    devtype: InternedString,
+  ) -> Result<Self, UdevBuilderError> {
+    let (sender, receiver) = oneshot::channel();
+    self
+      .sender
+      .send(BuilderCommand::MatchSubsystemDevtype(
+        subsystem, devtype, sender,
+      ))
+      .await?;
+    receiver.await??;
+    Ok(self)
+  }
+
+  /// Adds a filter that matches events for devices with the given tag.
+  #[allow(dead_code)]
+  pub async fn match_tag(self, tag: InternedString) -> Result<Self, UdevBuilderError> {
+    let (sender, receiver) = oneshot::channel();
+    self
+      .sender
+      .send(BuilderCommand::MatchTag(tag, sender))
+      .await?;
+    receiver.await??;
+    Ok(self)
+  }
+
+  /// Removes all filters currently set on the monitor.
+  #[allow(dead_code)]
+  pub async fn clear_filters(self) -> Result<Self, UdevBuilderError> {
+    let (sender, receiver) = oneshot::channel();
+    self
+      .sender
+      .send(BuilderCommand::ClearFilters(sender))
+      .await?;
+    receiver.await??;
+    Ok(self)
+  }
 
   /// Listens for events matching the current filters.
   ///
@@ -218,109 +325,350 @@ impl UdevEventStreamBuilder {
   }
 
   async fn bg_task(mut receiver: Receiver<BuilderCommand>) -> Result<(), UdevBuilderError> {
-    let mut builder = tokio_udev::MonitorBuilder::new()?;
+    let mut builder = match MonitorBuilder::new() {
+      Ok(builder) => builder,
+      Err(e) => return Self::unavailable(receiver, e).await,
+    };
+    let mut filters = Vec::new();
     let (socket, sender, signal_receiver) = loop {
-      match receiver.recv().await {
-        Some(v) => match v {
-          BuilderCommand::MatchSubsystem(subsystem, ret) => {
-            builder = match builder.match_subsystem(subsystem) {
-              Ok(builder) => {
-                ret.send(Ok(()));
-                builder
-              }
-              Err(e) => {
-                ret.send(Err(e.into()));
-                return Ok(());
-              }
-            }
-          }
-          BuilderCommand::MatchSubsystemDevtype(subsystem, devtype, ret) => {
-            builder = match builder.match_subsystem_devtype(subsystem, devtype) {
-              Ok(builder) => {
-                ret.send(Ok(()));
-                builder
-              }
-              Err(e) => {
-                ret.send(Err(e.into()));
-                return Ok(());
-              }
-            }
-          }
-          BuilderCommand::MatchTag(tag, ret) => {
-            builder = match builder.match_tag(tag) {
-              Ok(builder) => {
-                ret.send(Ok(()));
-                builder
-              }
-              Err(e) => {
-                ret.send(Err(e.into()));
-                return Ok(());
-              }
-            }
-          }
-          BuilderCommand::ClearFilters(ret) => {
-            builder = match builder.clear_filters() {
-              Ok(builder) => {
-                ret.send(Ok(()));
-                builder
-              }
-              Err(e) => {
-                ret.send(Err(e.into()));
-                return Ok(());
-              }
-            }
-          }
-          BuilderCommand::Listen(ret) => match builder.listen().and_then(AsyncMonitorSocket::new) {
+      let (filter, ret) = match receiver.recv().await {
+        Some(BuilderCommand::MatchSubsystem(subsystem, ret)) => {
+          (Some(Filter::Subsystem(subsystem)), ret)
+        }
+        Some(BuilderCommand::MatchSubsystemDevtype(subsystem, devtype, ret)) => {
+          (Some(Filter::SubsystemDevtype(subsystem, devtype)), ret)
+        }
+        Some(BuilderCommand::MatchTag(tag, ret)) => (Some(Filter::Tag(tag)), ret),
+        Some(BuilderCommand::ClearFilters(ret)) => (None, ret),
+        Some(BuilderCommand::Listen(ret)) => {
+          match builder.listen().and_then(AsyncMonitorSocket::new) {
             Ok(socket) => {
               let (sender, receiver) = channel(1);
               let (signal_sender, signal_receiver) = oneshot::channel();
-              ret.send(Ok((receiver, signal_sender)));
+              reply(ret, Ok((receiver, signal_sender)));
               break (socket, sender, signal_receiver);
             }
             Err(e) => {
-              ret.send(Err(e.into()));
+              reply(ret, Err(UdevBuilderError::MonitorUnavailable(e)));
               return Ok(());
             }
-          },
-        },
+          }
+        }
         None => return Ok(()),
+      };
+
+      let applied = match filter {
+        Some(filter) => filter.apply(builder),
+        None => builder.clear_filters(),
+      };
+      builder = match applied {
+        Ok(builder) => {
+          match filter {
+            Some(filter) => filters.push(filter),
+            None => filters.clear(),
+          }
+          reply(ret, Ok(()));
+          builder
+        }
+        Err(e) => {
+          reply(ret, Err(e.into()));
+          return Ok(());
+        }
+      };
+    };
+
+    forward(
+      || connect(&filters),
+      &filters,
+      socket,
+      sender,
+      signal_receiver,
+    )
+    .await;
+    Ok(())
+  }
+
+  /// Answers every command with the error the monitor failed to be created with, so it surfaces
+  /// to whoever uses the builder instead of as a closed channel
+  async fn unavailable(
+    mut receiver: Receiver<BuilderCommand>,
+    error: io::Error,
+  ) -> Result<(), UdevBuilderError> {
+    let unavailable = || {
+      let error = io::Error::new(error.kind(), error.to_string());
+      UdevBuilderError::MonitorUnavailable(error)
+    };
+
+    while let Some(command) = receiver.recv().await {
+      match command {
+        BuilderCommand::MatchSubsystem(_, ret)
+        | BuilderCommand::MatchSubsystemDevtype(_, _, ret)
+        | BuilderCommand::MatchTag(_, ret)
+        | BuilderCommand::ClearFilters(ret) => reply(ret, Err(unavailable())),
+        BuilderCommand::Listen(ret) => reply(ret, Err(unavailable())),
       }
+    }
+
+    Ok(())
+  }
+}
+
+/// Forwards the events of `socket` passing `filters` until the event stream is dropped. A socket
+/// that fails or ends is replaced by one from `connect`, and only a socket that can't be
+/// replaced ends the stream.
+async fn forward<S, T>(
+  mut connect: impl FnMut() -> io::Result<S>,
+  filters: &[Filter],
+  mut socket: S,
+  sender: Sender<Result<UdevEvent, UdevStreamError>>,
+  signal_receiver: oneshot::Receiver<()>,
+) where
+  S: Stream<Item = io::Result<T>> + Unpin,
+  T: TryInto<UdevEvent>,
+{
+  let mut signal = futures::stream::once(signal_receiver);
+  loop {
+    let e = select! {
+      _ = signal.next() => return,
+      e = socket.next() => e,
     };
 
-    let mut socket: AsyncMonitorSocket = socket;
-    let mut signal = futures::stream::once(signal_receiver);
-    loop {
-      let e = select! {
-        _ = signal.next() => return Ok(()),
-        e = socket.next() => match e { None => return Ok(()), Some(e) => e },
-      };
+    let error = match e {
+      Some(Ok(evt)) => match evt.try_into() {
+        Ok(evt) if Filter::passes(filters, &evt) => {
+          if sender.send(Ok(evt)).await.is_err() {
+            return;
+          }
+          continue;
+        }
+        _ => continue,
+      },
+      Some(Err(e)) => e,
+      None => io::Error::new(io::ErrorKind::UnexpectedEof, "udev monitor socket closed"),
+    };
 
-      let to_send = match e {
-        Err(e) => Err(e.into()),
-        Ok(evt) => match evt.try_into() {
-          Ok(evt) => Ok(evt),
-          Err(_) => continue,
-        },
-      };
-      if let Err(e) = sender.send(to_send).await {
-        return Ok(());
+    let (to_send, fatal) = match reconnect(&mut connect).await {
+      Ok(new_socket) => {
+        socket = new_socket;
+        (UdevStreamError::Reconnected(error), false)
+      }
+      Err(reconnect) => (UdevStreamError::Fatal { error, reconnect }, true),
+    };
+
+    // dropping the event stream is how listening stops, so a closed channel is expected -
+    // only a monitor error going unreported is worth mentioning
+    if let Err(SendError(lost)) = sender.send(Err(to_send)).await {
+      if let Err(e) = lost {
+        event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          "udev monitor error after the event stream was closed: {}",
+          e
+        );
       }
+
+      return;
     }
+
+    if fatal {
+      return;
+    }
+  }
+}
+
+/// Sets up a new monitor socket, retrying with exponential backoff. Only the error of the last
+/// attempt is returned.
+async fn reconnect<S>(connect: &mut impl FnMut() -> io::Result<S>) -> io::Result<S> {
+  let mut delay = RECONNECT_DELAY;
+  let mut attempt = 1;
+  loop {
+    match connect() {
+      Ok(socket) => return Ok(socket),
+      Err(e) if attempt < RECONNECT_ATTEMPTS => {
+        event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          attempt,
+          "failed to reconnect udev monitor, retrying in {:?}: {}",
+          delay,
+          e
+        );
+
+        time::sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+      }
+      Err(e) => return Err(e),
+    }
+  }
+}
+
+/// Replies to a builder command. The requester may have stopped waiting for the reply, which
+/// is fine for successes, but an error nobody receives is logged instead of being swallowed.
+fn reply<T, E: fmt::Display>(ret: oneshot::Sender<Result<T, E>>, result: Result<T, E>) {
+  if let Err(Err(e)) = ret.send(result) {
+    event!(
+      target: "udev-device-manager",
+      Level::WARN,
+      "udev monitor error could not be reported, the requester is gone: {}",
+      e
+    );
   }
 }
 
 #[pin_project]
+#[must_use = "streams do nothing unless polled"]
 pub struct EventStream {
   signal: oneshot::Sender<()>,
 
   #[pin]
-  receiver: Receiver<Result<UdevEvent, UdevDeviceError>>,
+  receiver: Receiver<Result<UdevEvent, UdevStreamError>>,
 }
 
 impl Stream for EventStream {
-  type Item = Result<UdevEvent, UdevDeviceError>;
+  type Item = Result<UdevEvent, UdevStreamError>;
 
   fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
     self.project().receiver.poll_recv(cx)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_log::logged;
+
+  #[tokio::test]
+  async fn failed_monitor_socket_is_reconnected() {
+    use futures::stream;
+
+    let event = |devnode: &str| {
+      UdevEvent::Add(UdevDevice::from_parts(
+        "tty",
+        &format!("/sys/devices{}", devnode),
+        devnode,
+        vec![],
+      ))
+    };
+    let first = stream::iter(vec![
+      Ok(event("/dev/ttyACM0")),
+      Err(io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        "socket dropped",
+      )),
+    ])
+    .chain(stream::pending());
+    let second = stream::iter(vec![Ok(event("/dev/ttyACM1"))]).chain(stream::pending());
+
+    // the first reconnect attempt fails too, the second one gets the new socket
+    let mut sockets = vec![Ok(second), Err(io::Error::from(io::ErrorKind::NotFound))];
+    let connect = move || sockets.pop().expect("no more reconnects");
+
+    let (sender, mut receiver) = channel(1);
+    let (signal, signal_receiver) = oneshot::channel();
+    let filters = [Filter::Subsystem(InternedString::from("tty"))];
+    let forwarding =
+      tokio::spawn(async move { forward(connect, &filters, first, sender, signal_receiver).await });
+
+    let devnode =
+      |e: Option<Result<UdevEvent, UdevStreamError>>| e.unwrap().unwrap().device().devnode();
+    assert_eq!(devnode(receiver.recv().await), "/dev/ttyACM0");
+    let error = receiver.recv().await.unwrap().unwrap_err();
+    assert!(error.is_recoverable(), "{}", error);
+    assert_eq!(devnode(receiver.recv().await), "/dev/ttyACM1");
+
+    drop(signal);
+    forwarding.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn events_of_unlisted_subsystems_are_filtered_out() {
+    use futures::stream;
+
+    let event = |subsystem: &str, devnode: &str| {
+      Ok(UdevEvent::Add(UdevDevice::from_parts(
+        subsystem,
+        &format!("/sys/devices{}", devnode),
+        devnode,
+        vec![],
+      )))
+    };
+    let socket = stream::iter(vec![
+      event("block", "/dev/sda"),
+      event("tty", "/dev/ttyACM0"),
+      event("input", "/dev/input/event0"),
+      event("tty", "/dev/ttyUSB0"),
+    ])
+    .chain(stream::pending());
+    let connect = || -> io::Result<_> { unreachable!("the socket never fails") };
+    let filters = [
+      Filter::Subsystem(InternedString::from("tty")),
+      Filter::Tag(InternedString::from("seat")),
+    ];
+
+    let (sender, mut receiver) = channel(1);
+    let (signal, signal_receiver) = oneshot::channel();
+    let forwarding =
+      tokio::spawn(
+        async move { forward(connect, &filters, socket, sender, signal_receiver).await },
+      );
+
+    for devnode in &["/dev/ttyACM0", "/dev/ttyUSB0"] {
+      let event = receiver.recv().await.unwrap().unwrap();
+      assert_eq!(event.device().devnode(), *devnode);
+    }
+
+    drop(signal);
+    forwarding.await.unwrap();
+    assert!(receiver.recv().await.is_none());
+  }
+
+  #[test]
+  fn no_filters_pass_no_events() {
+    let event = UdevEvent::Add(UdevDevice::from_parts(
+      "tty",
+      "/sys/devices/ttyACM0",
+      "/dev/ttyACM0",
+      vec![],
+    ));
+    let tty = InternedString::from("tty");
+
+    assert!(Filter::passes(&[Filter::Subsystem(tty)], &event));
+    assert!(!Filter::passes(
+      &[Filter::Subsystem(InternedString::from("block"))],
+      &event
+    ));
+    assert!(!Filter::passes(&[], &event));
+
+    // device types and tags are checked by the monitor socket
+    assert!(Filter::passes(
+      &[Filter::SubsystemDevtype(
+        tty,
+        InternedString::from("usb_device")
+      )],
+      &event
+    ));
+    assert!(Filter::passes(
+      &[Filter::Tag(InternedString::from("seat"))],
+      &event
+    ));
+  }
+
+  #[test]
+  fn unreceived_error_replies_are_logged() {
+    let output = logged(|| {
+      let (ret, receiver) = oneshot::channel::<Result<(), UdevBuilderError>>();
+      drop(receiver);
+      reply(ret, Err(UdevBuilderError::SendError));
+    });
+    assert!(output.contains("WARN"), "{}", output);
+    assert!(output.contains("Failed to send command"), "{}", output);
+
+    let output = logged(|| {
+      let (ret, receiver) = oneshot::channel::<Result<(), UdevBuilderError>>();
+      drop(receiver);
+      reply(ret, Ok(()));
+    });
+    assert!(output.is_empty(), "{}", output);
+  }
+}
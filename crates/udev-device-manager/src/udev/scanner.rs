@@ -0,0 +1,93 @@
+use super::UdevDevice;
+use crate::config::{Config, InternedString};
+use std::{collections::BTreeSet, convert::TryFrom, io};
+use tokio_udev::Enumerator;
+use tracing::{event, Level};
+
+/// Enumerates the udev devices relevant to a config, so every scan filters the same way.
+///
+/// Only the subsystems of enabled device types are scanned; the device type selectors do the
+/// finer grained matching afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceScanner {
+  subsystems: BTreeSet<InternedString>,
+}
+
+impl DeviceScanner {
+  pub fn new(config: &Config) -> Self {
+    Self {
+      subsystems: config.subsystems(),
+    }
+  }
+
+  /// Whether a device passes the scanner's filter
+  pub fn accepts(&self, device: &UdevDevice) -> bool {
+    self.subsystems.contains(&device.subsystem())
+  }
+
+  /// Scans all current devices passing the filter. Devices that can't be represented (e.g.
+  /// ones without a devnode) are skipped.
+  pub fn scan(&self) -> io::Result<impl Iterator<Item = UdevDevice>> {
+    // without any subsystem the enumerator would match everything
+    if self.subsystems.is_empty() {
+      return Ok(Vec::new().into_iter());
+    }
+
+    let mut enumerator = Enumerator::new()?;
+    for subsystem in &self.subsystems {
+      enumerator.match_subsystem(subsystem)?;
+    }
+
+    let devices = enumerator
+      .scan_devices()?
+      .filter_map(|d| UdevDevice::try_from(d).ok())
+      .filter(|d| self.accepts(d))
+      .collect::<Vec<_>>();
+
+    event!(
+      target: "udev-device-manager",
+      Level::TRACE,
+      subsystems = ?self.subsystems,
+      devices.len = devices.len(),
+      "scanned udev devices"
+    );
+
+    Ok(devices.into_iter())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn filters_by_configured_subsystems() {
+    let config: Config = toml::from_str(
+      r#"
+        deviceClasses = []
+
+        [[devices]]
+        name = "conbee2"
+        subsystem = "tty"
+        labels = {}
+        selector = {}
+
+        [[devices]]
+        name = "disabled"
+        subsystem = "drm"
+        enabled = false
+        labels = {}
+        selector = {}
+      "#,
+    )
+    .unwrap();
+
+    let scanner = DeviceScanner::new(&config);
+    let device = |subsystem| UdevDevice::from_parts(subsystem, "/sys/devices/a", "/dev/a", vec![]);
+    assert!(scanner.accepts(&device("tty")));
+    assert!(!scanner.accepts(&device("drm")));
+    assert!(!scanner.accepts(&device("block")));
+
+    assert_eq!(DeviceScanner::default().scan().unwrap().count(), 0);
+  }
+}
@@ -1,4 +1,3 @@
-use arc_swap::ArcSwap;
 use color_eyre::{
   eyre::{self, eyre},
   Report, Section,
@@ -6,10 +5,10 @@ use color_eyre::{
 use std::{
   error::Error,
   fmt,
-  future::Future,
-  pin::Pin,
-  sync::Arc,
-  task::{Context, Poll, Waker},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  },
 };
 
 pub trait AggregateErrorExt {
@@ -65,73 +64,82 @@ where
   }
 }
 
-struct NotifySingleState {
-  waker: Option<Waker>,
-  ready: bool,
+/// Runs a task one at a time, for the latest of the values submitted to it. Values submitted
+/// while a run is in progress replace each other, and are handled by a single follow-up run
+/// made by the caller whose run is in progress - using that caller's task.
+pub struct SingleFlight<T> {
+  pending: Mutex<Option<T>>,
+  running: AtomicBool,
 }
 
-impl Default for NotifySingleState {
-  fn default() -> Self {
-    Self {
-      waker: None,
-      ready: false,
-    }
+impl<T> fmt::Debug for SingleFlight<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct(stringify!(SingleFlight))
+      .field("running", &self.running.load(Ordering::SeqCst))
+      .finish_non_exhaustive()
   }
 }
 
-#[derive(Clone, Default)]
-pub struct NotifySingle {
-  inner: Arc<ArcSwap<NotifySingleState>>,
-}
-
-impl fmt::Debug for NotifySingle {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    f.debug_struct(stringify!(NotifySingle))
-      .field("ready", &self.inner.load().ready)
-      .finish_non_exhaustive()
+impl<T> Default for SingleFlight<T> {
+  fn default() -> Self {
+    Self {
+      pending: Mutex::new(None),
+      running: AtomicBool::new(false),
+    }
   }
 }
 
-impl NotifySingle {
+impl<T> SingleFlight<T> {
   pub fn new() -> Self {
     Self::default()
   }
 
-  pub fn notify(&self) {
-    let state = self.inner.swap(Arc::new(NotifySingleState {
-      waker: None,
-      ready: true,
-    }));
+  fn take(&self) -> Option<T> {
+    self.pending.lock().unwrap().take()
+  }
+
+  pub fn run(&self, value: T, mut f: impl FnMut(T)) {
+    *self.pending.lock().unwrap() = Some(value);
+
+    loop {
+      if self.running.swap(true, Ordering::SeqCst) {
+        return;
+      }
+
+      while let Some(value) = self.take() {
+        f(value);
+      }
 
-    let state = match Arc::try_unwrap(state) {
-      Ok(v) => v,
-      Err(_) => unreachable!(),
-    };
+      self.running.store(false, Ordering::SeqCst);
 
-    match state.waker {
-      None => (),
-      Some(waker) => waker.wake(),
+      // a value submitted between the last take and clearing the flag is ours to run
+      if self.pending.lock().unwrap().is_none() {
+        return;
+      }
     }
   }
 }
 
-impl Future for NotifySingle {
-  type Output = ();
-
-  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-    let state = self.inner.swap(Arc::new(NotifySingleState {
-      waker: Some(cx.waker().clone()),
-      ready: false,
-    }));
-
-    let state = match Arc::try_unwrap(state) {
-      Ok(v) => v,
-      Err(_) => unreachable!(),
-    };
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::RefCell;
+
+  #[test]
+  fn single_flight_coalesces_requests_made_during_a_run() {
+    let flight = SingleFlight::new();
+    let runs = RefCell::new(Vec::new());
+
+    flight.run(0, |value| {
+      runs.borrow_mut().push(value);
+      if value == 0 {
+        // a storm of requests while the first run is in progress
+        for value in 1..=100 {
+          flight.run(value, |_| unreachable!("runs overlap"));
+        }
+      }
+    });
 
-    match state.ready {
-      true => Poll::Ready(()),
-      false => Poll::Pending,
-    }
+    assert_eq!(runs.into_inner(), vec![0, 100]);
   }
 }
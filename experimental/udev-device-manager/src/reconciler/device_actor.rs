@@ -24,6 +24,10 @@ pub enum DeviceActorCommand {
   GetInfo,
 }
 
+/// Notifications from a device actor. Events are sent one at a time, first to the actor's own
+/// distributor and then to the system wide one, so every consumer sees them in the order they
+/// were emitted. A config update always emits `InfoUpdated` before any `DevicesUpdated` it
+/// causes, so device updates are never interpreted against stale labels.
 #[derive(Debug, Clone)]
 pub enum DeviceActorEvent {
   InfoUpdated(DeviceTypeInfo),
@@ -75,12 +79,44 @@ impl DeviceActor {
     }
   }
 
+  fn info(&self, config: &Device) -> DeviceTypeInfo {
+    DeviceTypeInfo {
+      labels: config.labels.clone(),
+      events: self.events,
+    }
+  }
+
   fn notify(&self, event: DeviceActorEvent) {
-    let _ = system::device::events().tell_everyone(event.clone());
-    let _ = self.events.tell_everyone(event);
+    let distributors = [
+      (self.events, "device type"),
+      (system::device::events(), "system"),
+    ];
+
+    for &(distributor, name) in &distributors {
+      if let Err(e) = distributor.tell_everyone(event.clone()) {
+        event!(
+          target: "udev-device-manager",
+          Level::WARN,
+          ?event,
+          distributor = name,
+          "failed to deliver device event: {:?}",
+          e
+        );
+      }
+    }
   }
 }
 
+/// Events for a config update, in the order they are emitted.
+fn config_updated_events(info: DeviceTypeInfo, devices_changed: bool) -> Vec<DeviceActorEvent> {
+  let mut events = vec![DeviceActorEvent::InfoUpdated(info)];
+  if devices_changed {
+    events.push(DeviceActorEvent::DevicesUpdated);
+  }
+
+  events
+}
+
 #[async_trait]
 impl Actor for DeviceActor {
   const NAME: &'static str = "device";
@@ -126,15 +162,24 @@ impl Actor for DeviceActor {
           event!(target: "udev-device-manager", Level::DEBUG, device.len = len, device.name = &*config.name, "received UpdateConfig");
           config = c;
           *self.config.lock().await = config.clone();
-          // TODO: Re-scan devices
+
+          let matched = System::get_udev_devices()
+            .await?
+            .into_iter()
+            .filter(|dev| match_device(dev, &config))
+            .map(|dev| (dev.syspath(), dev))
+            .collect::<BTreeMap<_, _>>();
+          let devices_changed = !matched.keys().eq(devices.keys());
+          devices = matched;
+
+          for event in config_updated_events(self.info(&config), devices_changed) {
+            self.notify(event);
+          }
         }
 
         Message::GetInfo(sender) => {
           event!(target: "udev-device-manager", Level::DEBUG, device.len = len, device.name = &*config.name, "received GetInfo");
-          let _ = sender.reply(DeviceTypeInfo {
-            labels: config.labels.clone(),
-            events: self.events,
-          });
+          let _ = sender.reply(self.info(&config));
         }
 
         Message::DeviceUpserted(device) => match devices.entry(device.syspath()) {
@@ -205,3 +250,31 @@ fn match_device(device: &UdevDevice, config: &Device) -> bool {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn config_update_emits_info_before_devices() {
+    let info = DeviceTypeInfo {
+      labels: serde_json::from_str("{}").unwrap(),
+      events: Distributor::named("device-test:events"),
+    };
+
+    let events = config_updated_events(info.clone(), true);
+    assert!(matches!(
+      events.as_slice(),
+      [
+        DeviceActorEvent::InfoUpdated(_),
+        DeviceActorEvent::DevicesUpdated
+      ]
+    ));
+
+    let events = config_updated_events(info, false);
+    assert!(matches!(
+      events.as_slice(),
+      [DeviceActorEvent::InfoUpdated(_)]
+    ));
+  }
+}
@@ -47,9 +47,16 @@ impl Message {
 
 impl UdevManager {
   fn notify(&self, update: UdevEvent) {
-    // this typically starts before any listeners, and we want to ignore not reaching anyone.
-    // TODO: deal with different errors differently
-    let _ = system::udev::events().tell_everyone(update);
+    // this typically starts before any listeners, so not reaching anyone is expected and
+    // only worth a trace
+    if let Err(e) = system::udev::events().tell_everyone(update) {
+      event!(
+        target: "udev-device-manager",
+        Level::TRACE,
+        "udev event not delivered: {:?}",
+        e
+      );
+    }
   }
 
   fn notify_upserted(&self, device: Device) {
@@ -6,6 +6,7 @@ use futures::Stream;
 use futures::StreamExt;
 use pin_project::pin_project;
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
@@ -13,12 +14,13 @@ use tokio::{
   runtime::Builder,
   select,
   sync::{
-    mpsc::{channel, Receiver, Sender},
+    mpsc::{channel, error::SendError, Receiver, Sender},
     oneshot,
   },
   task::LocalSet,
 };
 use tokio_udev::AsyncMonitorSocket;
+use tracing::{event, Level};
 
 #[derive(Clone, Debug)]
 pub enum DeviceEvent {
@@ -186,11 +188,11 @@ impl EventStreamBuilder {
           BuilderCommand::MatchSubsystem(subsystem, ret) => {
             builder = match builder.match_subsystem(subsystem) {
               Ok(builder) => {
-                ret.send(Ok(()));
+                reply(ret, Ok(()));
                 builder
               }
               Err(e) => {
-                ret.send(Err(e.into()));
+                reply(ret, Err(e.into()));
                 return Ok(());
               }
             }
@@ -198,11 +200,11 @@ impl EventStreamBuilder {
           BuilderCommand::MatchSubsystemDevtype(subsystem, devtype, ret) => {
             builder = match builder.match_subsystem_devtype(subsystem, devtype) {
               Ok(builder) => {
-                ret.send(Ok(()));
+                reply(ret, Ok(()));
                 builder
               }
               Err(e) => {
-                ret.send(Err(e.into()));
+                reply(ret, Err(e.into()));
                 return Ok(());
               }
             }
@@ -210,11 +212,11 @@ impl EventStreamBuilder {
           BuilderCommand::MatchTag(tag, ret) => {
             builder = match builder.match_tag(tag) {
               Ok(builder) => {
-                ret.send(Ok(()));
+                reply(ret, Ok(()));
                 builder
               }
               Err(e) => {
-                ret.send(Err(e.into()));
+                reply(ret, Err(e.into()));
                 return Ok(());
               }
             }
@@ -222,11 +224,11 @@ impl EventStreamBuilder {
           BuilderCommand::ClearFilters(ret) => {
             builder = match builder.clear_filters() {
               Ok(builder) => {
-                ret.send(Ok(()));
+                reply(ret, Ok(()));
                 builder
               }
               Err(e) => {
-                ret.send(Err(e.into()));
+                reply(ret, Err(e.into()));
                 return Ok(());
               }
             }
@@ -235,11 +237,11 @@ impl EventStreamBuilder {
             Ok(socket) => {
               let (sender, receiver) = channel(1);
               let (signal_sender, signal_receiver) = oneshot::channel();
-              ret.send(Ok((receiver, signal_sender)));
+              reply(ret, Ok((receiver, signal_sender)));
               break (socket, sender, signal_receiver);
             }
             Err(e) => {
-              ret.send(Err(e.into()));
+              reply(ret, Err(e.into()));
               return Ok(());
             }
           },
@@ -263,13 +265,37 @@ impl EventStreamBuilder {
           Err(_) => continue,
         },
       };
-      if let Err(e) = sender.send(to_send).await {
+      // dropping the event stream is how listening stops, so a closed channel is expected -
+      // only a monitor error going unreported is worth mentioning
+      if let Err(SendError(lost)) = sender.send(to_send).await {
+        if let Err(e) = lost {
+          event!(
+            target: "udev-device-manager",
+            Level::WARN,
+            "udev monitor error after the event stream was closed: {}",
+            e
+          );
+        }
+
         return Ok(());
       }
     }
   }
 }
 
+/// Replies to a builder command. The requester may have stopped waiting for the reply, which
+/// is fine for successes, but an error nobody receives is logged instead of being swallowed.
+fn reply<T, E: fmt::Display>(ret: oneshot::Sender<Result<T, E>>, result: Result<T, E>) {
+  if let Err(Err(e)) = ret.send(result) {
+    event!(
+      target: "udev-device-manager",
+      Level::WARN,
+      "udev monitor error could not be reported, the requester is gone: {}",
+      e
+    );
+  }
+}
+
 #[pin_project]
 pub struct EventStream {
   signal: oneshot::Sender<()>,